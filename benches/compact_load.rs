@@ -0,0 +1,69 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId};
+
+use variant_forest::data_interface::multi_x::{XDf, MultiX, ColSplitIndex};
+use variant_forest::data_interface::y_bool::{YBool, Y};
+use variant_forest::data_interface::three_val::ThreeValCol;
+use variant_forest::random_forest::{Forest, RandomForest};
+use variant_forest::random_number_generator::Rng;
+
+const SEED: u64 = 139547392210478;
+const NROW: usize = 2000;
+
+fn new_threeval_col(x: &[i8]) -> MultiX {
+    return MultiX::ThreeVal(ThreeValCol::new(x));
+}
+
+fn sample_0_1(rng: &mut Rng, k: usize) -> Vec<i8> {
+    (0..k).map(|_| (rng.rand_uni() > 0.5) as i8).collect::<Vec<i8>>()
+}
+
+// Compact form of a forest fit on noise, so trees grow to full size.
+fn setup(ntree: usize, ncol: usize) -> Vec<u8> {
+    let mut rng = Rng::new(SEED, 1);
+    let y = YBool::new(&sample_0_1(&mut rng, NROW).iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new((0..ncol).map(|_| new_threeval_col(&sample_0_1(&mut rng, NROW))).collect());
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(SEED);
+    let forest = rf.fit(&my_df, &y, ntree, (ncol as f64).sqrt() as usize, false, None);
+    let mut out = vec![];
+    forest.write_compact(&mut out).unwrap();
+    return out;
+}
+
+fn bench_compact_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compact load");
+    group.sample_size(10);
+
+    for (ntree, ncol) in [(100, 1000), (1000, 200_000)] {
+        let compact = setup(ntree, ncol);
+        group.bench_with_input(BenchmarkId::new("read", ncol), &compact, |b, compact| b.iter(|| {
+            black_box(Forest::<Y, ColSplitIndex>::read_compact(&mut compact.as_slice()).unwrap())
+        }));
+        let forest = Forest::<Y, ColSplitIndex>::read_compact(&mut compact.as_slice()).unwrap();
+        group.bench_with_input(BenchmarkId::new("write", ncol), &forest, |b, forest| b.iter(|| {
+            let mut out = Vec::with_capacity(compact.len());
+            forest.write_compact(&mut out).unwrap();
+            black_box(out)
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_compact_load);
+criterion_main!(benches);
@@ -0,0 +1,82 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId};
+
+use variant_forest::data_interface::multi_x::{XDf, MultiX, ColSplitIndex};
+use variant_forest::data_interface::y_bool::{YBool, Y};
+use variant_forest::data_interface::three_val::ThreeValCol;
+use variant_forest::data_interface::DataInterface;
+use variant_forest::random_forest::RandomForest;
+use variant_forest::mask::Mask;
+use variant_forest::random_number_generator::Rng;
+use variant_forest::random_number_generator::factory::RngFactory;
+
+const SEED: u64 = 139547392210478;
+
+fn new_threeval_col(x: &[i8]) -> MultiX {
+    return MultiX::ThreeVal(ThreeValCol::new(x));
+}
+
+fn sample_0_1(rng: &mut Rng, k: usize) -> Vec<i8> {
+    (0..k).map(|_| (rng.rand_uni() > 0.5) as i8).collect::<Vec<i8>>()
+}
+
+fn setup(nrow: usize, ncol: usize) -> (XDf, YBool) {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..ncol).map(|_| sample_0_1(&mut rng, nrow)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    return (my_df, y);
+}
+
+// Split search alone, where mtry 1 skips the candidate range and the comparison of candidates.
+fn bench_split_search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("split search");
+    let rng_factory = RngFactory::new(SEED, Some(10_000), Some(1));
+
+    for ncol in [100, 10_000] {
+        let (my_df, y) = setup(200, ncol);
+        let mask = Mask::new((0..200).collect());
+        for mtry in [1, 2] {
+            let mut rng = Rng::new(SEED, 1);
+            group.bench_with_input(BenchmarkId::new(format!("mtry {mtry}"), ncol), &mask, |b, mask| b.iter(|| {
+                black_box(my_df.find_min_idx(mask, &y, mtry, &mut rng, &rng_factory, true))
+            }));
+        }
+    }
+    group.finish();
+}
+
+// Importance stability setting: many trees, one candidate per split.
+fn bench_importance_mtry_one(c: &mut Criterion) {
+    let mut group = c.benchmark_group("importance mtry 1");
+    group.sample_size(10);
+
+    for ncol in [100, 1000] {
+        let (my_df, y) = setup(1000, ncol);
+        group.bench_with_input(BenchmarkId::new("ntree 1000", ncol), &ncol, |b, _| b.iter(|| {
+            let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+            black_box(rf.importance(&my_df, &y, 1000, 1, false, None, None))
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_split_search, bench_importance_mtry_one);
+criterion_main!(benches);
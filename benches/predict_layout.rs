@@ -0,0 +1,83 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId};
+
+use variant_forest::data_interface::multi_x::{XDf, MultiX, ColSplitIndex, RowMajorXDf};
+use variant_forest::data_interface::y_bool::{YBool, Y};
+use variant_forest::data_interface::three_val::ThreeValCol;
+use variant_forest::tree::Tree;
+use variant_forest::mask::Mask;
+use variant_forest::random_number_generator::Rng;
+use variant_forest::random_number_generator::factory::RngFactory;
+
+const SEED: u64 = 139547392210478;
+const NTREE: usize = 10;
+
+fn new_threeval_col(x: &[i8]) -> MultiX {
+    return MultiX::ThreeVal(ThreeValCol::new(x));
+}
+
+fn sample_0_1(rng: &mut Rng, k: usize) -> Vec<i8> {
+    (0..k).map(|_| (rng.rand_uni() > 0.5) as i8).collect::<Vec<i8>>()
+}
+
+// Trees are grown on the first half of the rows and predict the second half.
+fn setup(nrow: usize, ncol: usize) -> (XDf, Vec<Tree<Y, ColSplitIndex>>, Mask) {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..ncol).map(|_| sample_0_1(&mut rng, nrow)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let rng_factory = RngFactory::new(SEED, Some(ncol), Some(NTREE));
+    let trees = (0..NTREE).map(|ith_tree| {
+        let mut tree = Tree::new(ith_tree, &rng_factory);
+        tree.build_tree(&my_df, &y, &Mask::new((0..nrow/2).collect()), 31, false, None);
+        tree
+    }).collect();
+
+    return (my_df, trees, Mask::new((nrow/2..nrow).collect()));
+}
+
+fn bench_predict_layout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("predict layout");
+    group.sample_size(10);
+
+    for nrow in [4000, 16_000, 64_000] {
+        let (my_df, trees, oob_mask) = setup(nrow, 1000);
+        let rows = RowMajorXDf::new(&my_df);
+
+        // Tree::predict is left out, it also fills the importance caches on every call.
+        group.bench_with_input(BenchmarkId::new("column major", nrow), &oob_mask, |b, mask| b.iter(|| {
+            for tree in trees.iter() {
+                black_box(tree.predict_rows(&my_df, mask));
+            }
+        }));
+        group.bench_with_input(BenchmarkId::new("row major", nrow), &oob_mask, |b, mask| b.iter(|| {
+            for tree in trees.iter() {
+                black_box(tree.predict_rows(&rows, mask));
+            }
+        }));
+        group.bench_with_input(BenchmarkId::new("row major build", nrow), &my_df, |b, df| b.iter(|| {
+            black_box(RowMajorXDf::new(df))
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_predict_layout);
+criterion_main!(benches);
@@ -61,7 +61,7 @@ fn setup(nrow: usize, ncol: usize) -> (XDf, YBool) {
 
 fn rf_importance_performance_big_nrow(my_df: &XDf, y: &YBool, ntree: usize, multithred: Option<usize>) {
     let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
-    let res = rf.importance(my_df, y, ntree, 31, false, None, multithred, false);
+    let res = rf.importance(my_df, y, ntree, 31, false, None, multithred);
 }
 
 fn bench_rayon(c: &mut Criterion) {
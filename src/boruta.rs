@@ -15,15 +15,284 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::collections::{HashMap, HashSet};
-use std::fmt::Debug;
-use crate::binom::binom_cdf;
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::clock::Instant;
+use crate::stats::{binom_cdf, wilcoxon_signed_rank, Alternative};
 use crate::data_interface::{ColumnIdentifiable, DataInterface, Response, Shadowable};
+use crate::data_interface::col_keys::ColKeys;
 use crate::data_interface::y_bool::Y;
-use crate::random_forest::RandomForest;
+use crate::random_forest::{Importance, RandomForest, ScoredImportance};
 use crate::random_number_generator::factory::RngFactory;
+use crate::ranked_importance::csv_field;
+use crate::screening::screen_columns;
+use crate::boruta_report::json_f64;
+use tracing::{info, info_span};
 
 const P_VALUE: f64 = 0.01;
 
+// Importance attributes are compared with their shadows on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportanceSource {
+    PermutationZscore,
+    // Mean decrease in gini impurity, much faster on wide data as no permutations are needed.
+    Gini,
+}
+
+// When shadows are shuffled again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadowPolicy {
+    // New permutations every iteration, so an attribute never faces the same shadows twice.
+    #[default]
+    PerIteration,
+    // The same shadow streams every iteration. Shadow i still permutes the i-th attribute left,
+    // so shadows change as attributes are decided.
+    Fixed,
+}
+
+// Test deciding tentative attributes, unless a decision rule is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecisionTest {
+    // Hits against p=0.5 over all iterations so far.
+    #[default]
+    Binomial,
+    // Permutation importance of the attribute against the highest shadow importance within each tree of the
+    // current iteration, one sided Wilcoxon signed-rank tests with Bonferroni correction. Needs PermutationZscore.
+    Wilcoxon,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Confirmed,
+    Rejected,
+    Tentative,
+}
+
+// Importance of the columns of a Boruta iteration's data, attributes and their shadows alike. per_tree holds the
+// importance within each tree (or other unit of the model) for DecisionTest::Wilcoxon, empty otherwise.
+// truncated is set when the model stopped at the deadline with part of its trees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProvidedImportance<T: Hash + Eq> {
+    pub importance: Importance<T>,
+    pub per_tree: Vec<Importance<T>>,
+    pub truncated: bool
+}
+
+// Source of the importance Boruta compares attributes with their shadows on, e.g. to run the shadow and decision
+// machinery on another model or on scores computed outside. ImportanceSource is the random forest one.
+pub trait ImportanceProvider<T, U, SplitIndex> where
+    SplitIndex: ColumnIdentifiable
+{
+    // Importance of every column of df with shadows. seed and ntree come from the iteration and BorutaConfig, for
+    // models that use them; per_tree is set when the decision test needs ProvidedImportance::per_tree. deadline is
+    // the end of BorutaConfig::set_time_budget, for models that can stop early.
+    fn importance(&self, df: &T, y: &U, seed: u64, ntree: usize, per_tree: bool, deadline: Option<Instant>) -> ProvidedImportance<SplitIndex::Col>;
+
+    // Importance of the attributes left after the last iteration, df having no shadows, for
+    // BorutaConfig::set_final_importance, with the OOB error of the same model if known. None leaves the final
+    // importance of BorutaRes empty.
+    fn final_importance(&self, _df: &T, _y: &U, _seed: u64, _ntree: usize) -> Option<ScoredImportance<SplitIndex::Col>> {
+        return None;
+    }
+}
+
+impl<T, U, SplitIndex> ImportanceProvider<T, U, SplitIndex> for ImportanceSource where
+    SplitIndex: ColumnIdentifiable + Clone + Copy + Send + Sync,
+    T: DataInterface<SplitIndex, Y> + Sync + Send,
+    U: Response<Y> + Sync + Send
+{
+    fn importance(&self, df: &T, y: &U, seed: u64, ntree: usize, per_tree: bool, deadline: Option<Instant>) -> ProvidedImportance<SplitIndex::Col> {
+        let mut rf = RandomForest::new(seed);
+        rf.set_deadline(deadline);
+        let mtry = default_mtry(df.get_col_ids().len());
+        let (importance, per_tree) = match (self, per_tree) {
+            (ImportanceSource::PermutationZscore, false) => (rf.zscore(df, y, ntree, mtry, false, None, None), vec![]),
+            (ImportanceSource::PermutationZscore, true) => {
+                let by_tree = rf.importance_by_tree(df, y, ntree, mtry, false, None, None);
+                (by_tree.zscore, by_tree.trees)
+            },
+            (ImportanceSource::Gini, false) => (rf.gini_importance(df, y, ntree, mtry, false, None, None), vec![]),
+            (ImportanceSource::Gini, true) => panic!("Wilcoxon decision test needs permutation importance.")
+        };
+        return ProvidedImportance{importance, per_tree, truncated: rf.get_truncation().is_some()};
+    }
+
+    // Permutation importance whatever the source, as in randomForest.
+    fn final_importance(&self, df: &T, y: &U, seed: u64, ntree: usize) -> Option<ScoredImportance<SplitIndex::Col>> {
+        let mut rf = RandomForest::new(seed);
+        rf.set_keep_oob_votes(true);
+        let mtry = default_mtry(df.get_col_ids().len());
+        return Some(rf.importance_scored(df, y, ntree, mtry, false, None, None));
+    }
+}
+
+// Decides a tentative attribute from its number of hits and the number of iterations so far.
+pub type DecisionRule = Arc<dyn Fn(usize, usize) -> Decision + Send + Sync>;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BorutaConfigError {
+    // DecisionTest::Wilcoxon needs per tree permutation importance, which ImportanceSource::Gini does not give.
+    WilcoxonNeedsPermutation,
+}
+
+impl fmt::Display for BorutaConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BorutaConfigError::WilcoxonNeedsPermutation => write!(f, "Wilcoxon decision test needs permutation importance"),
+        }
+    }
+}
+
+impl Error for BorutaConfigError {}
+
+#[derive(Clone)]
+pub struct BorutaConfig {
+    pval_th: f64,
+    max_runs: usize,
+    ntree: usize,
+    importance_source: ImportanceSource,
+    rough_fix: bool,
+    decision_rule: Option<DecisionRule>,
+    screening: Option<f64>,
+    seed: u64,
+    shadow_policy: ShadowPolicy,
+    final_importance: bool,
+    decision_test: DecisionTest,
+    keep_history: bool,
+    time_budget: Option<Duration>,
+}
+
+impl BorutaConfig {
+    pub fn new(pval_th: f64, max_runs: usize, ntree: usize) -> Self {
+        return BorutaConfig{pval_th, max_runs, ntree, importance_source: ImportanceSource::PermutationZscore, rough_fix: false, decision_rule: None, screening: None,
+                            seed: 0, shadow_policy: ShadowPolicy::default(), final_importance: true, decision_test: DecisionTest::default(),
+                            keep_history: false, time_budget: None};
+    }
+
+    // Seeds of shadows and forests of every iteration are derived from seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub fn set_shadow_policy(&mut self, shadow_policy: ShadowPolicy) {
+        self.shadow_policy = shadow_policy;
+    }
+
+    // Refit a forest of ntree trees on confirmed and tentative attributes after the last iteration, giving their
+    // importance without shadows. On by default.
+    pub fn set_final_importance(&mut self, final_importance: bool) {
+        self.final_importance = final_importance;
+    }
+
+    // Keep z-scores of every iteration in BorutaRes, e.g. for BorutaRes::write_history_csv. Off by default,
+    // as it takes memory per attribute and iteration.
+    pub fn set_keep_history(&mut self, keep_history: bool) {
+        self.keep_history = keep_history;
+    }
+
+    // Wall-clock budget of the run. No new iteration is started once it is spent, attributes still tentative stay
+    // so (or go to rough fix) and the final importance forest is skipped, see BorutaRes::is_truncated. The first
+    // iteration always runs.
+    pub fn set_time_budget(&mut self, time_budget: Option<Duration>) {
+        self.time_budget = time_budget;
+    }
+
+    // Settings as (name, JSON value) pairs, for BorutaReport.
+    pub(crate) fn report_params(&self) -> Vec<(&'static str, String)> {
+        let decision = match self.decision_rule {
+            Some(_) => "custom".to_string(),
+            None => format!("{:?}", self.decision_test)
+        };
+        return vec![
+            ("pval_th", json_f64(Some(self.pval_th))),
+            ("max_runs", self.max_runs.to_string()),
+            ("ntree", self.ntree.to_string()),
+            ("importance_source", format!("\"{:?}\"", self.importance_source)),
+            ("decision", format!("\"{}\"", decision)),
+            ("rough_fix", self.rough_fix.to_string()),
+            ("screening", json_f64(self.screening)),
+            ("seed", self.seed.to_string()),
+            ("shadow_policy", format!("\"{:?}\"", self.shadow_policy)),
+            ("final_importance", self.final_importance.to_string()),
+            ("time_budget_secs", json_f64(self.time_budget.map(|x| x.as_secs_f64())))
+        ];
+    }
+
+    // Fails for ImportanceSource::Gini under DecisionTest::Wilcoxon, leaving the config as it was.
+    pub fn set_importance_source(&mut self, importance_source: ImportanceSource) -> Result<(), BorutaConfigError> {
+        check_decision_test(importance_source, self.decision_test)?;
+        self.importance_source = importance_source;
+        return Ok(());
+    }
+
+    // Decide attributes still tentative after max_runs by comparing their median importance
+    // with the median of max shadow importance over all iterations.
+    pub fn set_rough_fix(&mut self, rough_fix: bool) {
+        self.rough_fix = rough_fix;
+    }
+
+    // Rejects columns failing the univariate pre-screen at pval_th before the first iteration, see screen_columns.
+    // Makes very wide data tractable, as screened columns are never copied or shadowed.
+    pub fn set_screening(&mut self, pval_th: Option<f64>) {
+        self.screening = pval_th;
+    }
+
+    // Fails for DecisionTest::Wilcoxon under ImportanceSource::Gini, leaving the config as it was.
+    pub fn set_decision_test(&mut self, decision_test: DecisionTest) -> Result<(), BorutaConfigError> {
+        check_decision_test(self.importance_source, decision_test)?;
+        self.decision_test = decision_test;
+        return Ok(());
+    }
+
+    // Replaces the default binomial test with Bonferroni correction. pval_th is then unused.
+    pub fn set_decision_rule<F>(&mut self, rule: F)
+    where
+        F: Fn(usize, usize) -> Decision + Send + Sync + 'static
+    {
+        self.decision_rule = Some(Arc::new(rule));
+    }
+}
+
+fn check_decision_test(importance_source: ImportanceSource, decision_test: DecisionTest) -> Result<(), BorutaConfigError> {
+    return match (importance_source, decision_test) {
+        (ImportanceSource::Gini, DecisionTest::Wilcoxon) => Err(BorutaConfigError::WilcoxonNeedsPermutation),
+        _ => Ok(())
+    };
+}
+
+// Two sided binomial test of hits against p=0.5 with Bonferroni correction over ntentative attributes.
+fn binomial_decision(hits: usize, iter: usize, pval_th: f64, ntentative: usize) -> Decision {
+    let pval_rej = binom_cdf(hits as u64, iter as u64, 0.5);
+    if pval_rej < pval_th/(ntentative as f64) {
+        return Decision::Rejected;
+    }
+
+    if hits > 0 {
+        let pval_conf = binom_cdf((hits-1) as u64, iter as u64, 0.5);
+        if pval_conf > 1. - pval_th/(ntentative as f64) {
+            return Decision::Confirmed;
+        }
+    }
+    return Decision::Tentative;
+}
+
+// Wilcoxon signed-rank tests of per tree differences between the attribute and the shadow max, Bonferroni
+// corrected over ntentative attributes as binomial_decision.
+fn wilcoxon_decision(diffs: &[f64], pval_th: f64, ntentative: usize) -> Decision {
+    if wilcoxon_signed_rank(diffs, Alternative::Less) < pval_th/(ntentative as f64) {
+        return Decision::Rejected;
+    }
+    if wilcoxon_signed_rank(diffs, Alternative::Greater) < pval_th/(ntentative as f64) {
+        return Decision::Confirmed;
+    }
+    return Decision::Tentative;
+}
+
+// Runs with seed 0, see BorutaConfig::set_seed for other seeds.
 pub fn boruta<T, U, SplitIndex>(df: T, y: U, pval_th: f64, max_runs: usize, ntree: usize) -> BorutaRes<SplitIndex>
 where
     SplitIndex: ColumnIdentifiable + Clone + Copy + Send + Sync + Debug,
@@ -31,6 +300,29 @@ where
     T: Shadowable<SplitIndex, Y> + Sync + Send,
     U: Response<Y> + Sync + Send
 {
+    return boruta_with_config(df, y, &BorutaConfig::new(pval_th, max_runs, ntree));
+}
+
+pub fn boruta_with_config<T, U, SplitIndex>(df: T, y: U, config: &BorutaConfig) -> BorutaRes<SplitIndex>
+where
+    SplitIndex: ColumnIdentifiable + Clone + Copy + Send + Sync + Debug,
+    SplitIndex::Col: Debug,
+    T: Shadowable<SplitIndex, Y> + Sync + Send,
+    U: Response<Y> + Sync + Send
+{
+    return boruta_with_provider(df, y, config, &config.importance_source);
+}
+
+// Boruta on the importance of provider, BorutaConfig::set_importance_source is then unused.
+pub fn boruta_with_provider<T, U, SplitIndex, P>(df: T, y: U, config: &BorutaConfig, provider: &P) -> BorutaRes<SplitIndex>
+where
+    SplitIndex: ColumnIdentifiable + Clone + Copy + Send + Sync + Debug,
+    SplitIndex::Col: Debug,
+    T: Shadowable<SplitIndex, Y> + Sync + Send,
+    U: Response<Y> + Sync + Send,
+    P: ImportanceProvider<T, U, SplitIndex>
+{
+    let (pval_th, max_runs, ntree) = (config.pval_th, config.max_runs, config.ntree);
     let mut iter = 0;
 
     let mut hits_map: HashMap<SplitIndex::Col, usize> = HashMap::new();
@@ -41,26 +333,66 @@ where
     let mut res = BorutaRes{
         tentative: df.get_col_ids(),
         confirmed: vec![],
-        rejected: vec![]
+        rejected: vec![],
+        rough_fixed: vec![],
+        screened: vec![],
+        hits: HashMap::new(),
+        iterations: 0,
+        seed: config.seed,
+        final_importance: HashMap::new(),
+        final_zscore: HashMap::new(),
+        final_oob_error: None,
+        zscore_history: HashMap::new(),
+        shadow_max_history: vec![],
+        truncated: false,
+        col_keys: df.get_col_keys().cloned()
     };
+    if let Some(screening_pval_th) = config.screening {
+        let screen = screen_columns(&df, &y, screening_pval_th);
+        info!(kept = screen.kept.len(), dropped = screen.dropped.len(), "Columns screened");
+        res.tentative = screen.kept;
+        res.rejected = screen.dropped.clone();
+        res.screened = screen.dropped;
+    }
+    let seed_factory = RngFactory::new(config.seed, None, None);
+    let deadline = config.time_budget.map(|budget| Instant::now() + budget);
 
     while iter < max_runs && res.tentative.len() > 0 {
+        if res.truncated || (iter > 0 && deadline.is_some_and(|deadline| Instant::now() >= deadline)) {
+            info!(iter, "Boruta stopped at the time budget");
+            res.truncated = true;
+            break;
+        }
         iter += 1;
-        println!("Iter {}", iter);
+        let _span = info_span!("boruta_iter", iter).entered();
         let idxs = res.tentative.iter().cloned().chain(res.confirmed.iter().cloned()).collect();
         let mut cur_df = df.subset(&idxs);
 
         // Add shadow variables
-        let rng_factory = RngFactory::new((iter+451256125) as u64, None, None); // TODO change static seed
-        cur_df.add_shadows(rng_factory);
+        let (shadow_seed, forest_seed) = iteration_seeds(&seed_factory, iter);
+        let shadow_seed = match config.shadow_policy {
+            ShadowPolicy::PerIteration => shadow_seed,
+            ShadowPolicy::Fixed => iteration_seeds(&seed_factory, 1).0
+        };
+        cur_df.add_shadows(RngFactory::new(shadow_seed, None, None));
 
         // importance calculation
-        let rf = RandomForest::new((iter+75754) as u64); // TODO should it be really static?
-        let zscores = rf.zscore(&cur_df, &y, ntree, (cur_df.get_col_ids().len() as f64).sqrt().floor() as usize, false, None, None);
+        let per_tree = config.decision_test == DecisionTest::Wilcoxon;
+        // The forest stops at the deadline too, this iteration then counts with the trees done and is the last
+        let provided = provider.importance(&cur_df, &y, forest_seed, ntree, per_tree, deadline);
+        res.truncated = provided.truncated;
+        if per_tree && provided.per_tree.is_empty() {
+            panic!("Wilcoxon decision test needs per tree importance.");
+        }
+        let (zscores, tree_importance) = (provided.importance, provided.per_tree);
 
         let idxs_attr_set: HashSet<SplitIndex::Col> = HashSet::from_iter(idxs.iter().cloned());
         let idxs_all_set = HashSet::from_iter(cur_df.get_col_ids().iter().cloned());
         let idxs_shadow_set = &idxs_all_set-&idxs_attr_set;
+        // Highest shadow importance of each tree, trees not splitting on shadows give 0
+        let tree_shadow_max: Vec<f64> = tree_importance.iter()
+            .map(|tree_imp| idxs_shadow_set.iter().filter_map(|idx| tree_imp.get(idx)).fold(0., |a, &b| f64::max(a, b)))
+            .collect();
 
         // when z-score > max shadow z-score add hit
         let max_shadow_zscore = idxs_shadow_set.iter()
@@ -72,40 +404,177 @@ where
             }
         }
 
-        // use binom to check if attr should be confirmed/rejected
-        for idx in res.tentative.iter() {
-            let hits = *hits_map.get(idx).unwrap();
-            let pval_rej = binom_cdf(hits as u64, iter as u64, 0.5);
-            if pval_rej < pval_th/(res.tentative.len() as f64) {
-                // Add to rejected
-                res.rejected.push(idx.clone());
+        if config.rough_fix || config.keep_history {
+            res.shadow_max_history.push(*max_shadow_zscore);
+            for idx in idxs.iter() {
+                if let Some(&z) = zscores.get(idx) {
+                    res.zscore_history.entry(*idx).or_insert(vec![]).push((iter, z));
+                }
             }
+        }
 
-            if hits > 0 {
-                let pval_conf = binom_cdf((hits-1) as u64, iter as u64, 0.5);
-                if pval_conf > 1. - pval_th/(res.tentative.len() as f64) {
-                    // Add to confirmed
-                    res.confirmed.push(idx.clone());
+        // use binom (or user rule) to check if attr should be confirmed/rejected
+        for idx in res.tentative.iter() {
+            let hits = *hits_map.get(idx).unwrap();
+            let decision = match (&config.decision_rule, config.decision_test) {
+                (Some(rule), _) => rule(hits, iter),
+                (None, DecisionTest::Binomial) => binomial_decision(hits, iter, pval_th, res.tentative.len()),
+                (None, DecisionTest::Wilcoxon) => {
+                    let diffs: Vec<f64> = tree_importance.iter().zip(tree_shadow_max.iter())
+                        .map(|(tree_imp, shadow_max)| tree_imp.get(idx).unwrap_or(&0.) - shadow_max)
+                        .collect();
+                    wilcoxon_decision(&diffs, pval_th, res.tentative.len())
                 }
+            };
+            match decision {
+                Decision::Rejected => res.rejected.push(idx.clone()),
+                Decision::Confirmed => res.confirmed.push(idx.clone()),
+                Decision::Tentative => {}
             }
         }
 
         // update tentative for further analysis
         let idxs_rejected: HashSet<SplitIndex::Col> = HashSet::from_iter(res.rejected.iter().cloned());
-        let idxs_confirmed = HashSet::from_iter((res.confirmed.iter().cloned()));
-        let idxs_tentative = &(&HashSet::from_iter((res.tentative.iter().cloned())) - &idxs_rejected) - &idxs_confirmed;
-        res.tentative = idxs_tentative.into_iter().collect();
-        println!("Tentative: {} Rejected: {} Confirmed: {}", res.tentative.len(), res.rejected.len(), res.confirmed.len());
+        let idxs_confirmed: HashSet<SplitIndex::Col> = HashSet::from_iter(res.confirmed.iter().cloned());
+        // Filtered in place, as the column order decides shadows and forests of the next iteration
+        res.tentative.retain(|idx| !idxs_rejected.contains(idx) && !idxs_confirmed.contains(idx));
+        info!(tentative = res.tentative.len(), rejected = res.rejected.len(), confirmed = res.confirmed.len(), "Boruta iteration finished");
+    }
+
+    res.hits = hits_map;
+    res.iterations = iter;
+
+    if config.rough_fix && res.tentative.len() > 0 {
+        let zscore_history: HashMap<SplitIndex::Col, Vec<f64>> = res.zscore_history.iter()
+            .map(|(col, history)| (*col, history.iter().map(|&(_, z)| z).collect()))
+            .collect();
+        let (confirmed, rejected) = rough_fix(&res.tentative, &zscore_history, &res.shadow_max_history);
+        res.confirmed.extend(confirmed.iter().cloned());
+        res.rejected.extend(rejected.iter().cloned());
+        res.rough_fixed = std::mem::take(&mut res.tentative);
+    }
+    if !config.keep_history {
+        res.zscore_history.clear();
+        res.shadow_max_history.clear();
+    }
+
+    let final_idxs: Vec<SplitIndex::Col> = res.confirmed.iter().chain(res.tentative.iter()).cloned().collect();
+    if config.final_importance && !res.truncated && final_idxs.len() > 0 {
+        let _span = info_span!("boruta_final", nattr = final_idxs.len()).entered();
+        let final_df = df.subset(&final_idxs);
+        let final_seed = iteration_seeds(&seed_factory, iter + 1).1;
+        if let Some(scored) = provider.final_importance(&final_df, &y, final_seed, ntree) {
+            res.final_importance = scored.importance;
+            res.final_zscore = scored.zscore;
+            res.final_oob_error = scored.oob_error;
+        }
     }
 
     return res;
 }
 
+// Two stage Boruta for very wide data. A gini importance forest of screen_ntree trees on all columns drops the
+// drop_fraction of columns with the lowest importance, Boruta then runs on the rest. Columns no tree split on
+// have zero importance, so they go first.
+pub fn boruta_two_stage<T, U, SplitIndex>(df: T, y: U, drop_fraction: f64, screen_ntree: usize, config: &BorutaConfig) -> TwoStageBorutaRes<SplitIndex>
+where
+    SplitIndex: ColumnIdentifiable + Clone + Copy + Send + Sync + Debug,
+    SplitIndex::Col: Debug,
+    T: Shadowable<SplitIndex, Y> + Sync + Send,
+    U: Response<Y> + Sync + Send
+{
+    if !(0. ..1.).contains(&drop_fraction) {
+        panic!("Drop fraction must be within [0, 1).");
+    }
+
+    let screen_importance = {
+        let _span = info_span!("boruta_gini_screen", screen_ntree).entered();
+        let mut rf = RandomForest::new(iteration_seeds(&RngFactory::new(config.seed, None, None), 0).1);
+        rf.set_include_unused(true);
+        let mtry = default_mtry(df.get_ncol());
+        rf.gini_importance(&df, &y, screen_ntree, mtry, false, None, None)
+    };
+
+    // Stable sort keeps column order among ties
+    let mut cols = df.get_col_ids();
+    cols.sort_by(|a, b| screen_importance[a].total_cmp(&screen_importance[b]));
+    let n_drop = (cols.len() as f64 * drop_fraction).floor() as usize;
+    let kept = cols.split_off(n_drop);
+    info!(kept = kept.len(), dropped = cols.len(), "Gini screening finished");
+
+    let boruta = boruta_with_config(df.subset(&kept), y, config);
+    return TwoStageBorutaRes{screen_importance, screen_dropped: cols, boruta};
+}
+
+// Seeds of (shadows, forest) of iteration iter, starting from 1. Iteration 0 is the screening of boruta_two_stage.
+fn iteration_seeds(seed_factory: &RngFactory, iter: usize) -> (u64, u64) {
+    let mut rng = seed_factory.new_rng_iteration(iter);
+    return (rng.next_u64(), rng.next_u64());
+}
+
+// Square root of the column count as in the Boruta R package, at least one and at most all columns.
+fn default_mtry(ncol: usize) -> usize {
+    return ((ncol as f64).sqrt().floor() as usize).max(1).min(ncol);
+}
+
+fn median(x: &[f64]) -> Option<f64> {
+    let mut x: Vec<f64> = x.iter().cloned().filter(|v| !v.is_nan()).collect();
+    if x.is_empty() {
+        return None;
+    }
+    x.sort_by(|a, b| a.total_cmp(b));
+    let mid = x.len() / 2;
+    return match x.len() % 2 {
+        0 => Some((x[mid-1] + x[mid]) / 2.),
+        _ => Some(x[mid])
+    };
+}
+
+// Splits tentative attributes into (confirmed, rejected). Attributes without any importance recorded are rejected.
+fn rough_fix<T: Hash + Eq + Copy>(tentative: &[T], zscore_history: &HashMap<T, Vec<f64>>, shadow_max_history: &[f64]) -> (Vec<T>, Vec<T>) {
+    let shadow_median = median(shadow_max_history).unwrap_or(f64::NEG_INFINITY);
+    let (confirmed, rejected) = tentative.iter().partition(|idx| {
+        zscore_history.get(idx)
+            .and_then(|z| median(z))
+            .is_some_and(|z| z > shadow_median)
+    });
+    return (confirmed, rejected);
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "SplitIndex::Col: serde::Serialize",
+    deserialize = "SplitIndex::Col: serde::Deserialize<'de>"
+)))]
 pub struct BorutaRes<SplitIndex: ColumnIdentifiable> {
     confirmed: Vec<SplitIndex::Col>,
     rejected: Vec<SplitIndex::Col>,
-    tentative: Vec<SplitIndex::Col>
+    tentative: Vec<SplitIndex::Col>,
+    rough_fixed: Vec<SplitIndex::Col>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    screened: Vec<SplitIndex::Col>,
+    hits: HashMap<SplitIndex::Col, usize>,
+    iterations: usize,
+    // Seed of the run, reproducing it with the same data and config.
+    #[cfg_attr(feature = "serde", serde(default))]
+    seed: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    final_importance: HashMap<SplitIndex::Col, f64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    final_zscore: HashMap<SplitIndex::Col, f64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    final_oob_error: Option<f64>,
+    // (iteration, z-score) of each attribute, iterations starting from 1.
+    #[cfg_attr(feature = "serde", serde(default))]
+    zscore_history: HashMap<SplitIndex::Col, Vec<(usize, f64)>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    shadow_max_history: Vec<f64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    truncated: bool,
+    // Keys of the columns of the data Boruta ran on, see XDf::set_col_keys.
+    #[cfg_attr(feature = "serde", serde(default))]
+    col_keys: Option<ColKeys<String, SplitIndex>>
 }
 
 impl<SplitIndex: ColumnIdentifiable> BorutaRes<SplitIndex> {
@@ -116,4 +585,238 @@ impl<SplitIndex: ColumnIdentifiable> BorutaRes<SplitIndex> {
     pub fn get_rejected(&self) -> Vec<SplitIndex::Col> {
         self.rejected.clone()
     }
-}
\ No newline at end of file
+
+    pub fn get_tentative(&self) -> Vec<SplitIndex::Col> {
+        self.tentative.clone()
+    }
+
+    // Keys of the columns when the data had them, to label any result of the run with ColKeys::by_key.
+    pub fn get_col_keys(&self) -> Option<&ColKeys<String, SplitIndex>> {
+        self.col_keys.as_ref()
+    }
+
+    // Keys of the confirmed attributes, None when the data had no keys.
+    pub fn get_confirmed_keys(&self) -> Option<Vec<String>> {
+        self.col_keys.as_ref().map(|keys| keys.keys_of(&self.confirmed))
+    }
+
+    pub fn get_rejected_keys(&self) -> Option<Vec<String>> {
+        self.col_keys.as_ref().map(|keys| keys.keys_of(&self.rejected))
+    }
+
+    pub fn get_tentative_keys(&self) -> Option<Vec<String>> {
+        self.col_keys.as_ref().map(|keys| keys.keys_of(&self.tentative))
+    }
+
+    // Final importance keyed by column keys, None when the data had no keys.
+    pub fn get_final_importance_by_key(&self) -> Option<HashMap<String, f64>> {
+        self.col_keys.as_ref().map(|keys| keys.by_key(&self.final_importance))
+    }
+
+    // Attributes decided by the rough fix step, they are also in confirmed or rejected.
+    pub fn get_rough_fixed(&self) -> Vec<SplitIndex::Col> {
+        self.rough_fixed.clone()
+    }
+
+    // Attributes rejected by the pre-screen, they are also in rejected.
+    pub fn get_screened(&self) -> Vec<SplitIndex::Col> {
+        self.screened.clone()
+    }
+
+    // Number of iterations each attribute beat the best shadow.
+    pub fn get_hits(&self) -> &HashMap<SplitIndex::Col, usize> {
+        &self.hits
+    }
+
+    pub fn get_iterations(&self) -> usize {
+        self.iterations
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Whether the run stopped at the time budget before max_runs iterations or deciding all attributes.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    // Importance in the final forest on confirmed and tentative attributes, empty without final_importance.
+    pub fn get_final_importance(&self) -> &HashMap<SplitIndex::Col, f64> {
+        &self.final_importance
+    }
+
+    pub fn get_final_zscore(&self) -> &HashMap<SplitIndex::Col, f64> {
+        &self.final_zscore
+    }
+
+    // OOB error of the final forest, None without final_importance or from providers not giving one.
+    pub fn get_final_oob_error(&self) -> Option<f64> {
+        self.final_oob_error
+    }
+
+    // Z-score of each attribute in every iteration it took part in, empty without BorutaConfig::set_keep_history.
+    pub fn get_zscore_history(&self) -> &HashMap<SplitIndex::Col, Vec<(usize, f64)>> {
+        &self.zscore_history
+    }
+
+    // Z-score of the best shadow in each iteration.
+    pub fn get_shadow_max_history(&self) -> &[f64] {
+        &self.shadow_max_history
+    }
+
+    // Importance history in long format for plots as Boruta's importance history chart: one row per attribute
+    // and iteration, plus the best shadow of each iteration named shadowMax. decision is the final one of the
+    // attribute, empty for shadowMax.
+    pub fn write_history_csv<W, F>(&self, w: &mut W, name: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Fn(&SplitIndex::Col) -> String
+    {
+        writeln!(w, "iteration,name,zscore,decision")?;
+        for (i, z) in self.shadow_max_history.iter().enumerate() {
+            writeln!(w, "{},shadowMax,{},", i+1, z)?;
+        }
+        let decided = self.confirmed.iter().map(|col| (col, Decision::Confirmed))
+            .chain(self.tentative.iter().map(|col| (col, Decision::Tentative)))
+            .chain(self.rejected.iter().map(|col| (col, Decision::Rejected)));
+        for (col, decision) in decided {
+            for (iter, z) in self.zscore_history.get(col).into_iter().flatten() {
+                writeln!(w, "{},{},{},{:?}", iter, csv_field(&name(col)), z, decision)?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+// Gini screening stage and Boruta on the columns it kept.
+#[derive(Debug)]
+pub struct TwoStageBorutaRes<SplitIndex: ColumnIdentifiable> {
+    screen_importance: Importance<SplitIndex::Col>,
+    screen_dropped: Vec<SplitIndex::Col>,
+    boruta: BorutaRes<SplitIndex>
+}
+
+impl<SplitIndex: ColumnIdentifiable> TwoStageBorutaRes<SplitIndex> {
+    // Gini importance of every column in the screening forest.
+    pub fn get_screen_importance(&self) -> &Importance<SplitIndex::Col> {
+        &self.screen_importance
+    }
+
+    // Columns dropped by screening, lowest importance first.
+    pub fn get_screen_dropped(&self) -> Vec<SplitIndex::Col> {
+        self.screen_dropped.clone()
+    }
+
+    pub fn get_boruta(&self) -> &BorutaRes<SplitIndex> {
+        &self.boruta
+    }
+
+    pub fn get_confirmed(&self) -> Vec<SplitIndex::Col> {
+        self.boruta.get_confirmed()
+    }
+
+    // Rejected by Boruta and dropped by screening.
+    pub fn get_rejected(&self) -> Vec<SplitIndex::Col> {
+        let mut rejected = self.boruta.get_rejected();
+        rejected.extend(self.screen_dropped.iter().cloned());
+        rejected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::boruta::{binomial_decision, iteration_seeds, median, rough_fix, wilcoxon_decision, Decision};
+    use crate::random_number_generator::factory::RngFactory;
+
+    #[test]
+    fn median_skips_nan() {
+        assert_eq!(median(&[3., 1., 2.]), Some(2.));
+        assert_eq!(median(&[4., f64::NAN, 1., 2., 3.]), Some(2.5));
+        assert_eq!(median(&[f64::NAN]), None);
+    }
+
+    #[test]
+    fn iteration_seeds_follow_seed() {
+        let factory = RngFactory::new(5, None, None);
+        assert_eq!(iteration_seeds(&factory, 1), iteration_seeds(&RngFactory::new(5, None, None), 1));
+        assert_ne!(iteration_seeds(&factory, 1), iteration_seeds(&factory, 2));
+        assert_ne!(iteration_seeds(&factory, 1), iteration_seeds(&RngFactory::new(6, None, None), 1));
+        let (shadow, forest) = iteration_seeds(&factory, 1);
+        assert_ne!(shadow, forest);
+    }
+
+    #[test]
+    fn binomial_decision_thresholds() {
+        assert_eq!(binomial_decision(0, 10, 0.01, 1), Decision::Rejected);
+        assert_eq!(binomial_decision(10, 10, 0.01, 1), Decision::Confirmed);
+        assert_eq!(binomial_decision(5, 10, 0.01, 1), Decision::Tentative);
+        // Bonferroni correction over many tentative attributes needs more evidence
+        assert_eq!(binomial_decision(0, 10, 0.01, 100), Decision::Tentative);
+    }
+
+    #[test]
+    fn wilcoxon_decision_thresholds() {
+        let above: Vec<f64> = (1..=20).map(|i| i as f64 / 100.).collect();
+        let below: Vec<f64> = above.iter().map(|x| -x).collect();
+        let mixed: Vec<f64> = above.iter().zip(below.iter()).flat_map(|(&a, &b)| [a, b]).collect();
+        assert_eq!(wilcoxon_decision(&above, 0.01, 1), Decision::Confirmed);
+        assert_eq!(wilcoxon_decision(&below, 0.01, 1), Decision::Rejected);
+        assert_eq!(wilcoxon_decision(&mixed, 0.01, 1), Decision::Tentative);
+        assert_eq!(wilcoxon_decision(&above, 0.01, 10_000), Decision::Tentative);
+    }
+
+    #[test]
+    fn rough_fix_compares_medians() {
+        let history = HashMap::from([
+            (0, vec![5., 1., 4.]),
+            (1, vec![1., 2., 9.]),
+            (2, vec![])
+        ]);
+        let (confirmed, rejected) = rough_fix(&[0, 1, 2, 3], &history, &[3., 2., 3.5]);
+        assert_eq!(confirmed, vec![0]);
+        assert_eq!(rejected, vec![1, 2, 3]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn boruta_res_serde() {
+        use crate::boruta::BorutaRes;
+        use crate::data_interface::col_keys::ColKeys;
+        use crate::data_interface::multi_x::{ColSplitIndex, SplitColId};
+
+        let col = |col_id| SplitColId{col_id, shadow: false};
+        let res: BorutaRes<ColSplitIndex> = BorutaRes {
+            confirmed: vec![col(0)],
+            rejected: vec![col(1), col(2)],
+            tentative: vec![],
+            rough_fixed: vec![col(2)],
+            screened: vec![],
+            hits: HashMap::from([(col(0), 10), (col(1), 0), (col(2), 1)]),
+            iterations: 10,
+            seed: 3,
+            final_importance: HashMap::from([(col(0), 0.2)]),
+            final_zscore: HashMap::from([(col(0), 5.)]),
+            final_oob_error: Some(0.1),
+            zscore_history: HashMap::from([(col(0), vec![(1, 4.), (2, 5.)])]),
+            shadow_max_history: vec![1.5, 2.],
+            truncated: true,
+            col_keys: Some(ColKeys::new(vec!["rs0".to_string(), "rs1".to_string(), "rs2".to_string()]).unwrap())
+        };
+        let json = serde_json::to_string(&res).unwrap();
+        let parsed: BorutaRes<ColSplitIndex> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.get_confirmed(), res.get_confirmed());
+        assert_eq!(parsed.get_rejected(), res.get_rejected());
+        assert_eq!(parsed.get_rough_fixed(), res.get_rough_fixed());
+        assert_eq!(parsed.get_hits(), res.get_hits());
+        assert_eq!(parsed.get_iterations(), 10);
+        assert_eq!(parsed.get_seed(), 3);
+        assert!(parsed.is_truncated());
+        assert_eq!(parsed.get_final_zscore(), res.get_final_zscore());
+        assert_eq!(parsed.get_final_oob_error(), Some(0.1));
+        assert_eq!(parsed.get_zscore_history(), res.get_zscore_history());
+        assert_eq!(parsed.get_shadow_max_history(), res.get_shadow_max_history());
+        assert_eq!(parsed.get_confirmed_keys(), Some(vec!["rs0".to_string()]));
+    }
+}
@@ -0,0 +1,288 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::cmp::Reverse;
+use std::io::{self, Write};
+
+use crate::boruta::{BorutaConfig, BorutaRes};
+use crate::data_interface::ColumnIdentifiable;
+
+// Default template of BorutaReport::write_html. Each {{placeholder}} is replaced by the matching part of the
+// report, see write_html.
+pub const HTML_TEMPLATE: &str = "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>{{title}}</title></head>
+<body>
+<h1>{{title}}</h1>
+<h2>Parameters</h2>
+{{params}}
+<h2>Run</h2>
+{{summary}}
+<h2>Shadows</h2>
+{{shadow}}
+<h2>Confirmed</h2>
+{{confirmed}}
+<h2>Tentative</h2>
+{{tentative}}
+<h2>Rejected</h2>
+{{rejected}}
+</body>
+</html>
+";
+
+// Summary of a Boruta run: parameters, iterations, attribute tables and shadow statistics, written as JSON or as
+// HTML from a template. Attributes are listed by decision, most hits first.
+pub struct BorutaReport<'a, SplitIndex: ColumnIdentifiable> {
+    res: &'a BorutaRes<SplitIndex>,
+    params: Vec<(&'static str, String)>,
+    title: String
+}
+
+// Best shadow z-score over the iterations of BorutaRes::get_shadow_max_history.
+struct ShadowStats {
+    iterations: usize,
+    mean: f64,
+    min: f64,
+    max: f64
+}
+
+struct AttrRow {
+    name: String,
+    hits: usize,
+    final_importance: Option<f64>,
+    final_zscore: Option<f64>,
+    mean_zscore: Option<f64>,
+    rough_fixed: bool,
+    screened: bool
+}
+
+impl<'a, SplitIndex> BorutaReport<'a, SplitIndex> where
+    SplitIndex: ColumnIdentifiable
+{
+    // Report of res, config being the one of the run.
+    pub fn new(res: &'a BorutaRes<SplitIndex>, config: &BorutaConfig) -> Self {
+        return BorutaReport{res, params: config.report_params(), title: "Boruta feature selection".to_string()};
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+    }
+
+    // Single line JSON object. Shadow statistics are null unless the run kept its history
+    // (BorutaConfig::set_keep_history or rough fix), non finite scores are null as well.
+    pub fn write_json<W, F>(&self, w: &mut W, name: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Fn(&SplitIndex::Col) -> String
+    {
+        let params: Vec<String> = self.params.iter().map(|(key, val)| format!("{}:{}", json_str(key), val)).collect();
+        let shadow = match self.shadow_stats() {
+            Some(stats) => format!("{{\"iterations\":{},\"mean\":{},\"min\":{},\"max\":{}}}",
+                                   stats.iterations, json_f64(Some(stats.mean)), json_f64(Some(stats.min)), json_f64(Some(stats.max))),
+            None => "null".to_string()
+        };
+        write!(w, "{{\"title\":{},\"params\":{{{}}},\"iterations\":{},\"truncated\":{},\"seed\":{},\"final_oob_error\":{},\"shadow\":{}",
+               json_str(&self.title), params.join(","), self.res.get_iterations(), self.res.is_truncated(), self.res.get_seed(),
+               json_f64(self.res.get_final_oob_error()), shadow)?;
+        for (key, cols) in [("confirmed", self.res.get_confirmed()), ("tentative", self.res.get_tentative()), ("rejected", self.res.get_rejected())] {
+            let rows: Vec<String> = self.rows(&cols, &name).iter().map(|row| {
+                format!("{{\"name\":{},\"hits\":{},\"final_importance\":{},\"final_zscore\":{},\"mean_zscore\":{},\"rough_fixed\":{},\"screened\":{}}}",
+                        json_str(&row.name), row.hits, json_f64(row.final_importance), json_f64(row.final_zscore), json_f64(row.mean_zscore),
+                        row.rough_fixed, row.screened)
+            }).collect();
+            write!(w, ",\"{}\":[{}]", key, rows.join(","))?;
+        }
+        writeln!(w, "}}")?;
+        return Ok(());
+    }
+
+    // Fills template (e.g. HTML_TEMPLATE) replacing {{title}}, {{params}}, {{summary}}, {{shadow}}, {{confirmed}},
+    // {{tentative}} and {{rejected}} with escaped text and HTML tables. Other text is written as is.
+    pub fn write_html<W, F>(&self, w: &mut W, template: &str, name: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Fn(&SplitIndex::Col) -> String
+    {
+        let params = html_table(&["parameter", "value"], self.params.iter()
+            .map(|(key, val)| vec![key.to_string(), val.trim_matches('"').to_string()]));
+        let summary = html_table(&["iterations", "truncated", "seed", "final OOB error"], [vec![
+            self.res.get_iterations().to_string(), self.res.is_truncated().to_string(), self.res.get_seed().to_string(),
+            html_f64(self.res.get_final_oob_error())
+        ]].into_iter());
+        let shadow = match self.shadow_stats() {
+            Some(stats) => html_table(&["iterations", "mean", "min", "max"], [vec![
+                stats.iterations.to_string(), html_f64(Some(stats.mean)), html_f64(Some(stats.min)), html_f64(Some(stats.max))
+            ]].into_iter()),
+            None => "<p>Not kept, see BorutaConfig::set_keep_history.</p>".to_string()
+        };
+        let attr_table = |cols: Vec<SplitIndex::Col>| html_table(
+            &["name", "hits", "final importance", "final z-score", "mean z-score", "rough fixed", "screened"],
+            self.rows(&cols, &name).into_iter().map(|row| vec![
+                row.name, row.hits.to_string(), html_f64(row.final_importance), html_f64(row.final_zscore), html_f64(row.mean_zscore),
+                row.rough_fixed.to_string(), row.screened.to_string()
+            ])
+        );
+
+        let values = [
+            ("title", html_escape(&self.title)),
+            ("params", params),
+            ("summary", summary),
+            ("shadow", shadow),
+            ("confirmed", attr_table(self.res.get_confirmed())),
+            ("tentative", attr_table(self.res.get_tentative())),
+            ("rejected", attr_table(self.res.get_rejected()))
+        ];
+        w.write_all(fill_template(template, &values).as_bytes())?;
+        return Ok(());
+    }
+
+    fn shadow_stats(&self) -> Option<ShadowStats> {
+        let history = self.res.get_shadow_max_history();
+        if history.is_empty() {
+            return None;
+        }
+        return Some(ShadowStats{
+            iterations: history.len(),
+            mean: history.iter().sum::<f64>() / history.len() as f64,
+            min: history.iter().cloned().fold(f64::INFINITY, f64::min),
+            max: history.iter().cloned().fold(f64::NEG_INFINITY, f64::max)
+        });
+    }
+
+    fn rows<F>(&self, cols: &[SplitIndex::Col], name: &F) -> Vec<AttrRow>
+    where
+        F: Fn(&SplitIndex::Col) -> String
+    {
+        let (rough_fixed, screened) = (self.res.get_rough_fixed(), self.res.get_screened());
+        let mut rows: Vec<AttrRow> = cols.iter().map(|col| {
+            let history = self.res.get_zscore_history().get(col).filter(|history| !history.is_empty());
+            AttrRow{
+                name: name(col),
+                hits: self.res.get_hits().get(col).cloned().unwrap_or(0),
+                final_importance: self.res.get_final_importance().get(col).cloned(),
+                final_zscore: self.res.get_final_zscore().get(col).cloned(),
+                mean_zscore: history.map(|history| history.iter().map(|&(_, z)| z).sum::<f64>() / history.len() as f64),
+                rough_fixed: rough_fixed.contains(col),
+                screened: screened.contains(col)
+            }
+        }).collect();
+        rows.sort_by_key(|row| Reverse(row.hits));
+        return rows;
+    }
+}
+
+// Replaces each {{key}} of template with its value in a single pass, so placeholders inside the values (e.g. a
+// title of "{{confirmed}}") are written as is. Unknown placeholders are kept.
+fn fill_template(template: &str, values: &[(&str, String)]) -> String {
+    let mut res = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        res.push_str(&rest[..start]);
+        let tail = &rest[start + 2..];
+        let value = tail.find("}}").and_then(|end| {
+            values.iter().find(|(key, _)| *key == &tail[..end]).map(|(_, value)| (end, value))
+        });
+        match value {
+            Some((end, value)) => {
+                res.push_str(value);
+                rest = &tail[end + 2..];
+            },
+            None => {
+                res.push_str("{{");
+                rest = tail;
+            }
+        }
+    }
+    res.push_str(rest);
+    return res;
+}
+
+fn json_str(x: &str) -> String {
+    let mut res = String::with_capacity(x.len() + 2);
+    res.push('"');
+    for c in x.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c)
+        }
+    }
+    res.push('"');
+    return res;
+}
+
+// Finite numbers as is, NaN, infinities and None as null, which JSON has no other way to write.
+pub(crate) fn json_f64(x: Option<f64>) -> String {
+    return match x {
+        Some(x) if x.is_finite() => x.to_string(),
+        _ => "null".to_string()
+    };
+}
+
+fn html_f64(x: Option<f64>) -> String {
+    return x.map_or(String::new(), |x| format!("{:.4}", x));
+}
+
+fn html_escape(x: &str) -> String {
+    return x.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+}
+
+fn html_table<I>(header: &[&str], rows: I) -> String
+where
+    I: Iterator<Item = Vec<String>>
+{
+    let mut res = String::from("<table>\n<tr>");
+    for col in header {
+        res.push_str(&format!("<th>{}</th>", html_escape(col)));
+    }
+    res.push_str("</tr>\n");
+    for row in rows {
+        res.push_str("<tr>");
+        for val in row {
+            res.push_str(&format!("<td>{}</td>", html_escape(&val)));
+        }
+        res.push_str("</tr>\n");
+    }
+    res.push_str("</table>");
+    return res;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::boruta_report::{fill_template, html_escape, html_table, json_f64, json_str};
+
+    #[test]
+    fn report_escaping() {
+        assert_eq!(json_str("a\"b\\c\nd\t"), "\"a\\\"b\\\\c\\nd\\u0009\"");
+        assert_eq!(json_f64(Some(f64::NAN)), "null");
+        assert_eq!(json_f64(Some(f64::INFINITY)), "null");
+        assert_eq!(json_f64(None), "null");
+        assert_eq!(json_f64(Some(0.5)), "0.5");
+        assert_eq!(html_escape("<a href=\"x\">&</a>"), "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;");
+        assert_eq!(html_table(&["a<"], [vec!["1".to_string()]].into_iter()), "<table>\n<tr><th>a&lt;</th></tr>\n<tr><td>1</td></tr>\n</table>");
+    }
+
+    #[test]
+    fn report_template_single_pass() {
+        let values = [("title", "{{body}}".to_string()), ("body", "b".to_string())];
+        assert_eq!(fill_template("<h1>{{title}}</h1>{{body}}", &values), "<h1>{{body}}</h1>b");
+        assert_eq!(fill_template("{{other}} {{ {{body}}}}", &values), "{{other}} {{ b}}");
+        assert_eq!(fill_template("{{body", &values), "{{body");
+    }
+}
@@ -0,0 +1,205 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+// Maps forest scores (vote shares) to calibrated probabilities, fitted on out-of-bag scores, see Forest::calibrate.
+
+// Newton steps of Platt scaling before giving up on convergence.
+const PLATT_MAX_ITER: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CalibrationMethod {
+    // Logistic curve on the score, smooth and fine for few samples.
+    #[default]
+    Platt,
+    // Monotone step function, more flexible but needs more samples.
+    Isotonic,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Calibrator {
+    // p = 1 / (1 + exp(-(a * score + b)))
+    Platt{a: f64, b: f64},
+    // Mean score and label of each block of the isotonic fit, scores increasing. Interpolated linearly between.
+    Isotonic{scores: Vec<f64>, probs: Vec<f64>},
+}
+
+impl Calibrator {
+    pub fn fit(method: CalibrationMethod, scores: &[f64], labels: &[bool]) -> Calibrator {
+        if scores.len() != labels.len() {
+            panic!("Scores and labels must have the same length.");
+        }
+        if scores.is_empty() {
+            panic!("Calibration needs at least one sample.");
+        }
+
+        return match method {
+            CalibrationMethod::Platt => fit_platt(scores, labels),
+            CalibrationMethod::Isotonic => fit_isotonic(scores, labels),
+        };
+    }
+
+    pub fn calibrate(&self, score: f64) -> f64 {
+        return match self {
+            Calibrator::Platt{a, b} => sigmoid(a * score + b),
+            Calibrator::Isotonic{scores, probs} => {
+                let i = scores.partition_point(|&s| s < score);
+                match (i, i == scores.len()) {
+                    (_, true) => probs[scores.len() - 1],
+                    (0, _) => probs[0],
+                    _ => {
+                        let t = (score - scores[i-1]) / (scores[i] - scores[i-1]);
+                        probs[i-1] + t * (probs[i] - probs[i-1])
+                    }
+                }
+            }
+        };
+    }
+}
+
+#[inline]
+fn sigmoid(x: f64) -> f64 {
+    return match x >= 0. {
+        true => 1. / (1. + (-x).exp()),
+        false => x.exp() / (1. + x.exp())
+    };
+}
+
+// Platt's method: logistic regression of the labels on the score, with labels smoothed to (n_pos + 1) / (n_pos + 2)
+// and 1 / (n_neg + 2) so separable scores still give a finite fit.
+fn fit_platt(scores: &[f64], labels: &[bool]) -> Calibrator {
+    let n_pos = labels.iter().filter(|&&l| l).count() as f64;
+    let n_neg = labels.len() as f64 - n_pos;
+    let targets: Vec<f64> = labels.iter().map(|&l| match l {
+        true => (n_pos + 1.) / (n_pos + 2.),
+        false => 1. / (n_neg + 2.)
+    }).collect();
+    let loss = |a: f64, b: f64| -> f64 {
+        scores.iter().zip(targets.iter()).map(|(&s, &t)| {
+            let z = a * s + b;
+            // -t log p - (1 - t) log (1 - p), with log(1 + exp(z)) kept finite
+            z.max(0.) + (-z.abs()).exp().ln_1p() - t * z
+        }).sum()
+    };
+
+    let (mut a, mut b) = (0., ((n_pos + 1.) / (n_neg + 1.)).ln());
+    let mut cur = loss(a, b);
+    for _ in 0..PLATT_MAX_ITER {
+        let (mut ga, mut gb, mut haa, mut hab, mut hbb) = (0., 0., 1e-12, 0., 1e-12);
+        for (&s, &t) in scores.iter().zip(targets.iter()) {
+            let p = sigmoid(a * s + b);
+            let w = p * (1. - p);
+            ga += (p - t) * s;
+            gb += p - t;
+            haa += w * s * s;
+            hab += w * s;
+            hbb += w;
+        }
+        let det = haa * hbb - hab * hab;
+        let (da, db) = ((hbb * ga - hab * gb) / det, (haa * gb - hab * ga) / det);
+
+        // Halve the Newton step until the loss does not grow
+        let mut step = 1.;
+        while step > 1e-10 && loss(a - step * da, b - step * db) > cur {
+            step /= 2.;
+        }
+        let (new_a, new_b) = (a - step * da, b - step * db);
+        let converged = (new_a - a).abs() < 1e-10 && (new_b - b).abs() < 1e-10;
+        (a, b) = (new_a, new_b);
+        cur = loss(a, b);
+        if converged {
+            break;
+        }
+    }
+    return Calibrator::Platt{a, b};
+}
+
+// Pool adjacent violators on the labels sorted by score, tied scores pooled first.
+fn fit_isotonic(scores: &[f64], labels: &[bool]) -> Calibrator {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|&i, &j| scores[i].total_cmp(&scores[j]));
+
+    // (score, label sum, count) of each run of tied scores
+    let mut tied: Vec<(f64, f64, f64)> = Vec::with_capacity(scores.len());
+    for i in order {
+        let (s, l) = (scores[i], labels[i] as u8 as f64);
+        match tied.last_mut() {
+            Some(last) if last.0 == s => *last = (s, last.1 + l, last.2 + 1.),
+            _ => tied.push((s, l, 1.))
+        }
+    }
+
+    // (score sum, label sum, count) of each block
+    let mut blocks: Vec<(f64, f64, f64)> = Vec::with_capacity(tied.len());
+    for (s, l, n) in tied {
+        blocks.push((s * n, l, n));
+        while blocks.len() > 1 {
+            let (prev, last) = (blocks[blocks.len()-2], blocks[blocks.len()-1]);
+            if prev.1 / prev.2 < last.1 / last.2 {
+                break;
+            }
+            blocks.pop();
+            *blocks.last_mut().unwrap() = (prev.0 + last.0, prev.1 + last.1, prev.2 + last.2);
+        }
+    }
+    return Calibrator::Isotonic{
+        scores: blocks.iter().map(|b| b.0 / b.2).collect(),
+        probs: blocks.iter().map(|b| b.1 / b.2).collect()
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::assert_approx_eq;
+    use crate::calibration::{CalibrationMethod, Calibrator};
+
+    #[test]
+    fn platt_fits_logistic_curve() {
+        // Scores of positives overlap those of negatives, so the fit is finite and increasing
+        let scores = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, 0.3, 0.7];
+        let labels = [false, false, false, true, false, true, true, true, true, true, false];
+        let cal = Calibrator::fit(CalibrationMethod::Platt, &scores, &labels);
+        let Calibrator::Platt{a, b} = cal else { panic!("Expected a Platt calibrator") };
+        assert!(a > 0.);
+        assert!(cal.calibrate(0.1) < 0.5 && cal.calibrate(0.9) > 0.5);
+        assert_approx_eq!(f64, cal.calibrate(-b / a), 0.5);
+    }
+
+    #[test]
+    fn platt_separable_stays_finite() {
+        let cal = Calibrator::fit(CalibrationMethod::Platt, &[0., 0., 1., 1.], &[false, false, true, true]);
+        let (p0, p1) = (cal.calibrate(0.), cal.calibrate(1.));
+        assert!(p0.is_finite() && p1.is_finite());
+        assert!(p0 > 0. && p0 < 0.3);
+        assert!(p1 > 0.7 && p1 < 1.);
+    }
+
+    #[test]
+    fn isotonic_pools_violators() {
+        let scores = [0.1, 0.2, 0.3, 0.4, 0.5, 0.5];
+        let labels = [false, true, false, true, true, false];
+        let cal = Calibrator::fit(CalibrationMethod::Isotonic, &scores, &labels);
+        let Calibrator::Isotonic{scores: block_scores, probs} = &cal else { panic!("Expected an isotonic calibrator") };
+        assert_eq!(block_scores.len(), 3);
+        assert_approx_eq!(f64, block_scores[1], 0.25);
+        assert_approx_eq!(f64, block_scores[2], 1.4 / 3.);
+        assert_eq!(probs, &vec![0., 0.5, 2./3.]);
+        assert_eq!(cal.calibrate(0.), 0.);
+        assert_approx_eq!(f64, cal.calibrate(0.35), 0.5 + (0.35 - 0.25) / (1.4 / 3. - 0.25) * (2./3. - 0.5));
+        assert_approx_eq!(f64, cal.calibrate(0.9), 2./3.);
+    }
+}
@@ -0,0 +1,58 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+// Clock of timings and time budgets. std::time::Instant::now panics on wasm32-unknown-unknown, which has no
+// clock, so there time stands still: timings are zero and only a zero budget is ever spent.
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use std::time::Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub use still::Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod still {
+    use std::ops::Add;
+    use std::time::Duration;
+
+    // Now, or any point after it.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub enum Instant {
+        Now,
+        Later
+    }
+
+    impl Instant {
+        pub fn now() -> Instant {
+            return Instant::Now;
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            return Duration::ZERO;
+        }
+    }
+
+    impl Add<Duration> for Instant {
+        type Output = Instant;
+
+        fn add(self, rhs: Duration) -> Instant {
+            return match rhs.is_zero() {
+                true => self,
+                false => Instant::Later
+            };
+        }
+    }
+}
@@ -0,0 +1,392 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+// Compact binary form of fitted forests, see Forest::write_compact. Children are stored as their distance back to
+// the parent (children are pushed first), node kinds and pivots as 2 bit codes, column ids, classes and counts as
+// LEB128 varints, so a node takes a few bytes.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::calibration::Calibrator;
+use crate::data_interface::multi_x::{ColSplitIndex, MultiPivot};
+use crate::data_interface::three_val::ThreeValPivot;
+use crate::data_interface::dosage::DosagePivot;
+use crate::mask::Mask;
+
+// Start of every compact forest, the last byte being the format version. Version 2 added dosage splits to
+// the column codes, version 3 the other class of a binary calibration and tree weights, version 4 sample ids.
+// Older files are rejected as they would be misread.
+pub const COMPACT_MAGIC: &[u8; 4] = b"VFC4";
+
+// Node kind code of leaves, split nodes have the code of their pivot.
+pub(crate) const LEAF_CODE: u8 = 3;
+
+// Most elements allocated up front for a length read from the input. Longer sequences grow as their elements
+// are read, so a corrupt length fails at the end of the input instead of allocating memory it does not back.
+const MAX_PREALLOC: usize = 1 << 16;
+
+#[derive(Debug)]
+pub enum CompactError {
+    Io(io::Error),
+    // Input does not start with COMPACT_MAGIC, e.g. another format.
+    Magic,
+    // Compact forest of another format version, the version byte of its magic.
+    Version(u8),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for CompactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            CompactError::Io(e) => write!(f, "Reading compact forest failed: {}", e),
+            CompactError::Magic => write!(f, "Not a compact forest."),
+            CompactError::Version(v) => write!(f, "Compact forest of format version {}, expected {}.", *v as char, COMPACT_MAGIC[3] as char),
+            CompactError::Invalid(what) => write!(f, "Invalid compact forest: {}.", what)
+        };
+    }
+}
+
+impl Error for CompactError {}
+
+impl From<io::Error> for CompactError {
+    fn from(e: io::Error) -> Self {
+        return CompactError::Io(e);
+    }
+}
+
+// Leaf classes of compact forests, as small codes.
+pub trait CompactClass: Sized {
+    fn class_code(&self) -> u64;
+    fn from_class_code(code: u64) -> Option<Self>;
+}
+
+impl CompactClass for bool {
+    fn class_code(&self) -> u64 {
+        return *self as u64;
+    }
+
+    fn from_class_code(code: u64) -> Option<Self> {
+        return match code {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None
+        };
+    }
+}
+
+// Split indices of compact forests as a pivot code below LEAF_CODE and a column code.
+pub trait CompactSplit: Sized {
+    fn pivot_code(&self) -> u8;
+    fn col_code(&self) -> u64;
+    fn from_codes(pivot_code: u8, col_code: u64) -> Option<Self>;
+}
+
+impl CompactSplit for ColSplitIndex {
+    // Dosage splits take code 0, their threshold goes with the column code.
+    fn pivot_code(&self) -> u8 {
+        return match self.pivot {
+            MultiPivot::ThreeVal(ThreeValPivot::NotRed) => 0,
+            MultiPivot::ThreeVal(ThreeValPivot::NotGreen) => 1,
+            MultiPivot::ThreeVal(ThreeValPivot::NotBlue) => 2,
+            MultiPivot::Dosage(_) => 0
+        };
+    }
+
+    // Shadow flag in the lowest bit, dosage flag in the next one. Dosage splits keep the threshold code
+    // in the byte above the flags.
+    fn col_code(&self) -> u64 {
+        let col_id = self.col_id as u64;
+        return match self.pivot {
+            MultiPivot::ThreeVal(_) => col_id << 2 | self.shadow as u64,
+            MultiPivot::Dosage(p) => (col_id << 8 | p.code() as u64) << 2 | 2 | self.shadow as u64
+        };
+    }
+
+    fn from_codes(pivot_code: u8, col_code: u64) -> Option<Self> {
+        let shadow = col_code & 1 == 1;
+        if col_code & 2 == 2 {
+            if pivot_code != 0 {
+                return None;
+            }
+            let pivot = DosagePivot::from_code((col_code >> 2) as u8)?;
+            let col_id = usize::try_from(col_code >> 10).ok()?;
+            return Some(ColSplitIndex{col_id, pivot: MultiPivot::Dosage(pivot), shadow});
+        }
+        let pivot = match pivot_code {
+            0 => ThreeValPivot::NotRed,
+            1 => ThreeValPivot::NotGreen,
+            2 => ThreeValPivot::NotBlue,
+            _ => return None
+        };
+        let col_id = usize::try_from(col_code >> 2).ok()?;
+        return Some(ColSplitIndex{col_id, pivot: MultiPivot::ThreeVal(pivot), shadow});
+    }
+}
+
+pub(crate) fn write_varint<W: Write>(w: &mut W, mut x: u64) -> io::Result<()> {
+    let mut buf = [0u8; 10];
+    let mut n = 0;
+    loop {
+        let byte = (x & 0x7f) as u8;
+        x >>= 7;
+        match x {
+            0 => {
+                buf[n] = byte;
+                n += 1;
+                break;
+            },
+            _ => buf[n] = byte | 0x80
+        }
+        n += 1;
+    }
+    return w.write_all(&buf[..n]);
+}
+
+pub(crate) fn read_varint<R: Read>(r: &mut R) -> Result<u64, CompactError> {
+    let mut res = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8; 1];
+        r.read_exact(&mut byte)?;
+        res |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(res);
+        }
+    }
+    return Err(CompactError::Invalid("varint longer than 64 bits"));
+}
+
+pub(crate) fn read_usize<R: Read>(r: &mut R) -> Result<usize, CompactError> {
+    return usize::try_from(read_varint(r)?).map_err(|_| CompactError::Invalid("value out of usize range"));
+}
+
+// Optional values as varint x + 1, 0 for None.
+pub(crate) fn write_opt<W: Write>(w: &mut W, x: Option<usize>) -> io::Result<()> {
+    return write_varint(w, x.map_or(0, |x| x as u64 + 1));
+}
+
+pub(crate) fn read_opt<R: Read>(r: &mut R) -> Result<Option<usize>, CompactError> {
+    return Ok(read_usize(r)?.checked_sub(1));
+}
+
+// n elements read by read_one, see MAX_PREALLOC.
+pub(crate) fn read_vec<R, T, F>(r: &mut R, n: usize, mut read_one: F) -> Result<Vec<T>, CompactError>
+    where
+        R: Read,
+        F: FnMut(&mut R) -> Result<T, CompactError>
+{
+    let mut res = Vec::with_capacity(n.min(MAX_PREALLOC));
+    for _ in 0..n {
+        res.push(read_one(r)?);
+    }
+    return Ok(res);
+}
+
+pub(crate) fn write_flag<W: Write>(w: &mut W, flag: bool) -> io::Result<()> {
+    return w.write_all(&[flag as u8]);
+}
+
+pub(crate) fn read_flag<R: Read>(r: &mut R) -> Result<bool, CompactError> {
+    let mut byte = [0u8; 1];
+    r.read_exact(&mut byte)?;
+    return match byte[0] {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(CompactError::Invalid("flag other than 0 or 1"))
+    };
+}
+
+pub(crate) fn write_f64<W: Write>(w: &mut W, x: f64) -> io::Result<()> {
+    return w.write_all(&x.to_le_bytes());
+}
+
+pub(crate) fn read_f64<R: Read>(r: &mut R) -> Result<f64, CompactError> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    return Ok(f64::from_le_bytes(bytes));
+}
+
+// Length prefixed UTF-8.
+pub(crate) fn write_string<W: Write>(w: &mut W, x: &str) -> io::Result<()> {
+    write_varint(w, x.len() as u64)?;
+    return w.write_all(x.as_bytes());
+}
+
+pub(crate) fn read_string<R: Read>(r: &mut R) -> Result<String, CompactError> {
+    let len = read_usize(r)?;
+    let bytes = read_vec(r, len, |r| {
+        let mut byte = [0u8; 1];
+        r.read_exact(&mut byte)?;
+        Ok(byte[0])
+    })?;
+    return String::from_utf8(bytes).map_err(|_| CompactError::Invalid("string not in UTF-8"));
+}
+
+// 2 bit codes, four to a byte starting from the low bits.
+pub(crate) fn write_codes<W: Write>(w: &mut W, codes: &[u8]) -> io::Result<()> {
+    let packed: Vec<u8> = codes.chunks(4)
+        .map(|chunk| chunk.iter().enumerate().fold(0u8, |byte, (i, &code)| byte | (code & 3) << (2 * i)))
+        .collect();
+    return w.write_all(&packed);
+}
+
+pub(crate) fn read_codes<R: Read>(r: &mut R, n: usize) -> Result<Vec<u8>, CompactError> {
+    let n_bytes = n.div_ceil(4);
+    let mut packed = Vec::with_capacity(n_bytes.min(MAX_PREALLOC));
+    r.take(n_bytes as u64).read_to_end(&mut packed)?;
+    if packed.len() < n_bytes {
+        return Err(CompactError::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+    return Ok((0..n).map(|i| (packed[i / 4] >> (2 * (i % 4))) & 3).collect());
+}
+
+pub(crate) fn write_class<W: Write, Y: CompactClass>(w: &mut W, class: &Y) -> io::Result<()> {
+    return write_varint(w, class.class_code());
+}
+
+pub(crate) fn read_class<R: Read, Y: CompactClass>(r: &mut R) -> Result<Y, CompactError> {
+    return Y::from_class_code(read_varint(r)?).ok_or(CompactError::Invalid("unknown class code"));
+}
+
+// Rows as distances to the previous row, masks being sorted.
+pub(crate) fn write_mask<W: Write>(w: &mut W, mask: &Mask) -> io::Result<()> {
+    write_varint(w, mask.len() as u64)?;
+    let mut prev = 0;
+    for &row in mask.get_mask().iter() {
+        write_varint(w, (row - prev) as u64)?;
+        prev = row;
+    }
+    return Ok(());
+}
+
+pub(crate) fn read_mask<R: Read>(r: &mut R) -> Result<Mask, CompactError> {
+    let n = read_usize(r)?;
+    let mut prev = None;
+    let rows = read_vec(r, n, |r| {
+        let delta = read_usize(r)?;
+        let row = match prev {
+            None => delta,
+            Some(_) if delta == 0 => return Err(CompactError::Invalid("mask rows not increasing")),
+            Some(prev) => usize::checked_add(prev, delta).ok_or(CompactError::Invalid("mask row out of range"))?
+        };
+        prev = Some(row);
+        return Ok(row);
+    })?;
+    return Ok(Mask::from_sorted(rows));
+}
+
+pub(crate) fn write_calibrator<W: Write>(w: &mut W, calibrator: &Calibrator) -> io::Result<()> {
+    match calibrator {
+        Calibrator::Platt{a, b} => {
+            w.write_all(&[0])?;
+            write_f64(w, *a)?;
+            write_f64(w, *b)?;
+        },
+        Calibrator::Isotonic{scores, probs} => {
+            w.write_all(&[1])?;
+            write_varint(w, scores.len() as u64)?;
+            for &x in scores.iter().chain(probs.iter()) {
+                write_f64(w, x)?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+pub(crate) fn read_calibrator<R: Read>(r: &mut R) -> Result<Calibrator, CompactError> {
+    let mut tag = [0u8; 1];
+    r.read_exact(&mut tag)?;
+    return match tag[0] {
+        0 => Ok(Calibrator::Platt{a: read_f64(r)?, b: read_f64(r)?}),
+        1 => {
+            let n = read_usize(r)?;
+            let scores = read_vec(r, n, read_f64)?;
+            let probs = read_vec(r, n, read_f64)?;
+            Ok(Calibrator::Isotonic{scores, probs})
+        },
+        _ => Err(CompactError::Invalid("unknown calibrator"))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calibration::Calibrator;
+    use crate::compact::{read_calibrator, read_codes, read_mask, read_varint, write_calibrator, write_codes, write_mask, write_varint, CompactError, CompactSplit};
+    use crate::data_interface::multi_x::{ColSplitIndex, MultiPivot};
+    use crate::data_interface::three_val::ThreeValPivot;
+    use crate::data_interface::dosage::DosagePivot;
+    use crate::mask::Mask;
+
+    #[test]
+    fn compact_primitives() {
+        let mut out = vec![];
+        for x in [0, 1, 127, 128, 300, u64::MAX] {
+            write_varint(&mut out, x).unwrap();
+        }
+        assert_eq!(out.len(), 1 + 1 + 1 + 2 + 2 + 10);
+        let mut r = out.as_slice();
+        for x in [0, 1, 127, 128, 300, u64::MAX] {
+            assert_eq!(read_varint(&mut r).unwrap(), x);
+        }
+        assert!(matches!(read_varint(&mut [0x80u8; 11].as_slice()), Err(CompactError::Invalid(_))));
+        assert!(matches!(read_varint(&mut [0x80u8].as_slice()), Err(CompactError::Io(_))));
+
+        let codes = [0, 3, 2, 1, 1];
+        let mut out = vec![];
+        write_codes(&mut out, &codes).unwrap();
+        assert_eq!(out.len(), 2);
+        assert_eq!(read_codes(&mut out.as_slice(), 5).unwrap(), codes);
+
+        let mask = Mask::new(vec![3, 4, 200, 1000]);
+        let mut out = vec![];
+        write_mask(&mut out, &mask).unwrap();
+        assert_eq!(read_mask(&mut out.as_slice()).unwrap(), mask);
+
+        let isotonic = Calibrator::Isotonic{scores: vec![0.1, 0.5], probs: vec![0., 0.75]};
+        let mut out = vec![];
+        write_calibrator(&mut out, &isotonic).unwrap();
+        assert_eq!(read_calibrator(&mut out.as_slice()).unwrap(), isotonic);
+    }
+
+    #[test]
+    fn compact_untrusted_lengths() {
+        // Lengths far beyond the input fail at its end rather than allocate
+        let mut huge = vec![];
+        write_varint(&mut huge, 1 << 62).unwrap();
+        assert!(matches!(read_mask(&mut huge.as_slice()), Err(CompactError::Io(_))));
+        assert!(matches!(read_codes(&mut [0u8; 3].as_slice(), 1 << 62), Err(CompactError::Io(_))));
+        let mut calibrator = vec![1];
+        calibrator.extend(huge.iter());
+        assert!(matches!(read_calibrator(&mut calibrator.as_slice()), Err(CompactError::Io(_))));
+        assert!(matches!(read_mask(&mut [2u8, 5, 0].as_slice()), Err(CompactError::Invalid(_))));
+    }
+
+    #[test]
+    fn compact_split_codes() {
+        let split = ColSplitIndex{col_id: 199_999, pivot: MultiPivot::ThreeVal(ThreeValPivot::NotBlue), shadow: true};
+        assert_eq!(split.pivot_code(), 2);
+        let read = ColSplitIndex::from_codes(split.pivot_code(), split.col_code()).unwrap();
+        assert_eq!((read.col_id, read.pivot, read.shadow), (199_999, split.pivot, true));
+        assert!(ColSplitIndex::from_codes(3, split.col_code()).is_none());
+
+        let split = ColSplitIndex{col_id: 77, pivot: MultiPivot::Dosage(DosagePivot::new(1.25)), shadow: false};
+        let read = ColSplitIndex::from_codes(split.pivot_code(), split.col_code()).unwrap();
+        assert_eq!((read.col_id, read.pivot, read.shadow), (77, split.pivot, false));
+        assert!(ColSplitIndex::from_codes(1, split.col_code()).is_none());
+    }
+}
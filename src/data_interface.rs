@@ -14,9 +14,11 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
 use std::hash::Hash;
 use std::fmt::Debug;
 use crate::data_interface::y_bool::Y;
+use crate::data_interface::col_keys::ColKeys;
 
 use crate::mask::Mask;
 use crate::random_number_generator::factory::RngFactory;
@@ -24,7 +26,14 @@ use crate::random_number_generator::Rng;
 
 pub mod multi_x;
 pub mod three_val;
+pub mod dosage;
 pub mod y_bool;
+pub mod y_multi_bool;
+pub mod xdf_builder;
+pub mod typed_x;
+pub mod col_keys;
+#[cfg(feature = "ndarray")]
+pub mod ndarray_x;
 
 pub type Predicted<T> = Vec<T>;
 
@@ -32,46 +41,370 @@ pub trait Permutable {
     fn permute(&self, perm_rng: Rng, oob_mask: &Mask) -> Self;
 }
 
-pub trait Splittable<Y>: Permutable {
+// Splits of a column by a pivot, independent of the response.
+pub trait Partitionable: Permutable {
     type Pivot;
     fn split_with_pivot(&self, mask: &Mask, p: &Self::Pivot, shadow_rng: Option<Rng>) -> [Mask; 2];
-    fn gen_optimal_pivot<T>(&self, mask: &Mask, y:  &T, shadow_rng: Option<Rng>) -> (Self::Pivot, f64)
+    // As split_with_pivot, but clears and fills out with the two index sets, keeping mask order.
+    fn split_with_pivot_into(&self, mask: &Mask, p: &Self::Pivot, shadow_rng: Option<Rng>, out: &mut [Vec<usize>; 2]) {
+        let masks = self.split_with_pivot(mask, p, shadow_rng);
+        for (buf, mask) in out.iter_mut().zip(masks.iter()) {
+            buf.clear();
+            buf.extend_from_slice(mask.get_mask());
+        }
+    }
+    // Stable in place partition of sorted rows, rows of the first split_with_pivot mask go first.
+    // Returns their count. scratch is working space only.
+    fn partition_with_pivot(&self, rows: &mut [usize], p: &Self::Pivot, shadow_rng: Option<Rng>, scratch: &mut Vec<usize>) -> usize {
+        let mut out = [vec![], std::mem::take(scratch)];
+        self.split_with_pivot_into(&Mask::from_sorted(rows.to_vec()), p, shadow_rng, &mut out);
+        return copy_partition(rows, out, scratch);
+    }
+}
+
+pub trait Splittable<Y>: Partitionable {
+    // None when the column has a single level within mask, as no pivot can split it.
+    fn gen_optimal_pivot<T>(&self, mask: &Mask, y:  &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
     where
         T: Response<Y>;
+    // As gen_optimal_pivot, but a column sure to score above cutoff may be given up early with an infinite
+    // score. class_counts are those of y within mask, counted once per node for all candidate columns.
+    fn gen_optimal_pivot_bounded<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>, _class_counts: &[(Y, usize)], _cutoff: f64) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<Y>
+    {
+        return self.gen_optimal_pivot(mask, y, shadow_rng);
+    }
+    // As gen_optimal_pivot, with the gini of each side scaled by its n / (n - 1), for splits ranked on a
+    // subsample of the node, see DataInterface::find_min_idx_subsample. Falls back to the plain score.
+    fn gen_optimal_pivot_corrected<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<Y>
+    {
+        return self.gen_optimal_pivot(mask, y, shadow_rng);
+    }
+    // As gen_optimal_pivot, with row mask[i] counted weights[i] times, e.g. in-bag multiplicities of a bootstrap
+    // from Mask::from_draws. Falls back to repeating the rows in the mask.
+    fn gen_optimal_pivot_weighted<T>(&self, mask: &Mask, weights: &[u32], y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<Y>
+    {
+        let rows: Vec<usize> = mask.get_mask().iter().zip(weights.iter())
+            .flat_map(|(&i, &w)| std::iter::repeat(i).take(w as usize))
+            .collect();
+        return self.gen_optimal_pivot(&Mask::from_sorted(rows), y, shadow_rng);
+    }
+}
+
+// Loss permutation importance measures the increase of.
+// For a binary response, AUC of the hard class predictions of a tree is 1 - balanced error, so BalancedError gives
+// the AUC drop. With more classes BalancedError is the mean error rate of the classes, not an AUC.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorMetric {
+    #[default]
+    Misclassification,
+    // Errors of each class weighted by inverse class frequency within the mask, see Response::pred_balanced_error.
+    BalancedError,
+    // Log loss of the smoothed leaf probability of the true class, for proba_importance of the tree or forest.
+    // Hard predictions carry no probabilities, so importance on them does not take it.
+    CrossEntropy,
+    // Loss given to set_custom_error_metric of the tree or forest, which sets this variant.
+    Custom,
+}
+
+// Loss of predictions of the samples with the given true classes, summed over samples, so a mean loss per sample
+// is scaled by their number as in Response::pred_loss. RefUnwindSafe keeps trees and forests so.
+pub trait LossMetric<T>: Send + Sync + std::panic::RefUnwindSafe {
+    fn loss(&self, truth: &[T], preds: &[T]) -> f64;
+}
+
+impl<T: Copy + PartialEq> LossMetric<T> for ErrorMetric {
+    fn loss(&self, truth: &[T], preds: &[T]) -> f64 {
+        return match self {
+            ErrorMetric::Misclassification => truth.iter().zip(preds.iter()).filter(|(t, p)| t != p).count() as f64,
+            ErrorMetric::BalancedError => {
+                balanced_error(truth, &Mask::from_sorted((0..truth.len()).collect()), preds) * truth.len() as f64
+            },
+            ErrorMetric::CrossEntropy => panic!("Cross entropy must be computed on leaf probabilities, see proba_importance."),
+            ErrorMetric::Custom => panic!("Custom error metric must be given with set_custom_error_metric.")
+        };
+    }
+}
+
+// Mean of the error rates within each class present in mask, so every class weighs the same.
+fn balanced_error<T: Copy + PartialEq>(y: &[T], mask: &Mask, preds: &[T]) -> f64 {
+    // Samples and errors of each class in mask
+    let mut classes: Vec<(T, usize, usize)> = vec![];
+    for (&i, &pred) in mask.get_mask().iter().zip(preds.iter()) {
+        let class = y[i];
+        let idx = match classes.iter().position(|(c, _, _)| *c == class) {
+            Some(idx) => idx,
+            None => {
+                classes.push((class, 0, 0));
+                classes.len() - 1
+            }
+        };
+        classes[idx].1 += 1;
+        classes[idx].2 += (pred != class) as usize;
+    }
+    return match classes.len() {
+        0 => 0.,
+        n_classes => classes.iter().map(|&(_, n, err)| err as f64 / n as f64).sum::<f64>() / n_classes as f64
+    };
+}
+
+// Correction of split scores for the number of levels of the split column within the node. Impurity splits
+// favour columns offering more pivots, which biases selection and impurity importance towards them.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SplitPenalty {
+    #[default]
+    None,
+    // Adds the given gini to the score per level beyond two. Shadows get the penalty of their column.
+    LevelCount(f64),
+}
+
+impl SplitPenalty {
+    // A negative (or NaN) penalty would favour many levels instead, so setters reject it.
+    pub(crate) fn check(&self) {
+        if let SplitPenalty::LevelCount(per_level) = self {
+            if per_level.is_nan() || *per_level < 0. {
+                panic!("Split penalty must be non-negative.");
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn penalty(&self, n_levels: usize) -> f64 {
+        return match self {
+            SplitPenalty::None => 0.,
+            SplitPenalty::LevelCount(per_level) => per_level * n_levels.saturating_sub(2) as f64
+        };
+    }
+}
+
+// Class of a leaf whose samples are of several classes, see Response::get_major_class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TiePolicy {
+    // Majority class, ties go to the negative class (false). Draws nothing from the RNG, so leaf labels
+    // do not depend on how much of the stream earlier nodes used.
+    #[default]
+    Fixed,
+    // Majority class, ties decided by a coin flip from the RNG.
+    CoinFlip,
+    // Class drawn with probability proportional to its count in the leaf, ties or not. A draw landing
+    // exactly on the boundary goes to the negative class, as with Fixed.
+    Proportional,
 }
 
 pub trait Response<T> {
     fn pred_incorrect(&self, mask: &Mask, preds: &Predicted<T>) -> u64;
+    // Mean of the error rates within each class present in mask, so every class weighs the same.
+    fn pred_balanced_error(&self, mask: &Mask, preds: &Predicted<T>) -> f64
+    where
+        T: Copy + PartialEq
+    {
+        return balanced_error(self.as_vector_ref(), mask, preds);
+    }
+    // Loss of preds scaled by the number of samples in mask, so for Misclassification it is pred_incorrect.
+    fn pred_loss(&self, mask: &Mask, preds: &Predicted<T>, metric: ErrorMetric) -> f64
+    where
+        T: Copy + PartialEq
+    {
+        return match metric {
+            ErrorMetric::Misclassification => self.pred_incorrect(mask, preds) as f64,
+            ErrorMetric::BalancedError => self.pred_balanced_error(mask, preds) * mask.len() as f64,
+            _ => metric.loss(&mask.get_by_mask(self.as_vector_ref()), preds)
+        };
+    }
     fn get_class(&self, mask: &Mask) -> Option<T>;
+    // Majority class with ties decided by a coin flip, i.e. get_major_class_with under TiePolicy::CoinFlip.
     fn get_major_class(&self, mask: &Mask, rng: &mut Rng) -> T;
+    // Class of a mixed leaf under the given policy. Responses that do not override it ignore the policy.
+    fn get_major_class_with(&self, mask: &Mask, rng: &mut Rng, _ties: TiePolicy) -> T {
+        return self.get_major_class(mask, rng);
+    }
+    // Gini impurity of samples in mask, 0 for an empty mask.
+    fn impurity(&self, mask: &Mask) -> f64;
     fn pred_error(&self, mask: &Mask, preds: &Predicted<T>) -> f64;
+    // Number of samples of each class in mask, classes in order of first occurrence. Rows count with their
+    // in-bag multiplicity, see Mask::from_draws.
+    fn class_counts(&self, mask: &Mask) -> Vec<(T, usize)>
+    where
+        T: Copy + PartialEq
+    {
+        let y = self.as_vector_ref();
+        let mut counts: Vec<(T, usize)> = vec![];
+        for (i, w) in mask.iter_weighted() {
+            match counts.iter_mut().find(|(c, _)| *c == y[i]) {
+                Some((_, n)) => *n += w as usize,
+                None => counts.push((y[i], w as usize))
+            }
+        }
+        return counts;
+    }
     fn as_vector(&self) -> Vec<T>;
     fn as_vector_ref(&self) -> &Vec<T>;
     fn len(&self) -> usize;
 }
 
+// Responses restricted to the samples in mask, in mask order as XDf::subset_rows gives the rows, e.g. to drop
+// samples failing QC.
+pub trait Subsettable {
+    fn subset(&self, mask: &Mask) -> Self;
+}
+
 pub trait Shadowable<Split, Y>:  DataInterface<Split, Y> where
     Split: ColumnIdentifiable
 {
     fn subset(&self, idxs: &Vec<Split::Col>) -> Self;
     fn add_shadows(&mut self, rng_factory: RngFactory);
-    fn get_col_ids(&self) -> Vec<Split::Col>;
 }
 
-pub trait DataInterface<Split, Y> where
+// Column access and splitting of the data, independent of the response.
+pub trait ColumnData<Split> where
     Split: ColumnIdentifiable
 {
     type InternalType;
 
     fn get_ncol(&self) -> usize;
-    fn find_min_idx<T>(&self, mask: &Mask, y:  &T, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool) -> Split
+    fn get_col_ids(&self) -> Vec<Split::Col>;
+    // Ids of the shadow copies that are split candidates with shadow_vars, empty for data without shadows.
+    fn get_shadow_col_ids(&self) -> Vec<Split::Col> {
+        return vec![];
+    }
+    // Ids of the rows in row order, when the data has them.
+    fn get_sample_ids(&self) -> Option<&[String]> {
+        return None;
+    }
+    // Keys of the columns, when the data has them.
+    fn get_col_keys(&self) -> Option<&ColKeys<String, Split>> {
+        return None;
+    }
+    fn make_split(&self, idx: Split, mask: &Mask, rng_factory: &RngFactory, permuted_vec: Option<&Self::InternalType>) -> [Mask; 2];
+    // As make_split, but reuses the buffers in out.
+    fn make_split_into(&self, idx: Split, mask: &Mask, rng_factory: &RngFactory, out: &mut [Vec<usize>; 2]) {
+        let masks = self.make_split(idx, mask, rng_factory, None);
+        for (buf, mask) in out.iter_mut().zip(masks.iter()) {
+            buf.clear();
+            buf.extend_from_slice(mask.get_mask());
+        }
+    }
+    // Stable in place partition of sorted rows, rows of the first make_split mask go first.
+    // Returns their count, so both parts stay sorted. Used while building trees.
+    fn partition(&self, idx: Split, rows: &mut [usize], rng_factory: &RngFactory, scratch: &mut Vec<usize>) -> usize {
+        let mut out = [vec![], std::mem::take(scratch)];
+        self.make_split_into(idx, &Mask::from_sorted(rows.to_vec()), rng_factory, &mut out);
+        return copy_partition(rows, out, scratch);
+    }
+    fn permute_index(&self, idx: Split::Col, rng_factory: &RngFactory, oob_mask: &Mask, ith_tree: usize, round: usize) -> Self::InternalType;
+    fn permute_index_all(&self, idx: Split::Col, rng_factory: &RngFactory, round: usize) -> Self::InternalType;
+}
+
+// Split search against a response of type Y.
+pub trait DataInterface<Split, Y>: ColumnData<Split> where
+    Split: ColumnIdentifiable
+{
+    // None when no column can split the samples in mask.
+    fn find_min_idx<T>(&self, mask: &Mask, y:  &T, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool) -> Option<Split>
     where
         T: Response<Y>;
-    fn make_split(&self, idx: Split, mask: &Mask, rng_factory: &RngFactory, permuted_vec: Option<&Self::InternalType>) -> [Mask; 2];
-    fn permute_index(&self, idx: Split::Col, rng_factory: &RngFactory, oob_mask: &Mask, ith_tree: usize) -> Self::InternalType;
+    // find_min_idx with candidate scores corrected by penalty. Data without a notion of levels ignores it.
+    fn find_min_idx_penalized<T>(&self, mask: &Mask, y: &T, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool, _penalty: SplitPenalty) -> Option<Split>
+    where
+        T: Response<Y>
+    {
+        return self.find_min_idx(mask, y, mtry, rng, rng_factory, shadow_vars);
+    }
+    // find_min_idx_penalized on mask subsampled from a larger node, candidates scored with the finite-sample
+    // correction of Splittable::gen_optimal_pivot_corrected. Falls back to find_min_idx_penalized.
+    fn find_min_idx_subsample<T>(&self, mask: &Mask, y: &T, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool, penalty: SplitPenalty) -> Option<Split>
+    where
+        T: Response<Y>
+    {
+        return self.find_min_idx_penalized(mask, y, mtry, rng, rng_factory, shadow_vars, penalty);
+    }
+    // Gini impurity after the best split of col alone on samples in mask, as find_min_idx scores candidates.
+    // Columns are scored as stored, i.e. without the shuffle of shadows. None when col cannot split mask.
+    fn split_score<T>(&self, col: Split::Col, mask: &Mask, y: &T) -> Option<f64>
+    where
+        T: Response<Y>;
+    // Split on the column of idx fitted again on all samples in mask, for splits found on a subsample.
+    fn refine_split<T>(&self, idx: Split, _mask: &Mask, _y: &T, _rng_factory: &RngFactory) -> Split
+    where
+        T: Response<Y>
+    {
+        return idx;
+    }
+}
+
+// Writes both parts back to rows for the default partition implementations.
+fn copy_partition(rows: &mut [usize], out: [Vec<usize>; 2], scratch: &mut Vec<usize>) -> usize {
+    let [left, right] = out;
+    rows[..left.len()].copy_from_slice(&left);
+    rows[left.len()..].copy_from_slice(&right);
+    *scratch = right;
+    return left.len();
+}
+
+// Single row access for walking rows down a tree one at a time.
+pub trait RowView<Split> {
+    // True when the row falls into the first mask make_split would return.
+    fn goes_left(&self, row: usize, idx: &Split) -> bool;
+    // As goes_left, but None when the row misses the value of the split column.
+    fn try_goes_left(&self, row: usize, idx: &Split) -> Option<bool> {
+        return Some(self.goes_left(row, idx));
+    }
 }
 
 pub trait ColumnIdentifiable {
-    type Col: Hash + Eq + Copy + Send;
+    type Col: Hash + Eq + Copy + Send + Sync;
     fn get_col_id(&self) -> Self::Col;
+    // True for columns of shadow_vars, i.e. permuted copies of the real ones.
+    fn is_shadow_col(_col: &Self::Col) -> bool {
+        return false;
+    }
+    // Interned id of a real column, the index of its key in col_keys::ColKeys. None for shadows.
+    fn col_index(_col: &Self::Col) -> Option<usize> {
+        return None;
+    }
+}
+
+// Splits telling which pivot of their column they use, for importance broken down by pivot.
+pub trait PivotIdentifiable: ColumnIdentifiable {
+    type Pivot: Hash + Eq + Copy + Debug + Send + Sync;
+    fn get_pivot(&self) -> Self::Pivot;
+}
+
+// Whole column permutations keyed by column and permutation round, reused across trees.
+// Unlike permute_index, all rows are shuffled, so OOB rows may receive in-bag values.
+pub struct PermutationCache<Col, V> {
+    permuted: HashMap<(Col, usize), V>
+}
+
+impl<Col, V> PermutationCache<Col, V> where
+    Col: Hash + Eq + Copy
+{
+    pub fn new() -> Self {
+        return PermutationCache{permuted: HashMap::new()};
+    }
+
+    pub fn fill<T, Split>(&mut self, df: &T, col: Col, round: usize, rng_factory: &RngFactory)
+    where
+        T: ColumnData<Split, InternalType=V>,
+        Split: ColumnIdentifiable<Col=Col>
+    {
+        self.permuted.entry((col, round)).or_insert_with(|| df.permute_index_all(col, rng_factory, round));
+    }
+
+    #[inline]
+    pub fn get(&self, col: Col, round: usize) -> Option<&V> {
+        return self.permuted.get(&(col, round));
+    }
+
+    pub fn len(&self) -> usize {
+        return self.permuted.len();
+    }
 }
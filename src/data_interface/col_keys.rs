@@ -0,0 +1,131 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use crate::data_interface::ColumnIdentifiable;
+
+// User keys of the columns, e.g. rsIDs or (chrom, pos) pairs. Forests keep working on the small Copy column ids,
+// keys[i] belongs to the column with ColumnIdentifiable::col_index i, and results are labelled at the end.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "K: serde::Serialize",
+    deserialize = "K: serde::Deserialize<'de> + Hash + Eq"
+)))]
+pub struct ColKeys<K, SplitIndex> {
+    keys: Vec<K>,
+    index: HashMap<K, usize>,
+    split: PhantomData<SplitIndex>
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ColKeysError {
+    Duplicate{first: usize, second: usize},
+}
+
+impl fmt::Display for ColKeysError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColKeysError::Duplicate{first, second} => write!(f, "Columns {} and {} have the same key", first, second),
+        }
+    }
+}
+
+impl Error for ColKeysError {}
+
+// The index follows from the keys.
+impl<K: PartialEq, SplitIndex> PartialEq for ColKeys<K, SplitIndex> {
+    fn eq(&self, other: &Self) -> bool {
+        return self.keys == other.keys;
+    }
+}
+
+impl<K: Eq, SplitIndex> Eq for ColKeys<K, SplitIndex> {}
+
+impl<K, SplitIndex> ColKeys<K, SplitIndex> where
+    K: Hash + Eq + Clone,
+    SplitIndex: ColumnIdentifiable
+{
+    pub fn new(keys: Vec<K>) -> Result<ColKeys<K, SplitIndex>, ColKeysError> {
+        let mut index = HashMap::with_capacity(keys.len());
+        for (i, key) in keys.iter().enumerate() {
+            if let Some(first) = index.insert(key.clone(), i) {
+                return Err(ColKeysError::Duplicate{first, second: i});
+            }
+        }
+        return Ok(ColKeys{keys, index, split: PhantomData});
+    }
+
+    // Interned column index of key, the col_id of the column in XDf and XDfTyped.
+    pub fn index(&self, key: &K) -> Option<usize> {
+        return self.index.get(key).copied();
+    }
+
+    // None for shadows and columns without a key.
+    pub fn key(&self, col: &SplitIndex::Col) -> Option<&K> {
+        return SplitIndex::col_index(col).and_then(|i| self.keys.get(i));
+    }
+
+    // Keys of cols, e.g. BorutaRes::get_confirmed, skipping the ones without a key.
+    pub fn keys_of(&self, cols: &[SplitIndex::Col]) -> Vec<K> {
+        return cols.iter().filter_map(|col| self.key(col).cloned()).collect();
+    }
+
+    // Keys in column index order.
+    pub fn keys(&self) -> &[K] {
+        return &self.keys;
+    }
+
+    // Map keyed by column id, e.g. importance, relabelled with the keys. Shadows are dropped.
+    pub fn by_key<V: Clone>(&self, map: &HashMap<SplitIndex::Col, V>) -> HashMap<K, V> {
+        return map.iter().filter_map(|(col, v)| Some((self.key(col)?.clone(), v.clone()))).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::data_interface::col_keys::{ColKeys, ColKeysError};
+    use crate::data_interface::multi_x::{ColSplitIndex, SplitColId};
+
+    #[test]
+    fn keys_label_columns() {
+        let keys: ColKeys<(u8, u64), ColSplitIndex> = ColKeys::new(vec![(1, 100), (1, 250), (2, 40)]).unwrap();
+        assert_eq!(keys.index(&(1, 250)), Some(1));
+        assert_eq!(keys.index(&(3, 1)), None);
+        assert_eq!(keys.key(&SplitColId{col_id: 2, shadow: false}), Some(&(2, 40)));
+        assert_eq!(keys.key(&SplitColId{col_id: 2, shadow: true}), None);
+        assert_eq!(keys.key(&SplitColId{col_id: 3, shadow: false}), None);
+
+        let imp = HashMap::from([
+            (SplitColId{col_id: 0, shadow: false}, 0.5),
+            (SplitColId{col_id: 0, shadow: true}, 0.1),
+        ]);
+        assert_eq!(keys.by_key(&imp), HashMap::from([((1, 100), 0.5)]));
+        assert_eq!(keys.keys_of(&[SplitColId{col_id: 2, shadow: false}, SplitColId{col_id: 7, shadow: false}]), vec![(2, 40)]);
+    }
+
+    #[test]
+    fn duplicated_keys_rejected() {
+        let res: Result<ColKeys<String, ColSplitIndex>, _> = ColKeys::new(vec!["rs1".to_string(), "rs2".to_string(), "rs1".to_string()]);
+        assert_eq!(res.unwrap_err(), ColKeysError::Duplicate{first: 0, second: 2});
+    }
+}
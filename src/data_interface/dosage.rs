@@ -0,0 +1,303 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use crate::mask::Mask;
+use crate::data_interface::{Partitionable, Permutable, Response, Splittable};
+use crate::data_interface::y_bool::Y;
+use crate::data_interface::y_multi_bool::MultiY;
+use crate::gini::x_dosage_y_bool::{count_x_dosage_y_bool, gini_x_dosage_y_bool, gini_x_dosage_y_bool_corrected};
+use crate::random_number_generator::Rng;
+
+// Dosages are stored rounded to 0.01, as a byte code of dosage * 100.
+pub(crate) const DOSAGE_LEVELS: usize = 201;
+pub(crate) const MISSING_CODE: u8 = u8::MAX;
+
+// Imputed genotype dosages, expected allele counts in [0, 2].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DosageCol(Vec<u8>);
+
+// Rows with dosage at most the threshold go left.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DosagePivot(u8);
+
+fn dosage_code(x: f32) -> u8 {
+    if !(0. ..=2.).contains(&x) {
+        panic!("Dosage must be within [0, 2].");
+    }
+    return (x * 100.).round() as u8;
+}
+
+impl DosageCol {
+    pub fn new(arr: &[f32]) -> Self {
+        return Self(arr.iter().map(|&x| dosage_code(x)).collect());
+    }
+
+    // As new, with None for missing values. As with ThreeValCol, such columns are for prediction only.
+    pub fn new_with_missing(arr: &[Option<f32>]) -> Self {
+        return Self(arr.iter().map(|&x| x.map_or(MISSING_CODE, dosage_code)).collect());
+    }
+
+    pub fn len(&self) -> usize {
+        return self.0.len();
+    }
+
+    // Stored dosage of row i, None if missing.
+    pub fn get(&self, i: usize) -> Option<f32> {
+        return match self.0[i] {
+            MISSING_CODE => None,
+            code => Some(code as f32 / 100.)
+        };
+    }
+
+    pub fn subset_rows(&self, mask: &Mask) -> Self {
+        return Self(mask.get_by_mask(&self.0));
+    }
+
+    pub fn is_all_missing(&self) -> bool {
+        return self.0.iter().all(|&x| x == MISSING_CODE);
+    }
+
+    // Number of distinct stored dosages of the rows in mask, missing ones aside.
+    pub fn n_levels(&self, mask: &Mask) -> usize {
+        let mut seen = [false; DOSAGE_LEVELS];
+        for &i in mask.get_mask().iter() {
+            if let Some(seen) = seen.get_mut(self.0[i] as usize) {
+                *seen = true;
+            }
+        }
+        return seen.iter().filter(|&&s| s).count();
+    }
+
+    #[inline]
+    pub(crate) fn codes(&self) -> &[u8] {
+        return &self.0;
+    }
+
+    // Pivot with the lowest score gini gives for the codes, shuffled first for shadows.
+    fn optimal_pivot<F>(&self, shadow_rng: Option<Rng>, gini: F) -> Option<(DosagePivot, f64)>
+    where
+        F: Fn(&[u8]) -> Option<(u8, f64)>
+    {
+        let x;
+        let mut x_temp;
+
+        if shadow_rng.is_some() {
+            x_temp = self.0.clone();
+            shadow_rng.unwrap().shuffle(&mut x_temp);
+            x = &x_temp;
+        } else {
+            x = &self.0;
+        }
+
+        let (code, score) = gini(x)?;
+        return Some((DosagePivot(code), score));
+    }
+}
+
+impl DosagePivot {
+    // Pivot at the stored dosage nearest to threshold.
+    pub fn new(threshold: f32) -> Self {
+        return Self(dosage_code(threshold));
+    }
+
+    pub fn threshold(&self) -> f32 {
+        return self.0 as f32 / 100.;
+    }
+
+    #[inline]
+    pub(crate) fn code(&self) -> u8 {
+        return self.0;
+    }
+
+    pub(crate) fn from_code(code: u8) -> Option<Self> {
+        return match (code as usize) < DOSAGE_LEVELS {
+            true => Some(Self(code)),
+            false => None
+        };
+    }
+
+    // None for the missing code.
+    #[inline]
+    pub(crate) fn goes_left(&self, code: u8) -> Option<bool> {
+        return match code {
+            MISSING_CODE => None,
+            code => Some(code <= self.0)
+        };
+    }
+}
+
+impl Permutable for DosageCol {
+    fn permute(&self, mut perm_rng: Rng, oob_mask: &Mask) -> DosageCol {
+        let mut x = oob_mask.get_by_mask(&self.0);
+        perm_rng.shuffle(&mut x);
+
+        let mut x_full = self.0.clone();
+        for (&xv, &i) in x.iter().zip(oob_mask.get_mask().iter()) {
+            x_full[i] = xv;
+        }
+
+        return DosageCol(x_full);
+    }
+}
+
+impl Partitionable for DosageCol {
+    type Pivot = DosagePivot;
+
+    fn split_with_pivot(&self, mask: &Mask, p: &Self::Pivot, shadow_rng: Option<Rng>) -> [Mask; 2] {
+        let mut out = [Vec::new(), Vec::new()];
+        self.split_with_pivot_into(mask, p, shadow_rng, &mut out);
+        return out.map(Mask::from_sorted);
+    }
+
+    fn split_with_pivot_into(&self, mask: &Mask, p: &Self::Pivot, _shadow_rng: Option<Rng>, out: &mut [Vec<usize>; 2]) {
+        out[0].clear();
+        out[1].clear();
+        for &i in mask.get_mask().iter() {
+            match p.goes_left(self.0[i]).unwrap() {
+                true => out[0].push(i),
+                false => out[1].push(i)
+            }
+        }
+    }
+
+    fn partition_with_pivot(&self, rows: &mut [usize], p: &Self::Pivot, _shadow_rng: Option<Rng>, scratch: &mut Vec<usize>) -> usize {
+        scratch.clear();
+        let mut n_left = 0;
+        for i in 0..rows.len() {
+            let row = rows[i];
+            match p.goes_left(self.0[row]).unwrap() {
+                true => {
+                    rows[n_left] = row;
+                    n_left += 1;
+                },
+                false => scratch.push(row)
+            }
+        }
+        rows[n_left..].copy_from_slice(scratch);
+        return n_left;
+    }
+}
+
+impl Splittable<Y> for DosageCol {
+    fn gen_optimal_pivot<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<Y>
+    {
+        if let Some(weights) = mask.multiplicity() {
+            return self.gen_optimal_pivot_weighted(mask, &weights, y, shadow_rng);
+        }
+        return self.optimal_pivot(shadow_rng, |x| {
+            let mut x_fl = mask.get_mask().iter().map(|&i| x[i]);
+            let y_vec = y.as_vector_ref();
+            let mut y_fl = mask.get_mask().iter().map(|&i| y_vec[i]);
+            let c = count_x_dosage_y_bool(&mut x_fl, &mut y_fl);
+            gini_x_dosage_y_bool(&[c], mask.len()).map(|(code, s)| (code, s as f64))
+        });
+    }
+
+    fn gen_optimal_pivot_corrected<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<Y>
+    {
+        return self.optimal_pivot(shadow_rng, |x| {
+            let mut x_fl = mask.get_mask().iter().map(|&i| x[i]);
+            let y_vec = y.as_vector_ref();
+            let mut y_fl = mask.get_mask().iter().map(|&i| y_vec[i]);
+            let c = count_x_dosage_y_bool(&mut x_fl, &mut y_fl);
+            gini_x_dosage_y_bool_corrected(&[c], mask.len()).map(|(code, s)| (code, s as f64))
+        });
+    }
+}
+
+// Gini is averaged over responses, as for ThreeValCol.
+impl Splittable<MultiY> for DosageCol {
+    fn gen_optimal_pivot<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<MultiY>
+    {
+        if let Some(weights) = mask.multiplicity() {
+            return self.gen_optimal_pivot_weighted(mask, &weights, y, shadow_rng);
+        }
+        return self.optimal_pivot(shadow_rng, |x| {
+            let y_vec = y.as_vector_ref();
+            let n_resp = y_vec[mask.get_mask()[0]].len();
+            let c: Vec<Vec<[usize; 2]>> = (0..n_resp).map(|r| {
+                let mut x_fl = mask.get_mask().iter().map(|&i| x[i]);
+                let mut y_fl = mask.get_mask().iter().map(|&i| y_vec[i].get(r));
+                count_x_dosage_y_bool(&mut x_fl, &mut y_fl)
+            }).collect();
+            gini_x_dosage_y_bool(&c, mask.len()).map(|(code, s)| (code, s as f64))
+        });
+    }
+
+    fn gen_optimal_pivot_corrected<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<MultiY>
+    {
+        return self.optimal_pivot(shadow_rng, |x| {
+            let y_vec = y.as_vector_ref();
+            let n_resp = y_vec[mask.get_mask()[0]].len();
+            let c: Vec<Vec<[usize; 2]>> = (0..n_resp).map(|r| {
+                let mut x_fl = mask.get_mask().iter().map(|&i| x[i]);
+                let mut y_fl = mask.get_mask().iter().map(|&i| y_vec[i].get(r));
+                count_x_dosage_y_bool(&mut x_fl, &mut y_fl)
+            }).collect();
+            gini_x_dosage_y_bool_corrected(&c, mask.len()).map(|(code, s)| (code, s as f64))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::assert_approx_eq;
+    use crate::mask::Mask;
+    use crate::data_interface::dosage::{DosageCol, DosagePivot};
+    use crate::data_interface::{Partitionable, Splittable};
+    use crate::data_interface::y_bool::YBool;
+
+    #[test]
+    fn stores_rounded_dosage() {
+        let x = DosageCol::new_with_missing(&[Some(0.), Some(1.234), None, Some(2.)]);
+        assert_eq!(x.get(1), Some(1.23));
+        assert_eq!(x.get(2), None);
+        assert_eq!(x.n_levels(&Mask::new(vec![0, 1, 2, 3])), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Dosage must be within [0, 2].")]
+    fn rejects_out_of_range() {
+        DosageCol::new(&[0.5, 2.1]);
+    }
+
+    #[test]
+    fn threshold_split() {
+        let x = DosageCol::new(&[0.1, 1.7, 0.9, 1.2, 0.3]);
+        let masks = x.split_with_pivot(&Mask::new(vec![0, 1, 2, 3, 4]), &DosagePivot::new(0.9), None);
+        assert_eq!(masks, [Mask::new(vec![0, 2, 4]), Mask::new(vec![1, 3])]);
+    }
+
+    #[test]
+    fn gen_optimal_pivot() {
+        let x = DosageCol::new(&[0.1, 1.7, 0.9, 1.2, 0.3, 1.95]);
+        let y = YBool::new(&vec![false, true, false, true, false, true]);
+        let (piv, score) = x.gen_optimal_pivot(&Mask::new((0..6).collect()), &y, None).unwrap();
+        assert_eq!(piv, DosagePivot::new(0.9));
+        assert_approx_eq!(f64, score, 0.);
+        assert!(x.gen_optimal_pivot(&Mask::new(vec![1]), &y, None).is_none());
+    }
+}
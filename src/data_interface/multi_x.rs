@@ -14,31 +14,74 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cell::OnceCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
 use crate::mask::Mask;
-use crate::data_interface::three_val::{ThreeValCol, ThreeValPivot};
-use crate::data_interface::{DataInterface, Response, ColumnIdentifiable, Splittable, Permutable, Shadowable};
+use crate::data_interface::three_val::{ThreeVal, ThreeValCol, ThreeValPivot};
+use crate::data_interface::dosage::{DosageCol, DosagePivot, MISSING_CODE};
+use crate::data_interface::{ColumnData, DataInterface, Response, ColumnIdentifiable, Partitionable, PivotIdentifiable, Splittable, SplitPenalty, Permutable, Shadowable, RowView};
 use crate::random_number_generator::Rng;
-use crate::data_interface::y_bool::Y;
+use crate::data_interface::y_bool::{Y, YBool};
 use crate::random_number_generator::factory::RngFactory;
+use crate::regions::Locus;
+use crate::data_interface::col_keys::{ColKeys, ColKeysError};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct XDf {
     data: Vec<MultiX>,
     idx_to_splitid_map: Vec<usize>,
-    splitid_to_idx_map: Vec<usize>
+    splitid_to_idx_map: Vec<usize>,
+    // Optional id of each row, carried through row subsets into per sample results.
+    sample_ids: Option<Vec<String>>,
+    // Genomic position of each column id, None (or beyond the end) for columns without one.
+    col_loci: Vec<Option<Locus>>,
+    // User key of each column id, see set_col_keys.
+    col_keys: Option<ColKeys<String, ColSplitIndex>>
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MultiX {
-    ThreeVal(ThreeValCol)
+    ThreeVal(ThreeValCol),
+    Dosage(DosageCol)
 }
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MultiPivot {
-    ThreeVal(ThreeValPivot)
+    ThreeVal(ThreeValPivot),
+    Dosage(DosagePivot)
 }
 
-#[derive(Copy, Clone, Debug)]
+// Three value pivots hash as before dosage pivots were added, so model hashes of such forests stay put.
+impl Hash for MultiPivot {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            MultiPivot::ThreeVal(p) => p.hash(state),
+            MultiPivot::Dosage(p) => {
+                state.write_u8(u8::MAX);
+                p.hash(state);
+            }
+        }
+    }
+}
+
+// Row-major copy of XDf, so predicting a row reads one contiguous slice instead of a value per column.
+// Built once and shared by all trees predicting on the same data. Values are stored as byte codes,
+// MISSING_CODE for missing ones.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RowMajorXDf {
+    values: Vec<u8>,
+    ncol: usize,
+    splitid_to_idx_map: Vec<usize>
+}
+
+#[derive(Copy, Clone, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ColSplitIndex {
     pub col_id: usize,
     pub pivot: MultiPivot,
@@ -51,6 +94,63 @@ pub struct SplitColId {
     pub shadow: bool //TODO remove
 }
 
+// Written as a string ("12", or "shadow_12" for shadows), so importance maps keyed by column stay valid JSON objects.
+#[cfg(feature = "serde")]
+impl serde::Serialize for SplitColId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return match self.shadow {
+            true => serializer.collect_str(&format_args!("shadow_{}", self.col_id)),
+            false => serializer.collect_str(&self.col_id)
+        };
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SplitColId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        let (col_id, shadow) = match s.strip_prefix("shadow_") {
+            Some(col_id) => (col_id, true),
+            None => (s.as_ref(), false)
+        };
+        return match col_id.parse() {
+            Ok(col_id) => Ok(SplitColId{col_id, shadow}),
+            Err(_) => Err(serde::de::Error::custom(format!("invalid column id {:?}", s)))
+        };
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum XDfError {
+    LengthMismatch{col_id: usize, expected: usize, found: usize},
+    IdMapLength{ncol: usize, nid: usize},
+    InconsistentIdMap{idx: usize, col_id: usize},
+    AllMissing(usize),
+    SampleIdLength{nrow: usize, nid: usize},
+    DuplicateSampleId(String),
+    RowLength{row: usize, expected: usize, found: usize},
+    InvalidValue{row: usize, col_id: usize, value: i8},
+}
+
+impl fmt::Display for XDfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XDfError::LengthMismatch{col_id, expected, found} =>
+                write!(f, "Column {} has {} rows, expected {}", col_id, found, expected),
+            XDfError::IdMapLength{ncol, nid} => write!(f, "Data frame has {} columns but {} column ids", ncol, nid),
+            XDfError::InconsistentIdMap{idx, col_id} =>
+                write!(f, "Column at position {} maps to id {} which does not map back", idx, col_id),
+            XDfError::AllMissing(col_id) => write!(f, "Column {} has only missing values", col_id),
+            XDfError::SampleIdLength{nrow, nid} => write!(f, "Data frame has {} rows but {} sample ids", nrow, nid),
+            XDfError::DuplicateSampleId(id) => write!(f, "Sample id {:?} is not unique", id),
+            XDfError::RowLength{row, expected, found} => write!(f, "Row {} has {} values, expected {}", row, found, expected),
+            XDfError::InvalidValue{row, col_id, value} => write!(f, "Invalid value {} in column {} at row {}", value, col_id, row),
+        }
+    }
+}
+
+impl Error for XDfError {}
+
 impl ColumnIdentifiable for ColSplitIndex {
     type Col = SplitColId;
 
@@ -58,13 +158,190 @@ impl ColumnIdentifiable for ColSplitIndex {
     fn get_col_id(&self) -> Self::Col {
         return SplitColId{col_id: self.col_id, shadow: self.shadow};
     }
+
+    #[inline]
+    fn is_shadow_col(col: &Self::Col) -> bool {
+        return col.shadow;
+    }
+
+    #[inline]
+    fn col_index(col: &Self::Col) -> Option<usize> {
+        return match col.shadow {
+            true => None,
+            false => Some(col.col_id)
+        };
+    }
+}
+
+impl PivotIdentifiable for ColSplitIndex {
+    type Pivot = MultiPivot;
+
+    #[inline]
+    fn get_pivot(&self) -> Self::Pivot {
+        return self.pivot;
+    }
 }
 
 impl XDf {
     pub fn new(cols: Vec<MultiX>) -> XDf {
         let idx_to_splitid_map: Vec<usize> = (0..cols.len()).collect();
         let splitid_to_idx_map: Vec<usize> = (0..cols.len()).collect();
-        return XDf{data: cols, idx_to_splitid_map, splitid_to_idx_map};
+        return XDf{data: cols, idx_to_splitid_map, splitid_to_idx_map, sample_ids: None, col_loci: vec![], col_keys: None};
+    }
+
+    pub fn get_nrow(&self) -> usize {
+        return self.data.first().map_or(0, |x| x.len());
+    }
+
+    // Builds the data frame from chunks of rows as they are read, e.g. batches of samples from a genotype file.
+    // Each row holds ncol codes 0, 1, 2 or None when missing. Columns are allocated for nrow_hint rows up front
+    // and filled chunk by chunk, so all rows are never held at once.
+    pub fn from_row_chunks<I, C, R>(ncol: usize, nrow_hint: usize, chunks: I) -> Result<XDf, XDfError>
+    where
+        I: IntoIterator<Item=C>,
+        C: IntoIterator<Item=R>,
+        R: AsRef<[Option<i8>]>
+    {
+        let mut cols: Vec<ThreeValCol> = (0..ncol).map(|_| ThreeValCol::with_capacity(nrow_hint)).collect();
+        let mut row = 0;
+        for chunk in chunks {
+            for values in chunk {
+                let values = values.as_ref();
+                if values.len() != ncol {
+                    return Err(XDfError::RowLength{row, expected: ncol, found: values.len()});
+                }
+                for (col_id, (col, &value)) in cols.iter_mut().zip(values.iter()).enumerate() {
+                    match value.map(ThreeVal::from_code) {
+                        Some(None) => return Err(XDfError::InvalidValue{row, col_id, value: value.unwrap()}),
+                        x => col.push(x.flatten())
+                    }
+                }
+                row += 1;
+            }
+        }
+        return Ok(XDf::new(cols.into_iter().map(MultiX::ThreeVal).collect()));
+    }
+
+    // Ids of the rows, e.g. sample names, kept by subset_rows and returned with per sample results such as
+    // LocalImportance. Checked by validate.
+    pub fn set_sample_ids(&mut self, sample_ids: Option<Vec<String>>) {
+        self.sample_ids = sample_ids;
+    }
+
+    // Positions of the columns, loci[i] of the column with id i, e.g. to run per chromosome with
+    // regions::importance_per_region. Kept by row and column subsets. Results merged over regions are keyed
+    // by locus, so loci must be distinct.
+    pub fn set_col_loci(&mut self, loci: Vec<Option<Locus>>) {
+        if loci.len() > self.splitid_to_idx_map.len() {
+            panic!("Loci must not outnumber the column ids.");
+        }
+        let mut seen = HashSet::new();
+        if loci.iter().flatten().any(|locus| !seen.insert(locus)) {
+            panic!("Columns must have distinct loci.");
+        }
+        self.col_loci = loci;
+    }
+
+    // Keys of the columns, keys[i] of the column with id i, e.g. rsIDs. Kept by row and column subsets and
+    // returned with results, see RandomForest::importance_by_key and BorutaRes::get_confirmed_keys.
+    pub fn set_col_keys(&mut self, keys: Option<Vec<String>>) -> Result<(), ColKeysError> {
+        if keys.as_ref().is_some_and(|keys| keys.len() > self.splitid_to_idx_map.len()) {
+            panic!("Keys must not outnumber the column ids.");
+        }
+        self.col_keys = keys.map(ColKeys::new).transpose()?;
+        return Ok(());
+    }
+
+    pub fn get_locus(&self, col_id: usize) -> Option<&Locus> {
+        return self.col_loci.get(col_id).and_then(|locus| locus.as_ref());
+    }
+
+    // Appends col with an id after every id the data frame (or the one it was subset from) used, and returns it.
+    // Row count is checked by validate.
+    pub fn push_col(&mut self, col: MultiX) -> usize {
+        let col_id = self.splitid_to_idx_map.len();
+        self.data.push(col);
+        self.idx_to_splitid_map.push(col_id);
+        self.splitid_to_idx_map.resize(col_id + 1, 0);
+        self.splitid_to_idx_map[col_id] = self.data.len() - 1;
+        return col_id;
+    }
+
+    // Moves columns in place by descending scores, e.g. the importance of a screening forest, so get_col_ids and
+    // exports list the strongest columns first. Columns without a score go last, ties keep their order. Each
+    // column keeps its own buffer, so memory layout and speed are unchanged. Column ids are kept, so results are
+    // keyed as before, but trees draw candidates by position, so a reordered data frame grows other forests for
+    // the same seed.
+    pub fn reorder_by(&mut self, scores: &HashMap<SplitColId, f64>) {
+        let score = |idx: usize| scores.get(&SplitColId{col_id: self.idx_to_splitid(idx), shadow: false}).cloned();
+        let mut order: Vec<usize> = (0..self.data.len()).collect();
+        order.sort_by(|&a, &b| match (score(a), score(b)) {
+            (Some(a), Some(b)) => b.total_cmp(&a),
+            (a, b) => b.is_some().cmp(&a.is_some())
+        });
+
+        let mut data: Vec<Option<MultiX>> = std::mem::take(&mut self.data).into_iter().map(Some).collect();
+        self.data = order.iter().map(|&idx| data[idx].take().unwrap()).collect();
+        self.idx_to_splitid_map = order.iter().map(|&idx| self.idx_to_splitid_map[idx]).collect();
+        for (idx, &col_id) in self.idx_to_splitid_map.iter().enumerate() {
+            self.splitid_to_idx_map[col_id] = idx;
+        }
+    }
+
+    // Rows in mask, in mask order. Column and sample ids are kept.
+    pub fn subset_rows(&self, mask: &Mask) -> XDf {
+        return XDf{
+            data: self.data.iter().map(|col| col.subset_rows(mask)).collect(),
+            idx_to_splitid_map: self.idx_to_splitid_map.clone(),
+            splitid_to_idx_map: self.splitid_to_idx_map.clone(),
+            sample_ids: self.sample_ids.as_ref().map(|ids| mask.get_mask().iter().map(|&i| ids[i].clone()).collect()),
+            col_loci: self.col_loci.clone(),
+            col_keys: self.col_keys.clone()
+        };
+    }
+
+    // Drops rows with a missing response. Returns the remaining rows, their response and the mask of kept
+    // rows in the original data, e.g. to map local importance or predictions back.
+    pub fn with_observed_y(&self, y: &[Option<Y>]) -> (XDf, YBool, Mask) {
+        if y.len() != self.get_nrow() {
+            panic!("Response must have a value or None for every row.");
+        }
+        let (y, observed) = YBool::from_observed(y);
+        return (self.subset_rows(&observed), y, observed);
+    }
+
+    // Checks the data frame is consistent before fitting. Columns are reported by their id.
+    pub fn validate(&self, check_missing: bool) -> Result<(), XDfError> {
+        if self.idx_to_splitid_map.len() != self.data.len() {
+            return Err(XDfError::IdMapLength{ncol: self.data.len(), nid: self.idx_to_splitid_map.len()});
+        }
+
+        for (idx, &col_id) in self.idx_to_splitid_map.iter().enumerate() {
+            if self.splitid_to_idx_map.get(col_id) != Some(&idx) {
+                return Err(XDfError::InconsistentIdMap{idx, col_id});
+            }
+        }
+
+        let nrow = self.get_nrow();
+        if let Some(ids) = &self.sample_ids {
+            if ids.len() != nrow {
+                return Err(XDfError::SampleIdLength{nrow, nid: ids.len()});
+            }
+            let mut seen = HashSet::new();
+            if let Some(id) = ids.iter().find(|&id| !seen.insert(id)) {
+                return Err(XDfError::DuplicateSampleId(id.clone()));
+            }
+        }
+        for (idx, col) in self.data.iter().enumerate() {
+            let col_id = self.idx_to_splitid(idx);
+            if col.len() != nrow {
+                return Err(XDfError::LengthMismatch{col_id, expected: nrow, found: col.len()});
+            }
+            if check_missing && nrow > 0 && col.is_all_missing() {
+                return Err(XDfError::AllMissing(col_id));
+            }
+        }
+        return Ok(());
     }
 
     #[inline]
@@ -81,6 +358,16 @@ impl XDf {
     }
 }
 
+// Split candidates as (column index, shadow). Real and shadow copy of a column are separate
+// candidates, so sampling without replacement from this range never repeats a candidate.
+fn candidate_range(ncol: usize, shadow_vars: bool) -> Vec<(usize, bool)> {
+    let real = (0..ncol).map(|col| (col, false));
+    return match shadow_vars {
+        true => real.chain((0..ncol).map(|col| (col, true))).collect(),
+        false => real.collect()
+    };
+}
+
 impl Shadowable<ColSplitIndex, Y> for XDf {
     fn subset(&self, split_ids: &Vec<SplitColId>) -> Self {
         let idx_to_splitid_map: Vec<usize> = split_ids.iter().map(|x| x.col_id).collect();
@@ -93,6 +380,9 @@ impl Shadowable<ColSplitIndex, Y> for XDf {
             data: split_ids.iter().map(|&col_id| self.data[self.splitid_to_idx(col_id.col_id)].clone()).collect(),
             idx_to_splitid_map,
             splitid_to_idx_map,
+            sample_ids: self.sample_ids.clone(),
+            col_loci: self.col_loci.clone(),
+            col_keys: self.col_keys.clone()
         }
     }
 
@@ -114,48 +404,34 @@ impl Shadowable<ColSplitIndex, Y> for XDf {
             self.splitid_to_idx_map[max_splitid+i+1] = self.idx_to_splitid_map.len()-1;
         }
     }
-
-    fn get_col_ids(&self) -> Vec<SplitColId> {
-        self.idx_to_splitid_map.iter().map(|&i| SplitColId{col_id: i, shadow: false}).collect()
-    }
 }
 
-impl DataInterface<ColSplitIndex, Y> for XDf {
+impl ColumnData<ColSplitIndex> for XDf {
     type InternalType = MultiX;
+
+    fn get_sample_ids(&self) -> Option<&[String]> {
+        return self.sample_ids.as_deref();
+    }
+
+    fn get_col_keys(&self) -> Option<&ColKeys<String, ColSplitIndex>> {
+        return self.col_keys.as_ref();
+    }
+
     #[inline]
     fn get_ncol(&self) -> usize {
         return self.data.len();
     }
 
-    fn find_min_idx<U>(&self, mask: &Mask, y: &U, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool) -> ColSplitIndex
-    where
-        U: Response<Y>
-    {
-        let range: Vec<usize>;
-        if shadow_vars {
-            range = (0..self.get_ncol()*2).collect();
-        } else {
-            range = (0..self.get_ncol()).collect();
-        }
-
-        let min_idx = rng.sample(&range, mtry).iter_mut().map(|col| {
-            let mut shadow_rng = None;
-            if *col >= self.get_ncol() {
-                *col = *col-self.get_ncol();
-                shadow_rng = Some(rng_factory.new_rng_shadow(*col));
-            }
-
-
-            let x = self.get_col(*col);
-            let res = x.gen_optimal_pivot(&mask, y, shadow_rng);
-            return (res.0, res.1, self.idx_to_splitid(*col), shadow_rng.is_some());
-        }).min_by(|x, y| x.1.partial_cmp(&y.1).expect("Gini score has strange value (NaN like)"));
+    fn get_col_ids(&self) -> Vec<SplitColId> {
+        self.idx_to_splitid_map.iter().map(|&i| SplitColId{col_id: i, shadow: false}).collect()
+    }
 
-        let min_idx_un = min_idx.unwrap();
-        return ColSplitIndex {col_id: min_idx_un.2, pivot: min_idx_un.0, shadow: min_idx_un.3};
+    fn get_shadow_col_ids(&self) -> Vec<SplitColId> {
+        return self.idx_to_splitid_map.iter().map(|&i| SplitColId{col_id: i, shadow: true}).collect();
     }
 
     fn make_split(&self, idx: ColSplitIndex, mask: &Mask, rng_factory: &RngFactory, permuted_vec: Option<&MultiX>) -> [Mask; 2] {
+        debug_assert!(mask.within_bounds(self.get_nrow()), "Mask index out of data range");
         let col = match permuted_vec {
             Some(x) => x,
             None => self.get_col(self.splitid_to_idx(idx.col_id))
@@ -169,40 +445,350 @@ impl DataInterface<ColSplitIndex, Y> for XDf {
         return col.split_with_pivot(&mask, &idx.pivot, shadow_rng);
     }
 
-    fn permute_index(&self, col_id: SplitColId, rng_factory: &RngFactory, oob_mask: &Mask, ith_tree: usize) -> MultiX {
+    fn make_split_into(&self, idx: ColSplitIndex, mask: &Mask, rng_factory: &RngFactory, out: &mut [Vec<usize>; 2]) {
+        debug_assert!(mask.within_bounds(self.get_nrow()), "Mask index out of data range");
+        let col = self.get_col(self.splitid_to_idx(idx.col_id));
+        let shadow_rng = match idx.shadow {
+            true => Some(rng_factory.new_rng_shadow(idx.col_id)),
+            false => None
+        };
+        col.split_with_pivot_into(mask, &idx.pivot, shadow_rng, out);
+    }
+
+    fn partition(&self, idx: ColSplitIndex, rows: &mut [usize], rng_factory: &RngFactory, scratch: &mut Vec<usize>) -> usize {
+        debug_assert!(rows.iter().all(|&row| row < self.get_nrow()), "Row index out of data range");
+        let col = self.get_col(self.splitid_to_idx(idx.col_id));
+        let shadow_rng = match idx.shadow {
+            true => Some(rng_factory.new_rng_shadow(idx.col_id)),
+            false => None
+        };
+        return col.partition_with_pivot(rows, &idx.pivot, shadow_rng, scratch);
+    }
+
+    fn permute_index(&self, col_id: SplitColId, rng_factory: &RngFactory, oob_mask: &Mask, ith_tree: usize, round: usize) -> MultiX {
+        debug_assert!(oob_mask.within_bounds(self.get_nrow()), "Mask index out of data range");
         let col = self.get_col(self.splitid_to_idx(col_id.col_id));
-        let rng = rng_factory.new_rng_permutation(ith_tree, col_id.col_id);
+        let rng = rng_factory.new_rng_permutation(ith_tree, col_id.col_id, round);
         return col.permute(rng, oob_mask);
     }
+
+    fn permute_index_all(&self, col_id: SplitColId, rng_factory: &RngFactory, round: usize) -> MultiX {
+        let col = self.get_col(self.splitid_to_idx(col_id.col_id));
+        let rng = rng_factory.new_rng_permutation_round(round, col_id.col_id);
+        return col.permute(rng, &Mask::new((0..col.len()).collect()));
+    }
+}
+
+// Split search for any response the columns can score, e.g. Y or MultiY.
+impl<R> DataInterface<ColSplitIndex, R> for XDf where
+    R: Copy + PartialEq,
+    MultiX: Splittable<R, Pivot=MultiPivot>
+{
+    fn find_min_idx<U>(&self, mask: &Mask, y: &U, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool) -> Option<ColSplitIndex>
+    where
+        U: Response<R>
+    {
+        return self.find_min_idx_penalized(mask, y, mtry, rng, rng_factory, shadow_vars, SplitPenalty::None);
+    }
+
+    fn find_min_idx_penalized<U>(&self, mask: &Mask, y: &U, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool, penalty: SplitPenalty) -> Option<ColSplitIndex>
+    where
+        U: Response<R>
+    {
+        return self.find_min_idx_with(mask, y, mtry, rng, rng_factory, shadow_vars, penalty, false);
+    }
+
+    fn find_min_idx_subsample<U>(&self, mask: &Mask, y: &U, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool, penalty: SplitPenalty) -> Option<ColSplitIndex>
+    where
+        U: Response<R>
+    {
+        return self.find_min_idx_with(mask, y, mtry, rng, rng_factory, shadow_vars, penalty, true);
+    }
+
+    fn split_score<U>(&self, col: SplitColId, mask: &Mask, y: &U) -> Option<f64>
+    where
+        U: Response<R>
+    {
+        let x = self.get_col(self.splitid_to_idx(col.col_id));
+        return Splittable::<R>::gen_optimal_pivot(x, mask, y, None).map(|res| res.1);
+    }
+
+    fn refine_split<U>(&self, idx: ColSplitIndex, mask: &Mask, y: &U, rng_factory: &RngFactory) -> ColSplitIndex
+    where
+        U: Response<R>
+    {
+        let shadow_rng = match idx.shadow {
+            true => Some(rng_factory.new_rng_shadow(idx.col_id)),
+            false => None
+        };
+        return match Splittable::<R>::gen_optimal_pivot(self.get_col(self.splitid_to_idx(idx.col_id)), mask, y, shadow_rng) {
+            Some((pivot, _)) => ColSplitIndex {pivot, ..idx},
+            None => idx
+        };
+    }
+}
+
+impl XDf {
+    // Split search of find_min_idx_penalized, with corrected scores for find_min_idx_subsample. Corrected scores
+    // have no early cutoff, as its bound holds for plain gini only.
+    fn find_min_idx_with<R, U>(&self, mask: &Mask, y: &U, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool, penalty: SplitPenalty, corrected: bool) -> Option<ColSplitIndex>
+    where
+        R: Copy + PartialEq,
+        MultiX: Splittable<R, Pivot=MultiPivot>,
+        U: Response<R>
+    {
+        debug_assert!(mask.within_bounds(self.get_nrow()), "Mask index out of data range");
+        let class_counts = OnceCell::new();
+        let eval_candidate = |(col, shadow): (usize, bool), cutoff: Option<f64>| {
+            let shadow_rng = match shadow {
+                true => Some(rng_factory.new_rng_shadow(col)),
+                false => None
+            };
+
+            let x = self.get_col(col);
+            let col_penalty = match penalty {
+                SplitPenalty::None => 0.,
+                _ => penalty.penalty(x.n_levels(mask))
+            };
+            let res = match (corrected, cutoff) {
+                (true, _) => x.gen_optimal_pivot_corrected(mask, y, shadow_rng)?,
+                (false, Some(cutoff)) => x.gen_optimal_pivot_bounded(mask, y, shadow_rng, class_counts.get_or_init(|| y.class_counts(mask)), cutoff - col_penalty)?,
+                (false, None) => Splittable::<R>::gen_optimal_pivot(x, mask, y, shadow_rng)?
+            };
+            return Some((res.0, res.1 + col_penalty, self.idx_to_splitid(col), shadow));
+        };
+
+        let min_idx = find_min_candidate(self.get_ncol(), mtry, shadow_vars, rng, eval_candidate);
+        let min_idx_un = min_idx?;
+        return Some(ColSplitIndex {col_id: min_idx_un.2, pivot: min_idx_un.0, shadow: min_idx_un.3});
+    }
+
+}
+
+impl RowView<ColSplitIndex> for XDf {
+    #[inline]
+    fn goes_left(&self, row: usize, idx: &ColSplitIndex) -> bool {
+        return self.try_goes_left(row, idx).expect("Row misses the value of the split column, see try_goes_left.");
+    }
+
+    #[inline]
+    fn try_goes_left(&self, row: usize, idx: &ColSplitIndex) -> Option<bool> {
+        return match (self.get_col(self.splitid_to_idx(idx.col_id)), idx.pivot) {
+            (MultiX::ThreeVal(x), MultiPivot::ThreeVal(p)) => x.values()[row].map(|value| p == value),
+            (MultiX::Dosage(x), MultiPivot::Dosage(p)) => p.goes_left(x.codes()[row]),
+            _ => panic!("Incoherent X -- pivot mixture")
+        };
+    }
+}
+
+impl RowMajorXDf {
+    pub fn new(df: &XDf) -> RowMajorXDf {
+        let nrow = df.get_nrow();
+        let ncol = df.data.len();
+        let mut values = Vec::with_capacity(nrow * ncol);
+        for row in 0..nrow {
+            for col in df.data.iter() {
+                match col {
+                    MultiX::ThreeVal(x) => values.push(x.values()[row].map_or(MISSING_CODE, |value| value as u8)),
+                    MultiX::Dosage(x) => values.push(x.codes()[row])
+                }
+            }
+        }
+        return RowMajorXDf{values, ncol, splitid_to_idx_map: df.splitid_to_idx_map.clone()};
+    }
+
+    pub fn get_nrow(&self) -> usize {
+        return match self.ncol {
+            0 => 0,
+            ncol => self.values.len() / ncol
+        };
+    }
+}
+
+impl RowView<ColSplitIndex> for RowMajorXDf {
+    #[inline]
+    fn goes_left(&self, row: usize, idx: &ColSplitIndex) -> bool {
+        return self.try_goes_left(row, idx).expect("Row misses the value of the split column, see try_goes_left.");
+    }
+
+    #[inline]
+    fn try_goes_left(&self, row: usize, idx: &ColSplitIndex) -> Option<bool> {
+        let value = self.values[row * self.ncol + self.splitid_to_idx_map[idx.col_id]];
+        return match idx.pivot {
+            MultiPivot::ThreeVal(p) => ThreeVal::from_code(value as i8).map(|value| p == value),
+            MultiPivot::Dosage(p) => p.goes_left(value)
+        };
+    }
+}
+
+// Candidate sampling of find_min_idx shared by the data frames. eval_candidate scores a candidate
+// (column index, shadow) as (pivot, score, col_id, shadow), None when it cannot split the node.
+pub(crate) fn find_min_candidate<P, F>(ncol: usize, mtry: usize, shadow_vars: bool, rng: &mut Rng, eval_candidate: F) -> Option<(P, f64, usize, bool)>
+where
+    F: Fn((usize, bool), Option<f64>) -> Option<(P, f64, usize, bool)>
+{
+    let n_cand = match shadow_vars {
+        true => 2 * ncol,
+        false => ncol
+    };
+
+    let (candidates, mut min_idx) = match mtry {
+        // Common in importance stability runs with many trees. The candidate is the one sample would draw,
+        // found without building the candidate range.
+        1 if n_cand > 0 => {
+            let i = rng.sample_index(n_cand);
+            let cand = (i % ncol, i >= ncol);
+            match eval_candidate(cand, None) {
+                Some(res) => return Some(res),
+                None => (vec![cand], None)
+            }
+        },
+        _ => {
+            // mtry above the column count, e.g. for narrow data in late Boruta iterations, tries every column
+            let candidates = rng.sample(&candidate_range(ncol, shadow_vars), mtry.min(n_cand));
+            debug_assert!(candidates.iter().collect::<HashSet<_>>().len() == candidates.len(), "Duplicated split candidates");
+            // Later candidates get the best score so far as cutoff, to give up on those unable to beat it
+            let mut min_idx: Option<(P, f64, usize, bool)> = None;
+            for &cand in candidates.iter() {
+                if let Some(res) = eval_candidate(cand, min_idx.as_ref().map(|min| min.1)) {
+                    let better = match &min_idx {
+                        Some(min) => cmp_split_score((res.1, res.2, res.3), (min.1, min.2, min.3)) == Ordering::Less,
+                        None => true
+                    };
+                    if better {
+                        min_idx = Some(res);
+                    }
+                }
+            }
+            (candidates, min_idx)
+        }
+    };
+
+    // All candidates are constant within the node, take the first splittable of the remaining columns
+    if min_idx.is_none() {
+        let sampled: HashSet<(usize, bool)> = candidates.iter().cloned().collect();
+        let mut rest: Vec<(usize, bool)> = candidate_range(ncol, shadow_vars).into_iter().filter(|cand| !sampled.contains(cand)).collect();
+        rng.shuffle(&mut rest);
+        min_idx = rest.iter().find_map(|&cand| eval_candidate(cand, None));
+    }
+    return min_idx;
+}
+
+// Orders split candidates by (score, col_id, shadow). NaN scores are never preferred, so ties and
+// NaN-like kernel output give the same split regardless of candidate sampling order.
+#[inline]
+fn cmp_split_score(a: (f64, usize, bool), b: (f64, usize, bool)) -> Ordering {
+    let score_ord = match (a.0.is_nan(), b.0.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.0.partial_cmp(&b.0).unwrap()
+    };
+    return score_ord.then((a.1, a.2).cmp(&(b.1, b.2)));
 }
 
 impl Permutable for MultiX {
     fn permute(&self, mut perm_rng: Rng, oob_mask: &Mask) -> Self {
         match self {
             MultiX::ThreeVal(x) => MultiX::ThreeVal(x.permute(perm_rng, oob_mask)),
-            _ =>  panic!("Incoherent X")
+            MultiX::Dosage(x) => MultiX::Dosage(x.permute(perm_rng, oob_mask))
         }
     }
 }
 
-impl Splittable<Y> for MultiX {
+impl Partitionable for MultiX {
     type Pivot = MultiPivot;
 
     fn split_with_pivot(&self, mask: &Mask, p: &Self::Pivot, shadow_rng: Option<Rng>) -> [Mask; 2] {
         match (self, p) {
             (MultiX::ThreeVal(x), MultiPivot::ThreeVal(p)) => x.split_with_pivot(&mask, &p, shadow_rng),
+            (MultiX::Dosage(x), MultiPivot::Dosage(p)) => x.split_with_pivot(mask, p, shadow_rng),
             _ =>  panic!("Incoherent X -- pivot mixture")
         }
     }
 
-    fn gen_optimal_pivot<T>(&self, mask: &Mask, y:  &T, perm_seed_shadow: Option<Rng>) -> (Self::Pivot, f64)
+    fn split_with_pivot_into(&self, mask: &Mask, p: &Self::Pivot, shadow_rng: Option<Rng>, out: &mut [Vec<usize>; 2]) {
+        match (self, p) {
+            (MultiX::ThreeVal(x), MultiPivot::ThreeVal(p)) => x.split_with_pivot_into(mask, p, shadow_rng, out),
+            (MultiX::Dosage(x), MultiPivot::Dosage(p)) => x.split_with_pivot_into(mask, p, shadow_rng, out),
+            _ => panic!("Incoherent X -- pivot mixture")
+        }
+    }
+
+    fn partition_with_pivot(&self, rows: &mut [usize], p: &Self::Pivot, shadow_rng: Option<Rng>, scratch: &mut Vec<usize>) -> usize {
+        return match (self, p) {
+            (MultiX::ThreeVal(x), MultiPivot::ThreeVal(p)) => x.partition_with_pivot(rows, p, shadow_rng, scratch),
+            (MultiX::Dosage(x), MultiPivot::Dosage(p)) => x.partition_with_pivot(rows, p, shadow_rng, scratch),
+            _ => panic!("Incoherent X -- pivot mixture")
+        };
+    }
+
+}
+
+impl<R> Splittable<R> for MultiX where
+    ThreeValCol: Splittable<R, Pivot=ThreeValPivot>,
+    DosageCol: Splittable<R, Pivot=DosagePivot>
+{
+    fn gen_optimal_pivot<T>(&self, mask: &Mask, y:  &T, perm_seed_shadow: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<R>
+    {
+        match self {
+            MultiX::ThreeVal(x) => {
+                let (piv, score) = x.gen_optimal_pivot(&mask, y, perm_seed_shadow)?;
+                Some((MultiPivot::ThreeVal(piv), score))
+            },
+            MultiX::Dosage(x) => {
+                let (piv, score) = x.gen_optimal_pivot(&mask, y, perm_seed_shadow)?;
+                Some((MultiPivot::Dosage(piv), score))
+            }
+        }
+    }
+
+    fn gen_optimal_pivot_corrected<T>(&self, mask: &Mask, y: &T, perm_seed_shadow: Option<Rng>) -> Option<(Self::Pivot, f64)>
     where
-        T: Response<Y>
+        T: Response<R>
     {
         match self {
             MultiX::ThreeVal(x) => {
-                let (piv, score) = x.gen_optimal_pivot(&mask, y, perm_seed_shadow);
-                (MultiPivot::ThreeVal(piv), score)
+                let (piv, score) = x.gen_optimal_pivot_corrected(mask, y, perm_seed_shadow)?;
+                Some((MultiPivot::ThreeVal(piv), score))
+            },
+            MultiX::Dosage(x) => {
+                let (piv, score) = x.gen_optimal_pivot_corrected(mask, y, perm_seed_shadow)?;
+                Some((MultiPivot::Dosage(piv), score))
+            }
+        }
+    }
+
+    fn gen_optimal_pivot_bounded<T>(&self, mask: &Mask, y: &T, perm_seed_shadow: Option<Rng>, class_counts: &[(R, usize)], cutoff: f64) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<R>
+    {
+        match self {
+            MultiX::ThreeVal(x) => {
+                let (piv, score) = x.gen_optimal_pivot_bounded(mask, y, perm_seed_shadow, class_counts, cutoff)?;
+                Some((MultiPivot::ThreeVal(piv), score))
+            },
+            MultiX::Dosage(x) => {
+                let (piv, score) = x.gen_optimal_pivot_bounded(mask, y, perm_seed_shadow, class_counts, cutoff)?;
+                Some((MultiPivot::Dosage(piv), score))
+            }
+        }
+    }
+
+    fn gen_optimal_pivot_weighted<T>(&self, mask: &Mask, weights: &[u32], y: &T, perm_seed_shadow: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<R>
+    {
+        match self {
+            MultiX::ThreeVal(x) => {
+                let (piv, score) = x.gen_optimal_pivot_weighted(mask, weights, y, perm_seed_shadow)?;
+                Some((MultiPivot::ThreeVal(piv), score))
+            },
+            MultiX::Dosage(x) => {
+                let (piv, score) = x.gen_optimal_pivot_weighted(mask, weights, y, perm_seed_shadow)?;
+                Some((MultiPivot::Dosage(piv), score))
             }
         }
     }
@@ -211,7 +797,29 @@ impl Splittable<Y> for MultiX {
 impl MultiX {
     pub fn len(&self) -> usize{
         match self {
-            MultiX::ThreeVal(x) => x.len()
+            MultiX::ThreeVal(x) => x.len(),
+            MultiX::Dosage(x) => x.len()
+        }
+    }
+
+    pub fn is_all_missing(&self) -> bool {
+        match self {
+            MultiX::ThreeVal(x) => x.is_all_missing(),
+            MultiX::Dosage(x) => x.is_all_missing()
+        }
+    }
+
+    pub fn subset_rows(&self, mask: &Mask) -> MultiX {
+        match self {
+            MultiX::ThreeVal(x) => MultiX::ThreeVal(x.subset_rows(mask)),
+            MultiX::Dosage(x) => MultiX::Dosage(x.subset_rows(mask))
+        }
+    }
+
+    pub fn n_levels(&self, mask: &Mask) -> usize {
+        match self {
+            MultiX::ThreeVal(x) => x.n_levels(mask),
+            MultiX::Dosage(x) => x.n_levels(mask)
         }
     }
 }
@@ -220,13 +828,17 @@ impl MultiX {
 #[cfg(test)]
 mod tests {
     use float_cmp::assert_approx_eq;
+    use crate::gini::Float;
     use crate::mask::Mask;
     use crate::data_interface::three_val::{ThreeValCol, ThreeValPivot};
-    use crate::data_interface::{DataInterface, Shadowable, Splittable};
-    use crate::data_interface::multi_x::{MultiPivot, MultiX, ColSplitIndex, XDf, SplitColId};
+    use crate::data_interface::{ColumnData, DataInterface, Partitionable, Response, RowView, Shadowable, SplitPenalty, Splittable};
+    use std::cmp::Ordering;
+    use crate::data_interface::multi_x::{MultiPivot, MultiX, ColSplitIndex, RowMajorXDf, XDf, XDfError, SplitColId, candidate_range, cmp_split_score};
     use crate::random_number_generator::Rng;
     use crate::data_interface::y_bool::YBool;
     use crate::random_number_generator::factory::RngFactory;
+    use std::collections::HashMap;
+    use crate::data_interface::col_keys::ColKeysError;
 
     #[test]
     fn split_with_pivot_multi_x() {
@@ -241,9 +853,9 @@ mod tests {
     fn gen_optimal_pivot_multi_x() {
         let x = MultiX::ThreeVal(ThreeValCol::new(&vec![0, 2, 2, 1, 1, 0, 2, 0, 1]));
         let y = YBool::new(&vec![false, true, true, false, true, false, true, true, false]);
-        let (piv, score) = x.gen_optimal_pivot(&Mask::new((0..=8).collect()), &y, None);
+        let (piv, score) = x.gen_optimal_pivot(&Mask::new((0..=8).collect()), &y, None).unwrap();
         assert_eq!(piv, MultiPivot::ThreeVal(ThreeValPivot::NotBlue));
-        assert_approx_eq!(f64, score, 6./9. - (4*4+2*2) as f64/6./9.)
+        assert_approx_eq!(Float, score as Float, 6./9. - (4*4+2*2) as Float/6./9.)
     }
 
     #[test]
@@ -260,11 +872,289 @@ mod tests {
                                   &RngFactory::new(1,
                                   Some(100),
                                   Some(100)),
-                                  false);
+                                  false).unwrap();
         assert_eq!(res.col_id, 1);
         assert_eq!(res.pivot, MultiPivot::ThreeVal(ThreeValPivot::NotRed));
     }
 
+    #[test]
+    fn find_min_idx_df_mtry_1_as_sampled() {
+        let mut rng = Rng::new(5, 1);
+        let cols: Vec<MultiX> = (0..7).map(|_| MultiX::ThreeVal(ThreeValCol::new(&(0..20).map(|_| (rng.rand_uni() * 3.) as i8).collect::<Vec<i8>>()))).collect();
+        let df = XDf::new(cols);
+        let y = YBool::new(&(0..20).map(|i| i % 3 == 0).collect::<Vec<bool>>());
+        let mask = Mask::new((0..20).collect());
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+
+        for shadow_vars in [false, true] {
+            let range = candidate_range(df.get_ncol(), shadow_vars);
+            let mut rng = Rng::new(9, 1);
+            let mut rng_ref = Rng::new(9, 1);
+            for _ in 0..50 {
+                let res = df.find_min_idx(&mask, &y, 1, &mut rng, &rng_factory, shadow_vars).unwrap();
+                let (col, shadow) = rng_ref.sample(&range, 1)[0];
+                assert_eq!((res.col_id, res.shadow), (col, shadow));
+            }
+        }
+    }
+
+    #[test]
+    fn find_min_idx_df_level_count_penalty() {
+        // Column 0 has three levels and splits y better than the binary column 1
+        let x1 = MultiX::ThreeVal(ThreeValCol::new(&[0, 0, 0, 0, 1, 1, 1, 2, 2, 2]));
+        let x2 = MultiX::ThreeVal(ThreeValCol::new(&[0, 0, 0, 0, 1, 1, 1, 1, 1, 0]));
+        let df = XDf::new(vec![x1, x2]);
+        let y = YBool::new(&[false, false, false, false, true, true, true, true, true, true]);
+        let mask = Mask::new((0..10).collect());
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+        let score = |col_id| DataInterface::<ColSplitIndex, _>::split_score(&df, SplitColId{col_id, shadow: false}, &mask, &y).unwrap();
+        let gap = score(1) - score(0);
+        assert!(gap > 0.);
+        assert_eq!((df.get_col(0).n_levels(&mask), df.get_col(1).n_levels(&mask)), (3, 2));
+
+        let find = |penalty| df.find_min_idx_penalized(&mask, &y, 2, &mut Rng::new(4, 1), &rng_factory, false, penalty).unwrap().col_id;
+        assert_eq!(find(SplitPenalty::None), 0);
+        assert_eq!(find(SplitPenalty::LevelCount(gap / 2.)), 0);
+        assert_eq!(find(SplitPenalty::LevelCount(gap * 2.)), 1);
+    }
+
+    #[test]
+    fn find_min_idx_df_cutoff_as_full_scan() {
+        // Wide mtry on rows enough for the bounded kernel to give up on noise columns
+        let mut rng = Rng::new(5, 1);
+        let mut cols: Vec<MultiX> = (0..30).map(|_| MultiX::ThreeVal(ThreeValCol::new(&(0..1000).map(|_| (rng.rand_uni() * 3.) as i8).collect::<Vec<i8>>()))).collect();
+        let informative: Vec<i8> = (0..1000).map(|i| (i % 2) as i8).collect();
+        cols[17] = MultiX::ThreeVal(ThreeValCol::new(&informative));
+        let df = XDf::new(cols);
+        let y = YBool::new(&(0..1000).map(|i| i % 2 == 1 && i % 7 != 0).collect::<Vec<bool>>());
+        let mask = Mask::new((0..1000).filter(|i| i % 5 != 0).collect());
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+
+        for (mtry, shadow_vars) in [(10, false), (30, false), (25, true)] {
+            let mut rng = Rng::new(3, 1);
+            let mut rng_ref = Rng::new(3, 1);
+            for _ in 0..5 {
+                let res = df.find_min_idx(&mask, &y, mtry, &mut rng, &rng_factory, shadow_vars).unwrap();
+                let expected = rng_ref.sample(&candidate_range(df.get_ncol(), shadow_vars), mtry).into_iter()
+                    .filter_map(|(col, shadow)| {
+                        let shadow_rng = shadow.then(|| rng_factory.new_rng_shadow(col));
+                        let (pivot, score) = df.get_col(col).gen_optimal_pivot(&mask, &y, shadow_rng)?;
+                        Some((pivot, score, col, shadow))
+                    })
+                    .min_by(|a, b| cmp_split_score((a.1, a.2, a.3), (b.1, b.2, b.3)))
+                    .unwrap();
+                assert_eq!((res.col_id, res.pivot, res.shadow), (expected.2, expected.0, expected.3));
+            }
+        }
+    }
+
+    #[test]
+    fn validate_df() {
+        let mut df = XDf::new(vec![
+            MultiX::ThreeVal(ThreeValCol::new(&vec![0, 1, 2])),
+            MultiX::ThreeVal(ThreeValCol::new(&vec![2, 1, 0])),
+        ]);
+        assert_eq!(df.validate(true), Ok(()));
+
+        df.add_shadows(RngFactory::new(1, Some(2), Some(1)));
+        assert_eq!(df.validate(true), Ok(()));
+        let sub = df.subset(&vec![SplitColId{col_id: 3, shadow: false}, SplitColId{col_id: 0, shadow: false}]);
+        assert_eq!(sub.validate(true), Ok(()));
+
+        let df = XDf::new(vec![
+            MultiX::ThreeVal(ThreeValCol::new(&vec![0, 1, 2])),
+            MultiX::ThreeVal(ThreeValCol::new(&vec![2, 1])),
+        ]);
+        assert_eq!(df.validate(false), Err(XDfError::LengthMismatch{col_id: 1, expected: 3, found: 2}));
+    }
+
+    #[test]
+    fn push_col_takes_next_free_id() {
+        let df = XDf::new(vec![
+            MultiX::ThreeVal(ThreeValCol::new(&vec![0, 1, 2])),
+            MultiX::ThreeVal(ThreeValCol::new(&vec![2, 1, 0])),
+            MultiX::ThreeVal(ThreeValCol::new(&vec![1, 1, 0])),
+        ]);
+        let mut sub = df.subset(&vec![SplitColId{col_id: 0, shadow: false}]);
+        assert_eq!(sub.push_col(MultiX::ThreeVal(ThreeValCol::new(&vec![0, 0, 1]))), 3);
+        assert_eq!(sub.validate(true), Ok(()));
+        assert_eq!(sub.get_col_ids(), vec![SplitColId{col_id: 0, shadow: false}, SplitColId{col_id: 3, shadow: false}]);
+
+        sub.push_col(MultiX::ThreeVal(ThreeValCol::new(&vec![0, 1])));
+        assert_eq!(sub.validate(false), Err(XDfError::LengthMismatch{col_id: 4, expected: 3, found: 2}));
+    }
+
+    #[test]
+    fn reorder_by_scores() {
+        let cols = [vec![0, 1, 2], vec![2, 1, 0], vec![1, 1, 0], vec![0, 0, 1]];
+        let col = |col_id| SplitColId{col_id, shadow: false};
+        let orig = XDf::new(cols.iter().map(|x| MultiX::ThreeVal(ThreeValCol::new(x))).collect());
+        let mut df = XDf::new(cols.iter().map(|x| MultiX::ThreeVal(ThreeValCol::new(x))).collect());
+        // Column 2 has no score, the shadow score is ignored
+        let scores = HashMap::from([(col(0), 0.1), (col(1), 0.5), (col(3), 0.5), (SplitColId{col_id: 2, shadow: true}, 1.)]);
+        df.reorder_by(&scores);
+
+        assert_eq!(df.get_col_ids(), vec![col(1), col(3), col(0), col(2)]);
+        assert_eq!(df.validate(true), Ok(()));
+        let mask = Mask::new(vec![0, 1, 2]);
+        let idx = ColSplitIndex{col_id: 3, pivot: MultiPivot::ThreeVal(ThreeValPivot::NotRed), shadow: false};
+        let rng_factory = RngFactory::new(0, None, None);
+        assert_eq!(df.make_split(idx, &mask, &rng_factory, None), [Mask::new(vec![2]), Mask::new(vec![0, 1])]);
+        // Subsets still find columns by id
+        let sub = df.subset(&vec![col(2), col(0)]);
+        assert_eq!(sub.get_col_ids(), vec![col(2), col(0)]);
+        assert_eq!(sub.data, orig.subset(&vec![col(2), col(0)]).data);
+    }
+
+    #[test]
+    fn from_row_chunks() {
+        let chunks = vec![
+            vec![[Some(0), Some(2)], [Some(1), None]],
+            vec![],
+            vec![[Some(2), Some(0)]]
+        ];
+        let df = XDf::from_row_chunks(2, 2, chunks).unwrap();
+        assert_eq!(df, XDf::new(vec![
+            MultiX::ThreeVal(ThreeValCol::new(&[0, 1, 2])),
+            MultiX::ThreeVal(ThreeValCol::new_with_missing(&[Some(2), None, Some(0)]))
+        ]));
+
+        let rows: Vec<Vec<Option<i8>>> = vec![vec![Some(0), Some(1)], vec![Some(1)]];
+        assert_eq!(XDf::from_row_chunks(2, 0, vec![rows]), Err(XDfError::RowLength{row: 1, expected: 2, found: 1}));
+        assert_eq!(XDf::from_row_chunks(2, 0, vec![vec![[Some(0), Some(1)]], vec![[Some(3), Some(1)]]]),
+                   Err(XDfError::InvalidValue{row: 1, col_id: 0, value: 3}));
+    }
+
+    #[test]
+    fn validate_sample_ids() {
+        let ids = |x: &[&str]| Some(x.iter().map(|x| x.to_string()).collect());
+        let mut df = XDf::new(vec![MultiX::ThreeVal(ThreeValCol::new(&vec![0, 1, 2]))]);
+        df.set_sample_ids(ids(&["a", "b", "c"]));
+        assert_eq!(df.validate(true), Ok(()));
+        assert_eq!(df.subset_rows(&Mask::new(vec![2, 0])).get_sample_ids(), Some(&["a".to_string(), "c".to_string()][..]));
+
+        df.set_sample_ids(ids(&["a", "b"]));
+        assert_eq!(df.validate(true), Err(XDfError::SampleIdLength{nrow: 3, nid: 2}));
+        df.set_sample_ids(ids(&["a", "b", "a"]));
+        assert_eq!(df.validate(true), Err(XDfError::DuplicateSampleId("a".to_string())));
+    }
+
+    #[test]
+    fn col_keys_follow_subsets() {
+        let mut df = XDf::new(vec![MultiX::ThreeVal(ThreeValCol::new(&vec![0, 1, 2])), MultiX::ThreeVal(ThreeValCol::new(&vec![2, 1, 0]))]);
+        let keys = |x: &[&str]| Some(x.iter().map(|x| x.to_string()).collect());
+        assert_eq!(df.set_col_keys(keys(&["rs7", "rs7"])), Err(ColKeysError::Duplicate{first: 0, second: 1}));
+        df.set_col_keys(keys(&["rs7", "rs9"])).unwrap();
+        let col_1 = SplitColId{col_id: 1, shadow: false};
+        let sub = df.subset(&vec![col_1]).subset_rows(&Mask::new(vec![0, 2]));
+        assert_eq!(sub.get_col_keys().unwrap().key(&col_1), Some(&"rs9".to_string()));
+        let imp = HashMap::from([(col_1, 0.5), (SplitColId{col_id: 1, shadow: true}, 0.1)]);
+        assert_eq!(sub.get_col_keys().unwrap().by_key(&imp), HashMap::from([("rs9".to_string(), 0.5)]));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Mask index out of data range")]
+    fn make_split_df_checks_mask_bounds() {
+        let df = XDf::new(vec![MultiX::ThreeVal(ThreeValCol::new(&vec![0, 1, 2]))]);
+        let idx = ColSplitIndex {col_id: 0, pivot: MultiPivot::ThreeVal(ThreeValPivot::NotRed), shadow: false};
+        df.make_split(idx, &Mask::new(vec![0, 3]), &RngFactory::new(1, Some(100), Some(100)), None);
+    }
+
+    #[test]
+    fn find_min_idx_df_breaks_ties_by_col_id() {
+        let x1 = MultiX::ThreeVal(ThreeValCol::new(&vec![1, 0, 1, 1, 0, 1]));
+        let df = XDf::new(vec![x1.clone(), x1.clone(), x1]);
+        let y = YBool::new(&vec![false, true, true, false, true, false]);
+        let mask = &Mask::new((0..=5).collect());
+        for seed in 0..20 {
+            let res = df.find_min_idx(&mask,
+                                      &y,
+                                      3,
+                                      &mut Rng::new(seed, 1),
+                                      &RngFactory::new(1, Some(100), Some(100)),
+                                      false).unwrap();
+            assert_eq!(res.col_id, 0);
+        }
+    }
+
+    #[test]
+    fn find_min_idx_df_skips_constant_columns() {
+        let x1 = MultiX::ThreeVal(ThreeValCol::new(&vec![1, 1, 1, 1, 1, 1]));
+        let x2 = MultiX::ThreeVal(ThreeValCol::new(&vec![0, 0, 0, 0, 0, 0]));
+        let x3 = MultiX::ThreeVal(ThreeValCol::new(&vec![0, 1, 1, 0, 1, 0]));
+        let df = XDf::new(vec![x1, x2, x3]);
+        let y = YBool::new(&vec![false, true, true, false, true, false]);
+        let mask = &Mask::new((0..=5).collect());
+        for seed in 0..20 {
+            let res = df.find_min_idx(&mask,
+                                      &y,
+                                      1,
+                                      &mut Rng::new(seed, 1),
+                                      &RngFactory::new(1, Some(100), Some(100)),
+                                      false).unwrap();
+            assert_eq!(res.col_id, 2);
+        }
+
+        let df = XDf::new(vec![MultiX::ThreeVal(ThreeValCol::new(&vec![2, 2, 2, 2, 2, 2]))]);
+        assert!(df.find_min_idx(&mask, &y, 1, &mut Rng::new(1, 1), &RngFactory::new(1, Some(100), Some(100)), false).is_none());
+    }
+
+    #[test]
+    fn find_min_idx_df_mtry_above_ncol() {
+        let x1 = MultiX::ThreeVal(ThreeValCol::new(&vec![1, 1, 1, 1, 1, 1]));
+        let x2 = MultiX::ThreeVal(ThreeValCol::new(&vec![0, 1, 1, 0, 1, 0]));
+        let df = XDf::new(vec![x1, x2]);
+        let y = YBool::new(&vec![false, true, true, false, true, false]);
+        let mask = &Mask::new((0..=5).collect());
+        for (mtry, shadow_vars) in [(3, false), (10, false), (10, true), (0, false)] {
+            let res = df.find_min_idx(&mask, &y, mtry, &mut Rng::new(1, 1), &RngFactory::new(1, Some(100), Some(100)), shadow_vars).unwrap();
+            assert_eq!((res.col_id, res.shadow), (1, false));
+        }
+    }
+
+    #[test]
+    fn cmp_split_score_handles_nan() {
+        assert_eq!(cmp_split_score((f64::NAN, 0, false), (0.5, 1, false)), Ordering::Greater);
+        assert_eq!(cmp_split_score((0.5, 1, false), (f64::NAN, 0, false)), Ordering::Less);
+        assert_eq!(cmp_split_score((f64::NAN, 0, false), (f64::NAN, 1, false)), Ordering::Less);
+        assert_eq!(cmp_split_score((0.1, 3, false), (0.1, 2, false)), Ordering::Greater);
+        assert_eq!(cmp_split_score((0.1, 2, false), (0.1, 2, true)), Ordering::Less);
+    }
+
+    #[test]
+    fn candidate_range_shadow_vars() {
+        let x1 = MultiX::ThreeVal(ThreeValCol::new(&vec![0, 2, 2, 1]));
+        let x2 = MultiX::ThreeVal(ThreeValCol::new(&vec![0, 1, 2, 0]));
+        let df = XDf::new(vec![x1, x2]);
+        assert_eq!(candidate_range(df.get_ncol(), false), vec![(0, false), (1, false)]);
+        assert_eq!(candidate_range(df.get_ncol(), true), vec![(0, false), (1, false), (0, true), (1, true)]);
+
+        for seed in 0..100 {
+            let mut sampled = Rng::new(seed, 1).sample(&candidate_range(df.get_ncol(), true), 3);
+            sampled.sort();
+            sampled.dedup();
+            assert_eq!(sampled.len(), 3);
+        }
+    }
+
+    #[test]
+    fn find_min_idx_df_shadow_vars_prefers_real_signal() {
+        let x1 = MultiX::ThreeVal(ThreeValCol::new(&vec![0, 1, 1, 0, 1, 0, 1, 1, 0, 0, 1, 0]));
+        let x2 = MultiX::ThreeVal(ThreeValCol::new(&vec![0, 1, 2, 0, 1, 0, 1, 2, 0, 2, 2, 1]));
+        let df = XDf::new(vec![x1, x2]);
+        let y = YBool::new(&vec![false, true, true, false, true, false, true, true, false, false, true, false]);
+        let mask = &Mask::new((0..=11).collect());
+        let res = df.find_min_idx(&mask,
+                                  &y,
+                                  4,
+                                  &mut Rng::new(2, 1),
+                                  &RngFactory::new(1, Some(100), Some(100)),
+                                  true).unwrap();
+        assert_eq!(res.col_id, 0);
+        assert!(!res.shadow);
+    }
+
     // #[test]
     // fn find_min_idx_df_shadow_vars() {
     //     let x1 = MultiX::ThreeVal(ThreeValCol::new(&vec![0, 2, 2, 1, 1, 0, 2, 0, 1]));
@@ -284,6 +1174,37 @@ mod tests {
     //     assert_eq!(res.pivot, MultiPivot::ThreeVal(ThreeValPivot::NotGreen));
     // }
 
+    #[test]
+    fn row_major_goes_left_matches_make_split() {
+        let x_vec1 = ThreeValCol::new(&vec![0, 0, 1, 2, 2, 1, 0, 1]);
+        let x_vec2 = ThreeValCol::new(&vec![0, 1, 1, 1, 0, 1, 0, 1]);
+        let x_df = XDf{data: vec![MultiX::ThreeVal(x_vec1), MultiX::ThreeVal(x_vec2)], idx_to_splitid_map: vec![1, 0], splitid_to_idx_map: vec![1, 0], sample_ids: None, col_loci: vec![], col_keys: None};
+        let rows = RowMajorXDf::new(&x_df);
+        let mask = Mask::new((0..8).collect());
+        assert_eq!(rows.get_nrow(), 8);
+
+        for col_id in 0..2 {
+            for pivot in [ThreeValPivot::NotRed, ThreeValPivot::NotGreen, ThreeValPivot::NotBlue] {
+                let idx = ColSplitIndex {col_id, pivot: MultiPivot::ThreeVal(pivot), shadow: false};
+                let masks = x_df.make_split(idx, &mask, &RngFactory::new(1, Some(100), Some(100)), None);
+                let left: Vec<usize> = (0..8).filter(|&row| rows.goes_left(row, &idx)).collect();
+                assert_eq!(&left, masks[0].get_mask());
+                assert_eq!((0..8).filter(|&row| x_df.goes_left(row, &idx)).collect::<Vec<usize>>(), left);
+            }
+        }
+    }
+
+    #[test]
+    fn try_goes_left_missing() {
+        let x_vec = ThreeValCol::new_with_missing(&[Some(0), None, Some(2)]);
+        let x_df = XDf::new(vec![MultiX::ThreeVal(x_vec)]);
+        let rows = RowMajorXDf::new(&x_df);
+        let idx = ColSplitIndex {col_id: 0, pivot: MultiPivot::ThreeVal(ThreeValPivot::NotRed), shadow: false};
+        for view in [&x_df as &dyn RowView<ColSplitIndex>, &rows] {
+            assert_eq!((0..3).map(|row| view.try_goes_left(row, &idx)).collect::<Vec<_>>(), [Some(false), None, Some(true)]);
+        }
+    }
+
     #[test]
     fn make_split_df() {
         let x_vec1 = ThreeValCol::new(&vec![0, 0, 1, 2, 2, 1, 0, 1]);
@@ -292,7 +1213,7 @@ mod tests {
         let oob_mask = Mask::new(vec![0, 1, 2, 3, 4, 5, 6, 7]);
         let mult1 = MultiX::ThreeVal(x_vec1);
         let mult2 = MultiX::ThreeVal(x_vec2);
-        let x_df = XDf{data: vec!(mult1, mult2), idx_to_splitid_map: vec![0, 1], splitid_to_idx_map: vec![0, 1]};
+        let x_df = XDf{data: vec!(mult1, mult2), idx_to_splitid_map: vec![0, 1], splitid_to_idx_map: vec![0, 1], sample_ids: None, col_loci: vec![], col_keys: None};
         let idx = ColSplitIndex {col_id: 0, pivot: MultiPivot::ThreeVal(ThreeValPivot::NotRed), shadow: false};
         assert_eq!(x_df.make_split(idx,
                                    &mask,
@@ -301,6 +1222,17 @@ mod tests {
                    [Mask::new(vec![2, 3, 4, 5]), Mask::new(vec![0, 1, 6])]);
     }
 
+    #[test]
+    fn with_observed_y_drops_rows() {
+        let x_df = XDf::new(vec![MultiX::ThreeVal(ThreeValCol::new(&[0, 1, 2, 1])), MultiX::ThreeVal(ThreeValCol::new(&[2, 2, 0, 0]))]);
+        let (new_df, y, observed) = x_df.with_observed_y(&[Some(true), None, Some(false), Some(true)]);
+
+        assert_eq!(observed.get_mask(), &vec![0, 2, 3]);
+        assert_eq!(y.as_vector(), vec![true, false, true]);
+        assert_eq!(new_df, XDf::new(vec![MultiX::ThreeVal(ThreeValCol::new(&[0, 2, 1])), MultiX::ThreeVal(ThreeValCol::new(&[2, 0, 0]))]));
+        assert_eq!(new_df.get_nrow(), 3);
+    }
+
     #[test]
     fn shadowable_can_subset() {
         let x_vec1 = ThreeValCol::new(&vec![0, 0, 0]);
@@ -320,7 +1252,10 @@ mod tests {
         let expected_res = XDf {
             data: vec![mult4, mult3],
             idx_to_splitid_map: vec![3, 2],
-            splitid_to_idx_map: vec![0, 1, 1, 0]
+            splitid_to_idx_map: vec![0, 1, 1, 0],
+            sample_ids: None,
+            col_loci: vec![],
+            col_keys: None
         };
         assert_eq!(new_df, expected_res);
     }
@@ -368,4 +1303,17 @@ mod tests {
         assert_eq!(new_df.get_ncol(), 10);
         assert_eq!(new_df.idx_to_splitid_map, [2, 1, 3, 4, 5, 6, 7, 8, 9, 10]);
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn split_col_id_serde() {
+        use std::collections::HashMap;
+
+        let imp = HashMap::from([(SplitColId{col_id: 3, shadow: false}, 0.5), (SplitColId{col_id: 3, shadow: true}, 0.25)]);
+        let json = serde_json::to_value(&imp).unwrap();
+        assert_eq!(json["3"], 0.5);
+        assert_eq!(json["shadow_3"], 0.25);
+        assert_eq!(serde_json::from_value::<HashMap<SplitColId, f64>>(json).unwrap(), imp);
+        assert!(serde_json::from_str::<SplitColId>("\"shadow_x\"").is_err());
+    }
 }
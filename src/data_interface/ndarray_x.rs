@@ -0,0 +1,270 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use ndarray::{Array1, ArrayView1, ArrayView2, Axis, CowArray, Ix1};
+
+use crate::mask::Mask;
+use crate::data_interface::multi_x::{MultiX, XDf, XDfError};
+use crate::data_interface::three_val::{best_pivot, ThreeVal, ThreeValCol, ThreeValPivot};
+use crate::data_interface::typed_x::{TypedColumn, XDfTyped};
+use crate::data_interface::y_bool::{Y, YBool};
+use crate::data_interface::{Partitionable, Permutable, Response, Splittable};
+use crate::gini::x_threeval_y_bool::{gini_x_threeval_y_bool, gini_x_threeval_y_bool_weighted};
+use crate::random_number_generator::Rng;
+
+// Column of codes 0, 1 or 2 borrowed from an array, owned only once permuted or shuffled as a shadow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArrayCol<'a>(CowArray<'a, i8, Ix1>);
+
+// Data frame over the columns of a samples x columns array of codes, without copying it. Splits as XDf
+// of the same codes, see XDfTyped.
+pub type ArrayXDf<'a> = XDfTyped<ArrayCol<'a>>;
+
+impl XDf {
+    // Data frame of a samples x columns array of codes 0, 1 or 2, e.g. a genotype matrix. Column ids follow
+    // the array columns. Any layout works, columns are copied out one at a time.
+    pub fn from_array(x: ArrayView2<i8>) -> Result<XDf, XDfError> {
+        let mut cols = Vec::with_capacity(x.ncols());
+        for (col_id, values) in x.columns().into_iter().enumerate() {
+            let mut col = ThreeValCol::with_capacity(values.len());
+            for (row, &value) in values.iter().enumerate() {
+                match ThreeVal::from_code(value) {
+                    Some(v) => col.push(Some(v)),
+                    None => return Err(XDfError::InvalidValue{row, col_id, value})
+                }
+            }
+            cols.push(MultiX::ThreeVal(col));
+        }
+        return Ok(XDf::new(cols));
+    }
+}
+
+impl<'a> XDfTyped<ArrayCol<'a>> {
+    // Borrows the columns of x, checking every code once. Column ids follow the array columns.
+    pub fn from_array_view(x: ArrayView2<'a, i8>) -> Result<ArrayXDf<'a>, XDfError> {
+        for ((row, col_id), &value) in x.indexed_iter() {
+            if ThreeVal::from_code(value).is_none() {
+                return Err(XDfError::InvalidValue{row, col_id, value});
+            }
+        }
+        return Ok(XDfTyped::new((0..x.ncols()).map(|col_id| ArrayCol(CowArray::from(x.index_axis_move(Axis(1), col_id)))).collect()));
+    }
+}
+
+impl ArrayCol<'_> {
+    #[inline]
+    fn value(&self, row: usize) -> ThreeVal {
+        return ThreeVal::from_code(self.0[row]).expect("Out of enum bounds");
+    }
+
+    // Pivot with the lowest gini score, x values in mask read from the column or its shuffled copy.
+    fn optimal_pivot<F>(&self, mask: &Mask, shadow_rng: Option<Rng>, gini: F) -> Option<(ThreeValPivot, f64)>
+    where
+        F: Fn(&ArrayCol) -> (f64, f64, f64)
+    {
+        let shuffled;
+        let x = match shadow_rng {
+            Some(mut rng) => {
+                let mut values = self.0.to_vec();
+                rng.shuffle(&mut values);
+                shuffled = ArrayCol(CowArray::from(Array1::from(values)));
+                &shuffled
+            },
+            None => self
+        };
+
+        let first = x.0[mask.get_mask()[0]];
+        if mask.get_mask().iter().all(|&i| x.0[i] == first) {
+            return None;
+        }
+        return Some(best_pivot(gini(x)));
+    }
+}
+
+impl Permutable for ArrayCol<'_> {
+    fn permute(&self, mut perm_rng: Rng, oob_mask: &Mask) -> Self {
+        let mut x = self.0.to_vec();
+        let mut oob = oob_mask.get_by_mask(&x);
+        perm_rng.shuffle(&mut oob);
+        for (&xv, &i) in oob.iter().zip(oob_mask.get_mask().iter()) {
+            x[i] = xv;
+        }
+        return ArrayCol(CowArray::from(Array1::from(x)));
+    }
+}
+
+impl Partitionable for ArrayCol<'_> {
+    type Pivot = ThreeValPivot;
+
+    fn split_with_pivot(&self, mask: &Mask, p: &Self::Pivot, shadow_rng: Option<Rng>) -> [Mask; 2] {
+        let mut out = [Vec::new(), Vec::new()];
+        self.split_with_pivot_into(mask, p, shadow_rng, &mut out);
+        return out.map(Mask::from_sorted);
+    }
+
+    fn split_with_pivot_into(&self, mask: &Mask, p: &Self::Pivot, _shadow_rng: Option<Rng>, out: &mut [Vec<usize>; 2]) {
+        out[0].clear();
+        out[1].clear();
+        for &i in mask.get_mask().iter() {
+            match *p == self.value(i) {
+                true => out[0].push(i),
+                false => out[1].push(i)
+            }
+        }
+    }
+}
+
+impl Splittable<Y> for ArrayCol<'_> {
+    fn gen_optimal_pivot<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<Y>
+    {
+        if let Some(weights) = mask.multiplicity() {
+            return self.gen_optimal_pivot_weighted(mask, &weights, y, shadow_rng);
+        }
+        return self.optimal_pivot(mask, shadow_rng, |x| {
+            let mut x_fl = mask.get_mask().iter().map(|&i| Some(x.value(i)));
+            let y_vec = y.as_vector_ref();
+            let mut y_fl = mask.get_mask().iter().map(|&i| y_vec[i]);
+            let s = gini_x_threeval_y_bool(&mut x_fl, &mut y_fl, mask.len());
+            (s.0 as f64, s.1 as f64, s.2 as f64)
+        });
+    }
+
+    fn gen_optimal_pivot_weighted<T>(&self, mask: &Mask, weights: &[u32], y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<Y>
+    {
+        if weights.len() != mask.len() {
+            panic!("Weights must match the mask length.");
+        }
+
+        return self.optimal_pivot(mask, shadow_rng, |x| {
+            let mut x_fl = mask.get_mask().iter().map(|&i| Some(x.value(i)));
+            let y_vec = y.as_vector_ref();
+            let mut y_fl = mask.get_mask().iter().map(|&i| y_vec[i]);
+            let s = gini_x_threeval_y_bool_weighted(&mut x_fl, &mut y_fl, &mut weights.iter().copied());
+            (s.0 as f64, s.1 as f64, s.2 as f64)
+        });
+    }
+}
+
+impl TypedColumn for ArrayCol<'_> {
+    #[inline]
+    fn nrow(&self) -> usize {
+        return self.0.len();
+    }
+
+    #[inline]
+    fn try_goes_left(&self, row: usize, p: &Self::Pivot) -> Option<bool> {
+        return Some(*p == self.value(row));
+    }
+
+    fn n_levels(&self, mask: &Mask) -> usize {
+        let mut seen = [false; 3];
+        for &i in mask.get_mask().iter() {
+            seen[self.value(i) as usize] = true;
+            if seen.iter().all(|&s| s) {
+                break;
+            }
+        }
+        return seen.iter().filter(|&&s| s).count();
+    }
+}
+
+impl YBool {
+    pub fn from_array(y: ArrayView1<bool>) -> YBool {
+        return YBool::new(&y.to_vec());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array2, ShapeBuilder};
+
+    use crate::mask::Mask;
+    use crate::data_interface::multi_x::{ColSplitIndex, MultiPivot, MultiX, SplitColId, XDf, XDfError};
+    use crate::data_interface::three_val::{ThreeValCol, ThreeValPivot};
+    use crate::data_interface::typed_x::{TypedSplitIndex, XDfTyped};
+    use crate::data_interface::y_bool::{Y, YBool};
+    use crate::random_forest::RandomForest;
+    use crate::data_interface::{ColumnData, DataInterface, Response, RowView};
+    use crate::random_number_generator::Rng;
+    use crate::random_number_generator::factory::RngFactory;
+
+    #[test]
+    fn from_array_as_columns() {
+        let x = array![[0, 2], [1, 1], [2, 0]];
+        let expected = XDf::new(vec![
+            MultiX::ThreeVal(ThreeValCol::new(&[0, 1, 2])),
+            MultiX::ThreeVal(ThreeValCol::new(&[2, 1, 0]))
+        ]);
+        assert_eq!(XDf::from_array(x.view()).unwrap(), expected);
+        // Column-major arrays give the same data frame
+        let x_f = Array2::from_shape_vec((3, 2).f(), vec![0, 1, 2, 2, 1, 0]).unwrap();
+        assert_eq!(XDf::from_array(x_f.view()).unwrap(), expected);
+
+        let y = array![true, false, true];
+        assert_eq!(YBool::from_array(y.view()).as_vector_ref(), &vec![true, false, true]);
+    }
+
+    #[test]
+    fn from_array_rejects_invalid_codes() {
+        let x = array![[0, 2], [1, -1]];
+        assert_eq!(XDf::from_array(x.view()), Err(XDfError::InvalidValue{row: 1, col_id: 1, value: -1}));
+        assert_eq!(XDfTyped::from_array_view(x.view()), Err(XDfError::InvalidValue{row: 1, col_id: 1, value: -1}));
+    }
+
+    #[test]
+    fn array_view_splits_as_xdf() {
+        let x = array![[0, 0], [2, 1], [2, 2], [1, 0], [1, 1], [0, 0], [2, 1], [0, 2], [1, 0]];
+        let df = XDfTyped::from_array_view(x.view()).unwrap();
+        let xdf = XDf::from_array(x.view()).unwrap();
+        let y = YBool::new(&vec![false, true, true, false, true, false, true, true, false]);
+        let mask = Mask::new((0..9).collect());
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+
+        for shadow_vars in [false, true] {
+            let res = df.find_min_idx(&mask, &y, 2, &mut Rng::new(4, 1), &rng_factory, shadow_vars).unwrap();
+            let xdf_res = xdf.find_min_idx(&mask, &y, 2, &mut Rng::new(4, 1), &rng_factory, shadow_vars).unwrap();
+            assert_eq!((res.col_id, res.shadow, MultiPivot::ThreeVal(res.pivot)), (xdf_res.col_id, xdf_res.shadow, xdf_res.pivot));
+        }
+
+        let res = df.find_min_idx(&mask, &y, 2, &mut Rng::new(4, 1), &rng_factory, false).unwrap();
+        let split = df.make_split(res, &mask, &rng_factory, None);
+        assert_eq!(split[0].get_mask(), &vec![1, 2, 4, 6, 7]);
+        assert!(split[0].get_mask().iter().all(|&row| df.goes_left(row, &res)));
+        assert!(split[1].get_mask().iter().all(|&row| !df.goes_left(row, &res)));
+
+        // Bootstrap multiplicities weigh rows as XDf does
+        let bootstrap = Mask::from_draws(&[0, 1, 1, 2, 3, 3, 3, 5, 6, 8, 8]);
+        let col = SplitColId{col_id: 0, shadow: false};
+        assert_eq!(df.split_score(col, &bootstrap, &y), xdf.split_score(col, &bootstrap, &y));
+    }
+
+    #[test]
+    fn array_view_forest_as_xdf() {
+        let mut rng = Rng::new(7, 1);
+        let x = Array2::from_shape_fn((200, 4), |_| (rng.next_u32() % 2) as i8);
+        let y = YBool::new(&x.rows().into_iter().map(|row| row[0] == 1 && row[1] == 1).collect::<Vec<bool>>());
+        let df = XDfTyped::from_array_view(x.view()).unwrap();
+        let xdf = XDf::from_array(x.view()).unwrap();
+
+        let rf: RandomForest<Y, TypedSplitIndex<ThreeValPivot>> = RandomForest::new(0);
+        let rf_xdf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+        assert_eq!(rf.importance(&df, &y, 20, 2, true, None, None), rf_xdf.importance(&xdf, &y, 20, 2, true, None, None));
+    }
+}
@@ -15,10 +15,12 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::mask::Mask;
-use crate::data_interface::{Permutable, Response, Splittable};
+use crate::data_interface::{Partitionable, Permutable, Response, Splittable};
 use crate::data_interface::y_bool::Y;
+use crate::data_interface::y_multi_bool::MultiY;
 use crate::gini::x_bool_y_bool::gini_x_bool_y_bool;
-use crate::gini::x_threeval_y_bool::gini_x_threeval_y_bool;
+use crate::gini::x_threeval_y_bool::{gini_x_threeval_y_bool, gini_x_threeval_y_bool_bounded, gini_x_threeval_y_bool_corrected, gini_x_threeval_y_bool_weighted};
+use crate::gini::Float;
 use crate::random_number_generator::Rng;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -29,6 +31,7 @@ pub enum ThreeVal {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ThreeValPivot {
     NotRed,
     NotGreen,
@@ -40,19 +43,68 @@ pub type ThreeValOpt = Option<ThreeVal>;
 pub struct ThreeValCol(Vec<ThreeValOpt>);
 
 
+impl ThreeVal {
+    // Value of code 0, 1 or 2, None for any other code.
+    pub fn from_code(x: i8) -> Option<ThreeVal> {
+        return match x {
+            0 => Some(ThreeVal::Red),
+            1 => Some(ThreeVal::Green),
+            2 => Some(ThreeVal::Blue),
+            _ => None
+        };
+    }
+}
+
 impl ThreeValCol {
     pub fn new(arr: &[i8]) -> Self{
-        return Self(arr.iter().map(|&x| Some(match x {
-            0 => ThreeVal::Red,
-            1 => ThreeVal::Green,
-            2 => ThreeVal::Blue,
-            _ => panic!("Out of enum bounds")
-        })).collect());
+        return Self(arr.iter().map(|&x| Some(ThreeVal::from_code(x).expect("Out of enum bounds"))).collect());
+    }
+
+    // As new, with None for missing values. Trees cannot be grown on missing values yet, such columns
+    // are for prediction, see Forest::predict_votes.
+    pub fn new_with_missing(arr: &[Option<i8>]) -> Self {
+        return Self(arr.iter().map(|&x| x.map(|x| ThreeVal::from_code(x).expect("Out of enum bounds"))).collect());
+    }
+
+    pub(crate) fn with_capacity(n: usize) -> Self {
+        return Self(Vec::with_capacity(n));
+    }
+
+    #[inline]
+    pub(crate) fn push(&mut self, x: ThreeValOpt) {
+        self.0.push(x);
     }
 
     pub fn len(&self) -> usize {
         return self.0.len();
     }
+
+    pub fn subset_rows(&self, mask: &Mask) -> Self {
+        return Self(mask.get_by_mask(&self.0));
+    }
+
+    pub fn is_all_missing(&self) -> bool {
+        return self.0.iter().all(|x| x.is_none());
+    }
+
+    // Number of distinct values of the rows in mask, missing ones aside.
+    pub fn n_levels(&self, mask: &Mask) -> usize {
+        let mut seen = [false; 3];
+        for &i in mask.get_mask().iter() {
+            if let Some(x) = self.0[i] {
+                seen[x as usize] = true;
+                if seen.iter().all(|&s| s) {
+                    break;
+                }
+            }
+        }
+        return seen.iter().filter(|&&s| s).count();
+    }
+
+    #[inline]
+    pub(crate) fn values(&self) -> &[ThreeValOpt] {
+        return &self.0;
+    }
 }
 
 impl Permutable for ThreeValCol {
@@ -69,32 +121,53 @@ impl Permutable for ThreeValCol {
     }
 }
 
-impl Splittable<Y> for ThreeValCol {
+impl Partitionable for ThreeValCol {
     type Pivot = ThreeValPivot;
 
     fn split_with_pivot(&self, mask: &Mask, p: &Self::Pivot, shadow_rng: Option<Rng>) -> [Mask; 2] {
-        // let mut x: Vec<_> = self.0.clone();
-        let x = mask.get_by_mask(&self.0);
+        let mut out = [Vec::new(), Vec::new()];
+        self.split_with_pivot_into(mask, p, shadow_rng, &mut out);
+        return out.map(Mask::from_sorted);
+    }
+
+    fn split_with_pivot_into(&self, mask: &Mask, p: &Self::Pivot, shadow_rng: Option<Rng>, out: &mut [Vec<usize>; 2]) {
         // if shadow_rng.is_some() {
         //     shadow_rng.unwrap().shuffle(&mut x);
         // } // TODO remove shadows
+        out[0].clear();
+        out[1].clear();
+        for &i in mask.get_mask().iter() {
+            match *p == self.0[i].unwrap() {
+                true => out[0].push(i),
+                false => out[1].push(i)
+            }
+        }
+    }
 
-
-        return x.iter().zip(mask.get_mask().iter()).fold([Vec::new(), Vec::new()], |mut acc, row| {
-            if *p == row.0.unwrap() {
-                acc[0].push(*row.1)
-            } else {
-                acc[1].push(*row.1)
+    fn partition_with_pivot(&self, rows: &mut [usize], p: &Self::Pivot, _shadow_rng: Option<Rng>, scratch: &mut Vec<usize>) -> usize {
+        scratch.clear();
+        let mut n_left = 0;
+        for i in 0..rows.len() {
+            let row = rows[i];
+            match *p == self.0[row].unwrap() {
+                true => {
+                    rows[n_left] = row;
+                    n_left += 1;
+                },
+                false => scratch.push(row)
             }
-            acc
-        }).map(|x| Mask::new(x))
+        }
+        rows[n_left..].copy_from_slice(scratch);
+        return n_left;
     }
+}
 
-    fn gen_optimal_pivot<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>) -> (Self::Pivot, f64)
+impl ThreeValCol {
+    // Pivot with the lowest of the (NotRed, NotGreen, NotBlue) scores, which gini gives for the x values in mask.
+    fn optimal_pivot<F>(&self, mask: &Mask, shadow_rng: Option<Rng>, gini: F) -> Option<(ThreeValPivot, f64)>
     where
-        T: Response<Y>
+        F: Fn(&[ThreeValOpt]) -> (f64, f64, f64)
     {
-        use ThreeValPivot::*;
         let x;
         let mut x_temp;
 
@@ -106,19 +179,137 @@ impl Splittable<Y> for ThreeValCol {
             x = &self.0;
         }
 
-        let mut x_fl = mask.get_mask().iter().map(|&i| x[i]);
-        let y_vec = y.as_vector_ref();
-        let mut y_fl = mask.get_mask().iter().map(|&i| y_vec[i]);
+        let first = x[mask.get_mask()[0]];
+        if mask.get_mask().iter().all(|&i| x[i] == first) {
+            return None;
+        }
+
+        return Some(best_pivot(gini(x)));
+    }
+}
 
-        let s = gini_x_threeval_y_bool(&mut x_fl, &mut y_fl, mask.len());
+// Pivot with the lowest of the (NotRed, NotGreen, NotBlue) scores.
+pub(crate) fn best_pivot(s: (f64, f64, f64)) -> (ThreeValPivot, f64) {
+    use ThreeValPivot::*;
+    // Yo, partial sort net (;
+    return match (s.0 < s.1, s.0 < s.2, s.1 < s.2) {
+        (true, true, _) => (NotRed, s.0),
+        (false, _, true) => (NotGreen, s.1),
+        (_, false, false) => (NotBlue, s.2),
+        _ => unreachable!(),
+    };
+}
 
-        // Yo, partial sort net (;
-        return match (s.0 < s.1, s.0 < s.2, s.1 < s.2) {
-            (true, true, _) => (NotRed, s.0),
-            (false, _, true) => (NotGreen, s.1),
-            (_, false, false) => (NotBlue, s.2),
-            _ => unreachable!(),
-        };
+impl Splittable<Y> for ThreeValCol {
+    fn gen_optimal_pivot<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<Y>
+    {
+        if let Some(weights) = mask.multiplicity() {
+            return self.gen_optimal_pivot_weighted(mask, &weights, y, shadow_rng);
+        }
+        return self.optimal_pivot(mask, shadow_rng, |x| {
+            let mut x_fl = mask.get_mask().iter().map(|&i| x[i]);
+            let y_vec = y.as_vector_ref();
+            let mut y_fl = mask.get_mask().iter().map(|&i| y_vec[i]);
+            let s = gini_x_threeval_y_bool(&mut x_fl, &mut y_fl, mask.len());
+            (s.0 as f64, s.1 as f64, s.2 as f64)
+        });
+    }
+
+    fn gen_optimal_pivot_corrected<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<Y>
+    {
+        return self.optimal_pivot(mask, shadow_rng, |x| {
+            let mut x_fl = mask.get_mask().iter().map(|&i| x[i]);
+            let y_vec = y.as_vector_ref();
+            let mut y_fl = mask.get_mask().iter().map(|&i| y_vec[i]);
+            let s = gini_x_threeval_y_bool_corrected(&mut x_fl, &mut y_fl, mask.len());
+            (s.0 as f64, s.1 as f64, s.2 as f64)
+        });
+    }
+
+    fn gen_optimal_pivot_bounded<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>, class_counts: &[(Y, usize)], cutoff: f64) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<Y>
+    {
+        // The bound counts rows once
+        if mask.is_weighted() {
+            return self.gen_optimal_pivot(mask, y, shadow_rng);
+        }
+        let n_true = class_counts.iter().find(|(class, _)| *class).map_or(0, |(_, n)| *n);
+        return self.optimal_pivot(mask, shadow_rng, |x| {
+            let mut x_fl = mask.get_mask().iter().map(|&i| x[i]);
+            let y_vec = y.as_vector_ref();
+            let mut y_fl = mask.get_mask().iter().map(|&i| y_vec[i]);
+            return match gini_x_threeval_y_bool_bounded(&mut x_fl, &mut y_fl, mask.len(), n_true, cutoff as Float) {
+                Some(s) => (s.0 as f64, s.1 as f64, s.2 as f64),
+                None => (f64::INFINITY, f64::INFINITY, f64::INFINITY)
+            };
+        });
+    }
+
+    fn gen_optimal_pivot_weighted<T>(&self, mask: &Mask, weights: &[u32], y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<Y>
+    {
+        if weights.len() != mask.len() {
+            panic!("Weights must match the mask length.");
+        }
+
+        return self.optimal_pivot(mask, shadow_rng, |x| {
+            let mut x_fl = mask.get_mask().iter().map(|&i| x[i]);
+            let y_vec = y.as_vector_ref();
+            let mut y_fl = mask.get_mask().iter().map(|&i| y_vec[i]);
+            let s = gini_x_threeval_y_bool_weighted(&mut x_fl, &mut y_fl, &mut weights.iter().copied());
+            (s.0 as f64, s.1 as f64, s.2 as f64)
+        });
+    }
+}
+
+// Gini is averaged over responses, so scores stay comparable with single response ones.
+impl Splittable<MultiY> for ThreeValCol {
+    fn gen_optimal_pivot<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<MultiY>
+    {
+        if let Some(weights) = mask.multiplicity() {
+            return self.gen_optimal_pivot_weighted(mask, &weights, y, shadow_rng);
+        }
+        return self.optimal_pivot_multi(mask, y, shadow_rng, false);
+    }
+
+    fn gen_optimal_pivot_corrected<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>) -> Option<(Self::Pivot, f64)>
+    where
+        T: Response<MultiY>
+    {
+        return self.optimal_pivot_multi(mask, y, shadow_rng, true);
+    }
+}
+
+impl ThreeValCol {
+    // Pivot minimising gini averaged over responses, with the finite-sample correction when corrected is set.
+    fn optimal_pivot_multi<T>(&self, mask: &Mask, y: &T, shadow_rng: Option<Rng>, corrected: bool) -> Option<(ThreeValPivot, f64)>
+    where
+        T: Response<MultiY>
+    {
+        return self.optimal_pivot(mask, shadow_rng, |x| {
+            let y_vec = y.as_vector_ref();
+            let n_resp = y_vec[mask.get_mask()[0]].len();
+            let mut s = (0., 0., 0.);
+            for r in 0..n_resp {
+                let mut x_fl = mask.get_mask().iter().map(|&i| x[i]);
+                let mut y_fl = mask.get_mask().iter().map(|&i| y_vec[i].get(r));
+                let sr = match corrected {
+                    true => gini_x_threeval_y_bool_corrected(&mut x_fl, &mut y_fl, mask.len()),
+                    false => gini_x_threeval_y_bool(&mut x_fl, &mut y_fl, mask.len())
+                };
+                s = (s.0 + sr.0, s.1 + sr.1, s.2 + sr.2);
+            }
+            let n_resp = n_resp as Float;
+            ((s.0 / n_resp) as f64, (s.1 / n_resp) as f64, (s.2 / n_resp) as f64)
+        });
     }
 }
 
@@ -139,12 +330,22 @@ impl PartialEq<ThreeVal> for ThreeValPivot {
 #[cfg(test)]
 mod tests {
     use float_cmp::assert_approx_eq;
+    use crate::gini::Float;
     use crate::mask::Mask;
     use crate::data_interface::three_val::{ThreeVal, ThreeValCol, ThreeValPivot};
-    use crate::data_interface::{Permutable, Splittable};
+    use crate::data_interface::{Partitionable, Splittable};
     use crate::data_interface::y_bool::YBool;
     use crate::random_number_generator::Rng;
 
+    #[test]
+    fn n_levels_within_mask() {
+        let x = ThreeValCol::new_with_missing(&[Some(0), Some(2), None, Some(2), Some(1)]);
+        assert_eq!(x.n_levels(&Mask::new(vec![0, 1, 2, 3, 4])), 3);
+        assert_eq!(x.n_levels(&Mask::new(vec![1, 2, 3])), 1);
+        assert_eq!(x.n_levels(&Mask::new(vec![0, 2, 3])), 2);
+        assert_eq!(x.n_levels(&Mask::new(vec![2])), 0);
+    }
+
     #[test]
     fn make_split() {
         let x_vec = ThreeValCol(vec![0, 0, 1, 2, 2, 1, 0, 1].iter().map(|&x| Some(match x {
@@ -159,6 +360,28 @@ mod tests {
                    [Mask::new(vec![2, 3, 4, 5]), Mask::new(vec![0, 1, 6])]);
     }
 
+    #[test]
+    fn partition_with_pivot_is_stable() {
+        let x_vec = ThreeValCol::new(&vec![0, 0, 1, 2, 2, 1, 0, 1]);
+        let mut rows = vec![0, 2, 3, 5, 6, 7];
+        let mut scratch = vec![9, 9, 9, 9, 9, 9, 9];
+        let mask = Mask::new(rows.clone());
+        let n_left = x_vec.partition_with_pivot(&mut rows, &ThreeValPivot::NotRed, None, &mut scratch);
+        let masks = x_vec.split_with_pivot(&mask, &ThreeValPivot::NotRed, None);
+        assert_eq!(&rows[..n_left], masks[0].get_mask().as_slice());
+        assert_eq!(&rows[n_left..], masks[1].get_mask().as_slice());
+    }
+
+    #[test]
+    fn make_split_into_reuses_buffers() {
+        let x_vec = ThreeValCol::new(&vec![0, 0, 1, 2, 2, 1, 0, 1]);
+        let mut out = [vec![7, 7, 7], vec![]];
+        x_vec.split_with_pivot_into(&Mask::new(vec![0, 2, 3, 7]), &ThreeValPivot::NotGreen, None, &mut out);
+        assert_eq!(out, [vec![0, 3], vec![2, 7]]);
+        x_vec.split_with_pivot_into(&Mask::new(vec![1, 4]), &ThreeValPivot::NotGreen, None, &mut out);
+        assert_eq!(out, [vec![1, 4], vec![]]);
+    }
+
     // #[test]
     // fn it_permutes() {
     //     let x_vec = ThreeValCol::new(&vec![0, 0, 1, 2, 2, 1, 0, 1, 0]);
@@ -200,18 +423,41 @@ mod tests {
     fn gen_optimal_pivot() {
         let x = ThreeValCol::new(&vec![0, 2, 2, 1, 1, 0, 2, 0, 1]);
         let y = YBool::new(&vec![false, true, true, false, true, false, true, true, false]);
-        let (piv, score) = x.gen_optimal_pivot(&Mask::new((0..=8).collect()), &y, None);
+        let (piv, score) = x.gen_optimal_pivot(&Mask::new((0..=8).collect()), &y, None).unwrap();
         assert_eq!(piv, ThreeValPivot::NotBlue);
-        assert_approx_eq!(f64, score, 6./9. - (4*4+2*2) as f64/6./9.)
+        assert_approx_eq!(Float, score as Float, 6./9. - (4*4+2*2) as Float/6./9.)
     }
 
     #[test]
     fn gen_optimal_pivot_uses_mask() {
         let x = ThreeValCol::new(&vec![1, 0, 2, 2, 1, 1, 0, 2, 0, 1, 1, 1]);
         let y = YBool::new(&vec![false, false, true, true, false, true, false, true, true, false, false, false]);
-        let (piv, score) = x.gen_optimal_pivot(&Mask::new((1..=9).collect()), &y, None);
+        let (piv, score) = x.gen_optimal_pivot(&Mask::new((1..=9).collect()), &y, None).unwrap();
         assert_eq!(piv, ThreeValPivot::NotBlue);
-        assert_approx_eq!(f64, score, 6./9. - (4*4+2*2) as f64/6./9.)
+        assert_approx_eq!(Float, score as Float, 6./9. - (4*4+2*2) as Float/6./9.)
+    }
+
+    #[test]
+    fn gen_optimal_pivot_single_level_in_mask() {
+        let x = ThreeValCol::new(&vec![0, 2, 2, 2, 1]);
+        let y = YBool::new(&vec![false, true, false, true, false]);
+        assert!(x.gen_optimal_pivot(&Mask::new(vec![1, 2, 3]), &y, None).is_none());
+        assert!(x.gen_optimal_pivot(&Mask::new(vec![0, 1, 2, 3]), &y, None).is_some());
+    }
+
+    #[test]
+    fn gen_optimal_pivot_weighted_as_repeated_rows() {
+        let x = ThreeValCol::new(&vec![0, 2, 2, 1, 1, 0, 2, 0, 1]);
+        let y = YBool::new(&vec![false, true, true, false, true, false, true, true, false]);
+        let mask = Mask::from_draws(&[0, 1, 1, 2, 3, 3, 3, 5, 6, 8, 8]);
+        let repeated = Mask::new(vec![0, 1, 1, 2, 3, 3, 3, 5, 6, 8, 8]);
+        let (piv, score) = x.gen_optimal_pivot_weighted(&mask, &mask.multiplicity().unwrap(), &y, None).unwrap();
+        let (piv_rep, score_rep) = x.gen_optimal_pivot(&repeated, &y, None).unwrap();
+        assert_eq!(piv, piv_rep);
+        assert_approx_eq!(f64, score, score_rep);
+        // A mask with multiplicities is weighted by them, also through the bounded search
+        assert_eq!(x.gen_optimal_pivot(&mask, &y, None), Some((piv, score)));
+        assert_eq!(x.gen_optimal_pivot_bounded(&mask, &y, None, &[(true, 5), (false, 4)], 0.), Some((piv, score)));
     }
 
     // #[test]
@@ -221,7 +467,7 @@ mod tests {
     //     let y = YBool::new(&vec![false, false, true, true, false, true, false, true, true, false]);
     //     let (piv, score) = x.gen_optimal_pivot(&Mask::new((0..=9).collect()), &y, None);
     //     assert_eq!(piv, ThreeValPivot::NotBlue);
-    //     assert_approx_eq!(f64, score, 6./9. - (4*4+2*2) as f64/6./9.)
+    //     assert_approx_eq!(Float, score as Float, 6./9. - (4*4+2*2) as Float/6./9.)
     // }
 
     // #[test]
@@ -0,0 +1,294 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cell::OnceCell;
+use std::fmt::Debug;
+use std::hash::Hash;
+use crate::mask::Mask;
+use crate::data_interface::three_val::ThreeValCol;
+use crate::data_interface::multi_x::{SplitColId, find_min_candidate};
+use crate::data_interface::{ColumnData, DataInterface, Response, ColumnIdentifiable, Partitionable, PivotIdentifiable, Splittable, SplitPenalty, RowView};
+use crate::random_number_generator::Rng;
+use crate::random_number_generator::factory::RngFactory;
+
+// Data frame of columns of a single kind C, e.g. XDfTyped<ThreeValCol> for genotypes only. Splits are
+// monomorphized on C, so the hot loops skip the variant dispatch of MultiX. Use XDf for mixed columns
+// and Boruta, which needs shadows. Column ids are column positions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XDfTyped<C> {
+    data: Vec<C>
+}
+
+// Split of XDfTyped, as ColSplitIndex with the pivot of the column kind.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TypedSplitIndex<P> {
+    pub col_id: usize,
+    pub pivot: P,
+    pub shadow: bool
+}
+
+// Columns XDfTyped can hold.
+pub trait TypedColumn: Partitionable + Clone {
+    fn nrow(&self) -> usize;
+    // As RowView::try_goes_left for a single column.
+    fn try_goes_left(&self, row: usize, p: &Self::Pivot) -> Option<bool>;
+    // Number of distinct values of the rows in mask, for SplitPenalty.
+    fn n_levels(&self, mask: &Mask) -> usize;
+}
+
+impl TypedColumn for ThreeValCol {
+    #[inline]
+    fn nrow(&self) -> usize {
+        return self.len();
+    }
+
+    #[inline]
+    fn try_goes_left(&self, row: usize, p: &Self::Pivot) -> Option<bool> {
+        return self.values()[row].map(|value| *p == value);
+    }
+
+    fn n_levels(&self, mask: &Mask) -> usize {
+        return ThreeValCol::n_levels(self, mask);
+    }
+}
+
+impl<P> ColumnIdentifiable for TypedSplitIndex<P> {
+    type Col = SplitColId;
+
+    #[inline]
+    fn get_col_id(&self) -> Self::Col {
+        return SplitColId{col_id: self.col_id, shadow: self.shadow};
+    }
+
+    #[inline]
+    fn is_shadow_col(col: &Self::Col) -> bool {
+        return col.shadow;
+    }
+
+    #[inline]
+    fn col_index(col: &Self::Col) -> Option<usize> {
+        return match col.shadow {
+            true => None,
+            false => Some(col.col_id)
+        };
+    }
+}
+
+impl<P> PivotIdentifiable for TypedSplitIndex<P> where
+    P: Hash + Eq + Copy + Debug + Send + Sync
+{
+    type Pivot = P;
+
+    #[inline]
+    fn get_pivot(&self) -> Self::Pivot {
+        return self.pivot;
+    }
+}
+
+impl<C: TypedColumn> XDfTyped<C> {
+    pub fn new(cols: Vec<C>) -> XDfTyped<C> {
+        return XDfTyped{data: cols};
+    }
+
+    pub fn get_nrow(&self) -> usize {
+        return self.data.first().map_or(0, |x| x.nrow());
+    }
+
+    #[inline]
+    fn shadow_rng(idx: &TypedSplitIndex<C::Pivot>, rng_factory: &RngFactory) -> Option<Rng> {
+        return match idx.shadow {
+            true => Some(rng_factory.new_rng_shadow(idx.col_id)),
+            false => None
+        };
+    }
+}
+
+impl<C> ColumnData<TypedSplitIndex<C::Pivot>> for XDfTyped<C> where
+    C: TypedColumn,
+    C::Pivot: Copy
+{
+    type InternalType = C;
+
+    #[inline]
+    fn get_ncol(&self) -> usize {
+        return self.data.len();
+    }
+
+    fn get_col_ids(&self) -> Vec<SplitColId> {
+        return (0..self.data.len()).map(|col_id| SplitColId{col_id, shadow: false}).collect();
+    }
+
+    fn get_shadow_col_ids(&self) -> Vec<SplitColId> {
+        return (0..self.data.len()).map(|col_id| SplitColId{col_id, shadow: true}).collect();
+    }
+
+    fn make_split(&self, idx: TypedSplitIndex<C::Pivot>, mask: &Mask, rng_factory: &RngFactory, permuted_vec: Option<&C>) -> [Mask; 2] {
+        debug_assert!(mask.within_bounds(self.get_nrow()), "Mask index out of data range");
+        let col = permuted_vec.unwrap_or(&self.data[idx.col_id]);
+        return col.split_with_pivot(mask, &idx.pivot, Self::shadow_rng(&idx, rng_factory));
+    }
+
+    fn make_split_into(&self, idx: TypedSplitIndex<C::Pivot>, mask: &Mask, rng_factory: &RngFactory, out: &mut [Vec<usize>; 2]) {
+        debug_assert!(mask.within_bounds(self.get_nrow()), "Mask index out of data range");
+        self.data[idx.col_id].split_with_pivot_into(mask, &idx.pivot, Self::shadow_rng(&idx, rng_factory), out);
+    }
+
+    fn partition(&self, idx: TypedSplitIndex<C::Pivot>, rows: &mut [usize], rng_factory: &RngFactory, scratch: &mut Vec<usize>) -> usize {
+        debug_assert!(rows.iter().all(|&row| row < self.get_nrow()), "Row index out of data range");
+        return self.data[idx.col_id].partition_with_pivot(rows, &idx.pivot, Self::shadow_rng(&idx, rng_factory), scratch);
+    }
+
+    fn permute_index(&self, col_id: SplitColId, rng_factory: &RngFactory, oob_mask: &Mask, ith_tree: usize, round: usize) -> C {
+        debug_assert!(oob_mask.within_bounds(self.get_nrow()), "Mask index out of data range");
+        let rng = rng_factory.new_rng_permutation(ith_tree, col_id.col_id, round);
+        return self.data[col_id.col_id].permute(rng, oob_mask);
+    }
+
+    fn permute_index_all(&self, col_id: SplitColId, rng_factory: &RngFactory, round: usize) -> C {
+        let col = &self.data[col_id.col_id];
+        let rng = rng_factory.new_rng_permutation_round(round, col_id.col_id);
+        return col.permute(rng, &Mask::new((0..col.nrow()).collect()));
+    }
+}
+
+impl<C> XDfTyped<C> where
+    C: TypedColumn
+{
+    // Split search of find_min_idx_penalized, with corrected scores for find_min_idx_subsample, as in XDf.
+    fn find_min_idx_with<R, U>(&self, mask: &Mask, y: &U, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool, penalty: SplitPenalty, corrected: bool) -> Option<TypedSplitIndex<C::Pivot>>
+    where
+        R: Copy + PartialEq,
+        C: Splittable<R>,
+        U: Response<R>
+    {
+        debug_assert!(mask.within_bounds(self.get_nrow()), "Mask index out of data range");
+        let class_counts = OnceCell::new();
+        let eval_candidate = |(col, shadow): (usize, bool), cutoff: Option<f64>| {
+            let shadow_rng = match shadow {
+                true => Some(rng_factory.new_rng_shadow(col)),
+                false => None
+            };
+            let col_penalty = match penalty {
+                SplitPenalty::None => 0.,
+                _ => penalty.penalty(self.data[col].n_levels(mask))
+            };
+            let res = match (corrected, cutoff) {
+                (true, _) => self.data[col].gen_optimal_pivot_corrected(mask, y, shadow_rng)?,
+                (false, Some(cutoff)) => self.data[col].gen_optimal_pivot_bounded(mask, y, shadow_rng, class_counts.get_or_init(|| y.class_counts(mask)), cutoff - col_penalty)?,
+                (false, None) => self.data[col].gen_optimal_pivot(mask, y, shadow_rng)?
+            };
+            return Some((res.0, res.1 + col_penalty, col, shadow));
+        };
+
+        let (pivot, _, col_id, shadow) = find_min_candidate(self.data.len(), mtry, shadow_vars, rng, eval_candidate)?;
+        return Some(TypedSplitIndex{col_id, pivot, shadow});
+    }
+}
+
+// Same split search as XDf, so both give the same trees on the same data.
+impl<C, R> DataInterface<TypedSplitIndex<C::Pivot>, R> for XDfTyped<C> where
+    R: Copy + PartialEq,
+    C: TypedColumn + Splittable<R>,
+    C::Pivot: Copy
+{
+    fn find_min_idx<U>(&self, mask: &Mask, y: &U, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool) -> Option<TypedSplitIndex<C::Pivot>>
+    where
+        U: Response<R>
+    {
+        return self.find_min_idx_penalized(mask, y, mtry, rng, rng_factory, shadow_vars, SplitPenalty::None);
+    }
+
+    fn find_min_idx_penalized<U>(&self, mask: &Mask, y: &U, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool, penalty: SplitPenalty) -> Option<TypedSplitIndex<C::Pivot>>
+    where
+        U: Response<R>
+    {
+        return self.find_min_idx_with(mask, y, mtry, rng, rng_factory, shadow_vars, penalty, false);
+    }
+
+    fn find_min_idx_subsample<U>(&self, mask: &Mask, y: &U, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool, penalty: SplitPenalty) -> Option<TypedSplitIndex<C::Pivot>>
+    where
+        U: Response<R>
+    {
+        return self.find_min_idx_with(mask, y, mtry, rng, rng_factory, shadow_vars, penalty, true);
+    }
+
+    fn split_score<U>(&self, col: SplitColId, mask: &Mask, y: &U) -> Option<f64>
+    where
+        U: Response<R>
+    {
+        return self.data[col.col_id].gen_optimal_pivot(mask, y, None).map(|res| res.1);
+    }
+
+    fn refine_split<U>(&self, idx: TypedSplitIndex<C::Pivot>, mask: &Mask, y: &U, rng_factory: &RngFactory) -> TypedSplitIndex<C::Pivot>
+    where
+        U: Response<R>
+    {
+        return match self.data[idx.col_id].gen_optimal_pivot(mask, y, Self::shadow_rng(&idx, rng_factory)) {
+            Some((pivot, _)) => TypedSplitIndex{pivot, ..idx},
+            None => idx
+        };
+    }
+}
+
+impl<C: TypedColumn> RowView<TypedSplitIndex<C::Pivot>> for XDfTyped<C> {
+    #[inline]
+    fn goes_left(&self, row: usize, idx: &TypedSplitIndex<C::Pivot>) -> bool {
+        return self.try_goes_left(row, idx).expect("Row misses the value of the split column, see try_goes_left.");
+    }
+
+    #[inline]
+    fn try_goes_left(&self, row: usize, idx: &TypedSplitIndex<C::Pivot>) -> Option<bool> {
+        return self.data[idx.col_id].try_goes_left(row, &idx.pivot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mask::Mask;
+    use crate::data_interface::three_val::{ThreeValCol, ThreeValPivot};
+    use crate::data_interface::multi_x::{MultiX, XDf};
+    use crate::data_interface::typed_x::XDfTyped;
+    use crate::data_interface::y_bool::YBool;
+    use crate::data_interface::{ColumnData, DataInterface, RowView};
+    use crate::random_number_generator::Rng;
+    use crate::random_number_generator::factory::RngFactory;
+
+    #[test]
+    fn typed_find_min_idx_as_xdf() {
+        let x1 = vec![0, 2, 2, 1, 1, 0, 2, 0, 1];
+        let x2 = vec![0, 1, 2, 0, 1, 0, 1, 2, 0];
+        let df = XDfTyped::new(vec![ThreeValCol::new(&x1), ThreeValCol::new(&x2)]);
+        let multi_df = XDf::new(vec![MultiX::ThreeVal(ThreeValCol::new(&x1)), MultiX::ThreeVal(ThreeValCol::new(&x2))]);
+        let y = YBool::new(&vec![false, true, true, false, true, false, true, true, false]);
+        let mask = Mask::new((0..=8).collect());
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+
+        for mtry in [1, 2] {
+            let res = df.find_min_idx(&mask, &y, mtry, &mut Rng::new(4, 1), &rng_factory, true).unwrap();
+            let multi_res = multi_df.find_min_idx(&mask, &y, mtry, &mut Rng::new(4, 1), &rng_factory, true).unwrap();
+            assert_eq!((res.col_id, res.shadow), (multi_res.col_id, multi_res.shadow));
+        }
+
+        let res = df.find_min_idx(&mask, &y, 2, &mut Rng::new(4, 1), &rng_factory, false).unwrap();
+        assert_eq!(res.col_id, 1);
+        assert_eq!(res.pivot, ThreeValPivot::NotRed);
+        let split = df.make_split(res, &mask, &rng_factory, None);
+        assert_eq!(split[0].get_mask(), &vec![1, 2, 4, 6, 7]);
+        assert!(split[0].get_mask().iter().all(|&row| df.goes_left(row, &res)));
+        assert!(split[1].get_mask().iter().all(|&row| !df.goes_left(row, &res)));
+    }
+}
@@ -0,0 +1,194 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+
+use crate::data_interface::multi_x::{MultiX, XDf};
+use crate::data_interface::three_val::ThreeValCol;
+
+// Levels a column can be encoded with as long as MultiX only has the three-valued variant.
+const MAX_LEVELS: usize = 3;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum XDfBuilderError {
+    LengthMismatch{name: String, expected: usize, found: usize},
+    DuplicateName(String),
+    InvalidValue{name: String, pos: usize},
+    TooManyLevels{name: String, levels: usize},
+    UnsupportedKind{name: String, kind: &'static str},
+}
+
+impl fmt::Display for XDfBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XDfBuilderError::LengthMismatch{name, expected, found} =>
+                write!(f, "Column {} has {} rows, expected {}", name, found, expected),
+            XDfBuilderError::DuplicateName(name) => write!(f, "Duplicated column name {}", name),
+            XDfBuilderError::InvalidValue{name, pos} => write!(f, "Invalid value in column {} at position {}", name, pos),
+            XDfBuilderError::TooManyLevels{name, levels} =>
+                write!(f, "Column {} has {} levels, at most {} are supported", name, levels, MAX_LEVELS),
+            XDfBuilderError::UnsupportedKind{name, kind} => write!(f, "Column {} is {}, which is not supported yet", name, kind),
+        }
+    }
+}
+
+impl Error for XDfBuilderError {}
+
+enum ColumnKind {
+    ThreeVal(Vec<i8>),
+    Bool(Vec<bool>),
+    Numeric(Vec<f64>),
+    Categorical(Vec<String>),
+}
+
+impl ColumnKind {
+    fn len(&self) -> usize {
+        match self {
+            ColumnKind::ThreeVal(x) => x.len(),
+            ColumnKind::Bool(x) => x.len(),
+            ColumnKind::Numeric(x) => x.len(),
+            ColumnKind::Categorical(x) => x.len(),
+        }
+    }
+}
+
+// Collects named columns of different kinds and turns them into XDf. Column ids in the resulting
+// XDf follow the order columns were added, so names[col_id] is the name of the column.
+#[derive(Default)]
+pub struct XDfBuilder {
+    cols: Vec<(String, ColumnKind)>,
+}
+
+impl XDfBuilder {
+    pub fn new() -> Self {
+        return XDfBuilder{cols: vec![]};
+    }
+
+    // Values 0, 1, 2.
+    pub fn three_val(mut self, name: &str, x: &[i8]) -> Self {
+        self.cols.push((name.to_string(), ColumnKind::ThreeVal(x.to_vec())));
+        return self;
+    }
+
+    pub fn bool(mut self, name: &str, x: &[bool]) -> Self {
+        self.cols.push((name.to_string(), ColumnKind::Bool(x.to_vec())));
+        return self;
+    }
+
+    pub fn numeric(mut self, name: &str, x: &[f64]) -> Self {
+        self.cols.push((name.to_string(), ColumnKind::Numeric(x.to_vec())));
+        return self;
+    }
+
+    // Levels are coded in order of first appearance.
+    pub fn categorical<S: AsRef<str>>(mut self, name: &str, x: &[S]) -> Self {
+        self.cols.push((name.to_string(), ColumnKind::Categorical(x.iter().map(|s| s.as_ref().to_string()).collect())));
+        return self;
+    }
+
+    pub fn build(self) -> Result<(XDf, Vec<String>), XDfBuilderError> {
+        let nrow = self.cols.first().map_or(0, |(_, col)| col.len());
+        let mut seen: HashSet<&str> = HashSet::new();
+        for (name, col) in self.cols.iter() {
+            if !seen.insert(name) {
+                return Err(XDfBuilderError::DuplicateName(name.clone()));
+            }
+            if col.len() != nrow {
+                return Err(XDfBuilderError::LengthMismatch{name: name.clone(), expected: nrow, found: col.len()});
+            }
+        }
+
+        let mut data = Vec::with_capacity(self.cols.len());
+        let mut names = Vec::with_capacity(self.cols.len());
+        for (name, col) in self.cols {
+            let coded: Vec<i8> = match col {
+                ColumnKind::ThreeVal(x) => {
+                    if let Some(pos) = x.iter().position(|&v| !(0..=2).contains(&v)) {
+                        return Err(XDfBuilderError::InvalidValue{name, pos});
+                    }
+                    x
+                },
+                ColumnKind::Bool(x) => x.iter().map(|&v| v as i8).collect(),
+                ColumnKind::Numeric(_) => return Err(XDfBuilderError::UnsupportedKind{name, kind: "numeric"}),
+                ColumnKind::Categorical(x) => {
+                    let mut levels: Vec<&str> = vec![];
+                    let mut coded = Vec::with_capacity(x.len());
+                    for v in x.iter() {
+                        let code = match levels.iter().position(|&l| l == v) {
+                            Some(code) => code,
+                            None => {
+                                levels.push(v);
+                                levels.len() - 1
+                            }
+                        };
+                        coded.push(code as i8);
+                    }
+                    if levels.len() > MAX_LEVELS {
+                        return Err(XDfBuilderError::TooManyLevels{name, levels: levels.len()});
+                    }
+                    coded
+                }
+            };
+            data.push(MultiX::ThreeVal(ThreeValCol::new(&coded)));
+            names.push(name);
+        }
+
+        return Ok((XDf::new(data), names));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_interface::multi_x::{MultiX, XDf};
+    use crate::data_interface::three_val::ThreeValCol;
+    use crate::data_interface::xdf_builder::{XDfBuilder, XDfBuilderError};
+
+    #[test]
+    fn build_mixed_columns() {
+        let (df, names) = XDfBuilder::new()
+            .three_val("snp1", &[0, 2, 1])
+            .bool("sex", &[true, false, true])
+            .categorical("site", &["b", "a", "b"])
+            .build().unwrap();
+
+        assert_eq!(names, vec!["snp1", "sex", "site"]);
+        assert_eq!(df, XDf::new(vec![
+            MultiX::ThreeVal(ThreeValCol::new(&[0, 2, 1])),
+            MultiX::ThreeVal(ThreeValCol::new(&[1, 0, 1])),
+            MultiX::ThreeVal(ThreeValCol::new(&[0, 1, 0])),
+        ]));
+    }
+
+    #[test]
+    fn build_errors() {
+        let err = XDfBuilder::new().three_val("a", &[0, 1]).bool("b", &[true]).build();
+        assert_eq!(err.unwrap_err(), XDfBuilderError::LengthMismatch{name: "b".to_string(), expected: 2, found: 1});
+
+        let err = XDfBuilder::new().three_val("a", &[0, 1]).three_val("a", &[1, 0]).build();
+        assert_eq!(err.unwrap_err(), XDfBuilderError::DuplicateName("a".to_string()));
+
+        let err = XDfBuilder::new().three_val("a", &[0, 3]).build();
+        assert_eq!(err.unwrap_err(), XDfBuilderError::InvalidValue{name: "a".to_string(), pos: 1});
+
+        let err = XDfBuilder::new().categorical("a", &["w", "x", "y", "z"]).build();
+        assert_eq!(err.unwrap_err(), XDfBuilderError::TooManyLevels{name: "a".to_string(), levels: 4});
+
+        let err = XDfBuilder::new().numeric("a", &[0.5, 1.]).build();
+        assert_eq!(err.unwrap_err(), XDfBuilderError::UnsupportedKind{name: "a".to_string(), kind: "numeric"});
+    }
+}
@@ -15,9 +15,11 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
 
 use crate::mask::Mask;
-use crate::data_interface::{Predicted, Response};
+use crate::data_interface::{Predicted, Response, Subsettable, TiePolicy};
 use crate::random_number_generator::Rng;
 
 pub type Y = bool;
@@ -25,10 +27,75 @@ pub type Y = bool;
 #[derive(Debug)]
 pub struct YBool(Vec<Y>);
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum YBoolError {
+    MoreThanTwoLabels(Vec<String>),
+    PositiveLabelNotFound(String),
+    UnknownCode{pos: usize, code: i8},
+    MixedCoding,
+    AmbiguousCoding,
+}
+
+impl fmt::Display for YBoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YBoolError::MoreThanTwoLabels(labels) => write!(f, "More than two distinct labels: {:?}", labels),
+            YBoolError::PositiveLabelNotFound(label) => write!(f, "Positive label {:?} not found in labels", label),
+            YBoolError::UnknownCode{pos, code} => write!(f, "Unknown response code {} at position {}", code, pos),
+            YBoolError::MixedCoding => write!(f, "Codes mix 0/1 and 1/2 coding"),
+            YBoolError::AmbiguousCoding => write!(f, "Cannot tell 0/1 from 1/2 coding when only 1 is present"),
+        }
+    }
+}
+
+impl Error for YBoolError {}
+
 impl YBool {
     pub fn new(x: &[Y]) -> YBool {
         return YBool(x.clone().to_vec());
     }
+
+    // Response of the rows with an observed value, and the mask of those rows, e.g. for XDf::subset_rows.
+    pub fn from_observed(x: &[Option<Y>]) -> (YBool, Mask) {
+        let observed = Mask::from_sorted((0..x.len()).filter(|&i| x[i].is_some()).collect());
+        return (YBool(x.iter().flatten().copied().collect()), observed);
+    }
+
+    // Labels equal to positive_label are true, the single other label is false (e.g. "case"/"control").
+    pub fn from_labels<S: AsRef<str>>(labels: &[S], positive_label: &str) -> Result<YBool, YBoolError> {
+        let mut distinct: Vec<String> = vec![];
+        for label in labels.iter() {
+            if !distinct.iter().any(|x| x == label.as_ref()) {
+                distinct.push(label.as_ref().to_string());
+            }
+        }
+
+        if distinct.len() > 2 {
+            return Err(YBoolError::MoreThanTwoLabels(distinct));
+        }
+        if !distinct.iter().any(|x| x == positive_label) {
+            return Err(YBoolError::PositiveLabelNotFound(positive_label.to_string()));
+        }
+
+        return Ok(YBool(labels.iter().map(|x| x.as_ref() == positive_label).collect()));
+    }
+
+    // Accepts 0/1 (0 false, 1 true) or PLINK style 1/2 (1 control, 2 case) coding.
+    pub fn from_i8(codes: &[i8]) -> Result<YBool, YBoolError> {
+        if let Some(pos) = codes.iter().position(|&x| x < 0 || x > 2) {
+            return Err(YBoolError::UnknownCode{pos, code: codes[pos]});
+        }
+
+        let has_zero = codes.iter().any(|&x| x == 0);
+        let has_two = codes.iter().any(|&x| x == 2);
+
+        return match (has_zero, has_two) {
+            (true, true) => Err(YBoolError::MixedCoding),
+            (true, false) => Ok(YBool(codes.iter().map(|&x| x == 1).collect())),
+            (false, true) => Ok(YBool(codes.iter().map(|&x| x == 2).collect())),
+            (false, false) => Err(YBoolError::AmbiguousCoding),
+        };
+    }
 }
 
 impl Response<Y> for YBool {
@@ -46,26 +113,41 @@ impl Response<Y> for YBool {
     }
 
     fn get_major_class(&self, mask: &Mask, rng: &mut Rng) -> Y {
+        return self.get_major_class_with(mask, rng, TiePolicy::CoinFlip);
+    }
+
+    fn get_major_class_with(&self, mask: &Mask, rng: &mut Rng, ties: TiePolicy) -> Y {
         if mask.get_mask().len() == 0 {
             panic!("Cannot give major class for empty vector.");
         }
 
-        let acc = mask.get_by_mask(&self.0)
-            .iter()
-            .fold((0, 0), |acc, &x| {
-                match x {
-                    false => (acc.0+1, acc.1),
-                    true => (acc.0, acc.1+1)
+        // Rows count with their in-bag multiplicity
+        let acc = mask.iter_weighted()
+            .fold((0, 0), |acc, (i, w)| {
+                match self.0[i] {
+                    false => (acc.0 + w as u64, acc.1),
+                    true => (acc.0, acc.1 + w as u64)
                 }
             });
 
-        return match acc.0.cmp(&acc.1) {
-            Ordering::Greater => false,
-            Ordering::Less => true,
-            Ordering::Equal => rng.rand_uni() > 0.5,
+        return match (acc.0.cmp(&acc.1), ties) {
+            (_, TiePolicy::Proportional) => rng.rand_uni() * (acc.0 + acc.1) as f64 > acc.0 as f64,
+            (Ordering::Greater, _) => false,
+            (Ordering::Less, _) => true,
+            (Ordering::Equal, TiePolicy::Fixed) => false,
+            (Ordering::Equal, TiePolicy::CoinFlip) => rng.rand_uni() > 0.5,
         }
     }
 
+    fn impurity(&self, mask: &Mask) -> f64 {
+        if mask.len() == 0 {
+            return 0.;
+        }
+        let (n_true, n) = mask.iter_weighted().fold((0, 0), |acc, (i, w)| (acc.0 + self.0[i] as u64 * w as u64, acc.1 + w as u64));
+        let p = n_true as f64 / n as f64;
+        return 2. * p * (1. - p);
+    }
+
     #[inline]
     fn pred_incorrect(&self, mask: &Mask, preds: &Predicted<Y>) -> u64 {
         mask.get_by_mask(&self.0).iter().zip(preds.iter()).fold(0, |mut acc, x| {
@@ -80,6 +162,10 @@ impl Response<Y> for YBool {
         return self.pred_incorrect(&mask, &preds) as f64/preds.len() as f64;
     }
 
+    fn pred_balanced_error(&self, mask: &Mask, preds: &Predicted<Y>) -> f64 {
+        return balanced_error(mask.get_mask().iter().zip(preds.iter()).map(|(&i, &pred)| (self.0[i], pred)));
+    }
+
     #[inline]
     fn as_vector(&self) -> Vec<Y> {
         return self.0.clone();
@@ -96,13 +182,83 @@ impl Response<Y> for YBool {
     }
 }
 
+impl Subsettable for YBool {
+    fn subset(&self, mask: &Mask) -> YBool {
+        return YBool(mask.get_by_mask(&self.0));
+    }
+}
+
+// Balanced error of (true class, prediction) pairs, 0 for no pairs.
+pub(crate) fn balanced_error<I>(pairs: I) -> f64
+    where
+        I: Iterator<Item = (Y, Y)>
+{
+    // Samples and errors of false and true class
+    let mut n = [0u64; 2];
+    let mut err = [0u64; 2];
+    for (class, pred) in pairs {
+        n[class as usize] += 1;
+        err[class as usize] += (pred != class) as u64;
+    }
+    let rates: Vec<f64> = (0..2).filter(|&c| n[c] > 0).map(|c| err[c] as f64 / n[c] as f64).collect();
+    return match rates.len() {
+        0 => 0.,
+        n_rates => rates.iter().sum::<f64>() / n_rates as f64
+    };
+}
+
 #[cfg(test)]
 mod tests {
+    use float_cmp::assert_approx_eq;
     use crate::mask::Mask;
-    use crate::data_interface::Response;
-    use crate::data_interface::y_bool::YBool;
+    use crate::data_interface::{ErrorMetric, LossMetric, Predicted, Response, Subsettable, TiePolicy};
+    use crate::data_interface::y_bool::{YBool, YBoolError};
     use crate::random_number_generator::Rng;
 
+    #[test]
+    fn from_observed() {
+        let (y, observed) = YBool::from_observed(&[Some(true), None, Some(false), None, Some(true)]);
+        assert_eq!(y.0, vec![true, false, true]);
+        assert_eq!(observed.get_mask(), &vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn subset() {
+        let y = YBool::new(&[true, false, false, true, true]);
+        assert_eq!(y.subset(&Mask::new(vec![4, 1, 2])).0, vec![false, false, true]);
+        assert_eq!(y.subset(&Mask::new(vec![])).len(), 0);
+    }
+
+    #[test]
+    fn from_labels() {
+        let y = YBool::from_labels(&["case", "control", "control", "case"], "case").unwrap();
+        assert_eq!(y.0, vec![true, false, false, true]);
+
+        let labels = vec!["case".to_string(), "control".to_string(), "Case".to_string()];
+        assert_eq!(YBool::from_labels(&labels, "case").unwrap_err(),
+                   YBoolError::MoreThanTwoLabels(labels.clone()));
+        assert_eq!(YBool::from_labels(&["Case", "control"], "case").unwrap_err(),
+                   YBoolError::PositiveLabelNotFound("case".to_string()));
+    }
+
+    #[test]
+    fn from_i8() {
+        assert_eq!(YBool::from_i8(&[0, 1, 1, 0]).unwrap().0, vec![false, true, true, false]);
+        assert_eq!(YBool::from_i8(&[1, 2, 2, 1]).unwrap().0, vec![false, true, true, false]);
+        assert_eq!(YBool::from_i8(&[0, 1, 2]).unwrap_err(), YBoolError::MixedCoding);
+        assert_eq!(YBool::from_i8(&[1, 1]).unwrap_err(), YBoolError::AmbiguousCoding);
+        assert_eq!(YBool::from_i8(&[1, 2, -9]).unwrap_err(), YBoolError::UnknownCode{pos: 2, code: -9});
+    }
+
+    #[test]
+    fn impurity() {
+        let y = YBool::new(&[true, false, true, true]);
+        assert_eq!(y.impurity(&Mask::new(vec![0, 1, 2, 3])), 0.375);
+        assert_eq!(y.impurity(&Mask::new(vec![0, 2])), 0.);
+        assert_eq!(y.impurity(&Mask::new(vec![0, 1])), 0.5);
+        assert_eq!(y.impurity(&Mask::new(vec![])), 0.);
+    }
+
     #[test]
     fn pred_incorrect_returns_correct_value() {
         let y = YBool(vec![true, true, true, false, false, false, false, false]);
@@ -119,6 +275,67 @@ mod tests {
         assert_eq!(y.pred_incorrect(&mask, &preds), 0);
     }
 
+    #[test]
+    fn pred_balanced_error() {
+        let y = YBool(vec![true, false, false, false, false]);
+        let mask = Mask::new(vec![0, 1, 2, 3, 4]);
+        // Predicting the majority class is right 4 times out of 5, but balanced error is 0.5
+        assert_eq!(y.pred_error(&mask, &vec![false; 5]), 0.2);
+        assert_eq!(y.pred_balanced_error(&mask, &vec![false; 5]), 0.5);
+        assert_eq!(y.pred_balanced_error(&mask, &vec![true, false, false, false, false]), 0.);
+        assert_eq!(y.pred_balanced_error(&Mask::new(vec![]), &vec![]), 0.);
+    }
+
+    #[test]
+    fn pred_balanced_error_default() {
+        // Responses without their own balanced error, of three classes here
+        struct Classes(Vec<u8>);
+        impl Response<u8> for Classes {
+            fn pred_incorrect(&self, _mask: &Mask, _preds: &Predicted<u8>) -> u64 { unimplemented!() }
+            fn get_class(&self, _mask: &Mask) -> Option<u8> { unimplemented!() }
+            fn get_major_class(&self, _mask: &Mask, _rng: &mut Rng) -> u8 { unimplemented!() }
+            fn impurity(&self, _mask: &Mask) -> f64 { unimplemented!() }
+            fn pred_error(&self, _mask: &Mask, _preds: &Predicted<u8>) -> f64 { unimplemented!() }
+            fn as_vector(&self) -> Vec<u8> { unimplemented!() }
+            fn as_vector_ref(&self) -> &Vec<u8> { &self.0 }
+            fn len(&self) -> usize { self.0.len() }
+        }
+
+        let y = Classes(vec![0, 0, 1, 2, 2, 2]);
+        let mask = Mask::new(vec![0, 1, 2, 3, 4, 5]);
+        assert_approx_eq!(f64, y.pred_balanced_error(&mask, &vec![0, 1, 1, 2, 2, 0]), (0.5 + 0. + 1./3.) / 3.);
+        assert_eq!(y.pred_balanced_error(&Mask::new(vec![]), &vec![]), 0.);
+        // Same as the YBool one on two classes
+        let y_bool = YBool(vec![true, false, false, false, false]);
+        let preds = vec![false, false, true, false, false];
+        let as_u8 = Classes(y_bool.0.iter().map(|&x| x as u8).collect());
+        let mask = Mask::new(vec![0, 1, 2, 3, 4]);
+        assert_approx_eq!(f64, as_u8.pred_balanced_error(&mask, &preds.iter().map(|&x| x as u8).collect()), y_bool.pred_balanced_error(&mask, &preds));
+    }
+
+    #[test]
+    fn pred_loss_balanced_error() {
+        let y = YBool(vec![true, true, false, false, false, false, false, false]);
+        let mask = Mask::new(vec![0, 1, 2, 3, 4, 5, 6, 7]);
+        let preds = vec![false, true, false, false, false, false, false, true];
+        assert_eq!(y.pred_loss(&mask, &preds, ErrorMetric::Misclassification), 2.);
+        // (1/2 + 1/6) / 2 of 8 samples
+        assert_approx_eq!(f64, y.pred_loss(&mask, &preds, ErrorMetric::BalancedError), 8. / 3.);
+        // Single class within the mask
+        assert_eq!(y.pred_loss(&Mask::new(vec![2, 7]), &vec![false, true], ErrorMetric::BalancedError), 1.);
+    }
+
+    #[test]
+    fn loss_metric_as_pred_loss() {
+        let y = YBool(vec![true, true, false, false]);
+        let mask = Mask::new(vec![0, 1, 2, 3]);
+        let preds = vec![false, true, false, false];
+        // Same losses through LossMetric on the classes of the mask
+        let truth = mask.get_by_mask(y.as_vector_ref());
+        assert_approx_eq!(f64, ErrorMetric::BalancedError.loss(&truth, &preds), y.pred_loss(&mask, &preds, ErrorMetric::BalancedError));
+        assert_eq!(ErrorMetric::Misclassification.loss(&truth, &preds), 1.);
+    }
+
     #[test]
     fn pred_error_returns_correct_value() {
         let y = YBool(vec![true, true, true, false, false, false, false, false]);
@@ -142,6 +359,13 @@ mod tests {
         assert!(y.get_class(&mask).is_none());
     }
 
+    #[test]
+    fn class_counts_in_mask() {
+        let y = YBool(vec![true, true, false, true]);
+        assert_eq!(y.class_counts(&Mask::new(vec![0, 2, 3])), [(true, 2), (false, 1)]);
+        assert_eq!(y.class_counts(&Mask::new(vec![2])), [(false, 1)]);
+    }
+
     #[test]
     fn get_class_returns_false_class() {
         let y = YBool(vec![true, true, false, true]);
@@ -171,7 +395,7 @@ mod tests {
         let mut rng = Rng::new(0, 1);
         let y = YBool(vec![true, true, false, true]);
         let mask = Mask::new(vec![0, 1, 2, 3]);
-        assert_eq!(y.get_major_class(&mask, &mut rng), true);
+        assert_eq!(y.get_major_class_with(&mask, &mut rng, TiePolicy::Fixed), true);
 
         let y = YBool(vec![true, false, false, false]);
         let mask = Mask::new(vec![0, 1, 2, 3]);
@@ -182,6 +406,28 @@ mod tests {
         assert_eq!(y.get_major_class(&mask, &mut rng), true);
     }
 
+    #[test]
+    fn get_major_class_ties() {
+        let y = YBool(vec![true, true, false, false, true, false, false, false]);
+        let tie = Mask::new(vec![0, 1, 2, 3]);
+        let mut rng = Rng::new(0, 1);
+        assert!((0..20).all(|_| !y.get_major_class_with(&tie, &mut rng, TiePolicy::Fixed)));
+        // Fixed draws nothing from the stream
+        assert_eq!(rng.rand_uni(), Rng::new(0, 1).rand_uni());
+
+        let flips = (0..1000).filter(|_| y.get_major_class_with(&tie, &mut rng, TiePolicy::CoinFlip)).count();
+        assert!(flips > 400 && flips < 600);
+        // The policy-free signature keeps the coin flip
+        let (mut a, mut b) = (Rng::new(3, 1), Rng::new(3, 1));
+        assert!((0..100).all(|_| y.get_major_class(&tie, &mut a) == y.get_major_class_with(&tie, &mut b, TiePolicy::CoinFlip)));
+        // One in four is true
+        let mask = Mask::new(vec![4, 5, 6, 7]);
+        let drawn = (0..1000).filter(|_| y.get_major_class_with(&mask, &mut rng, TiePolicy::Proportional)).count();
+        assert!(drawn > 180 && drawn < 320);
+        assert!((0..100).all(|_| y.get_major_class_with(&Mask::new(vec![0, 1]), &mut rng, TiePolicy::Proportional)));
+        assert!((0..100).all(|_| !y.get_major_class_with(&Mask::new(vec![2, 3]), &mut rng, TiePolicy::Proportional)));
+    }
+
     #[test]
     #[should_panic(expected = "Cannot give major class for empty vector.")]
     fn get_major_class_empty_vector_should_panic() {
@@ -0,0 +1,300 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+
+use crate::mask::Mask;
+use crate::data_interface::{Predicted, Response, Subsettable, TiePolicy};
+use crate::data_interface::y_bool::{balanced_error, YBool};
+use crate::random_number_generator::Rng;
+
+// Classes of one sample for up to 64 binary responses, bit i holds response i.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MultiY {
+    bits: u64,
+    n: u8
+}
+
+impl MultiY {
+    #[inline]
+    pub fn get(&self, response: usize) -> bool {
+        return (self.bits >> response) & 1 == 1;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.n as usize;
+    }
+
+    #[inline]
+    fn count_different(&self, other: &MultiY) -> u32 {
+        return (self.bits ^ other.bits).count_ones();
+    }
+}
+
+// Several correlated binary responses (e.g. related phenotypes) fitted by one forest. Splits minimise gini
+// averaged over responses. Errors are counted over all responses, or over the scored one only.
+#[derive(Debug, Clone)]
+pub struct YMultiBool {
+    y: Vec<MultiY>,
+    n: usize,
+    scored: Option<usize>
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum YMultiBoolError {
+    NoResponses,
+    TooManyResponses(usize),
+    LengthMismatch{response: usize, expected: usize, found: usize},
+}
+
+impl fmt::Display for YMultiBoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YMultiBoolError::NoResponses => write!(f, "No responses given"),
+            YMultiBoolError::TooManyResponses(n) => write!(f, "{} responses given, at most 64 are supported", n),
+            YMultiBoolError::LengthMismatch{response, expected, found} =>
+                write!(f, "Response {} has {} samples, expected {}", response, found, expected),
+        }
+    }
+}
+
+impl Error for YMultiBoolError {}
+
+impl YMultiBool {
+    pub fn new(responses: &[Vec<bool>]) -> Result<YMultiBool, YMultiBoolError> {
+        if responses.is_empty() {
+            return Err(YMultiBoolError::NoResponses);
+        }
+        if responses.len() > 64 {
+            return Err(YMultiBoolError::TooManyResponses(responses.len()));
+        }
+        let nrow = responses[0].len();
+        if let Some(response) = responses.iter().position(|x| x.len() != nrow) {
+            return Err(YMultiBoolError::LengthMismatch{response, expected: nrow, found: responses[response].len()});
+        }
+
+        let y = (0..nrow).map(|i| MultiY {
+            bits: responses.iter().enumerate().fold(0, |acc, (r, x)| acc | ((x[i] as u64) << r)),
+            n: responses.len() as u8
+        }).collect();
+        return Ok(YMultiBool{y, n: responses.len(), scored: None});
+    }
+
+    pub fn n_responses(&self) -> usize {
+        return self.n;
+    }
+
+    pub fn get_response(&self, response: usize) -> YBool {
+        return YBool::new(&self.y.iter().map(|x| x.get(response)).collect::<Vec<bool>>());
+    }
+
+    // Same responses, with errors counted only for the given response (all for None).
+    // Trees are grown the same way whichever response is scored.
+    pub fn scoring(&self, scored: Option<usize>) -> YMultiBool {
+        if scored.is_some_and(|r| r >= self.n) {
+            panic!("Scored response out of range.");
+        }
+        return YMultiBool{y: self.y.clone(), n: self.n, scored};
+    }
+
+    fn n_scored(&self) -> usize {
+        return match self.scored {
+            Some(_) => 1,
+            None => self.n
+        };
+    }
+}
+
+impl Response<MultiY> for YMultiBool {
+    fn get_class(&self, mask: &Mask) -> Option<MultiY> {
+        let first = self.y[mask.get_mask()[0]];
+        return match mask.get_mask().iter().all(|&i| self.y[i] == first) {
+            true => Some(first),
+            false => None
+        };
+    }
+
+    fn get_major_class(&self, mask: &Mask, rng: &mut Rng) -> MultiY {
+        return self.get_major_class_with(mask, rng, TiePolicy::CoinFlip);
+    }
+
+    fn get_major_class_with(&self, mask: &Mask, rng: &mut Rng, ties: TiePolicy) -> MultiY {
+        if mask.get_mask().len() == 0 {
+            panic!("Cannot give major class for empty vector.");
+        }
+
+        // Rows count with their in-bag multiplicity
+        let total: usize = mask.iter_weighted().map(|(_, w)| w as usize).sum();
+        let mut bits = 0;
+        for r in 0..self.n {
+            let n_true: usize = mask.iter_weighted().filter(|&(i, _)| self.y[i].get(r)).map(|(_, w)| w as usize).sum();
+            let major = match (n_true.cmp(&(total - n_true)), ties) {
+                (_, TiePolicy::Proportional) => rng.rand_uni() * total as f64 > (total - n_true) as f64,
+                (Ordering::Greater, _) => true,
+                (Ordering::Less, _) => false,
+                (Ordering::Equal, TiePolicy::Fixed) => false,
+                (Ordering::Equal, TiePolicy::CoinFlip) => rng.rand_uni() > 0.5,
+            };
+            bits |= (major as u64) << r;
+        }
+        return MultiY{bits, n: self.n as u8};
+    }
+
+    // Mean gini impurity over responses.
+    fn impurity(&self, mask: &Mask) -> f64 {
+        if mask.len() == 0 {
+            return 0.;
+        }
+        let total: usize = mask.iter_weighted().map(|(_, w)| w as usize).sum();
+        return (0..self.n).map(|r| {
+            let n_true: usize = mask.iter_weighted().filter(|&(i, _)| self.y[i].get(r)).map(|(_, w)| w as usize).sum();
+            let p = n_true as f64 / total as f64;
+            2. * p * (1. - p)
+        }).sum::<f64>() / self.n as f64;
+    }
+
+    #[inline]
+    fn pred_incorrect(&self, mask: &Mask, preds: &Predicted<MultiY>) -> u64 {
+        return mask.get_mask().iter().zip(preds.iter()).map(|(&i, pred)| {
+            match self.scored {
+                Some(r) => (self.y[i].get(r) != pred.get(r)) as u64,
+                None => self.y[i].count_different(pred) as u64
+            }
+        }).sum();
+    }
+
+    // Share of incorrect predictions over samples and scored responses.
+    fn pred_error(&self, mask: &Mask, preds: &Predicted<MultiY>) -> f64 {
+        return self.pred_incorrect(mask, preds) as f64 / (preds.len() * self.n_scored()) as f64;
+    }
+
+    // Balanced error of each scored response, averaged.
+    fn pred_balanced_error(&self, mask: &Mask, preds: &Predicted<MultiY>) -> f64 {
+        let responses = match self.scored {
+            Some(r) => r..r+1,
+            None => 0..self.n
+        };
+        let n_resp = responses.len();
+        return responses.map(|r| {
+            balanced_error(mask.get_mask().iter().zip(preds.iter()).map(|(&i, pred)| (self.y[i].get(r), pred.get(r))))
+        }).sum::<f64>() / n_resp as f64;
+    }
+
+    #[inline]
+    fn as_vector(&self) -> Vec<MultiY> {
+        return self.y.clone();
+    }
+
+    #[inline]
+    fn as_vector_ref(&self) -> &Vec<MultiY> {
+        return &self.y;
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        return self.y.len();
+    }
+}
+
+// The scored response is kept.
+impl Subsettable for YMultiBool {
+    fn subset(&self, mask: &Mask) -> YMultiBool {
+        return YMultiBool{y: mask.get_by_mask(&self.y), n: self.n, scored: self.scored};
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mask::Mask;
+    use crate::data_interface::{Response, Subsettable, TiePolicy};
+    use crate::data_interface::y_multi_bool::{YMultiBool, YMultiBoolError};
+    use crate::random_number_generator::Rng;
+
+    fn y_multi() -> YMultiBool {
+        return YMultiBool::new(&[vec![true, true, false, false], vec![true, false, false, true]]).unwrap();
+    }
+
+    #[test]
+    fn new_checks_responses() {
+        assert_eq!(YMultiBool::new(&[]).unwrap_err(), YMultiBoolError::NoResponses);
+        assert_eq!(YMultiBool::new(&vec![vec![true]; 65]).unwrap_err(), YMultiBoolError::TooManyResponses(65));
+        assert_eq!(YMultiBool::new(&[vec![true, false], vec![true]]).unwrap_err(),
+                   YMultiBoolError::LengthMismatch{response: 1, expected: 2, found: 1});
+
+        let y = y_multi();
+        assert_eq!(y.n_responses(), 2);
+        assert_eq!(y.get_response(1).as_vector(), vec![true, false, false, true]);
+    }
+
+    #[test]
+    fn subset_keeps_scored() {
+        let y = y_multi().scoring(Some(1)).subset(&Mask::new(vec![3, 0]));
+        assert_eq!((y.len(), y.n_responses(), y.scored), (2, 2, Some(1)));
+        assert_eq!(y.get_response(0).as_vector(), vec![true, false]);
+        assert_eq!(y.get_response(1).as_vector(), vec![true, true]);
+    }
+
+    #[test]
+    fn get_class() {
+        let y = y_multi();
+        assert!(y.get_class(&Mask::new(vec![0, 1])).is_none());
+        let class = y.get_class(&Mask::new(vec![2])).unwrap();
+        assert_eq!((class.get(0), class.get(1), class.len()), (false, false, 2));
+    }
+
+    #[test]
+    fn get_major_class() {
+        let mut rng = Rng::new(0, 1);
+        let y = YMultiBool::new(&[vec![true, true, false], vec![false, true, false]]).unwrap();
+        let class = y.get_major_class_with(&Mask::new(vec![0, 1, 2]), &mut rng, TiePolicy::Fixed);
+        assert_eq!((class.get(0), class.get(1)), (true, false));
+    }
+
+    #[test]
+    fn impurity() {
+        let y = y_multi();
+        assert_eq!(y.impurity(&Mask::new(vec![0, 1, 2, 3])), 0.5);
+        assert_eq!(y.impurity(&Mask::new(vec![0, 1])), 0.25);
+        assert_eq!(y.impurity(&Mask::new(vec![])), 0.);
+    }
+
+    #[test]
+    fn pred_balanced_error() {
+        let y = y_multi();
+        let mask = Mask::new(vec![0, 1, 2, 3]);
+        let preds = vec![y.as_vector()[2]; 4];
+        assert_eq!(y.scoring(Some(0)).pred_balanced_error(&mask, &preds), 0.5);
+        assert_eq!(y.pred_balanced_error(&mask, &y.as_vector()), 0.);
+    }
+
+    #[test]
+    fn pred_incorrect_by_scored_response() {
+        let y = y_multi();
+        let mask = Mask::new(vec![0, 1, 2, 3]);
+        let preds = vec![y.as_vector()[0]; 4];
+        assert_eq!(y.pred_incorrect(&mask, &preds), 4);
+        assert_eq!(y.pred_error(&mask, &preds), 4. / 8.);
+        assert_eq!(y.scoring(Some(0)).pred_incorrect(&mask, &preds), 2);
+        let preds = vec![y.as_vector()[2]; 4];
+        assert_eq!(y.scoring(Some(1)).pred_incorrect(&mask, &preds), 2);
+        assert_eq!(y.scoring(Some(1)).pred_error(&mask, &preds), 2. / 4.);
+        assert_eq!(y.scoring(Some(0)).pred_incorrect(&mask, &preds), 2);
+    }
+}
@@ -0,0 +1,133 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Debug;
+
+use crate::data_interface::{ColumnIdentifiable, DataInterface, Response};
+use crate::random_forest::{DepthStats, Importance, RandomForest};
+
+// First difference verify_determinism found between the serial run and one on threads threads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeterminismError {
+    TreeImportance{threads: usize, tree: usize},
+    GiniImportance{threads: usize},
+    TreeCounts{threads: usize},
+    DepthStats{threads: usize},
+}
+
+impl fmt::Display for DeterminismError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeterminismError::TreeImportance{threads, tree} =>
+                write!(f, "Permutation importance of tree {} differs on {} threads", tree, threads),
+            DeterminismError::GiniImportance{threads} => write!(f, "Gini importance differs on {} threads", threads),
+            DeterminismError::TreeCounts{threads} => write!(f, "Split columns of the trees differ on {} threads", threads),
+            DeterminismError::DepthStats{threads} => write!(f, "Depths and leaf counts of the trees differ on {} threads", threads),
+        }
+    }
+}
+
+impl Error for DeterminismError {}
+
+// Outputs of one run compared between thread counts.
+struct RunDigest<T> {
+    trees: Vec<Importance<T>>,
+    tree_counts: Option<HashMap<T, usize>>,
+    depth_stats: Option<DepthStats>,
+    gini: Importance<T>
+}
+
+fn run_digest<Y, SplitIndex, T, U>(rf: &RandomForest<Y, SplitIndex>, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, threads: Option<usize>) -> RunDigest<SplitIndex::Col>
+where
+    Y: Copy + Send + Sync + Debug + PartialEq,
+    SplitIndex: ColumnIdentifiable + Clone + Copy + Send + Sync,
+    T: DataInterface<SplitIndex, Y> + Sync + Send,
+    U: Response<Y> + Sync + Send
+{
+    let trees = rf.importance_by_tree(df, y, ntree, mtry, shadow_vars, max_tree_depth, threads).trees;
+    let (tree_counts, depth_stats) = (rf.get_tree_counts(), rf.get_depth_stats());
+    let gini = rf.gini_importance(df, y, ntree, mtry, shadow_vars, max_tree_depth, threads);
+    return RunDigest{trees, tree_counts, depth_stats, gini};
+}
+
+// Checks rf grows the same trees and gives bitwise the same importance on each of threads as serially, e.g. after
+// changing its config or before trusting a new platform with a long run. Trees draw their random streams from
+// their index, so the thread a tree lands on must not matter. Overwrites the last run state of rf, such as
+// get_tree_counts.
+pub fn verify_determinism<Y, SplitIndex, T, U>(rf: &RandomForest<Y, SplitIndex>, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, threads: &[usize]) -> Result<(), DeterminismError>
+where
+    Y: Copy + Send + Sync + Debug + PartialEq,
+    SplitIndex: ColumnIdentifiable + Clone + Copy + Send + Sync,
+    T: DataInterface<SplitIndex, Y> + Sync + Send,
+    U: Response<Y> + Sync + Send
+{
+    if threads.contains(&0) {
+        panic!("Thread counts must be larger than 0.");
+    }
+
+    let serial = run_digest(rf, df, y, ntree, mtry, shadow_vars, max_tree_depth, None);
+    for &thrs in threads {
+        let res = run_digest(rf, df, y, ntree, mtry, shadow_vars, max_tree_depth, Some(thrs));
+        if let Some(tree) = serial.trees.iter().zip(res.trees.iter()).position(|(a, b)| a != b) {
+            return Err(DeterminismError::TreeImportance{threads: thrs, tree});
+        }
+        if res.tree_counts != serial.tree_counts {
+            return Err(DeterminismError::TreeCounts{threads: thrs});
+        }
+        if res.depth_stats != serial.depth_stats {
+            return Err(DeterminismError::DepthStats{threads: thrs});
+        }
+        if res.gini != serial.gini {
+            return Err(DeterminismError::GiniImportance{threads: thrs});
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_determinism, DeterminismError};
+    use crate::data_interface::multi_x::{ColSplitIndex, MultiX, XDf};
+    use crate::data_interface::three_val::ThreeValCol;
+    use crate::data_interface::y_bool::{Y, YBool};
+    use crate::random_forest::RandomForest;
+    use crate::random_number_generator::Rng;
+
+    #[test]
+    fn same_forest_on_any_thread_count() {
+        let mut rng = Rng::new(7, 1);
+        let xs: Vec<Vec<i8>> = (0..6).map(|_| (0..150).map(|_| (rng.rand_uni() * 3.) as i8).collect()).collect();
+        let y = YBool::new(&(0..150).map(|i| xs[0][i] + xs[1][i] > 2 || i % 11 == 0).collect::<Vec<bool>>());
+        let df = XDf::new(xs.iter().map(|x| MultiX::ThreeVal(ThreeValCol::new(x))).collect());
+        let n = std::thread::available_parallelism().map_or(2, |n| n.get());
+
+        let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(3);
+        assert_eq!(verify_determinism(&rf, &df, &y, 40, 2, false, None, &[1, 4, n]), Ok(()));
+        let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(3);
+        rf.set_n_perm(2);
+        rf.set_include_unused(true);
+        assert_eq!(verify_determinism(&rf, &df, &y, 40, 2, true, Some(3), &[4, n]), Ok(()));
+    }
+
+    #[test]
+    fn determinism_error_names_threads() {
+        let err = DeterminismError::TreeImportance{threads: 4, tree: 12};
+        assert_eq!(err.to_string(), "Permutation importance of tree 12 differs on 4 threads");
+    }
+}
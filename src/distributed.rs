@@ -0,0 +1,96 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+// Importance of a forest computed by worker processes over TCP. The driver listens, hands each connecting worker
+// a range of trees from split_trees and merges the parts they send back. Workers hold the same data and forest
+// settings and compute their range with RandomForest::partial_importance, so the merged result equals a single run.
+// The line protocol: the driver sends "#trees\t<start>\t<end>", the worker answers with write_tsv and closes.
+
+use std::hash::Hash;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::Range;
+use crate::partial_importance::{split_trees, PartialImportance, PartialImportanceError};
+
+// Waits for n_workers workers on listener, gives each a range of the ntree trees and merges their answers.
+// Fewer workers are waited for when ntree < n_workers, see split_trees.
+pub fn drive<T, F>(listener: &TcpListener, ntree: usize, n_workers: usize, parse_col: F) -> Result<PartialImportance<T>, PartialImportanceError>
+where
+    T: Hash + Eq + Copy,
+    F: Fn(&str) -> Option<T>
+{
+    // All ranges are handed out before any answer is read, so workers compute at the same time
+    let mut streams = Vec::with_capacity(n_workers);
+    for trees in split_trees(ntree, n_workers) {
+        let (mut stream, _) = listener.accept()?;
+        writeln!(stream, "#trees\t{}\t{}", trees.start, trees.end)?;
+        streams.push((stream, trees));
+    }
+    let mut parts = Vec::with_capacity(streams.len());
+    for (stream, trees) in streams {
+        let part = PartialImportance::read_tsv(BufReader::new(stream), &parse_col)?;
+        if part.trees != trees || part.ntree != ntree {
+            return Err(PartialImportanceError::Mismatch);
+        }
+        parts.push(part);
+    }
+    return PartialImportance::merge(parts);
+}
+
+// Connects to the driver at addr, computes the range of trees it is given and sends the part back.
+pub fn run_worker<A, T, F, C>(addr: A, compute: F, col_name: C) -> Result<(), PartialImportanceError>
+where
+    A: ToSocketAddrs,
+    T: Hash + Eq + Copy,
+    F: FnOnce(Range<usize>) -> PartialImportance<T>,
+    C: Fn(&T) -> String
+{
+    let mut stream = TcpStream::connect(addr)?;
+    let mut line = String::new();
+    BufReader::new(stream.try_clone()?).read_line(&mut line)?;
+    let trees = parse_trees(line.trim_end()).ok_or_else(|| PartialImportanceError::Format(1, line.clone()))?;
+    compute(trees).write_tsv(&mut stream, col_name)?;
+    stream.flush()?;
+    stream.shutdown(Shutdown::Write)?;
+    return Ok(());
+}
+
+fn parse_trees(line: &str) -> Option<Range<usize>> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 3 || fields[0] != "#trees" {
+        return None;
+    }
+    let start = fields[1].parse().ok()?;
+    let end = fields[2].parse().ok()?;
+    return match start <= end {
+        true => Some(start..end),
+        false => None
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::distributed::parse_trees;
+
+    #[test]
+    fn parse_trees_line() {
+        assert_eq!(parse_trees("#trees\t3\t7"), Some(3..7));
+        assert_eq!(parse_trees("#trees\t7\t3"), None);
+        assert_eq!(parse_trees("#trees\t3"), None);
+        assert_eq!(parse_trees("trees\t3\t7"), None);
+    }
+}
@@ -0,0 +1,117 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::stats::hypergeom_sf;
+use crate::ranked_importance::RankedImportance;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnrichmentRes<S> {
+    pub set: S,
+    // Columns of the universe in the set.
+    pub set_size: usize,
+    // Selected columns in the set.
+    pub overlap: usize,
+    pub expected: f64,
+    // One sided hypergeometric test of overlap at least as large, not corrected for the number of sets.
+    pub pvalue: f64
+}
+
+// Over-representation of selected columns, e.g. confirmed by Boruta, in the sets (genes, pathways) columns are
+// annotated with. universe holds all tested columns, selected and annotated columns outside of it are ignored.
+// Results are sorted by p-value, ties by set.
+pub fn enrichment<T, S>(selected: &[T], universe: &[T], annotation: &HashMap<T, Vec<S>>) -> Vec<EnrichmentRes<S>>
+where
+    T: Hash + Eq + Copy,
+    S: Hash + Eq + Ord + Clone
+{
+    let universe: HashSet<T> = universe.iter().copied().collect();
+    let selected: HashSet<T> = selected.iter().copied().filter(|col| universe.contains(col)).collect();
+
+    let mut counts: HashMap<&S, (usize, usize)> = HashMap::new();
+    for col in universe.iter() {
+        // A column listed twice for a set counts once
+        let sets: HashSet<&S> = annotation.get(col).map_or(HashSet::new(), |sets| sets.iter().collect());
+        for set in sets {
+            let count = counts.entry(set).or_insert((0, 0));
+            count.0 += 1;
+            if selected.contains(col) {
+                count.1 += 1;
+            }
+        }
+    }
+
+    let (n_pop, n_draw) = (universe.len(), selected.len());
+    let mut res: Vec<EnrichmentRes<S>> = counts.into_iter().map(|(set, (set_size, overlap))| EnrichmentRes {
+        set: set.clone(),
+        set_size,
+        overlap,
+        expected: (set_size * n_draw) as f64 / n_pop as f64,
+        pvalue: hypergeom_sf(overlap as u64, n_pop as u64, set_size as u64, n_draw as u64)
+    }).collect();
+    res.sort_by(|a, b| a.pvalue.total_cmp(&b.pvalue).then_with(|| a.set.cmp(&b.set)));
+    return res;
+}
+
+// Enrichment of the k top ranked columns among all ranked ones.
+pub fn enrichment_top_k<T, S>(ranked: &RankedImportance<T>, k: usize, annotation: &HashMap<T, Vec<S>>) -> Vec<EnrichmentRes<S>>
+where
+    T: Hash + Eq + Copy,
+    S: Hash + Eq + Ord + Clone
+{
+    let universe: Vec<T> = ranked.entries().iter().map(|e| e.col).collect();
+    return enrichment(&universe[..k.min(universe.len())], &universe, annotation);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use float_cmp::assert_approx_eq;
+    use crate::enrichment::{enrichment, enrichment_top_k};
+    use crate::random_forest::Importance;
+    use crate::ranked_importance::RankedImportance;
+
+    fn annotation() -> HashMap<usize, Vec<&'static str>> {
+        // Columns 0..10 in "a", 10..20 in "b", column 60 outside of the universe
+        let mut res: HashMap<usize, Vec<&str>> = (0..20).map(|col| (col, vec![if col < 10 {"a"} else {"b"}])).collect();
+        res.get_mut(&3).unwrap().push("a");
+        res.insert(60, vec!["a"]);
+        return res;
+    }
+
+    #[test]
+    fn enrichment_hypergeometric() {
+        let universe: Vec<usize> = (0..50).collect();
+        let res = enrichment(&[0, 1, 2, 3, 10, 30, 40, 45, 60], &universe, &annotation());
+        assert_eq!(res.len(), 2);
+        assert_eq!((res[0].set, res[0].set_size, res[0].overlap), ("a", 10, 4));
+        assert_approx_eq!(f64, res[0].expected, 1.6);
+        assert_approx_eq!(f64, res[0].pvalue, 0.04069878, epsilon=0.000001);
+        assert_eq!((res[1].set, res[1].overlap), ("b", 1));
+        assert_approx_eq!(f64, res[1].pvalue, 0.8567559, epsilon=0.000001);
+    }
+
+    #[test]
+    fn enrichment_of_top_k() {
+        let score: Importance<usize> = (0..50).map(|col| (col, match col {10..=13 => 1., _ => 0.})).collect();
+        let ranked = RankedImportance::new(&score, None, None, |c| format!("{:02}", c));
+        let res = enrichment_top_k(&ranked, 4, &annotation());
+        assert_eq!((res[0].set, res[0].overlap), ("b", 4));
+        assert_eq!((res[1].set, res[1].overlap, res[1].pvalue), ("a", 0, 1.));
+    }
+}
@@ -15,4 +15,11 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 pub mod x_bool_y_bool;
-pub mod x_threeval_y_bool;
\ No newline at end of file
+pub mod x_dosage_y_bool;
+pub mod x_threeval_y_bool;
+
+// Float type of gini kernels and importance accumulators, see the f32 feature.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
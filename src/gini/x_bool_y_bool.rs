@@ -14,57 +14,38 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::gini::Float;
+use crate::data_interface::three_val::ThreeVal::{Green, Red};
+use crate::gini::x_threeval_y_bool::gini_x_threeval_y_bool_weighted;
+
+// Gini of splitting on x, through the weighted three value kernel with true as its first value and unit weights.
 #[inline]
-pub fn gini_x_bool_y_bool(x: &Vec<bool>, y: &Vec<bool>) -> f64 {
+pub fn gini_x_bool_y_bool(x: &Vec<bool>, y: &Vec<bool>) -> Float {
     if x.len() != y.len() {
         panic!("X & Y size mismatch!");
     }
 
-    if x.len() == 0 {
-        panic!("Empty vectors given.");
-    }
-
-    let [num_xt_yt, num_xt_yf, num_xf_yt, num_xf_yf]: [usize; 4] = x
-        .iter()
-        .zip(y.iter())
-        .fold([0, 0, 0, 0], |mut c, (&x, &y)| {
-            match (x, y) {
-                (true, true) => c[0] += 1,
-                (true, false) => c[1] += 1,
-                (false, true) => c[2] += 1,
-                (false, false) => c[3] += 1
-            }
-            c
-        });
-
-    let n = x.len() as f64;
-    let (np_xt_yt, np_xt_yf, np_xf_yt, np_xf_yf) = (num_xt_yt as f64, num_xt_yf as f64, num_xf_yt as f64, num_xf_yf as f64);
-    let mut res = 0.;
-
-    if num_xt_yt + num_xt_yf > 0 {
-        res += (np_xt_yt + np_xt_yf) / n - (np_xt_yt * np_xt_yt + np_xt_yf * np_xt_yf ) / (np_xt_yt + np_xt_yf) / n;
-    }
-
-    if num_xf_yt + num_xf_yf > 0 {
-        res += (np_xf_yt + np_xf_yf) / n - (np_xf_yt * np_xf_yt + np_xf_yf * np_xf_yf ) / (np_xf_yt + np_xf_yf) / n;
-    }
-
-    return res;
+    let mut x_three = x.iter().map(|&x| Some(match x {
+        true => Red,
+        false => Green
+    }));
+    return gini_x_threeval_y_bool_weighted(&mut x_three, &mut y.iter().copied(), &mut std::iter::repeat_n(1u32, x.len())).0;
 }
 
 #[cfg(test)]
 mod tests {
     use super::gini_x_bool_y_bool;
     use float_cmp::assert_approx_eq;
+    use crate::gini::Float;
 
     #[test]
     fn gini_calculated_correctly() {
         let x = vec![true, false, false, false, false, true, false, true, false];
         let y = vec![false, true, true, false, true, false, true, true, false];
 
-        let p1 = 3./9. - (1 + 2*2) as f64/3./9.;
-        let p2 = 6./9. - (4*4 + 2*2) as f64/6./9.;
-        assert_approx_eq!(f64, gini_x_bool_y_bool(&x, &y), p1+p2);
+        let p1 = 3./9. - (1 + 2*2) as Float/3./9.;
+        let p2 = 6./9. - (4*4 + 2*2) as Float/6./9.;
+        assert_approx_eq!(Float, gini_x_bool_y_bool(&x, &y), p1+p2);
     }
 
     #[test]
@@ -72,12 +53,12 @@ mod tests {
         let x = vec![true, true, true, true];
         let y = vec![true, true, false, false];
 
-        assert_approx_eq!(f64, gini_x_bool_y_bool(&x, &y), 0.5);
+        assert_approx_eq!(Float, gini_x_bool_y_bool(&x, &y), 0.5);
 
         let x = vec![false, false, false, false];
         let y = vec![true, true, false, false];
 
-        assert_approx_eq!(f64, gini_x_bool_y_bool(&x, &y), 0.5)
+        assert_approx_eq!(Float, gini_x_bool_y_bool(&x, &y), 0.5)
     }
 
     #[test]
@@ -85,12 +66,12 @@ mod tests {
         let x = vec![true, true, false, false];
         let y = vec![true, true, true, true];
 
-        assert_approx_eq!(f64, gini_x_bool_y_bool(&x, &y), 0.);
+        assert_approx_eq!(Float, gini_x_bool_y_bool(&x, &y), 0.);
 
         let x = vec![true, true, false, false];
         let y = vec![false, false, false, false];
 
-        assert_approx_eq!(f64, gini_x_bool_y_bool(&x, &y), 0.);
+        assert_approx_eq!(Float, gini_x_bool_y_bool(&x, &y), 0.);
     }
 
     #[test]
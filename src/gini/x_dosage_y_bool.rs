@@ -0,0 +1,107 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use crate::data_interface::dosage::DOSAGE_LEVELS;
+use crate::gini::Float;
+use crate::gini::x_threeval_y_bool::{_gini, _gini_corrected};
+
+// Rows per dosage code, as (y true, y false) counts. Missing codes panic, as trees cannot be grown on them.
+pub fn count_x_dosage_y_bool<Ix, Iy>(x: &mut Ix, y: &mut Iy) -> Vec<[usize; 2]>
+where
+    Ix: Iterator<Item=u8>,
+    Iy: Iterator<Item=bool>
+{
+    let mut c = vec![[0; 2]; DOSAGE_LEVELS];
+    for (x, y) in x.zip(y) {
+        if x as usize >= DOSAGE_LEVELS {
+            panic!("Trees cannot be grown on missing dosages.");
+        }
+        match y {
+            true => c[x as usize][0] += 1,
+            false => c[x as usize][1] += 1
+        }
+    }
+    return c;
+}
+
+// Threshold code with the lowest gini of splitting n rows into codes up to it and above it, averaged over
+// the counts of each response. Ties go to the lowest threshold. None when all rows share one code.
+pub fn gini_x_dosage_y_bool(counts: &[Vec<[usize; 2]>], n: usize) -> Option<(u8, Float)> {
+    return gini_x_dosage_y_bool_with(counts, n, _gini);
+}
+
+// As gini_x_dosage_y_bool, with the finite-sample correction of _gini_corrected.
+pub fn gini_x_dosage_y_bool_corrected(counts: &[Vec<[usize; 2]>], n: usize) -> Option<(u8, Float)> {
+    return gini_x_dosage_y_bool_with(counts, n, _gini_corrected);
+}
+
+fn gini_x_dosage_y_bool_with(counts: &[Vec<[usize; 2]>], n: usize, gini: fn(usize, usize, usize, usize, Float) -> Float) -> Option<(u8, Float)> {
+    if n == 0 {
+        panic!("Empty vectors given.");
+    }
+
+    let totals: Vec<[usize; 2]> = counts.iter()
+        .map(|c| c.iter().fold([0; 2], |t, c| [t[0] + c[0], t[1] + c[1]]))
+        .collect();
+    let mut left = vec![[0; 2]; counts.len()];
+    let mut n_left = 0;
+    let mut best: Option<(u8, Float)> = None;
+    for code in 0..DOSAGE_LEVELS - 1 {
+        let n_code = counts[0][code][0] + counts[0][code][1];
+        if n_code == 0 {
+            continue;
+        }
+        n_left += n_code;
+        if n_left == n {
+            break;
+        }
+        let mut score = 0.;
+        for ((l, c), t) in left.iter_mut().zip(counts.iter()).zip(totals.iter()) {
+            l[0] += c[code][0];
+            l[1] += c[code][1];
+            score += gini(l[0], l[1], t[0] - l[0], t[1] - l[1], n as Float);
+        }
+        let score = score / counts.len() as Float;
+        if best.map_or(true, |(_, min)| score < min) {
+            best = Some((code as u8, score));
+        }
+    }
+    return best;
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::assert_approx_eq;
+    use crate::gini::Float;
+    use crate::gini::x_dosage_y_bool::{count_x_dosage_y_bool, gini_x_dosage_y_bool};
+
+    #[test]
+    fn best_threshold() {
+        let x = [0u8, 50, 50, 120, 200, 200];
+        let y = [false, false, true, true, true, true];
+        let c = count_x_dosage_y_bool(&mut x.iter().copied(), &mut y.iter().copied());
+        let (code, score) = gini_x_dosage_y_bool(&[c], 6).unwrap();
+        assert_eq!(code, 50);
+        assert_approx_eq!(Float, score, 3./6. - (1 + 2*2) as Float/3./6.);
+    }
+
+    #[test]
+    fn constant_x() {
+        let c = count_x_dosage_y_bool(&mut [7u8, 7, 7].into_iter(), &mut [true, false, true].into_iter());
+        assert!(gini_x_dosage_y_bool(&[c], 3).is_none());
+    }
+}
@@ -14,13 +14,16 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::ops::AddAssign;
+
 use crate::data_interface::three_val::{ThreeValCol, ThreeValOpt};
 use crate::data_interface::three_val::ThreeVal::*;
+use crate::gini::Float;
 
 #[inline]
-fn _gini(num_xt_yt: usize, num_xt_yf: usize, num_xf_yt: usize, num_xf_yf: usize, n: f64) -> f64 {
+pub(crate) fn _gini(num_xt_yt: usize, num_xt_yf: usize, num_xf_yt: usize, num_xf_yf: usize, n: Float) -> Float {
     let mut res = 0.;
-    let (np_xt_yt, np_xt_yf, np_xf_yt, np_xf_yf) = (num_xt_yt as f64, num_xt_yf as f64, num_xf_yt as f64, num_xf_yf as f64);
+    let (np_xt_yt, np_xt_yf, np_xf_yt, np_xf_yf) = (num_xt_yt as Float, num_xt_yf as Float, num_xf_yt as Float, num_xf_yf as Float);
 
     if num_xt_yt + num_xt_yf > 0 {
         res += (np_xt_yt + np_xt_yf) / n - (np_xt_yt * np_xt_yt + np_xt_yf * np_xt_yf ) / (np_xt_yt + np_xt_yf) / n;
@@ -33,8 +36,94 @@ fn _gini(num_xt_yt: usize, num_xt_yf: usize, num_xf_yt: usize, num_xf_yf: usize,
     return res;
 }
 
+// As _gini, for weighted counts. Kept in f64 whatever Float, as sums of many weights lose precision in f32.
+#[inline]
+fn _gini_weighted(np_xt_yt: f64, np_xt_yf: f64, np_xf_yt: f64, np_xf_yf: f64, n: f64) -> f64 {
+    let mut res = 0.;
+
+    if np_xt_yt + np_xt_yf > 0. {
+        res += (np_xt_yt + np_xt_yf) / n - (np_xt_yt * np_xt_yt + np_xt_yf * np_xt_yf ) / (np_xt_yt + np_xt_yf) / n;
+    }
+
+    if np_xf_yt + np_xf_yf > 0. {
+        res += (np_xf_yt + np_xf_yf) / n - (np_xf_yt * np_xf_yt + np_xf_yf * np_xf_yf ) / (np_xf_yt + np_xf_yf) / n;
+    }
+
+    return res;
+}
+
+// As _gini, with the gini of each side scaled by its n / (n - 1). Unbiased for the gini of the population the
+// rows are drawn from, which matters when splits are ranked on a subsample of the node: small sides look purer
+// than they are otherwise.
+#[inline]
+pub(crate) fn _gini_corrected(num_xt_yt: usize, num_xt_yf: usize, num_xf_yt: usize, num_xf_yf: usize, n: Float) -> Float {
+    let side = |yt: usize, yf: usize| {
+        let n_side = (yt + yf) as Float;
+        match yt + yf > 1 {
+            true => (n_side - (yt * yt + yf * yf) as Float / n_side) / n * n_side / (n_side - 1.),
+            false => 0.
+        }
+    };
+    return side(num_xt_yt, num_xt_yf) + side(num_xf_yt, num_xf_yf);
+}
+
+// Rows counted between checks of the bound in gini_x_threeval_y_bool_bounded.
+const BOUND_CHECK_ROWS: usize = 256;
+
+#[inline]
+fn _count<W: AddAssign>(c: &mut [W; 6], x: ThreeValOpt, y: bool, w: W) {
+    match (x.unwrap(), y) {
+        (Red, true) => c[0] += w,
+        (Red, false) => c[1] += w,
+        (Green, true) => c[2] += w,
+        (Green, false) => c[3] += w,
+        (Blue, true) => c[4] += w,
+        (Blue, false) => c[5] += w,
+    }
+}
+
+#[inline]
+fn _scores(c: &[usize; 6], n: Float) -> (Float, Float, Float) {
+    return _scores_with(c, n, _gini);
+}
+
+#[inline]
+fn _scores_with(c: &[usize; 6], n: Float, gini: fn(usize, usize, usize, usize, Float) -> Float) -> (Float, Float, Float) {
+    let [num_xr_yt, num_xr_yf, num_xg_yt, num_xg_yf, num_xb_yt, num_xb_yf] = *c;
+    return (
+        gini(num_xg_yt+num_xb_yt, num_xg_yf+num_xb_yf, num_xr_yt, num_xr_yf, n),
+        gini(num_xr_yt+num_xb_yt, num_xr_yf+num_xb_yf, num_xg_yt, num_xg_yf, n),
+        gini(num_xr_yt+num_xg_yt, num_xr_yf+num_xg_yf, num_xb_yt, num_xb_yf, n)
+    );
+}
+
 #[inline]
-pub fn gini_x_threeval_y_bool<'a, Ix, Iy>(x: &mut Ix, y: &mut Iy, n: usize) -> (f64, f64, f64)
+fn _scores_weighted(c: &[f64; 6], n: f64) -> (Float, Float, Float) {
+    let [num_xr_yt, num_xr_yf, num_xg_yt, num_xg_yf, num_xb_yt, num_xb_yf] = *c;
+    return (
+        _gini_weighted(num_xg_yt+num_xb_yt, num_xg_yf+num_xb_yf, num_xr_yt, num_xr_yf, n) as Float,
+        _gini_weighted(num_xr_yt+num_xb_yt, num_xr_yf+num_xb_yf, num_xg_yt, num_xg_yf, n) as Float,
+        _gini_weighted(num_xr_yt+num_xg_yt, num_xr_yf+num_xg_yf, num_xb_yt, num_xb_yf, n) as Float
+    );
+}
+
+// Lowest _gini a pivot can end with, given partial counts of its two sides and the rows of each class not
+// counted yet. Impurity of a side is concave and grows in both counts, so the minimum over placements of
+// the remaining rows is at a corner, with all remaining rows of a class on one side.
+#[inline]
+fn _gini_bound(side: [usize; 2], other: [usize; 2], rest: [usize; 2], n: Float) -> Float {
+    let mut bound = Float::INFINITY;
+    for t in [0, rest[0]] {
+        for f in [0, rest[1]] {
+            bound = bound.min(_gini(side[0] + t, side[1] + f, other[0] + rest[0] - t, other[1] + rest[1] - f, n));
+        }
+    }
+    return bound;
+}
+
+// Gini of the three pivots over n rows.
+#[inline]
+pub fn gini_x_threeval_y_bool<'a, Ix, Iy>(x: &mut Ix, y: &mut Iy, n: usize) -> (Float, Float, Float)
 where
     Ix: Iterator<Item=ThreeValOpt>,
     Iy: Iterator<Item=bool>
@@ -43,57 +132,143 @@ where
         panic!("Empty vectors given.");
     }
 
-    let [num_xr_yt, num_xr_yf, num_xg_yt, num_xg_yf, num_xb_yt, num_xb_yf]: [usize; 6] = x
+    let c = x
         .zip(y)
-        .fold([0, 0, 0, 0, 0, 0], |mut c, (x, y)| {
-            match (x.unwrap(), y) {
-                (Red, true) => c[0] += 1,
-                (Red, false) => c[1] += 1,
-                (Green, true) => c[2] += 1,
-                (Green, false) => c[3] += 1,
-                (Blue, true) => c[4] += 1,
-                (Blue, false) => c[5] += 1,
-            }
+        .fold([0; 6], |mut c, (x, y)| {
+            _count(&mut c, x, y, 1);
             c
         });
+    return _scores(&c, n as Float);
+}
+
+// As gini_x_threeval_y_bool, with each row counting with its weight, e.g. an in-bag multiplicity from
+// Mask::from_draws or a class weight. Scores are relative to the sum of the weights, which is accumulated in f64.
+pub fn gini_x_threeval_y_bool_weighted<Ix, Iy, Iw, W>(x: &mut Ix, y: &mut Iy, w: &mut Iw) -> (Float, Float, Float)
+where
+    Ix: Iterator<Item=ThreeValOpt>,
+    Iy: Iterator<Item=bool>,
+    Iw: Iterator<Item=W>,
+    W: Into<f64>
+{
+    let mut rows = 0;
+    let mut c = [0.; 6];
+    for (x, y) in x.zip(y) {
+        match w.next() {
+            Some(w) => _count(&mut c, x, y, w.into()),
+            None => panic!("Weights must match the number of rows.")
+        }
+        rows += 1;
+    }
+    if rows == 0 {
+        panic!("Empty vectors given.");
+    }
+    if w.next().is_some() {
+        panic!("Weights must match the number of rows.");
+    }
 
+    let total: f64 = c.iter().sum();
+    if total <= 0. {
+        panic!("Weights must sum to more than 0.");
+    }
+    return _scores_weighted(&c, total);
+}
 
-    let nf = n as f64;
-    let s = (
-        _gini(num_xg_yt+num_xb_yt, num_xg_yf+num_xb_yf, num_xr_yt, num_xr_yf, nf),
-        _gini(num_xr_yt+num_xb_yt, num_xr_yf+num_xb_yf, num_xg_yt, num_xg_yf, nf),
-        _gini(num_xr_yt+num_xg_yt, num_xr_yf+num_xg_yf, num_xb_yt, num_xb_yf, nf)
-    );
+// As gini_x_threeval_y_bool, with the finite-sample correction of _gini_corrected.
+pub fn gini_x_threeval_y_bool_corrected<Ix, Iy>(x: &mut Ix, y: &mut Iy, n: usize) -> (Float, Float, Float)
+where
+    Ix: Iterator<Item=ThreeValOpt>,
+    Iy: Iterator<Item=bool>
+{
+    if n == 0 {
+        panic!("Empty vectors given.");
+    }
+
+    let c = x
+        .zip(y)
+        .fold([0; 6], |mut c, (x, y)| {
+            _count(&mut c, x, y, 1);
+            c
+        });
+    return _scores_with(&c, n as Float, _gini_corrected);
+}
+
+// As gini_x_threeval_y_bool, but gives up with None as soon as no pivot can score cutoff or less, so hopeless
+// candidate columns are not counted to the end. n_true is the number of true labels among the n rows, known
+// from the node before any column is scanned.
+pub fn gini_x_threeval_y_bool_bounded<Ix, Iy>(x: &mut Ix, y: &mut Iy, n: usize, n_true: usize, cutoff: Float) -> Option<(Float, Float, Float)>
+where
+    Ix: Iterator<Item=ThreeValOpt>,
+    Iy: Iterator<Item=bool>
+{
+    if n == 0 {
+        panic!("Empty vectors given.");
+    }
+
+    let nf = n as Float;
+    // Final scores may differ from the bound in the last bits, only clearly worse columns are given up
+    let cutoff = cutoff + 16. * Float::EPSILON;
+    let mut c = [0; 6];
+    for (counted, (x, y)) in x.zip(y).enumerate() {
+        _count(&mut c, x, y, 1);
+        if (counted + 1) % BOUND_CHECK_ROWS == 0 && counted + 1 < n {
+            let counted_true = c[0] + c[2] + c[4];
+            let rest = [n_true - counted_true, n - n_true - (counted + 1 - counted_true)];
+            let bound = [0, 2, 4].iter()
+                .map(|&v| _gini_bound([c[v], c[v + 1]], [c[0] + c[2] + c[4] - c[v], c[1] + c[3] + c[5] - c[v + 1]], rest, nf))
+                .fold(Float::INFINITY, Float::min);
+            if bound > cutoff {
+                return None;
+            }
+        }
+    }
 
-    return s;
+    return Some(_scores(&c, nf));
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{_gini, gini_x_threeval_y_bool};
+    use super::{_gini, _gini_bound, _gini_corrected, gini_x_threeval_y_bool, gini_x_threeval_y_bool_bounded, gini_x_threeval_y_bool_corrected, gini_x_threeval_y_bool_weighted};
     use float_cmp::assert_approx_eq;
+    use crate::gini::Float;
     use crate::data_interface::three_val::{ThreeVal, ThreeValOpt};
 
     #[test]
     fn part_gini_calculated_correctly() {
-        let p1 = 3./9. - (1 + 2*2) as f64/3./9.;
-        let p2 = 6./9. - (4*4 + 2*2) as f64/6./9.;
-        assert_approx_eq!(f64, _gini(1, 2, 4, 2, 9.), p1+p2);
+        let p1 = 3./9. - (1 + 2*2) as Float/3./9.;
+        let p2 = 6./9. - (4*4 + 2*2) as Float/6./9.;
+        assert_approx_eq!(Float, _gini(1, 2, 4, 2, 9.), p1+p2);
     }
 
     #[test]
     fn part_gini_can_handle_single_x_class() {
-        assert_approx_eq!(f64, _gini(2, 2, 0, 0, 4.), 0.5);
-        assert_approx_eq!(f64, _gini(0, 0, 2, 2, 4.), 0.5)
+        assert_approx_eq!(Float, _gini(2, 2, 0, 0, 4.), 0.5);
+        assert_approx_eq!(Float, _gini(0, 0, 2, 2, 4.), 0.5)
     }
 
     #[test]
     fn part_gini_can_handle_single_y_class() {
-        assert_approx_eq!(f64, _gini(2, 0, 2, 0, 4.), 0.);
-        assert_approx_eq!(f64, _gini(0, 2, 0, 2, 4.), 0.);
+        assert_approx_eq!(Float, _gini(2, 0, 2, 0, 4.), 0.);
+        assert_approx_eq!(Float, _gini(0, 2, 0, 2, 4.), 0.);
     }
 
 
+    #[test]
+    fn part_gini_corrected_scales_sides() {
+        // Sides of 3 and 6 rows, each scaled by n / (n - 1)
+        let p1 = (3./9. - (1 + 2*2) as Float/3./9.) * 3./2.;
+        let p2 = (6./9. - (4*4 + 2*2) as Float/6./9.) * 6./5.;
+        assert_approx_eq!(Float, _gini_corrected(1, 2, 4, 2, 9.), p1+p2);
+        // A single row side is pure either way
+        assert_approx_eq!(Float, _gini_corrected(1, 0, 2, 2, 5.), 0.5 * 4./5. * 4./3.);
+        assert_approx_eq!(Float, _gini_corrected(2, 0, 0, 2, 4.), 0.);
+
+        let x: Vec<ThreeValOpt> = vec![Some(ThreeVal::Red), Some(ThreeVal::Red), Some(ThreeVal::Green), Some(ThreeVal::Blue)];
+        let y = vec![true, false, true, false];
+        let res = gini_x_threeval_y_bool_corrected(&mut x.into_iter(), &mut y.into_iter(), 4);
+        assert_approx_eq!(Float, res.0, _gini_corrected(1, 1, 1, 1, 4.));
+        assert_approx_eq!(Float, res.1, _gini_corrected(1, 2, 1, 0, 4.));
+    }
+
     #[test]
     fn gini_should_panic_with_empty_vectors() {
         let x: Vec<ThreeValOpt> = vec![];
@@ -117,8 +292,80 @@ mod tests {
         let y = vec![false, true, true, false, true, false, true, true, false];
 
         let res = gini_x_threeval_y_bool(&mut x.into_iter(), &mut y.into_iter(), 9);
-        assert_approx_eq!(f64, res.0, 3./9. - (1.+2.*2.)/3./9. + 6./9. - (2.*2.+4.*4.)/6./9.);
-        assert_approx_eq!(f64, res.1, 3./9. - (1.+2.*2.)/3./9. + 6./9. - (2.*2.+4.*4.)/6./9.);
-        assert_approx_eq!(f64, res.2, 6./9. - (4*4+2*2) as f64/6./9.);
+        assert_approx_eq!(Float, res.0, 3./9. - (1.+2.*2.)/3./9. + 6./9. - (2.*2.+4.*4.)/6./9.);
+        assert_approx_eq!(Float, res.1, 3./9. - (1.+2.*2.)/3./9. + 6./9. - (2.*2.+4.*4.)/6./9.);
+        assert_approx_eq!(Float, res.2, 6./9. - (4*4+2*2) as Float/6./9.);
+    }
+
+    #[test]
+    fn gini_bound_below_every_placement() {
+        // Sides hold (1, 2) and (3, 0), 2 true and 3 false rows are still to come
+        let bound = _gini_bound([1, 2], [3, 0], [2, 3], 11.);
+        for t in 0..=2 {
+            for f in 0..=3 {
+                assert!(bound <= _gini(1 + t, 2 + f, 3 + 2 - t, 3 - f, 11.) + Float::EPSILON);
+            }
+        }
+        assert_approx_eq!(Float, bound, _gini(1, 5, 5, 0, 11.));
+    }
+
+    #[test]
+    fn gini_bounded_gives_up_only_when_hopeless() {
+        let x: Vec<ThreeValOpt> = (0..2000).map(|i| Some(match i % 3 {
+            0 => ThreeVal::Red,
+            1 => ThreeVal::Green,
+            _ => ThreeVal::Blue
+        })).collect();
+        let y_noise: Vec<bool> = (0..2000).map(|i| (i / 3) % 2 == 0).collect();
+        let n_true = y_noise.iter().filter(|&&y| y).count();
+
+        let full = gini_x_threeval_y_bool(&mut x.iter().cloned(), &mut y_noise.iter().cloned(), 2000);
+        let loose = gini_x_threeval_y_bool_bounded(&mut x.iter().cloned(), &mut y_noise.iter().cloned(), 2000, n_true, 1.);
+        assert_eq!(loose, Some(full));
+        let best = full.0.min(full.1).min(full.2);
+        let at_best = gini_x_threeval_y_bool_bounded(&mut x.iter().cloned(), &mut y_noise.iter().cloned(), 2000, n_true, best);
+        assert_eq!(at_best, Some(full));
+        // A perfect split elsewhere leaves a column unrelated to y no chance
+        assert_eq!(gini_x_threeval_y_bool_bounded(&mut x.iter().cloned(), &mut y_noise.iter().cloned(), 2000, n_true, 0.), None);
+    }
+
+    #[test]
+    fn gini_weighted_as_duplicated_rows() {
+        let vals = [0, 2, 2, 1, 1, 0, 2, 0, 1];
+        let to_x = |v: &usize| Some(match v {
+            0 => ThreeVal::Red,
+            1 => ThreeVal::Green,
+            _ => ThreeVal::Blue
+        });
+        let y = vec![false, true, true, false, true, false, true, true, false];
+        let w: Vec<u32> = vec![1, 3, 1, 2, 1, 1, 4, 2, 1];
+
+        let dup_x: Vec<ThreeValOpt> = vals.iter().zip(w.iter()).flat_map(|(v, &k)| std::iter::repeat(to_x(v)).take(k as usize)).collect();
+        let dup_y: Vec<bool> = y.iter().zip(w.iter()).flat_map(|(&v, &k)| std::iter::repeat(v).take(k as usize)).collect();
+        let n = dup_y.len();
+        let expected = gini_x_threeval_y_bool(&mut dup_x.into_iter(), &mut dup_y.into_iter(), n);
+
+        let res = gini_x_threeval_y_bool_weighted(&mut vals.iter().map(to_x), &mut y.iter().copied(), &mut w.iter().copied());
+        assert_approx_eq!(Float, res.0, expected.0);
+        assert_approx_eq!(Float, res.1, expected.1);
+        assert_approx_eq!(Float, res.2, expected.2);
+
+        // Float weights only matter relative to each other
+        let wf: Vec<Float> = w.iter().map(|&k| k as Float * 0.25).collect();
+        let res = gini_x_threeval_y_bool_weighted(&mut vals.iter().map(to_x), &mut y.into_iter(), &mut wf.iter().copied());
+        assert_approx_eq!(Float, res.0, expected.0);
+        assert_approx_eq!(Float, res.1, expected.1);
+        assert_approx_eq!(Float, res.2, expected.2);
+    }
+
+    #[test]
+    fn gini_weights_must_match_rows() {
+        let x: Vec<ThreeValOpt> = vec![Some(ThreeVal::Red), Some(ThreeVal::Blue)];
+        let y = vec![true, false];
+
+        let res = std::panic::catch_unwind(|| {
+            gini_x_threeval_y_bool_weighted(&mut x.into_iter(), &mut y.into_iter(), &mut [1.].into_iter());
+        });
+        assert!(res.is_err());
     }
 }
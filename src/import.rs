@@ -0,0 +1,342 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+// Forests trained by ranger or scikit-learn on the genotype columns of an XDf, read from JSON into Tree nodes so
+// they predict, and get permutation importance (Forest::permutation_importance), as forests grown here.
+//
+// A split x <= threshold sends a row left, thresholds must fall in [0, 2) to split 0/1/2 genotypes. Classes are
+// those of YBool, the second class of the model being true. Column indices must be XDf column ids.
+
+use std::error::Error;
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::data_interface::multi_x::{ColSplitIndex, MultiPivot};
+use crate::data_interface::three_val::ThreeValPivot;
+use crate::data_interface::y_bool::Y;
+use crate::random_forest::Forest;
+use crate::random_number_generator::factory::RngFactory;
+use crate::tree::Tree;
+
+#[derive(Debug)]
+pub enum ImportError {
+    Json(serde_json::Error),
+    // Field missing or of the wrong type, tree None for forest level fields.
+    Field{tree: Option<usize>, field: &'static str},
+    // The model has other than two classes.
+    Classes(usize),
+    Column{tree: usize, node: usize, col_id: usize, ncol: usize},
+    Threshold{tree: usize, node: usize, threshold: f64},
+    // Child out of range, or a node reached twice.
+    Child{tree: usize, node: usize},
+    LeafClass{tree: usize, node: usize},
+    EmptyTree(usize),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            ImportError::Json(e) => write!(f, "Model is not valid JSON: {}", e),
+            ImportError::Field{tree: Some(tree), field} => write!(f, "Tree {} has no valid field {}.", tree, field),
+            ImportError::Field{tree: None, field} => write!(f, "Model has no valid field {}.", field),
+            ImportError::Classes(n) => write!(f, "Model must have 2 classes, found {}.", n),
+            ImportError::Column{tree, node, col_id, ncol} => write!(f, "Node {} of tree {} splits on column {} of {}.", node, tree, col_id, ncol),
+            ImportError::Threshold{tree, node, threshold} => write!(f, "Threshold {} of node {} of tree {} does not split genotypes 0/1/2.", threshold, node, tree),
+            ImportError::Child{tree, node} => write!(f, "Node {} of tree {} has an invalid child.", node, tree),
+            ImportError::LeafClass{tree, node} => write!(f, "Leaf {} of tree {} has no class of the model.", node, tree),
+            ImportError::EmptyTree(tree) => write!(f, "Tree {} has no nodes.", tree)
+        };
+    }
+}
+
+impl Error for ImportError {}
+
+// Node of an imported tree, children as node indices of the model.
+struct ExtNode {
+    split: Option<(usize, f64, usize, usize)>,
+    class: usize,
+    n: usize
+}
+
+// scikit-learn RandomForestClassifier exported as
+// {"classes": clf.classes_.tolist(), "estimators": [{k: getattr(e.tree_, k).tolist() for k in
+//  ["children_left", "children_right", "feature", "threshold", "value", "n_node_samples"]} for e in clf.estimators_]}.
+// Leaves (children -1) vote for the class with the largest value, n_node_samples is optional. ncol is the number
+// of columns of the data and seed that of the permutations of importance.
+pub fn import_sklearn(json: &str, ncol: usize, seed: u64) -> Result<Forest<Y, ColSplitIndex>, ImportError> {
+    let model: Value = serde_json::from_str(json).map_err(ImportError::Json)?;
+    let n_classes = field(&model, None, "classes")?.as_array().ok_or(ImportError::Field{tree: None, field: "classes"})?.len();
+    if n_classes != 2 {
+        return Err(ImportError::Classes(n_classes));
+    }
+    let estimators = field(&model, None, "estimators")?.as_array().ok_or(ImportError::Field{tree: None, field: "estimators"})?;
+
+    let mut trees = vec![];
+    for (t, est) in estimators.iter().enumerate() {
+        let left = usizes_or_leaf(field(est, Some(t), "children_left")?, t, "children_left")?;
+        let right = usizes_or_leaf(field(est, Some(t), "children_right")?, t, "children_right")?;
+        let feature = floats(field(est, Some(t), "feature")?, t, "feature")?;
+        let threshold = floats(field(est, Some(t), "threshold")?, t, "threshold")?;
+        let value = field(est, Some(t), "value")?.as_array().ok_or(ImportError::Field{tree: Some(t), field: "value"})?;
+        let n_nodes = left.len();
+        if [right.len(), feature.len(), threshold.len(), value.len()].iter().any(|&len| len != n_nodes) {
+            return Err(ImportError::Field{tree: Some(t), field: "children_left"});
+        }
+        let node_n = match est.get("n_node_samples") {
+            Some(x) => floats(x, t, "n_node_samples")?.into_iter().map(|n| n as usize).collect(),
+            None => vec![0; n_nodes]
+        };
+
+        let mut nodes = Vec::with_capacity(n_nodes);
+        for node in 0..n_nodes {
+            let split = match (left[node], right[node]) {
+                (Some(l), Some(r)) => Some((feature[node] as usize, threshold[node], l, r)),
+                _ => None
+            };
+            // Per node values are [n_outputs][n_classes], or [n_classes] for a single output
+            let counts = match value[node].as_array().and_then(|x| x.first()) {
+                Some(Value::Array(inner)) => inner,
+                _ => value[node].as_array().ok_or(ImportError::Field{tree: Some(t), field: "value"})?
+            };
+            let counts: Vec<f64> = counts.iter().map(|x| x.as_f64().ok_or(ImportError::Field{tree: Some(t), field: "value"})).collect::<Result<_, _>>()?;
+            if split.is_none() && counts.len() != n_classes {
+                return Err(ImportError::LeafClass{tree: t, node});
+            }
+            let class = (0..counts.len()).fold(0, |best, k| match counts[k] > counts[best] {
+                true => k,
+                false => best
+            });
+            nodes.push(ExtNode{split, class, n: *node_n.get(node).unwrap_or(&0)});
+        }
+        trees.push(nodes);
+    }
+    return build_forest(trees, ncol, seed);
+}
+
+// ranger forest (rf$forest) written with jsonlite::write_json(rf$forest, digits = NA), using child.nodeIDs,
+// split.varIDs, split.values and class.values. Terminal nodes have both children 0 and their class value in
+// split.values. ranger keeps no node sizes, so missing values go left with NaPolicy::Majority. ncol and seed as in
+// import_sklearn.
+pub fn import_ranger(json: &str, ncol: usize, seed: u64) -> Result<Forest<Y, ColSplitIndex>, ImportError> {
+    let model: Value = serde_json::from_str(json).map_err(ImportError::Json)?;
+    let class_values = floats(field(&model, None, "class.values")?, 0, "class.values").map_err(|_| ImportError::Field{tree: None, field: "class.values"})?;
+    if class_values.len() != 2 {
+        return Err(ImportError::Classes(class_values.len()));
+    }
+    let child_ids = field(&model, None, "child.nodeIDs")?.as_array().ok_or(ImportError::Field{tree: None, field: "child.nodeIDs"})?;
+    let var_ids = field(&model, None, "split.varIDs")?.as_array().ok_or(ImportError::Field{tree: None, field: "split.varIDs"})?;
+    let values = field(&model, None, "split.values")?.as_array().ok_or(ImportError::Field{tree: None, field: "split.values"})?;
+    if var_ids.len() != child_ids.len() || values.len() != child_ids.len() {
+        return Err(ImportError::Field{tree: None, field: "split.varIDs"});
+    }
+
+    let mut trees = vec![];
+    for t in 0..child_ids.len() {
+        let children = child_ids[t].as_array().filter(|x| x.len() == 2).ok_or(ImportError::Field{tree: Some(t), field: "child.nodeIDs"})?;
+        let left = floats(&children[0], t, "child.nodeIDs")?;
+        let right = floats(&children[1], t, "child.nodeIDs")?;
+        let var = floats(&var_ids[t], t, "split.varIDs")?;
+        let value = floats(&values[t], t, "split.values")?;
+        let n_nodes = left.len();
+        if [right.len(), var.len(), value.len()].iter().any(|&len| len != n_nodes) {
+            return Err(ImportError::Field{tree: Some(t), field: "child.nodeIDs"});
+        }
+
+        let mut nodes = Vec::with_capacity(n_nodes);
+        for node in 0..n_nodes {
+            let (split, class) = match (left[node] as usize, right[node] as usize) {
+                (0, 0) => match class_values.iter().position(|&x| x == value[node]) {
+                    Some(class) => (None, class),
+                    None => return Err(ImportError::LeafClass{tree: t, node})
+                },
+                (l, r) => (Some((var[node] as usize, value[node], l, r)), 0)
+            };
+            nodes.push(ExtNode{split, class, n: 0});
+        }
+        trees.push(nodes);
+    }
+    return build_forest(trees, ncol, seed);
+}
+
+fn field<'a>(x: &'a Value, tree: Option<usize>, field: &'static str) -> Result<&'a Value, ImportError> {
+    return x.get(field).ok_or(ImportError::Field{tree, field});
+}
+
+fn floats(x: &Value, tree: usize, field: &'static str) -> Result<Vec<f64>, ImportError> {
+    return x.as_array()
+        .and_then(|x| x.iter().map(|x| x.as_f64()).collect::<Option<Vec<f64>>>())
+        .ok_or(ImportError::Field{tree: Some(tree), field});
+}
+
+// scikit-learn children, -1 marking a leaf.
+fn usizes_or_leaf(x: &Value, tree: usize, field: &'static str) -> Result<Vec<Option<usize>>, ImportError> {
+    return Ok(floats(x, tree, field)?.into_iter().map(|x| (x >= 0.).then_some(x as usize)).collect());
+}
+
+fn build_forest(trees: Vec<Vec<ExtNode>>, ncol: usize, seed: u64) -> Result<Forest<Y, ColSplitIndex>, ImportError> {
+    let rng_factory = RngFactory::new(seed, Some(ncol), Some(trees.len()));
+    let mut res = Vec::with_capacity(trees.len());
+    for (t, nodes) in trees.iter().enumerate() {
+        // A tree without nodes would fail on prediction, as in compact
+        if nodes.is_empty() {
+            return Err(ImportError::EmptyTree(t));
+        }
+        let mut tree = Tree::new(t, &rng_factory);
+        push_tree(&mut tree, nodes, t, ncol)?;
+        res.push(tree);
+    }
+    return Ok(Forest::from_trees(res));
+}
+
+// Step of push_tree: a node to push with its parent, or a split whose children are pushed.
+enum Visit {
+    Node(usize, usize),
+    Split(usize, ColSplitIndex),
+}
+
+// Pushes the nodes of a tree children first, as trees grown here are stored. Walks an explicit stack, so deep
+// trees of other libraries cannot overflow the call stack.
+fn push_tree(tree: &mut Tree<Y, ColSplitIndex>, nodes: &[ExtNode], t: usize, ncol: usize) -> Result<(), ImportError> {
+    let mut visited = vec![false; nodes.len()];
+    let mut stack = vec![Visit::Node(0, 0)];
+    // Handles of pushed subtrees not yet joined by their parent
+    let mut handles = vec![];
+    while let Some(visit) = stack.pop() {
+        let (node, parent) = match visit {
+            Visit::Node(node, parent) => (node, parent),
+            Visit::Split(node, split_index) => {
+                let r_handle = handles.pop().unwrap();
+                let l_handle = handles.pop().unwrap();
+                handles.push(tree.push_imported_split(split_index, l_handle, r_handle, nodes[node].n));
+                continue;
+            }
+        };
+        if visited[node] {
+            return Err(ImportError::Child{tree: t, node: parent});
+        }
+        visited[node] = true;
+        let ext = &nodes[node];
+        let (col_id, threshold, l, r) = match ext.split {
+            Some(split) => split,
+            None => {
+                handles.push(tree.push_imported_leaf(ext.class == 1, ext.n));
+                continue;
+            }
+        };
+        if col_id >= ncol {
+            return Err(ImportError::Column{tree: t, node, col_id, ncol});
+        }
+        if l >= nodes.len() || r >= nodes.len() || l == r {
+            return Err(ImportError::Child{tree: t, node});
+        }
+        // Pivot rows go left, rows of the pivot genotype right, so {0} | {1, 2} swaps the children
+        let (pivot, l, r) = match threshold {
+            x if (0. ..1.).contains(&x) => (ThreeValPivot::NotRed, r, l),
+            x if (1. ..2.).contains(&x) => (ThreeValPivot::NotBlue, l, r),
+            _ => return Err(ImportError::Threshold{tree: t, node, threshold})
+        };
+        stack.push(Visit::Split(node, ColSplitIndex{col_id, pivot: MultiPivot::ThreeVal(pivot), shadow: false}));
+        stack.push(Visit::Node(r, node));
+        stack.push(Visit::Node(l, node));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_interface::multi_x::{MultiX, XDf};
+    use crate::data_interface::three_val::ThreeValCol;
+    use crate::data_interface::y_bool::YBool;
+    use crate::import::{import_ranger, import_sklearn, ImportError};
+    use crate::mask::Mask;
+    use crate::tree::NaPolicy;
+
+    // x0 <= 0.5 ? (x1 <= 1.5 ? 0 : 1) : 1, with node sizes
+    const SKLEARN: &str = r#"{"classes": [0, 1], "estimators": [{
+        "children_left": [1, 2, -1, -1, -1], "children_right": [4, 3, -1, -1, -1],
+        "feature": [0, 1, -2, -2, -2], "threshold": [0.5, 1.5, -2, -2, -2],
+        "value": [[[5, 5]], [[4, 1]], [[4, 0]], [[0, 1]], [[1, 4]]],
+        "n_node_samples": [10, 5, 4, 1, 5]}]}"#;
+
+    #[test]
+    fn sklearn_forest() {
+        let forest = import_sklearn(SKLEARN, 2, 0).unwrap();
+        let df = XDf::new(vec![
+            MultiX::ThreeVal(ThreeValCol::new(&[0, 0, 1, 2])),
+            MultiX::ThreeVal(ThreeValCol::new(&[0, 2, 0, 1]))
+        ]);
+        let mask = Mask::new(vec![0, 1, 2, 3]);
+        assert_eq!(forest.predict_votes(&df, &mask, true, NaPolicy::Majority), [0., 1., 1., 1.]);
+        // The root swaps its children, x0 = 0 going right
+        assert_eq!(forest.get_trees()[0].node_depths(), [1, 2, 2, 1, 0]);
+
+        // Column 1 decides the first two rows only
+        let y = YBool::new(&[false, true, true, true]);
+        let importance = forest.permutation_importance(&df, &y, &mask, 1);
+        assert_eq!(importance.len(), 2);
+
+        assert!(matches!(import_sklearn(SKLEARN, 1, 0), Err(ImportError::Column{tree: 0, node: 1, col_id: 1, ncol: 1})));
+        assert!(matches!(import_sklearn(&SKLEARN.replace("0.5, 1.5", "0.5, 2.5"), 2, 0), Err(ImportError::Threshold{tree: 0, node: 1, ..})));
+        assert!(matches!(import_sklearn(&SKLEARN.replace("[0, 1]", "[0, 1, 2]"), 2, 0), Err(ImportError::Classes(3))));
+        assert!(matches!(import_sklearn(&SKLEARN.replace("[4, 3, -1", "[4, 1, -1"), 2, 0), Err(ImportError::Child{tree: 0, node: 1})));
+        assert!(matches!(import_sklearn("{\"classes\": [0, 1]}", 2, 0), Err(ImportError::Field{tree: None, field: "estimators"})));
+    }
+
+    #[test]
+    fn sklearn_deep_tree() {
+        // Splits on x0 at even nodes, each with a leaf on the left, deeper than the call stack would take
+        let n_nodes = 400_001;
+        let per_node = |split: &dyn Fn(usize) -> String, leaf: &str| (0..n_nodes)
+            .map(|i| match i % 2 == 0 && i < n_nodes - 1 {
+                true => split(i),
+                false => leaf.to_string()
+            })
+            .collect::<Vec<String>>().join(", ");
+        let json = format!(r#"{{"classes": [0, 1], "estimators": [{{"children_left": [{}], "children_right": [{}],
+            "feature": [{}], "threshold": [{}], "value": [{}], "n_node_samples": [{}]}}]}}"#,
+            per_node(&|i| (i + 1).to_string(), "-1"), per_node(&|i| (i + 2).to_string(), "-1"),
+            per_node(&|_| "0".to_string(), "-2"), per_node(&|_| "0.5".to_string(), "-2"),
+            per_node(&|_| "[[1, 1]]".to_string(), "[[1, 1]]"), per_node(&|_| "2".to_string(), "1"));
+        let forest = import_sklearn(&json, 1, 0).unwrap();
+        assert_eq!(forest.get_trees()[0].node_depths().len(), n_nodes);
+    }
+
+    #[test]
+    fn ranger_forest() {
+        // Same tree as SKLEARN, class values 1 and 2 of a factor
+        let json = r#"{"num.trees": 1, "class.values": [1, 2],
+            "child.nodeIDs": [[[1, 2, 0, 0, 0], [4, 3, 0, 0, 0]]],
+            "split.varIDs": [[0, 1, 0, 0, 0]], "split.values": [[0.5, 1.5, 1, 2, 2]]}"#;
+        let forest = import_ranger(json, 2, 0).unwrap();
+        let sklearn = import_sklearn(SKLEARN, 2, 0).unwrap();
+        assert_eq!(forest.get_trees()[0].structure_hash(), sklearn.get_trees()[0].structure_hash());
+        assert!(matches!(import_ranger(&json.replace("1.5, 1, 2, 2", "1.5, 1, 3, 2"), 2, 0), Err(ImportError::LeafClass{tree: 0, node: 3})));
+    }
+
+    #[test]
+    fn empty_tree() {
+        let empty = r#"{"children_left": [], "children_right": [], "feature": [], "threshold": [], "value": [], "n_node_samples": []}"#;
+        let json = SKLEARN.replace("]}]}", &format!("]}}, {}]}}", empty));
+        assert!(matches!(import_sklearn(&json, 2, 0), Err(ImportError::EmptyTree(1))));
+
+        let json = r#"{"num.trees": 2, "class.values": [1, 2],
+            "child.nodeIDs": [[[1, 2, 0, 0, 0], [4, 3, 0, 0, 0]], [[], []]],
+            "split.varIDs": [[0, 1, 0, 0, 0], []], "split.values": [[0.5, 1.5, 1, 2, 2], []]}"#;
+        assert!(matches!(import_ranger(json, 2, 0), Err(ImportError::EmptyTree(1))));
+    }
+}
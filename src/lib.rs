@@ -20,5 +20,28 @@ pub mod tree;
 mod gini;
 pub mod random_number_generator;
 pub mod random_forest;
-mod binom;
+pub mod stats;
+mod stable_hash;
 pub mod boruta;
+pub mod boruta_report;
+pub mod model_selection;
+pub mod ranked_importance;
+pub mod local_importance;
+pub mod manifest;
+pub mod preprocessing;
+pub mod screening;
+pub mod enrichment;
+pub mod calibration;
+pub mod compact;
+pub mod determinism;
+pub mod regions;
+pub mod clock;
+pub mod partial_importance;
+#[cfg(feature = "perf")]
+pub mod perf;
+#[cfg(feature = "linfa")]
+pub mod linfa_forest;
+#[cfg(feature = "import")]
+pub mod import;
+#[cfg(feature = "distributed")]
+pub mod distributed;
@@ -0,0 +1,330 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::fmt;
+
+use linfa::dataset::{AsSingleTargets, DatasetBase};
+use linfa::traits::{Fit, PredictInplace};
+use ndarray::{Array1, ArrayBase, Data, Ix2};
+
+use crate::data_interface::multi_x::{SplitColId, XDfError};
+use crate::data_interface::ndarray_x::{ArrayCol, ArrayXDf};
+use crate::data_interface::three_val::ThreeValPivot;
+use crate::data_interface::typed_x::{TypedSplitIndex, XDfTyped};
+use crate::data_interface::y_bool::{Y, YBool};
+use crate::data_interface::{ColumnData, DataInterface, Predicted, Response, RowView, SplitPenalty, TiePolicy};
+use crate::mask::Mask;
+use crate::random_forest::{Forest, RandomForest};
+use crate::random_number_generator::Rng;
+use crate::random_number_generator::factory::RngFactory;
+use crate::tree::NaPolicy;
+
+type Split = TypedSplitIndex<ThreeValPivot>;
+
+#[derive(Debug)]
+pub enum ForestFitError {
+    Data(XDfError),
+    Linfa(linfa::Error),
+}
+
+impl fmt::Display for ForestFitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForestFitError::Data(e) => write!(f, "Invalid records: {}", e),
+            ForestFitError::Linfa(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ForestFitError {}
+
+impl From<XDfError> for ForestFitError {
+    fn from(e: XDfError) -> Self {
+        return ForestFitError::Data(e);
+    }
+}
+
+impl From<linfa::Error> for ForestFitError {
+    fn from(e: linfa::Error) -> Self {
+        return ForestFitError::Linfa(e);
+    }
+}
+
+// Linfa dataset with codes 0, 1 or 2 as records and bool targets, as data and response of a forest.
+// Records are split where they are, see ArrayXDf. Targets are collected once, as Response hands out a Vec.
+pub struct LinfaData<'a> {
+    records: ArrayXDf<'a>,
+    targets: YBool
+}
+
+impl<'a> LinfaData<'a> {
+    pub fn new<D, T>(dataset: &'a DatasetBase<ArrayBase<D, Ix2>, T>) -> Result<LinfaData<'a>, ForestFitError>
+    where
+        D: Data<Elem=i8>,
+        T: AsSingleTargets<Elem=bool>
+    {
+        let targets = dataset.as_single_targets();
+        if targets.len() != dataset.records.nrows() {
+            return Err(linfa::Error::MismatchedShapes(dataset.records.nrows(), targets.len()).into());
+        }
+        return Ok(LinfaData{records: XDfTyped::from_array_view(dataset.records.view())?, targets: YBool::from_array(targets)});
+    }
+}
+
+impl<'a> ColumnData<Split> for LinfaData<'a> {
+    type InternalType = ArrayCol<'a>;
+
+    fn get_ncol(&self) -> usize {
+        return self.records.get_ncol();
+    }
+
+    fn get_col_ids(&self) -> Vec<SplitColId> {
+        return self.records.get_col_ids();
+    }
+
+    fn get_shadow_col_ids(&self) -> Vec<SplitColId> {
+        return self.records.get_shadow_col_ids();
+    }
+
+    fn make_split(&self, idx: Split, mask: &Mask, rng_factory: &RngFactory, permuted_vec: Option<&ArrayCol<'a>>) -> [Mask; 2] {
+        return self.records.make_split(idx, mask, rng_factory, permuted_vec);
+    }
+
+    fn make_split_into(&self, idx: Split, mask: &Mask, rng_factory: &RngFactory, out: &mut [Vec<usize>; 2]) {
+        self.records.make_split_into(idx, mask, rng_factory, out);
+    }
+
+    fn partition(&self, idx: Split, rows: &mut [usize], rng_factory: &RngFactory, scratch: &mut Vec<usize>) -> usize {
+        return self.records.partition(idx, rows, rng_factory, scratch);
+    }
+
+    fn permute_index(&self, idx: SplitColId, rng_factory: &RngFactory, oob_mask: &Mask, ith_tree: usize, round: usize) -> ArrayCol<'a> {
+        return self.records.permute_index(idx, rng_factory, oob_mask, ith_tree, round);
+    }
+
+    fn permute_index_all(&self, idx: SplitColId, rng_factory: &RngFactory, round: usize) -> ArrayCol<'a> {
+        return self.records.permute_index_all(idx, rng_factory, round);
+    }
+}
+
+impl DataInterface<Split, Y> for LinfaData<'_> {
+    fn find_min_idx<U>(&self, mask: &Mask, y: &U, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool) -> Option<Split>
+    where
+        U: Response<Y>
+    {
+        return self.records.find_min_idx(mask, y, mtry, rng, rng_factory, shadow_vars);
+    }
+
+    fn find_min_idx_penalized<U>(&self, mask: &Mask, y: &U, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool, penalty: SplitPenalty) -> Option<Split>
+    where
+        U: Response<Y>
+    {
+        return self.records.find_min_idx_penalized(mask, y, mtry, rng, rng_factory, shadow_vars, penalty);
+    }
+
+    fn find_min_idx_subsample<U>(&self, mask: &Mask, y: &U, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool, penalty: SplitPenalty) -> Option<Split>
+    where
+        U: Response<Y>
+    {
+        return self.records.find_min_idx_subsample(mask, y, mtry, rng, rng_factory, shadow_vars, penalty);
+    }
+
+    fn split_score<U>(&self, col: SplitColId, mask: &Mask, y: &U) -> Option<f64>
+    where
+        U: Response<Y>
+    {
+        return self.records.split_score(col, mask, y);
+    }
+
+    fn refine_split<U>(&self, idx: Split, mask: &Mask, y: &U, rng_factory: &RngFactory) -> Split
+    where
+        U: Response<Y>
+    {
+        return self.records.refine_split(idx, mask, y, rng_factory);
+    }
+}
+
+impl RowView<Split> for LinfaData<'_> {
+    #[inline]
+    fn goes_left(&self, row: usize, idx: &Split) -> bool {
+        return self.records.goes_left(row, idx);
+    }
+
+    #[inline]
+    fn try_goes_left(&self, row: usize, idx: &Split) -> Option<bool> {
+        return self.records.try_goes_left(row, idx);
+    }
+}
+
+impl Response<Y> for LinfaData<'_> {
+    fn get_class(&self, mask: &Mask) -> Option<Y> {
+        return self.targets.get_class(mask);
+    }
+
+    fn get_major_class(&self, mask: &Mask, rng: &mut Rng) -> Y {
+        return self.targets.get_major_class(mask, rng);
+    }
+
+    fn get_major_class_with(&self, mask: &Mask, rng: &mut Rng, ties: TiePolicy) -> Y {
+        return self.targets.get_major_class_with(mask, rng, ties);
+    }
+
+    fn impurity(&self, mask: &Mask) -> f64 {
+        return self.targets.impurity(mask);
+    }
+
+    fn pred_incorrect(&self, mask: &Mask, preds: &Predicted<Y>) -> u64 {
+        return self.targets.pred_incorrect(mask, preds);
+    }
+
+    fn pred_error(&self, mask: &Mask, preds: &Predicted<Y>) -> f64 {
+        return self.targets.pred_error(mask, preds);
+    }
+
+    fn pred_balanced_error(&self, mask: &Mask, preds: &Predicted<Y>) -> f64 {
+        return self.targets.pred_balanced_error(mask, preds);
+    }
+
+    fn as_vector(&self) -> Vec<Y> {
+        return self.targets.as_vector();
+    }
+
+    fn as_vector_ref(&self) -> &Vec<Y> {
+        return self.targets.as_vector_ref();
+    }
+
+    fn len(&self) -> usize {
+        return self.targets.len();
+    }
+}
+
+// Forest config with the arguments of RandomForest::fit, so the forest fits as a linfa estimator.
+pub struct ForestParams {
+    rf: RandomForest<Y, Split>,
+    ntree: usize,
+    mtry: usize,
+    max_tree_depth: Option<usize>
+}
+
+impl ForestParams {
+    pub fn new(rf: RandomForest<Y, Split>, ntree: usize, mtry: usize, max_tree_depth: Option<usize>) -> Self {
+        return ForestParams{rf, ntree, mtry, max_tree_depth};
+    }
+}
+
+// Forest fitted through ForestParams. Predicts the class most trees vote for, ties go to false.
+pub struct FittedForest {
+    forest: Forest<Y, Split>,
+    ncol: usize
+}
+
+impl FittedForest {
+    pub fn get_forest(&self) -> &Forest<Y, Split> {
+        return &self.forest;
+    }
+}
+
+impl<D, T> Fit<ArrayBase<D, Ix2>, T, ForestFitError> for ForestParams where
+    D: Data<Elem=i8>,
+    T: AsSingleTargets<Elem=bool>
+{
+    type Object = FittedForest;
+
+    fn fit(&self, dataset: &DatasetBase<ArrayBase<D, Ix2>, T>) -> Result<FittedForest, ForestFitError> {
+        let data = LinfaData::new(dataset)?;
+        let forest = self.rf.fit(&data, &data, self.ntree, self.mtry, false, self.max_tree_depth);
+        return Ok(FittedForest{forest, ncol: dataset.records.ncols()});
+    }
+}
+
+// Predictions come as a Result, records of the wrong shape or with codes other than 0, 1 or 2 give an error.
+impl<D> PredictInplace<ArrayBase<D, Ix2>, Result<Array1<bool>, linfa::Error>> for FittedForest where
+    D: Data<Elem=i8>
+{
+    fn predict_inplace(&self, x: &ArrayBase<D, Ix2>, y: &mut Result<Array1<bool>, linfa::Error>) {
+        if x.ncols() != self.ncol {
+            *y = Err(linfa::Error::MismatchedShapes(self.ncol, x.ncols()));
+            return;
+        }
+        let pred = match y {
+            Ok(pred) if pred.len() == x.nrows() => pred,
+            Ok(pred) => {
+                *y = Err(linfa::Error::MismatchedShapes(x.nrows(), pred.len()));
+                return;
+            },
+            Err(_) => return
+        };
+        let df = match XDfTyped::from_array_view(x.view()) {
+            Ok(df) => df,
+            Err(e) => {
+                *y = Err(linfa::Error::Parameters(format!("records: {}", e)));
+                return;
+            }
+        };
+
+        let votes = self.forest.predict_votes(&df, &Mask::from_sorted((0..x.nrows()).collect()), true, NaPolicy::Majority);
+        for (pred, share) in pred.iter_mut().zip(votes) {
+            *pred = share > 0.5;
+        }
+    }
+
+    fn default_target(&self, x: &ArrayBase<D, Ix2>) -> Result<Array1<bool>, linfa::Error> {
+        return Ok(Array1::from_elem(x.nrows(), false));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linfa::Dataset;
+    use linfa::traits::{Fit, Predict};
+    use ndarray::{Array1, Array2};
+
+    use super::{ForestFitError, ForestParams};
+    use crate::random_forest::RandomForest;
+
+    #[test]
+    fn fits_and_predicts_dataset() {
+        let x = Array2::from_shape_fn((200, 3), |(i, j)| ((i * (j + 1) + i / 7) % 3) as i8);
+        let y: Array1<bool> = x.column(0).iter().map(|&v| v == 1).collect();
+        let dataset = Dataset::new(x.clone(), y.clone());
+
+        let params = ForestParams::new(RandomForest::new(0), 20, 2, None);
+        let fitted = params.fit(&dataset).unwrap();
+        assert_eq!(fitted.get_forest().get_trees().len(), 20);
+        let pred: Result<Array1<bool>, linfa::Error> = fitted.predict(&x);
+        assert_eq!(pred.unwrap(), y);
+    }
+
+    #[test]
+    fn predict_rejects_invalid_records() {
+        let x = Array2::from_shape_fn((40, 2), |(i, j)| ((i + j) % 3) as i8);
+        let y: Array1<bool> = x.column(0).iter().map(|&v| v == 1).collect();
+        let fitted = ForestParams::new(RandomForest::new(0), 5, 1, None).fit(&Dataset::new(x, y)).unwrap();
+
+        let pred: Result<Array1<bool>, linfa::Error> = fitted.predict(&Array2::from_elem((3, 2), 3i8));
+        assert!(matches!(pred, Err(linfa::Error::Parameters(_))));
+        let pred: Result<Array1<bool>, linfa::Error> = fitted.predict(&Array2::from_elem((3, 4), 0i8));
+        assert!(matches!(pred, Err(linfa::Error::MismatchedShapes(2, 4))));
+    }
+
+    #[test]
+    fn fit_rejects_invalid_codes() {
+        let dataset = Dataset::new(Array2::from_elem((4, 2), 3i8), Array1::from_elem(4, true));
+        let res = ForestParams::new(RandomForest::new(0), 5, 1, None).fit(&dataset);
+        assert!(matches!(res, Err(ForestFitError::Data(_))));
+    }
+}
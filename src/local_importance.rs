@@ -0,0 +1,174 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{self, Write};
+#[cfg(feature = "gzip")]
+use std::{fs::File, io::BufWriter, path::Path};
+
+// Per sample importance of each column, values of a column are indexed by sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalImportance<T: Hash + Eq> {
+    values: HashMap<T, Vec<f64>>,
+    nrow: usize,
+    sample_ids: Option<Vec<String>>,
+    // Row of each sample id, for get_by_id.
+    id_rows: HashMap<String, usize>
+}
+
+impl<T: Hash + Eq + Copy> LocalImportance<T> {
+    pub(crate) fn new(values: HashMap<T, Vec<f64>>, nrow: usize, sample_ids: Option<Vec<String>>) -> Self {
+        let id_rows = sample_ids.iter().flatten().enumerate().map(|(row, id)| (id.clone(), row)).collect();
+        return LocalImportance{values, nrow, sample_ids, id_rows};
+    }
+
+    pub fn get(&self, col: &T) -> Option<&[f64]> {
+        return self.values.get(col).map(|x| x.as_slice());
+    }
+
+    pub fn cols(&self) -> Vec<T> {
+        return self.values.keys().copied().collect();
+    }
+
+    pub fn nrow(&self) -> usize {
+        return self.nrow;
+    }
+
+    // Ids of the samples from the data importance was computed on, see XDf::set_sample_ids.
+    pub fn sample_ids(&self) -> Option<&[String]> {
+        return self.sample_ids.as_deref();
+    }
+
+    // Importance of col for the sample with id, None without sample ids or when either is unknown.
+    pub fn get_by_id(&self, col: &T, id: &str) -> Option<f64> {
+        let row = *self.id_rows.get(id)?;
+        return self.get(col).map(|vals| vals[row]);
+    }
+
+    // Samples x columns matrix with a header line. Columns are sorted by name, NaN is written as NA.
+    // Samples are named by sample_name, see to_tsv_by_id to name them by sample id.
+    pub fn to_tsv<W, F, G>(&self, w: &mut W, col_name: F, sample_name: G) -> io::Result<()>
+    where
+        W: Write,
+        F: Fn(&T) -> String,
+        G: Fn(usize) -> String
+    {
+        let mut cols: Vec<(String, &Vec<f64>)> = self.values.iter().map(|(col, vals)| (col_name(col), vals)).collect();
+        cols.sort_by(|a, b| a.0.cmp(&b.0));
+
+        write!(w, "sample")?;
+        for (name, _) in cols.iter() {
+            write!(w, "\t{}", tsv_field(name))?;
+        }
+        writeln!(w)?;
+        for i in 0..self.nrow {
+            write!(w, "{}", tsv_field(&sample_name(i)))?;
+            for (_, vals) in cols.iter() {
+                match vals[i].is_nan() {
+                    true => write!(w, "\tNA")?,
+                    false => write!(w, "\t{}", vals[i])?
+                }
+            }
+            writeln!(w)?;
+        }
+        return Ok(());
+    }
+
+    // to_tsv with samples named by their ids, or by row index without sample ids.
+    pub fn to_tsv_by_id<W, F>(&self, w: &mut W, col_name: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Fn(&T) -> String
+    {
+        return match &self.sample_ids {
+            Some(ids) => self.to_tsv(w, col_name, |i| ids[i].clone()),
+            None => self.to_tsv(w, col_name, |i| i.to_string())
+        };
+    }
+
+    // to_tsv into a gzip compressed file, e.g. local_importance.tsv.gz.
+    #[cfg(feature = "gzip")]
+    pub fn write_tsv_gz<P, F, G>(&self, path: P, col_name: F, sample_name: G) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        F: Fn(&T) -> String,
+        G: Fn(usize) -> String
+    {
+        let file = BufWriter::new(File::create(path)?);
+        let mut gz = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        self.to_tsv(&mut gz, col_name, sample_name)?;
+        gz.finish()?.flush()?;
+        return Ok(());
+    }
+}
+
+// Tabs and line breaks would shift cells, so they are replaced with spaces.
+pub(crate) fn tsv_field(s: &str) -> String {
+    return s.replace(['\t', '\n', '\r'], " ");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::local_importance::LocalImportance;
+
+    fn example() -> LocalImportance<usize> {
+        let values = HashMap::from([(0, vec![0.5, f64::NAN, 0.]), (1, vec![1., 0.25, -0.5])]);
+        return LocalImportance::new(values, 3, None);
+    }
+
+    #[test]
+    fn local_to_tsv() {
+        let names = vec!["b", "a\tc"];
+        let mut out = vec![];
+        example().to_tsv(&mut out, |&c| names[c].to_string(), |i| format!("s{}", i)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "sample\ta c\tb\ns0\t1\t0.5\ns1\t0.25\tNA\ns2\t-0.5\t0\n");
+    }
+
+    #[test]
+    fn local_by_sample_id() {
+        assert_eq!(example().get_by_id(&1, "s1"), None);
+        let values = HashMap::from([(0, vec![0.5, f64::NAN, 0.]), (1, vec![1., 0.25, -0.5])]);
+        let local = LocalImportance::new(values, 3, Some(vec!["s0".to_string(), "s1".to_string(), "s2".to_string()]));
+        assert_eq!(local.get_by_id(&1, "s1"), Some(0.25));
+        assert_eq!(local.get_by_id(&1, "s3"), None);
+        assert_eq!(local.get_by_id(&2, "s1"), None);
+
+        let mut out = vec![];
+        local.to_tsv_by_id(&mut out, |c| c.to_string()).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with("sample\t0\t1\ns0\t0.5\t1\ns1\tNA\t0.25\n"));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn local_write_tsv_gz() {
+        use std::io::Read;
+
+        let path = std::env::temp_dir().join(format!("variant_forest_local_{}.tsv.gz", std::process::id()));
+        let local = example();
+        local.write_tsv_gz(&path, |c| c.to_string(), |i| i.to_string()).unwrap();
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(&path).unwrap()).read_to_string(&mut text).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut expected = vec![];
+        local.to_tsv(&mut expected, |c| c.to_string(), |i| i.to_string()).unwrap();
+        assert_eq!(text.as_bytes(), expected.as_slice());
+    }
+}
@@ -0,0 +1,116 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::{self, Write};
+use std::ops::Range;
+
+use crate::data_interface::ErrorMetric;
+
+// Everything needed to reproduce a forest run with the same crate version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunManifest {
+    pub crate_version: &'static str,
+    pub seed: u64,
+    pub salt: u64,
+    pub ntree: usize,
+    pub trees: Range<usize>,
+    pub mtry: usize,
+    pub shadow_vars: bool,
+    pub sample_fraction: f64,
+    pub max_tree_depth: Option<usize>,
+    pub threads: Option<usize>,
+    pub n_perm: usize,
+    pub importance_batch: Option<usize>,
+    pub permutation_cache: bool,
+    pub split_subsample: Option<usize>,
+    // In-bag masks were given by the caller, sample_fraction does not apply.
+    pub custom_masks: bool,
+    // Trees sampled matched sets of rows instead of rows.
+    pub matched_sets: bool,
+    // Trees drew a bootstrap of sample_fraction times the rows with replacement.
+    pub bootstrap: bool,
+    pub error_metric: ErrorMetric,
+    // Built with the f32 feature, so gini and importance were accumulated in single precision.
+    pub f32: bool,
+    pub nrow: usize,
+    pub ncol: usize,
+}
+
+impl RunManifest {
+    pub fn to_json<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "{{\"crate_version\":\"{}\",\"seed\":{},\"salt\":{},\"ntree\":{},\"trees\":[{},{}],\"mtry\":{},\"shadow_vars\":{},\
+                     \"sample_fraction\":{},\"max_tree_depth\":{},\"threads\":{},\"n_perm\":{},\"importance_batch\":{},\
+                     \"permutation_cache\":{},\"split_subsample\":{},\"custom_masks\":{},\"matched_sets\":{},\"bootstrap\":{},\"error_metric\":\"{:?}\",\"f32\":{},\"nrow\":{},\"ncol\":{}}}",
+                 self.crate_version, self.seed, self.salt, self.ntree, self.trees.start, self.trees.end, self.mtry, self.shadow_vars,
+                 self.sample_fraction, json_opt(self.max_tree_depth), json_opt(self.threads), self.n_perm,
+                 json_opt(self.importance_batch), self.permutation_cache, json_opt(self.split_subsample), self.custom_masks, self.matched_sets, self.bootstrap, self.error_metric, self.f32, self.nrow, self.ncol)?;
+        return Ok(());
+    }
+}
+
+fn json_opt(x: Option<usize>) -> String {
+    return x.map_or("null".to_string(), |x| x.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_interface::ErrorMetric;
+    use crate::manifest::RunManifest;
+
+    #[test]
+    fn manifest_to_json() {
+        let manifest = RunManifest {
+            crate_version: env!("CARGO_PKG_VERSION"),
+            seed: 7,
+            salt: 0,
+            ntree: 100,
+            trees: 0..100,
+            mtry: 3,
+            shadow_vars: false,
+            sample_fraction: 0.66,
+            max_tree_depth: None,
+            threads: Some(4),
+            n_perm: 1,
+            importance_batch: None,
+            permutation_cache: false,
+            split_subsample: Some(1000),
+            custom_masks: false,
+            matched_sets: true,
+            bootstrap: false,
+            error_metric: ErrorMetric::BalancedError,
+            f32: false,
+            nrow: 10,
+            ncol: 9
+        };
+        let mut out = vec![];
+        manifest.to_json(&mut out).unwrap();
+        let parsed: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(parsed["crate_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(parsed["error_metric"], "BalancedError");
+        assert_eq!(parsed["seed"], 7);
+        assert_eq!(parsed["salt"], 0);
+        assert_eq!(parsed["trees"], serde_json::json!([0, 100]));
+        assert_eq!(parsed["sample_fraction"], 0.66);
+        assert!(parsed["max_tree_depth"].is_null());
+        assert_eq!(parsed["threads"], 4);
+        assert_eq!(parsed["split_subsample"], 1000);
+        assert_eq!(parsed["custom_masks"], false);
+        assert_eq!(parsed["matched_sets"], true);
+        assert_eq!(parsed["bootstrap"], false);
+        assert_eq!(parsed["f32"], false);
+        assert_eq!(parsed["ncol"], 9);
+    }
+}
@@ -15,16 +15,93 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::collections::HashSet;
+use std::sync::Arc;
+use std::error::Error;
+use std::fmt;
 use crate::random_number_generator::Rng;
 
-#[derive(Eq, PartialEq, Debug, Clone)]
-pub struct Mask(Vec<usize>);
+// Sorted row indices, with the in-bag multiplicity of every row of the data when rows were drawn with
+// replacement (see from_draws). Multiplicities are indexed by row, so masks refilled from any rows of the same
+// draw keep them, e.g. nodes partitioned from the root while growing a tree. They are not serialized, so a
+// deserialized mask is unweighted, as are the results of union, intersection and difference.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "Vec<usize>", into = "Vec<usize>"))]
+pub struct Mask(Vec<usize>, Option<Arc<Vec<u32>>>);
+
+#[cfg(feature = "serde")]
+impl From<Vec<usize>> for Mask {
+    fn from(rows: Vec<usize>) -> Mask {
+        return Mask(rows, None);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Mask> for Vec<usize> {
+    fn from(mask: Mask) -> Vec<usize> {
+        return mask.0;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MaskError {
+    OutOfRange{idx: usize, n: usize},
+    Duplicate(usize),
+}
+
+impl fmt::Display for MaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaskError::OutOfRange{idx, n} => write!(f, "Mask index {} out of range for {} rows", idx, n),
+            MaskError::Duplicate(idx) => write!(f, "Duplicated mask index {}", idx),
+        }
+    }
+}
+
+impl Error for MaskError {}
 
 impl Mask {
     pub fn new(mask_val: Vec<usize>) -> Mask {
         let mut x = mask_val.clone();
         x.sort();
-        Mask(x)
+        Mask(x, None)
+    }
+
+    // For indices already in increasing order, e.g. a partition of another mask. Skips the copy and sort of new.
+    pub fn from_sorted(mask_val: Vec<usize>) -> Mask {
+        debug_assert!(mask_val.windows(2).all(|x| x[0] <= x[1]), "Mask indices must be sorted");
+        return Mask(mask_val, None);
+    }
+
+    // Gives the buffer back for reuse.
+    pub fn into_vec(self) -> Vec<usize> {
+        return self.0;
+    }
+
+    // Replaces the indices with sorted mask_val, keeping the allocation.
+    pub fn assign(&mut self, mask_val: &[usize]) {
+        debug_assert!(mask_val.windows(2).all(|x| x[0] <= x[1]), "Mask indices must be sorted");
+        self.0.clear();
+        self.0.extend_from_slice(mask_val);
+    }
+
+    // Checked constructor for user built masks over data with n rows.
+    pub fn try_new(mask_val: Vec<usize>, n: usize) -> Result<Mask, MaskError> {
+        let mask = Mask::new(mask_val);
+        for (i, &idx) in mask.0.iter().enumerate() {
+            if idx >= n {
+                return Err(MaskError::OutOfRange{idx, n});
+            }
+            if i > 0 && mask.0[i-1] == idx {
+                return Err(MaskError::Duplicate(idx));
+            }
+        }
+        return Ok(mask);
+    }
+
+    #[inline]
+    pub fn within_bounds(&self, n: usize) -> bool {
+        return self.0.last().map_or(true, |&idx| idx < n);
     }
 
     #[inline]
@@ -35,6 +112,66 @@ impl Mask {
         return Mask::new(mask_vec);
     }
 
+    // Draws sample_fraction of the matched sets and takes all their rows, so sets are never split
+    // between in-bag and OOB rows. matched_sets holds the set id of each row.
+    pub fn random_matched_mask(matched_sets: &[usize], sample_fraction: f64, rng: &mut Rng) -> Mask {
+        let mut set_ids: Vec<usize> = matched_sets.to_vec();
+        set_ids.sort();
+        set_ids.dedup();
+        let k = (set_ids.len() as f64 * sample_fraction).floor() as usize;
+        let sampled: HashSet<usize> = rng.sample(set_ids.as_slice(), k).into_iter().collect();
+        return Mask((0..matched_sets.len()).filter(|&i| sampled.contains(&matched_sets[i])).collect(), None);
+    }
+
+    // Distinct rows of draws taken with replacement, each with the number of times it was drawn as its
+    // multiplicity. Duplicated in-bag rows then weigh multiply in split statistics without copying them.
+    pub fn from_draws(draws: &[usize]) -> Mask {
+        let mut rows = draws.to_vec();
+        rows.sort();
+        rows.dedup();
+        let mut multiplicity = vec![0; rows.last().map_or(0, |&row| row + 1)];
+        for &row in draws.iter() {
+            multiplicity[row] += 1;
+        }
+        return Mask(rows, Some(Arc::new(multiplicity)));
+    }
+
+    // Bootstrap sample of floor(n * sample_fraction) draws with replacement, see from_draws.
+    pub fn random_bootstrap(n: usize, sample_fraction: f64, rng: &mut Rng) -> Mask {
+        let k = (n as f64 * sample_fraction).floor() as usize;
+        let draws: Vec<usize> = (0..k).map(|_| rng.sample_index(n)).collect();
+        return Mask::from_draws(&draws);
+    }
+
+    // Multiplicity of each row in mask order, None for rows not drawn with replacement.
+    pub fn multiplicity(&self) -> Option<Vec<u32>> {
+        return self.1.as_ref().map(|m| self.0.iter().map(|&row| m[row]).collect());
+    }
+
+    #[inline]
+    pub fn is_weighted(&self) -> bool {
+        return self.1.is_some();
+    }
+
+    // Rows in mask order with their multiplicity, 1 for every row of a mask without one.
+    pub fn iter_weighted(&self) -> impl Iterator<Item=(usize, u32)> + '_ {
+        return self.0.iter().map(move |&row| (row, self.1.as_ref().map_or(1, |m| m[row])));
+    }
+
+    // Number of draws of the rows in mask, its length without multiplicities.
+    pub fn n_draws(&self) -> usize {
+        return match &self.1 {
+            Some(m) => self.0.iter().map(|&row| m[row] as usize).sum(),
+            None => self.0.len()
+        };
+    }
+
+    // The same rows with the multiplicities of other, which must cover them, e.g. a node and the in-bag draw.
+    pub fn with_multiplicity_of(mut self, other: &Mask) -> Mask {
+        self.1 = other.1.clone();
+        return self;
+    }
+
     #[inline]
     pub fn get_mask(&self) -> &Vec<usize> {
         return &self.0;
@@ -57,12 +194,71 @@ impl Mask {
     pub fn len(&self) -> usize {
         return self.0.len();
     }
+
+    pub fn from_bools(x: &[bool]) -> Mask {
+        return Mask(x.iter().enumerate().filter(|(_, &b)| b).map(|(i, _)| i).collect(), None);
+    }
+
+    // Rows in either mask, unweighted: multiplicities of either are dropped.
+    pub fn union(&self, other: &Mask) -> Mask {
+        let mut res = Vec::with_capacity(self.len() + other.len());
+        let (mut i, mut j) = (0, 0);
+        while i < self.len() || j < other.len() {
+            let next = match (self.0.get(i), other.0.get(j)) {
+                (Some(&a), Some(&b)) if a < b => { i += 1; a },
+                (Some(&a), Some(&b)) if a > b => { j += 1; b },
+                (Some(&a), Some(_)) => { i += 1; j += 1; a },
+                (Some(&a), None) => { i += 1; a },
+                (None, Some(&b)) => { j += 1; b },
+                (None, None) => unreachable!()
+            };
+            if res.last() != Some(&next) {
+                res.push(next);
+            }
+        }
+        return Mask(res, None);
+    }
+
+    // Rows in both masks, unweighted.
+    pub fn intersection(&self, other: &Mask) -> Mask {
+        let mut res = vec![];
+        let (mut i, mut j) = (0, 0);
+        while i < self.len() && j < other.len() {
+            if self.0[i] < other.0[j] {
+                i += 1;
+            } else if self.0[i] > other.0[j] {
+                j += 1;
+            } else {
+                if res.last() != Some(&self.0[i]) {
+                    res.push(self.0[i]);
+                }
+                i += 1;
+                j += 1;
+            }
+        }
+        return Mask(res, None);
+    }
+
+    // Indices of self which are not in other, unweighted.
+    pub fn difference(&self, other: &Mask) -> Mask {
+        let mut res = vec![];
+        let mut j = 0;
+        for &a in self.0.iter() {
+            while j < other.len() && other.0[j] < a {
+                j += 1;
+            }
+            if (j >= other.len() || other.0[j] != a) && res.last() != Some(&a) {
+                res.push(a);
+            }
+        }
+        return Mask(res, None);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use float_cmp::assert_approx_eq;
-    use crate::mask::Mask;
+    use crate::mask::{Mask, MaskError};
     use crate::random_number_generator::Rng;
 
     #[test]
@@ -73,6 +269,47 @@ mod tests {
         assert_eq!(res, vec![0, 2, 4]);
     }
 
+    #[test]
+    fn try_new_mask() {
+        assert_eq!(Mask::try_new(vec![3, 0, 2], 4), Ok(Mask::new(vec![0, 2, 3])));
+        assert_eq!(Mask::try_new(vec![3, 0, 4], 4), Err(MaskError::OutOfRange{idx: 4, n: 4}));
+        assert_eq!(Mask::try_new(vec![1, 0, 1], 4), Err(MaskError::Duplicate(1)));
+        assert!(Mask::new(vec![0, 3]).within_bounds(4));
+        assert!(!Mask::new(vec![0, 4]).within_bounds(4));
+    }
+
+    #[test]
+    fn random_matched_mask_keeps_sets() {
+        let sets = [0, 0, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6];
+        let mut rng = Rng::new(3, 1);
+        for _ in 0..20 {
+            let mask = Mask::random_matched_mask(&sets, 0.66, &mut rng);
+            let inbag: Vec<usize> = mask.get_mask().iter().map(|&i| sets[i]).collect();
+            assert!((0..sets.len()).all(|i| inbag.contains(&sets[i]) == mask.get_mask().contains(&i)));
+            let mut inbag_sets = inbag.clone();
+            inbag_sets.dedup();
+            assert_eq!(inbag_sets.len(), 4);
+        }
+    }
+
+    #[test]
+    fn from_bools_mask() {
+        let mask = Mask::from_bools(&[true, false, false, true, true]);
+        assert_eq!(mask, Mask::new(vec![0, 3, 4]));
+    }
+
+    #[test]
+    fn set_algebra_mask() {
+        let a = Mask::new(vec![5, 1, 3, 7]);
+        let b = Mask::new(vec![3, 4, 5, 5, 9]);
+        assert_eq!(a.union(&b), Mask::new(vec![1, 3, 4, 5, 7, 9]));
+        assert_eq!(a.intersection(&b), Mask::new(vec![3, 5]));
+        assert_eq!(a.difference(&b), Mask::new(vec![1, 7]));
+        assert_eq!(b.difference(&a), Mask::new(vec![4, 9]));
+        assert_eq!(a.union(&Mask::new(vec![])), a);
+        assert_eq!(a.intersection(&Mask::new(vec![])), Mask::new(vec![]));
+    }
+
     #[test]
     fn get_random_mask() {
         const N: usize = 100000;
@@ -91,4 +328,51 @@ mod tests {
                 assert_approx_eq!(f64, p, FRAC, epsilon=0.01)
             });
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn from_draws_counts_duplicates() {
+        let mask = Mask::from_draws(&[4, 1, 4, 0, 4, 1]);
+        assert_eq!(mask.get_mask(), &vec![0, 1, 4]);
+        assert_eq!(mask.multiplicity(), Some(vec![1, 2, 3]));
+        assert_eq!(mask.iter_weighted().collect::<Vec<_>>(), vec![(0, 1), (1, 2), (4, 3)]);
+        assert_eq!(mask.n_draws(), 6);
+
+        // Nodes refilled from the rows of the draw keep their multiplicity
+        let mut node = Mask::default().with_multiplicity_of(&mask);
+        node.assign(&[1, 4]);
+        assert_eq!(node.multiplicity(), Some(vec![2, 3]));
+        assert_eq!(Mask::new(vec![1, 4]).multiplicity(), None);
+        assert_eq!(Mask::new(vec![1, 4]).iter_weighted().collect::<Vec<_>>(), vec![(1, 1), (4, 1)]);
+    }
+
+    #[test]
+    fn random_bootstrap_keeps_draw_count() {
+        let mask = Mask::random_bootstrap(20, 0.5, &mut Rng::new(5, 1));
+        let counts = mask.multiplicity().unwrap();
+        assert_eq!(mask.get_mask().len(), counts.len());
+        assert_eq!(counts.iter().sum::<u32>(), 10);
+        assert!(mask.within_bounds(20));
+    }
+
+    #[test]
+    fn set_algebra_drops_multiplicity() {
+        let mask = Mask::from_draws(&[4, 1, 4, 0, 4, 1]);
+        let other = Mask::new(vec![1, 2]);
+        for res in [mask.union(&other), mask.intersection(&other), mask.difference(&other), other.union(&mask)] {
+            assert!(!res.is_weighted());
+            assert_eq!(res.n_draws(), res.len());
+        }
+        assert_eq!(mask.union(&mask), Mask::new(vec![0, 1, 4]));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_drops_multiplicity() {
+        let mask = Mask::from_draws(&[4, 1, 4]);
+        let json = serde_json::to_string(&mask).unwrap();
+        assert_eq!(json, "[1,4]");
+        let back: Mask = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, Mask::new(vec![1, 4]));
+        assert!(!back.is_weighted());
+    }
+}
@@ -0,0 +1,240 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::data_interface::Response;
+use crate::data_interface::multi_x::{ColSplitIndex, XDf};
+use crate::data_interface::y_bool::{Y, YBool};
+use crate::mask::Mask;
+use crate::random_forest::RandomForest;
+use crate::random_number_generator::Rng;
+use crate::random_number_generator::factory::RngFactory;
+use crate::tree::NaPolicy;
+
+// Stratified split into (train, test) masks, keeping floor(fraction * class size) samples of each class in train.
+pub fn train_test_split<U>(y: &U, fraction: f64, seed: u64) -> (Mask, Mask)
+where
+    U: Response<Y>
+{
+    if !(0. ..=1.).contains(&fraction) {
+        panic!("Fraction must be within [0, 1].");
+    }
+
+    let mut rng = Rng::new(seed, 1);
+    let mut train = vec![];
+    let mut test = vec![];
+
+    for class in [false, true] {
+        let mut idxs: Vec<usize> = y.as_vector_ref().iter().enumerate()
+            .filter(|(_, &x)| x == class)
+            .map(|(i, _)| i)
+            .collect();
+        rng.shuffle(&mut idxs);
+
+        let k = (idxs.len() as f64 * fraction).floor() as usize;
+        train.extend_from_slice(&idxs[..k]);
+        test.extend_from_slice(&idxs[k..]);
+    }
+
+    return (Mask::new(train), Mask::new(test));
+}
+
+// Stratified k-fold split, test mask of each fold. Samples of each class are shuffled and dealt to folds in turn.
+pub fn stratified_folds<U>(y: &U, k: usize, rng: &mut Rng) -> Vec<Mask>
+where
+    U: Response<Y>
+{
+    if k < 2 {
+        panic!("Number of folds must be at least 2.");
+    }
+
+    let mut folds = vec![vec![]; k];
+    let mut next = 0;
+    for class in [false, true] {
+        let mut idxs: Vec<usize> = y.as_vector_ref().iter().enumerate()
+            .filter(|(_, &x)| x == class)
+            .map(|(i, _)| i)
+            .collect();
+        rng.shuffle(&mut idxs);
+        for i in idxs {
+            folds[next].push(i);
+            next = (next + 1) % k;
+        }
+    }
+    return folds.into_iter().map(Mask::new).collect();
+}
+
+// Point of the hyperparameter grid of nested_cv.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TuneParams {
+    pub mtry: usize,
+    pub max_tree_depth: Option<usize>,
+    pub min_node_size: usize
+}
+
+// Parameters chosen by the inner folds of an outer fold, their inner error and the error on the outer test fold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OuterFold {
+    pub params: TuneParams,
+    pub inner_error: f64,
+    pub test_error: f64
+}
+
+// params are chosen by inner CV on all samples, error is the mean outer test error of the tuning procedure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NestedCvRes {
+    pub params: TuneParams,
+    pub error: f64,
+    pub folds: Vec<OuterFold>
+}
+
+// Nested cross-validation of the grid. Each outer training set picks the grid point with the lowest inner_k-fold
+// validation error, which is then scored on its outer test fold, so the error estimate is not biased by tuning.
+// make_rf builds a configured forest from a seed. Folds and seeds come from RngFactory streams of seed: stream 0
+// splits outer folds, stream f + 1 serves outer fold f and stream outer_k + 1 the final choice on all samples.
+// Grid points share forest seeds within a stream, so they are compared on the same bootstrap draws.
+pub fn nested_cv<F>(df: &XDf, y: &YBool, grid: &[TuneParams], ntree: usize, outer_k: usize, inner_k: usize, seed: u64, make_rf: F) -> NestedCvRes
+where
+    F: Fn(u64) -> RandomForest<Y, ColSplitIndex>
+{
+    if grid.is_empty() {
+        panic!("Grid must hold at least one point.");
+    }
+
+    let rng_factory = RngFactory::new(seed, None, None);
+    let y_vec = y.as_vector_ref();
+    let all = Mask::new((0..y_vec.len()).collect());
+    let outer = stratified_folds(y, outer_k, &mut rng_factory.new_rng_iteration(0));
+
+    let folds = outer.iter().enumerate().map(|(f, test)| {
+        let train = test.inverse(all.get_mask());
+        let mut rng = rng_factory.new_rng_iteration(f + 1);
+        let (params, inner_error) = select_params(df, y_vec, &train, grid, ntree, inner_k, &mut rng, &make_rf);
+        let test_error = holdout_error(df, y_vec, &train, test, &params, ntree, rng.next_u64(), &make_rf);
+        OuterFold{params, inner_error, test_error}
+    }).collect::<Vec<OuterFold>>();
+
+    let error = folds.iter().map(|fold| fold.test_error).sum::<f64>() / folds.len() as f64;
+    let (params, _) = select_params(df, y_vec, &all, grid, ntree, inner_k, &mut rng_factory.new_rng_iteration(outer_k + 1), &make_rf);
+    return NestedCvRes{params, error, folds};
+}
+
+// Grid point with the lowest mean validation error over k folds of rows, the first one among ties.
+fn select_params<F>(df: &XDf, y: &[bool], rows: &Mask, grid: &[TuneParams], ntree: usize, k: usize, rng: &mut Rng, make_rf: &F) -> (TuneParams, f64)
+where
+    F: Fn(u64) -> RandomForest<Y, ColSplitIndex>
+{
+    let folds: Vec<Mask> = stratified_folds(&YBool::new(&rows.get_by_mask(y)), k, rng).iter()
+        .map(|fold| Mask::new(fold.get_by_mask(rows.get_mask())))
+        .collect();
+    let seed = rng.next_u64();
+
+    let mut errors = vec![0.; grid.len()];
+    for val in folds.iter() {
+        let train = val.inverse(rows.get_mask());
+        for (params, error) in grid.iter().zip(errors.iter_mut()) {
+            *error += holdout_error(df, y, &train, val, params, ntree, seed, make_rf) / k as f64;
+        }
+    }
+
+    let best = (0..grid.len()).fold(0, |best, i| match errors[i] < errors[best] {
+        true => i,
+        false => best
+    });
+    return (grid[best], errors[best]);
+}
+
+// Misclassification rate on the test rows of a forest grown on the train rows.
+fn holdout_error<F>(df: &XDf, y: &[bool], train: &Mask, test: &Mask, params: &TuneParams, ntree: usize, seed: u64, make_rf: &F) -> f64
+where
+    F: Fn(u64) -> RandomForest<Y, ColSplitIndex>
+{
+    let mut rf = make_rf(seed);
+    rf.set_min_node_size(params.min_node_size);
+    let forest = rf.fit(&df.subset_rows(train), &YBool::new(&train.get_by_mask(y)), ntree, params.mtry, false, params.max_tree_depth);
+    let votes = forest.predict_votes(df, test, true, NaPolicy::Majority);
+    let wrong = votes.iter().zip(test.get_mask().iter()).filter(|&(&v, &i)| (v > 0.5) != y[i]).count();
+    return wrong as f64 / test.len() as f64;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_interface::multi_x::{ColSplitIndex, MultiX, XDf};
+    use crate::data_interface::three_val::ThreeValCol;
+    use crate::data_interface::y_bool::{Y, YBool};
+    use crate::model_selection::{nested_cv, stratified_folds, train_test_split, TuneParams};
+    use crate::random_forest::RandomForest;
+    use crate::random_number_generator::Rng;
+
+    #[test]
+    fn train_test_split_is_stratified() {
+        let y_vec: Vec<bool> = (0..100).map(|i| i % 5 == 0).collect();
+        let y = YBool::new(&y_vec);
+        let (train, test) = train_test_split(&y, 0.7, 1);
+
+        assert_eq!(train.len(), 70);
+        assert_eq!(test.len(), 30);
+        assert_eq!(train.get_by_mask(&y_vec).iter().filter(|&&x| x).count(), 14);
+        assert_eq!(test.get_by_mask(&y_vec).iter().filter(|&&x| x).count(), 6);
+
+        let mut all = train.get_mask().clone();
+        all.extend(test.get_mask());
+        all.sort();
+        assert_eq!(all, (0..100).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn train_test_split_is_reproducible() {
+        let y = YBool::new(&(0..50).map(|i| i % 3 == 0).collect::<Vec<bool>>());
+        assert_eq!(train_test_split(&y, 0.5, 7), train_test_split(&y, 0.5, 7));
+        assert_ne!(train_test_split(&y, 0.5, 7).0, train_test_split(&y, 0.5, 8).0);
+    }
+
+    #[test]
+    fn stratified_folds_partition_samples() {
+        let y_vec: Vec<bool> = (0..23).map(|i| i % 4 == 0).collect();
+        let folds = stratified_folds(&YBool::new(&y_vec), 3, &mut Rng::new(1, 1));
+
+        assert_eq!(folds.len(), 3);
+        for fold in folds.iter() {
+            assert!(fold.len() == 7 || fold.len() == 8);
+            assert_eq!(fold.get_by_mask(&y_vec).iter().filter(|&&x| x).count(), 2);
+        }
+        let mut all: Vec<usize> = folds.iter().flat_map(|fold| fold.get_mask().clone()).collect();
+        all.sort();
+        assert_eq!(all, (0..23).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn nested_cv_picks_from_grid() {
+        let x1: Vec<i8> = (0..90).map(|i| (i % 3) as i8).collect();
+        let x2: Vec<i8> = (0..90).map(|i| (i / 3 % 3) as i8).collect();
+        let df = XDf::new(vec![MultiX::ThreeVal(ThreeValCol::new(&x1)), MultiX::ThreeVal(ThreeValCol::new(&x2))]);
+        // x0 decides y up to every seventh sample
+        let y = YBool::new(&(0..90).map(|i| (x1[i] == 1) != (i % 7 == 0)).collect::<Vec<bool>>());
+        let grid = [
+            TuneParams{mtry: 1, max_tree_depth: Some(1), min_node_size: 1},
+            TuneParams{mtry: 2, max_tree_depth: None, min_node_size: 5},
+        ];
+        let make_rf = |seed| RandomForest::<Y, ColSplitIndex>::new(seed);
+
+        let res = nested_cv(&df, &y, &grid, 10, 3, 2, 5, make_rf);
+        assert_eq!(res.folds.len(), 3);
+        assert!(grid.contains(&res.params));
+        assert!(res.folds.iter().all(|fold| grid.contains(&fold.params) && fold.inner_error <= 0.5));
+        assert!(res.error > 0. && res.error < 0.3);
+        assert_eq!(nested_cv(&df, &y, &grid, 10, 3, 2, 5, make_rf), res);
+    }
+}
@@ -0,0 +1,264 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+// Importance of a forest computed in parts, e.g. on several machines. Each tree draws its random streams from
+// its index, so any range of trees gives the trees of the whole forest. split_trees plans the ranges,
+// RandomForest::partial_importance computes one, write_tsv and read_tsv carry it between processes and
+// PartialImportance::merge checks the parts belong to one forest and cover it before they are joined.
+// With the distributed feature, distributed::drive and run_worker do the same over TCP.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use crate::random_forest::{mda_means, Importance, ImportancePerTree, WeightedStats};
+
+// Permutation importance of each tree of trees out of a forest of ntree trees, grown with seed and salt.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "T: serde::Serialize + Hash + Eq",
+    deserialize = "T: serde::Deserialize<'de> + Hash + Eq"
+)))]
+pub struct PartialImportance<T: Hash + Eq> {
+    pub ntree: usize,
+    pub seed: u64,
+    pub salt: u64,
+    pub n_perm: usize,
+    pub trees: Range<usize>,
+    // OOB size, weight and summed error increase per column of each tree in trees, in tree order.
+    pub per_tree: Vec<PartialTree<T>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "T: serde::Serialize + Hash + Eq",
+    deserialize = "T: serde::Deserialize<'de> + Hash + Eq"
+)))]
+pub struct PartialTree<T: Hash + Eq> {
+    pub oob_n: usize,
+    pub weight: f64,
+    pub importance: HashMap<T, f64>,
+}
+
+#[derive(Debug)]
+pub enum PartialImportanceError {
+    Io(io::Error),
+    // Line number (from 1) and content of a line that could not be read.
+    Format(usize, String),
+    // Parts come from forests differing in size, seed, salt or number of permutations.
+    Mismatch,
+    // First tree computed twice, or the first tree missing.
+    Overlap(usize),
+    Missing(usize),
+    Empty,
+}
+
+impl fmt::Display for PartialImportanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartialImportanceError::Io(e) => write!(f, "Cannot read partial importance: {}", e),
+            PartialImportanceError::Format(line, content) => write!(f, "Malformed partial importance line {}: {}", line, content),
+            PartialImportanceError::Mismatch => write!(f, "Partial importance of different forests"),
+            PartialImportanceError::Overlap(tree) => write!(f, "Tree {} is in more than one part", tree),
+            PartialImportanceError::Missing(tree) => write!(f, "Tree {} is in no part", tree),
+            PartialImportanceError::Empty => write!(f, "No partial importance to merge"),
+        }
+    }
+}
+
+impl Error for PartialImportanceError {}
+
+impl From<io::Error> for PartialImportanceError {
+    fn from(e: io::Error) -> Self {
+        return PartialImportanceError::Io(e);
+    }
+}
+
+// n_parts consecutive ranges covering 0..ntree, sizes differing by at most one. Fewer when ntree < n_parts.
+pub fn split_trees(ntree: usize, n_parts: usize) -> Vec<Range<usize>> {
+    if n_parts == 0 {
+        panic!("Number of parts must be positive.");
+    }
+    let n_parts = n_parts.min(ntree.max(1));
+    return (0..n_parts)
+        .map(|i| (i * ntree / n_parts)..((i + 1) * ntree / n_parts))
+        .collect();
+}
+
+impl<T: Hash + Eq + Copy> PartialImportance<T> {
+    // Joins parts of one forest in any order. They must share ntree, seed, salt and n_perm and cover every tree
+    // exactly once.
+    pub fn merge(mut parts: Vec<PartialImportance<T>>) -> Result<PartialImportance<T>, PartialImportanceError> {
+        parts.sort_by_key(|part| part.trees.start);
+        let mut parts = parts.into_iter();
+        let mut res = parts.next().ok_or(PartialImportanceError::Empty)?;
+        if res.trees.start != 0 {
+            return Err(PartialImportanceError::Missing(0));
+        }
+        for part in parts {
+            if (part.ntree, part.seed, part.salt, part.n_perm) != (res.ntree, res.seed, res.salt, res.n_perm) {
+                return Err(PartialImportanceError::Mismatch);
+            }
+            match part.trees.start.cmp(&res.trees.end) {
+                std::cmp::Ordering::Less => return Err(PartialImportanceError::Overlap(part.trees.start)),
+                std::cmp::Ordering::Greater => return Err(PartialImportanceError::Missing(res.trees.end)),
+                std::cmp::Ordering::Equal => {}
+            }
+            res.trees.end = part.trees.end;
+            res.per_tree.extend(part.per_tree);
+        }
+        if res.trees.end != res.ntree {
+            return Err(PartialImportanceError::Missing(res.trees.end));
+        }
+        return Ok(res);
+    }
+
+    // Importance of the trees in the part, as RandomForest::importance gives for the whole forest. Columns no
+    // tree split on are left out whatever RandomForest::set_include_unused.
+    pub fn importance(&self) -> Importance<T> {
+        let mut stats: HashMap<T, WeightedStats> = HashMap::new();
+        for tree in self.per_tree.iter() {
+            for (&col, &val) in tree.importance.iter() {
+                stats.entry(col).or_default().push(val, tree.weight);
+            }
+        }
+        let oob_total = self.per_tree.iter().map(|tree| tree.oob_n).sum();
+        return mda_means(stats, oob_total, self.per_tree.len(), self.n_perm);
+    }
+
+    // Values of each column over the trees it was split on, see RandomForest::importance_per_tree_range.
+    pub fn per_column(&self) -> ImportancePerTree<T> {
+        let mut res: ImportancePerTree<T> = HashMap::new();
+        for tree in self.per_tree.iter() {
+            for (&col, &val) in tree.importance.iter() {
+                res.entry(col).or_default().push(val);
+            }
+        }
+        return res;
+    }
+
+    // Text form for sending a part between processes. A header line with ntree, seed, salt, n_perm and the
+    // range, then a line per tree with its index, OOB size and weight followed by column and value pairs.
+    // Floats are written in their shortest form that reads back to the same value.
+    pub fn write_tsv<W, F>(&self, w: &mut W, col_name: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Fn(&T) -> String
+    {
+        writeln!(w, "#partial_importance\t{}\t{}\t{}\t{}\t{}\t{}", self.ntree, self.seed, self.salt, self.n_perm, self.trees.start, self.trees.end)?;
+        for (ith_tree, tree) in self.trees.clone().zip(self.per_tree.iter()) {
+            write!(w, "{}\t{}\t{:?}", ith_tree, tree.oob_n, tree.weight)?;
+            for (col, val) in tree.importance.iter() {
+                write!(w, "\t{}\t{:?}", col_name(col), val)?;
+            }
+            writeln!(w)?;
+        }
+        return Ok(());
+    }
+
+    // Reads write_tsv output, parse_col giving the column of each name written by col_name.
+    pub fn read_tsv<R, F>(r: R, parse_col: F) -> Result<PartialImportance<T>, PartialImportanceError>
+    where
+        R: BufRead,
+        F: Fn(&str) -> Option<T>
+    {
+        let mut lines = r.lines();
+        let header = lines.next().ok_or(PartialImportanceError::Empty)??;
+        let fields: Vec<&str> = header.split('\t').collect();
+        let bad_header = || PartialImportanceError::Format(1, header.clone());
+        if fields.len() != 7 || fields[0] != "#partial_importance" {
+            return Err(bad_header());
+        }
+        let num = |i: usize| fields[i].parse::<u64>().map_err(|_| bad_header());
+        let (ntree, seed, salt, n_perm) = (num(1)? as usize, num(2)?, num(3)?, num(4)? as usize);
+        let trees = (num(5)? as usize)..(num(6)? as usize);
+
+        let mut per_tree = Vec::with_capacity(trees.len());
+        for (line_no, line) in (2..).zip(lines) {
+            let line = line?;
+            let bad_line = || PartialImportanceError::Format(line_no, line.clone());
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 3 || fields.len().is_multiple_of(2) || fields[0].parse::<usize>().ok() != Some(trees.start + per_tree.len()) {
+                return Err(bad_line());
+            }
+            let oob_n = fields[1].parse::<usize>().map_err(|_| bad_line())?;
+            let weight = fields[2].parse::<f64>().map_err(|_| bad_line())?;
+            let mut importance = HashMap::new();
+            for pair in fields[3..].chunks(2) {
+                let col = parse_col(pair[0]).ok_or_else(bad_line)?;
+                let val = pair[1].parse::<f64>().map_err(|_| bad_line())?;
+                importance.insert(col, val);
+            }
+            per_tree.push(PartialTree{oob_n, weight, importance});
+        }
+        if per_tree.len() != trees.len() {
+            return Err(PartialImportanceError::Missing(trees.start + per_tree.len()));
+        }
+        return Ok(PartialImportance{ntree, seed, salt, n_perm, trees, per_tree});
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::partial_importance::{split_trees, PartialImportance, PartialImportanceError, PartialTree};
+
+    fn part(trees: std::ops::Range<usize>) -> PartialImportance<usize> {
+        let per_tree = trees.clone()
+            .map(|i| PartialTree{oob_n: 10 + i, weight: 1., importance: HashMap::from([(0, i as f64 * 0.1), (i % 3, -0.5)])})
+            .collect();
+        return PartialImportance{ntree: 10, seed: 4, salt: 7, n_perm: 1, trees, per_tree};
+    }
+
+    #[test]
+    fn split_trees_covers_forest() {
+        assert_eq!(split_trees(10, 3), vec![0..3, 3..6, 6..10]);
+        assert_eq!(split_trees(2, 4), vec![0..1, 1..2]);
+        assert_eq!(split_trees(0, 2), vec![0..0]);
+    }
+
+    #[test]
+    fn tsv_round_trip() {
+        let p = part(3..6);
+        let mut buf = vec![];
+        p.write_tsv(&mut buf, |col| format!("c{}", col)).unwrap();
+        let read = PartialImportance::read_tsv(&buf[..], |name| name.strip_prefix('c')?.parse().ok()).unwrap();
+        assert_eq!(read, p);
+
+        // Unknown column on the first tree line
+        let bad = String::from_utf8(buf).unwrap().replacen("\tc0\t", "\tx0\t", 1);
+        assert!(matches!(PartialImportance::<usize>::read_tsv(bad.as_bytes(), |name| name.strip_prefix('c')?.parse().ok()),
+                         Err(PartialImportanceError::Format(2, _))));
+    }
+
+    #[test]
+    fn merge_checks_coverage() {
+        let merged = PartialImportance::merge(vec![part(4..10), part(0..4)]).unwrap();
+        assert_eq!(merged, part(0..10));
+        assert_eq!(merged.importance(), part(0..10).importance());
+
+        assert!(matches!(PartialImportance::merge(vec![part(0..4), part(3..10)]), Err(PartialImportanceError::Overlap(3))));
+        assert!(matches!(PartialImportance::merge(vec![part(0..4), part(5..10)]), Err(PartialImportanceError::Missing(4))));
+        assert!(matches!(PartialImportance::merge(vec![part(0..4)]), Err(PartialImportanceError::Missing(4))));
+        let mut other = part(4..10);
+        other.seed = 5;
+        assert!(matches!(PartialImportance::merge(vec![part(0..4), other]), Err(PartialImportanceError::Mismatch)));
+    }
+}
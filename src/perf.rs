@@ -0,0 +1,202 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::Duration;
+use crate::clock::Instant;
+
+use crate::data_interface::multi_x::{ColSplitIndex, MultiX, XDf};
+use crate::data_interface::three_val::ThreeValCol;
+use crate::data_interface::y_bool::{Y, YBool};
+use crate::random_forest::{RandomForest, RunStats};
+use crate::random_number_generator::Rng;
+
+// Fixed, so every release runs the same inputs and trees.
+pub const PERF_SEED: u64 = 139547392210478;
+
+// Synthetic genotype-like run. Columns hold 0/1/2 minor allele counts, the response is
+// true when both alleles of columns 0 and 1 are minor, so the rest are noise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub name: String,
+    pub nrow: usize,
+    pub ncol: usize,
+    pub ntree: usize,
+    pub mtry: usize,
+    // As multithread of RandomForest::importance.
+    pub threads: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScenarioError {
+    WrongFieldCount{line: usize, found: usize},
+    InvalidNumber{line: usize, field: String},
+    Empty{line: usize, field: &'static str},
+    // The response depends on the first two columns.
+    TooFewColumns{line: usize},
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::WrongFieldCount{line, found} => write!(f, "Line {}: expected 5 or 6 fields, found {}", line, found),
+            ScenarioError::InvalidNumber{line, field} => write!(f, "Line {}: invalid number {:?}", line, field),
+            ScenarioError::Empty{line, field} => write!(f, "Line {}: {} must be larger than 0", line, field),
+            ScenarioError::TooFewColumns{line} => write!(f, "Line {}: ncol must be at least 2", line),
+        }
+    }
+}
+
+impl Error for ScenarioError {}
+
+impl Scenario {
+    // mtry defaults to the square root of ncol.
+    pub fn new(name: &str, nrow: usize, ncol: usize, ntree: usize, threads: Option<usize>) -> Scenario {
+        if nrow == 0 || ncol < 2 || ntree == 0 {
+            panic!("Scenario must have rows, at least 2 columns and trees.");
+        }
+        let mtry = ((ncol as f64).sqrt() as usize).max(1);
+        return Scenario{name: name.to_string(), nrow, ncol, ntree, mtry, threads};
+    }
+
+    // One scenario per line: name nrow ncol ntree threads [mtry], whitespace separated.
+    // threads is - for the default thread pool. Empty lines and lines starting with # are skipped.
+    pub fn parse_all(config: &str) -> Result<Vec<Scenario>, ScenarioError> {
+        let mut scenarios = vec![];
+        for (i, raw) in config.lines().enumerate() {
+            let line = i + 1;
+            let raw = raw.trim();
+            if raw.is_empty() || raw.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = raw.split_whitespace().collect();
+            if fields.len() != 5 && fields.len() != 6 {
+                return Err(ScenarioError::WrongFieldCount{line, found: fields.len()});
+            }
+            let num = |field: &str, what: &'static str| -> Result<usize, ScenarioError> {
+                let x = field.parse::<usize>().map_err(|_| ScenarioError::InvalidNumber{line, field: field.to_string()})?;
+                return match x {
+                    0 => Err(ScenarioError::Empty{line, field: what}),
+                    x => Ok(x)
+                };
+            };
+
+            let nrow = num(fields[1], "nrow")?;
+            let ncol = num(fields[2], "ncol")?;
+            if ncol < 2 {
+                return Err(ScenarioError::TooFewColumns{line});
+            }
+            let ntree = num(fields[3], "ntree")?;
+            let threads = match fields[4] {
+                "-" => None,
+                x => Some(num(x, "threads")?)
+            };
+
+            let mut scenario = Scenario::new(fields[0], nrow, ncol, ntree, threads);
+            if let Some(mtry) = fields.get(5) {
+                scenario.mtry = num(mtry, "mtry")?;
+            }
+            scenarios.push(scenario);
+        }
+        return Ok(scenarios);
+    }
+
+    pub fn setup(&self) -> (XDf, YBool) {
+        let mut rng = Rng::new(PERF_SEED, 1);
+        let cols: Vec<Vec<i8>> = (0..self.ncol).map(|_| {
+            return (0..self.nrow).map(|_| (rng.rand_uni() * 3.) as i8).collect();
+        }).collect();
+
+        let y: Vec<Y> = cols[0].iter().zip(cols[1].iter()).map(|(&a, &b)| a == 2 && b == 2).collect();
+        let x = XDf::new(cols.iter().map(|col| MultiX::ThreeVal(ThreeValCol::new(col))).collect());
+        return (x, YBool::new(&y));
+    }
+
+    // Builds the data and times a permutation importance run on it.
+    pub fn run(&self) -> PerfResult {
+        let setup_start = Instant::now();
+        let (x, y) = self.setup();
+        let setup = setup_start.elapsed();
+
+        let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(PERF_SEED);
+        rf.set_collect_stats(true);
+        rf.importance(&x, &y, self.ntree, self.mtry, false, None, self.threads);
+        return PerfResult{scenario: self.clone(), setup, stats: rf.get_stats().expect("Stats are collected.")};
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PerfResult {
+    pub scenario: Scenario,
+    pub setup: Duration,
+    pub stats: RunStats,
+}
+
+impl PerfResult {
+    // One JSON object per line, times in seconds, so runs of several releases can be appended to one file.
+    pub fn to_json<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let s = &self.scenario;
+        let threads = s.threads.map_or("null".to_string(), |x| x.to_string());
+        writeln!(w, "{{\"crate_version\":\"{}\",\"scenario\":{:?},\"nrow\":{},\"ncol\":{},\"ntree\":{},\"mtry\":{},\"threads\":{},\
+                     \"setup\":{},\"total\":{},\"split_search\":{},\"prediction\":{},\"permutation\":{},\"aggregation\":{}}}",
+                 env!("CARGO_PKG_VERSION"), s.name, s.nrow, s.ncol, s.ntree, s.mtry, threads,
+                 self.setup.as_secs_f64(), self.stats.total.as_secs_f64(), self.stats.split_search.as_secs_f64(),
+                 self.stats.prediction.as_secs_f64(), self.stats.permutation.as_secs_f64(), self.stats.aggregation.as_secs_f64())?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_interface::{ColumnData, Response};
+    use crate::perf::{Scenario, ScenarioError};
+
+    #[test]
+    fn parse_scenarios() {
+        let config = "# name nrow ncol ntree threads\n\nsmall 100 16 10 -\nwide 200 400 20 4 7\n";
+        let scenarios = Scenario::parse_all(config).unwrap();
+        assert_eq!(scenarios, vec![Scenario::new("small", 100, 16, 10, None),
+                                   Scenario{mtry: 7, ..Scenario::new("wide", 200, 400, 20, Some(4))}]);
+        assert_eq!(scenarios[0].mtry, 4);
+
+        assert_eq!(Scenario::parse_all("a 1 2 3"), Err(ScenarioError::WrongFieldCount{line: 1, found: 4}));
+        assert_eq!(Scenario::parse_all("\na 1 x 3 -"), Err(ScenarioError::InvalidNumber{line: 2, field: "x".to_string()}));
+        assert_eq!(Scenario::parse_all("a 1 2 0 -"), Err(ScenarioError::Empty{line: 1, field: "ntree"}));
+        assert_eq!(Scenario::parse_all("a 1 1 3 -"), Err(ScenarioError::TooFewColumns{line: 1}));
+    }
+
+    #[test]
+    fn run_to_json() {
+        let scenario = Scenario::new("tiny", 60, 8, 4, Some(1));
+        let (x, y1) = scenario.setup();
+        let (_, y2) = scenario.setup();
+        assert_eq!((x.get_nrow(), x.get_ncol()), (60, 8));
+        assert_eq!(y1.as_vector(), y2.as_vector());
+
+        let res = scenario.run();
+        assert_eq!(res.stats.ntree, 4);
+
+        let mut out = vec![];
+        res.to_json(&mut out).unwrap();
+        let parsed: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(parsed["scenario"], "tiny");
+        assert_eq!(parsed["threads"], 1);
+        assert!(parsed["total"].as_f64().unwrap() > 0.);
+    }
+}
@@ -0,0 +1,367 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use crate::data_interface::multi_x::{MultiX, XDf};
+use crate::data_interface::three_val::ThreeValCol;
+
+// Levels of the categorical column numeric values are binned into.
+const MAX_BINS: usize = 3;
+
+// Bins numeric values into k quantile bins. Edges are kept so new data (e.g. for prediction)
+// is binned the same way as the data the binner was fitted on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantileBinner {
+    edges: Vec<f64>
+}
+
+impl QuantileBinner {
+    pub fn fit(x: &[f64], k: usize) -> QuantileBinner {
+        if k == 0 || k > MAX_BINS {
+            panic!("Number of bins must be between 1 and {}.", MAX_BINS);
+        }
+        if x.is_empty() {
+            panic!("Numeric column must not be empty.");
+        }
+        if x.iter().any(|v| v.is_nan()) {
+            panic!("Numeric column must not contain NaN.");
+        }
+
+        let mut sorted = x.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        // Cut at i/k quantiles. Repeated values can make cuts coincide, those are merged so no bin is empty.
+        let mut edges: Vec<f64> = (1..k).map(|i| sorted[i*sorted.len()/k]).collect();
+        edges.dedup();
+        edges.retain(|&e| e > sorted[0]);
+        return QuantileBinner{edges};
+    }
+
+    pub fn from_edges(edges: Vec<f64>) -> QuantileBinner {
+        if edges.len() >= MAX_BINS {
+            panic!("Number of bins must be between 1 and {}.", MAX_BINS);
+        }
+        if edges.iter().any(|e| e.is_nan()) || edges.windows(2).any(|w| w[0] >= w[1]) {
+            panic!("Bin edges must be strictly increasing.");
+        }
+        return QuantileBinner{edges};
+    }
+
+    // Value v falls into bin i if edges[i-1] <= v < edges[i].
+    pub fn get_edges(&self) -> &[f64] {
+        return &self.edges;
+    }
+
+    pub fn nbins(&self) -> usize {
+        return self.edges.len() + 1;
+    }
+
+    pub fn transform(&self, x: &[f64]) -> Vec<i8> {
+        return x.iter().map(|&v| {
+            if v.is_nan() {
+                panic!("Numeric column must not contain NaN.");
+            }
+            self.edges.iter().take_while(|&&e| v >= e).count() as i8
+        }).collect();
+    }
+
+    pub fn transform_col(&self, x: &[f64]) -> MultiX {
+        return MultiX::ThreeVal(ThreeValCol::new(&self.transform(x)));
+    }
+}
+
+// Dummy coding of a categorical column into one 0/1 column per level, so columns with more levels than
+// ThreeValCol holds can still be fit. Levels are kept sorted, so new data is coded into the same columns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OneHotEncoder<K> {
+    levels: Vec<K>
+}
+
+impl<K: Ord + Clone> OneHotEncoder<K> {
+    pub fn fit(x: &[Option<K>]) -> OneHotEncoder<K> {
+        let mut levels: Vec<K> = x.iter().flatten().cloned().collect();
+        levels.sort();
+        levels.dedup();
+        return OneHotEncoder{levels};
+    }
+
+    pub fn get_levels(&self) -> &[K] {
+        return &self.levels;
+    }
+
+    // One column per level, a missing value is missing in every column. Levels not seen by fit are 0 everywhere.
+    pub fn transform(&self, x: &[Option<K>]) -> Vec<Vec<Option<i8>>> {
+        let codes: Vec<Option<Result<usize, usize>>> = x.iter().map(|v| v.as_ref().map(|v| self.levels.binary_search(v))).collect();
+        return (0..self.levels.len()).map(|level| {
+            codes.iter().map(|code| code.map(|code| (code == Ok(level)) as i8)).collect()
+        }).collect();
+    }
+
+    pub fn transform_cols(&self, x: &[Option<K>]) -> Vec<MultiX> {
+        return self.transform(x).iter().map(|col| MultiX::ThreeVal(ThreeValCol::new_with_missing(col))).collect();
+    }
+
+    // Appends the dummy columns of x to df and links each to categorical column source and its level.
+    pub fn append(&self, df: &mut XDf, x: &[Option<K>], source: usize, links: &mut DummyLinks) {
+        for (level, col) in self.transform_cols(x).into_iter().enumerate() {
+            let col_id = df.push_col(col);
+            links.links.insert(col_id, (source, level));
+        }
+    }
+}
+
+// Categorical column and level index (see OneHotEncoder::get_levels) of each dummy column, keyed by column id.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DummyLinks {
+    links: HashMap<usize, (usize, usize)>
+}
+
+impl DummyLinks {
+    pub fn new() -> DummyLinks {
+        return DummyLinks::default();
+    }
+
+    // None for columns that are not dummies.
+    pub fn source_of(&self, col: usize) -> Option<(usize, usize)> {
+        return self.links.get(&col).copied();
+    }
+
+    // Dummy columns of categorical column source, by level.
+    pub fn dummies_of(&self, source: usize) -> Vec<usize> {
+        let mut dummies: Vec<(usize, usize)> = self.links.iter()
+            .filter(|(_, &(src, _))| src == source)
+            .map(|(&col, &(_, level))| (level, col))
+            .collect();
+        dummies.sort();
+        return dummies.into_iter().map(|(_, col)| col).collect();
+    }
+
+    // Folds results of the dummy columns, e.g. importance, into one per categorical column with f, e.g. f64::max.
+    // Columns that are not dummies are left out.
+    pub fn collapse<T, F>(&self, res: &HashMap<usize, T>, f: F) -> HashMap<usize, T>
+    where
+        T: Clone,
+        F: Fn(T, T) -> T
+    {
+        let mut collapsed: HashMap<usize, T> = HashMap::new();
+        let mut cols: Vec<(&usize, &T)> = res.iter().filter(|(col, _)| self.links.contains_key(col)).collect();
+        // Column order, so f sees values in the same order every run
+        cols.sort_by_key(|(&col, _)| col);
+        for (col, val) in cols {
+            let source = self.links[col].0;
+            let val = match collapsed.remove(&source) {
+                Some(acc) => f(acc, val.clone()),
+                None => val.clone()
+            };
+            collapsed.insert(source, val);
+        }
+        return collapsed;
+    }
+}
+
+// Greedy LD clumping of genotype columns (0/1/2 codes as for ThreeValCol::new). Columns are visited by
+// priority, each column not clumped yet becomes the representative of a clump and takes in the columns not
+// clumped yet with r^2 at least r2_th. Fitting on representatives only keeps correlated columns from
+// sharing (and diluting) permutation importance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clumps {
+    // Representative of each column, indexed by column.
+    representative: Vec<usize>
+}
+
+impl Clumps {
+    // Only columns at most window positions apart are compared, columns are expected in genomic order.
+    // None compares all pairs. Lower priority goes first (e.g. screening p-values), None keeps column order.
+    pub fn fit(cols: &[Vec<i8>], r2_th: f64, window: Option<usize>, priority: Option<&[f64]>) -> Clumps {
+        if !(0. ..=1.).contains(&r2_th) {
+            panic!("r2 threshold must be within [0, 1].");
+        }
+        if priority.is_some_and(|p| p.len() != cols.len()) {
+            panic!("Priority must be given for every column.");
+        }
+
+        let mut order: Vec<usize> = (0..cols.len()).collect();
+        if let Some(priority) = priority {
+            order.sort_by(|&a, &b| priority[a].total_cmp(&priority[b]));
+        }
+
+        let mut representative = vec![usize::MAX; cols.len()];
+        for &col in order.iter() {
+            if representative[col] != usize::MAX {
+                continue;
+            }
+            representative[col] = col;
+            let (lo, hi) = match window {
+                Some(w) => (col.saturating_sub(w), (col + w).min(cols.len() - 1)),
+                None => (0, cols.len() - 1)
+            };
+            for other in lo..=hi {
+                if representative[other] == usize::MAX && r2(&cols[col], &cols[other]) >= r2_th {
+                    representative[other] = col;
+                }
+            }
+        }
+        return Clumps{representative};
+    }
+
+    // Columns to fit on, in column order. Subsetting keeps column ids (see Shadowable::subset),
+    // so results on the subset are keyed by original columns.
+    pub fn representatives(&self) -> Vec<usize> {
+        return (0..self.representative.len()).filter(|&col| self.representative[col] == col).collect();
+    }
+
+    pub fn representative_of(&self, col: usize) -> usize {
+        return self.representative[col];
+    }
+
+    // Columns of the clump of representative rep, in column order.
+    pub fn members(&self, rep: usize) -> Vec<usize> {
+        return (0..self.representative.len()).filter(|&col| self.representative[col] == rep).collect();
+    }
+
+    // Gives every column the result of its representative, e.g. importance or a Boruta decision.
+    // Columns with representatives missing from res are left out.
+    pub fn expand<T: Clone>(&self, res: &HashMap<usize, T>) -> HashMap<usize, T> {
+        return (0..self.representative.len())
+            .filter_map(|col| res.get(&self.representative[col]).map(|val| (col, val.clone())))
+            .collect();
+    }
+}
+
+// Squared Pearson correlation, 0 when either column is constant.
+fn r2(x: &[i8], y: &[i8]) -> f64 {
+    let n = x.len() as f64;
+    let (mut sx, mut sy, mut sxx, mut syy, mut sxy) = (0., 0., 0., 0., 0.);
+    for (&a, &b) in x.iter().zip(y.iter()) {
+        let (a, b) = (a as f64, b as f64);
+        sx += a;
+        sy += b;
+        sxx += a * a;
+        syy += b * b;
+        sxy += a * b;
+    }
+    let cov = sxy - sx * sy / n;
+    let var_x = sxx - sx * sx / n;
+    let var_y = syy - sy * sy / n;
+    if var_x <= 0. || var_y <= 0. {
+        return 0.;
+    }
+    return cov * cov / (var_x * var_y);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_interface::multi_x::MultiX;
+    use crate::data_interface::three_val::ThreeValCol;
+    use std::collections::HashMap;
+    use float_cmp::assert_approx_eq;
+    use crate::data_interface::multi_x::XDf;
+    use crate::preprocessing::{r2, Clumps, DummyLinks, OneHotEncoder, QuantileBinner};
+
+    #[test]
+    fn quantile_binner_fit() {
+        let x: Vec<f64> = (0..9).rev().map(|v| v as f64).collect();
+        let binner = QuantileBinner::fit(&x, 3);
+        assert_eq!(binner.get_edges(), &[3., 6.]);
+        assert_eq!(binner.transform(&x), vec![2, 2, 2, 1, 1, 1, 0, 0, 0]);
+        assert_eq!(binner.transform(&[-10., 3.5, 100.]), vec![0, 1, 2]);
+        assert_eq!(binner.transform_col(&[0., 6.]), MultiX::ThreeVal(ThreeValCol::new(&[0, 2])));
+    }
+
+    #[test]
+    fn quantile_binner_ties() {
+        let binner = QuantileBinner::fit(&[1., 1., 1., 1., 2., 5.], 3);
+        assert_eq!(binner.get_edges(), &[2.]);
+        assert_eq!(binner.nbins(), 2);
+
+        let binner = QuantileBinner::fit(&[4., 4., 4.], 3);
+        assert_eq!(binner.nbins(), 1);
+        assert_eq!(binner.transform(&[0., 4., 9.]), vec![0, 0, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Number of bins must be between 1 and 3.")]
+    fn quantile_binner_too_many_bins() {
+        QuantileBinner::fit(&[1., 2., 3., 4.], 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "Numeric column must not be empty.")]
+    fn quantile_binner_empty() {
+        QuantileBinner::fit(&[], 2);
+    }
+
+    #[test]
+    fn one_hot_encoder() {
+        let x = vec![Some("b"), Some("a"), None, Some("c"), Some("a")];
+        let enc = OneHotEncoder::fit(&x);
+        assert_eq!(enc.get_levels(), &["a", "b", "c"]);
+        assert_eq!(enc.transform(&x), vec![
+            vec![Some(0), Some(1), None, Some(0), Some(1)],
+            vec![Some(1), Some(0), None, Some(0), Some(0)],
+            vec![Some(0), Some(0), None, Some(1), Some(0)],
+        ]);
+        assert_eq!(enc.transform(&[Some("z")]), vec![vec![Some(0)], vec![Some(0)], vec![Some(0)]]);
+    }
+
+    #[test]
+    fn one_hot_append_links_dummies() {
+        let mut df = XDf::new(vec![MultiX::ThreeVal(ThreeValCol::new(&[0, 1, 2, 1]))]);
+        let mut links = DummyLinks::new();
+        let x = vec![Some(30), Some(10), Some(20), Some(10)];
+        OneHotEncoder::fit(&x).append(&mut df, &x, 7, &mut links);
+        assert_eq!(df.validate(true), Ok(()));
+
+        assert_eq!(links.dummies_of(7), vec![1, 2, 3]);
+        assert_eq!(links.source_of(2), Some((7, 1)));
+        assert_eq!(links.source_of(0), None);
+
+        let imp = HashMap::from([(0, 0.5), (1, 0.1), (2, 0.3), (3, 0.)]);
+        assert_eq!(links.collapse(&imp, f64::max), HashMap::from([(7, 0.3)]));
+    }
+
+    #[test]
+    fn r2_of_genotypes() {
+        assert_approx_eq!(f64, r2(&[0, 1, 2, 0], &[0, 1, 2, 0]), 1.);
+        assert_approx_eq!(f64, r2(&[0, 1, 2, 0], &[2, 1, 0, 2]), 1.);
+        assert_approx_eq!(f64, r2(&[0, 0, 1, 1], &[0, 1, 0, 1]), 0.);
+        assert_eq!(r2(&[1, 1, 1, 1], &[0, 1, 0, 1]), 0.);
+    }
+
+    #[test]
+    fn clumps_fit() {
+        let a = vec![0, 1, 2, 0, 1, 2];
+        let b = vec![0, 0, 1, 1, 2, 2];
+        let cols = vec![a.clone(), b.clone(), a.clone(), b.clone(), a.clone()];
+
+        let clumps = Clumps::fit(&cols, 0.8, None, None);
+        assert_eq!(clumps.representatives(), vec![0, 1]);
+        assert_eq!(clumps.members(0), vec![0, 2, 4]);
+        assert_eq!(clumps.representative_of(3), 1);
+
+        // Column 4 is out of reach of column 0
+        let clumps = Clumps::fit(&cols, 0.8, Some(2), None);
+        assert_eq!(clumps.representatives(), vec![0, 1, 4]);
+
+        // Lower priority becomes the representative
+        let clumps = Clumps::fit(&cols, 0.8, None, Some(&[0.5, 0.5, 0.1, 0.5, 0.5]));
+        assert_eq!(clumps.representatives(), vec![1, 2]);
+
+        let expanded = clumps.expand(&HashMap::from([(2, "confirmed")]));
+        assert_eq!(expanded, HashMap::from([(0, "confirmed"), (2, "confirmed"), (4, "confirmed")]));
+    }
+}
@@ -15,32 +15,467 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 const SAMPLE_FRACTION: f64 = 0.66;
+const BOOTSTRAP_FRACTION: f64 = 1.0;
 
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::marker::PhantomData;
-use std::hash::Hash;
-use std::thread;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
-use std::sync::mpsc::channel;
+#[cfg(feature = "threads")]
+use std::{thread, collections::VecDeque, sync::Condvar, sync::mpsc::channel};
+#[cfg(feature = "threads")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use crate::clock::Instant;
 
-use crate::data_interface::{DataInterface, Response, ColumnIdentifiable};
+use crate::calibration::{CalibrationMethod, Calibrator};
+use crate::compact::{read_calibrator, read_class, read_f64, read_flag, read_mask, read_string, read_usize, read_vec, write_calibrator, write_class, write_f64, write_flag, write_mask, write_string, write_varint, CompactClass, CompactError, CompactSplit, COMPACT_MAGIC};
+use crate::data_interface::{DataInterface, ErrorMetric, LossMetric, Response, ColumnIdentifiable, PermutationCache, PivotIdentifiable, Predicted, RowView, SplitPenalty, TiePolicy};
+use crate::data_interface::y_bool::YBool;
+use crate::data_interface::y_multi_bool::{MultiY, YMultiBool};
+use crate::manifest::RunManifest;
 use crate::mask::Mask;
 use crate::random_number_generator::factory::RngFactory;
-use crate::random_number_generator::Rng;
-use crate::tree::{ImportanceTree, Tree};
+use crate::random_number_generator::{Rng, DEFAULT_SALT};
+use crate::stable_hash::StableHasher;
+use crate::gini::Float;
+#[cfg(feature = "threads")]
+use crate::tree::ImportanceBase;
+use crate::tree::{ApplyCache, ImportanceTree, ImpurityTree, LocalImportanceTree, NaPolicy, SplitWeight, Tree, TreeShape, TreeTiming};
+use crate::local_importance::LocalImportance;
+use crate::partial_importance::{PartialImportance, PartialTree};
+use tracing::{debug_span, info_span, warn};
+#[cfg(feature = "threads")]
+use tracing::Span;
 
 pub type Importance<T> = HashMap<T, f64>;
+pub type ImportancePerTree<T> = HashMap<T, Vec<f64>>;
+// Importance of each (column, pivot) pair, see RandomForest::pivot_importance.
+pub type PivotImportance<T, P> = HashMap<(T, P), f64>;
 
-pub struct RandomForest<Y, SplitIndex> {
+// Timings of the last forest run. Phase times are summed over threads, total is wall clock time.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RunStats {
+    pub ntree: usize,
+    pub total: Duration,
+    pub split_search: Duration,
+    pub prediction: Duration,
+    pub permutation: Duration,
+    pub aggregation: Duration,
+}
+
+impl RunStats {
+    fn add_tree(&mut self, timing: TreeTiming) {
+        self.ntree += 1;
+        self.split_search += timing.split_search;
+        self.prediction += timing.prediction;
+        self.permutation += timing.permutation;
+    }
+}
+
+// Depths and leaf counts trees of the last run reached, e.g. to check how often max_tree_depth stopped them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthStats {
+    pub min_depth: usize,
+    pub median_depth: f64,
+    pub max_depth: usize,
+    pub min_leaves: usize,
+    pub median_leaves: f64,
+    pub max_leaves: usize,
+}
+
+impl DepthStats {
+    fn from_shapes(shapes: &[TreeShape]) -> Option<DepthStats> {
+        if shapes.is_empty() {
+            return None;
+        }
+        let mut depths: Vec<usize> = shapes.iter().map(|x| x.depth).collect();
+        let mut leaves: Vec<usize> = shapes.iter().map(|x| x.n_leaves).collect();
+        depths.sort();
+        leaves.sort();
+        return Some(DepthStats{
+            min_depth: depths[0],
+            median_depth: sorted_median(&depths),
+            max_depth: depths[depths.len()-1],
+            min_leaves: leaves[0],
+            median_leaves: sorted_median(&leaves),
+            max_leaves: leaves[leaves.len()-1]
+        });
+    }
+}
+
+// Trees of the last run when it stopped at the time budget of RandomForest::set_time_budget.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Truncation {
+    pub trees_done: usize,
+    pub trees_planned: usize,
+}
+
+// f of each tree of trees, in tree order. With the threads feature and several threads given, threads take the
+// next tree in turn.
+fn map_trees<R, F>(trees: Range<usize>, multithread: Option<usize>, f: F) -> Vec<R>
+where
+    R: Send,
+    F: Fn(usize) -> R + Sync
+{
+    #[cfg(feature = "threads")]
+    if let Some(thrs) = multithread.filter(|&thrs| thrs > 1) {
+        let next = AtomicUsize::new(trees.start);
+        let mut done: Vec<(usize, R)> = thread::scope(|s| {
+            let handles: Vec<_> = (0..thrs).map(|_| s.spawn(|| {
+                let mut done = vec![];
+                loop {
+                    let ith_tree = next.fetch_add(1, Ordering::Relaxed);
+                    if ith_tree >= trees.end {
+                        break;
+                    }
+                    done.push((ith_tree, f(ith_tree)));
+                }
+                done
+            })).collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        });
+        done.sort_by_key(|(ith_tree, _)| *ith_tree);
+        return done.into_iter().map(|(_, res)| res).collect();
+    }
+    #[cfg(not(feature = "threads"))]
+    let _ = multithread;
+    return trees.map(f).collect();
+}
+
+fn out_of_time(deadline: Option<Instant>) -> bool {
+    return deadline.is_some_and(|deadline| Instant::now() >= deadline);
+}
+
+fn sorted_median(x: &[usize]) -> f64 {
+    let mid = x.len() / 2;
+    return match x.len() % 2 {
+        0 => (x[mid-1] + x[mid]) as f64 / 2.,
+        _ => x[mid] as f64
+    };
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TwoWayImportance<T: Hash + Eq> {
+    pub mda: Importance<T>,
+    pub mdi: Importance<T>
+}
+
+// Permutation importance and its z-score from a single forest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoredImportance<T: Hash + Eq> {
+    pub importance: Importance<T>,
+    pub zscore: Importance<T>,
+    // OOB error of the forest the importance comes from, with RandomForest::set_keep_oob_votes.
+    pub oob_error: Option<f64>
+}
+
+// Z-score of a column with the number of trees it was estimated from. unstable is set when the score is not a
+// usable estimate: fewer than two trees, zero variance across trees (score is then +-inf or NaN), or no tree
+// splitting on the column at all (score 0, with RandomForest::set_include_unused).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZScore {
+    pub score: f64,
+    pub n_trees: usize,
+    pub unstable: bool
+}
+
+// Forest importance as ScoredImportance, and the importance per OOB sample of each tree in tree order.
+// Columns a tree does not split on have no entry in it, their importance is 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportanceByTree<T: Hash + Eq> {
+    pub importance: Importance<T>,
+    pub zscore: Importance<T>,
+    pub trees: Vec<Importance<T>>
+}
+
+// Permutation importance of a forest with shadow_vars, real and shadow columns apart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowImportance<T: Hash + Eq> {
+    pub real: Importance<T>,
+    pub shadow: Importance<T>,
+    // Highest shadow importance within each tree, in tree order. Trees not splitting on shadows give 0.
+    pub shadow_max: Vec<f64>
+}
+
+// Rows a tree was grown on and the out-of-bag rows its importance was computed on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeMasks {
+    pub inbag: Mask,
+    pub oob: Mask
+}
+
+// Breiman's (2001) margin, strength and correlation estimated from OOB votes, see RandomForest::diagnostics.
+// A row's margin is its share of votes for the true class less the largest share for another class, None for
+// rows in-bag of every tree. Strength is the mean margin and correlation the mean correlation between trees'
+// raw margins. error_bound is Breiman's bound correlation * (1 - strength^2) / strength^2 on the generalization
+// error, infinite for a non-positive strength.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForestDiagnostics {
+    pub margins: Vec<Option<f64>>,
+    pub strength: f64,
+    pub correlation: f64,
+    pub error_bound: f64
+}
+
+// Trees fit has grown so far, handed to the callback of RandomForest::set_snapshot. Saved (e.g. serialized with
+// the serde feature) it lets RandomForest::fit_resume finish a fit that was cut short. Trees are grown from their
+// own random streams, so the resumed forest is the one an uninterrupted fit grows.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "Y: serde::Serialize, SplitIndex: serde::Serialize, SplitIndex::Col: serde::Serialize",
+    deserialize = "Y: serde::Deserialize<'de>, SplitIndex: serde::Deserialize<'de>, SplitIndex::Col: serde::Deserialize<'de>"
+)))]
+pub struct ForestSnapshot<Y, SplitIndex> where
+    SplitIndex: ColumnIdentifiable
+{
+    seed: u64,
+    salt: u64,
+    ntree: usize,
+    nrow: usize,
+    ncol: usize,
+    forest: Forest<Y, SplitIndex>
+}
+
+impl<Y, SplitIndex> ForestSnapshot<Y, SplitIndex> where
+    SplitIndex: ColumnIdentifiable
+{
+    // Number of trees grown, out of ntree.
+    pub fn n_done(&self) -> usize {
+        return self.forest.trees.len();
+    }
+
+    pub fn get_ntree(&self) -> usize {
+        return self.ntree;
+    }
+}
+
+type SnapshotCallback<Y, SplitIndex> = Box<dyn Fn(&ForestSnapshot<Y, SplitIndex>) + Send + Sync>;
+
+// Out-of-bag rows of a tree grown by fit and the class the tree votes for each of them, in mask order.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TreeOobVotes<Y> {
+    pub oob: Mask,
+    pub votes: Predicted<Y>
+}
+
+// Importance measures computed by a forest run.
+#[derive(Clone, Copy)]
+struct Measures {
+    mda: bool,
+    mdi: bool,
+    local: bool,
+    // Keep MDA of every tree instead of only its running mean and variance.
+    per_tree: bool
+}
+
+// Tree of RandomForest::run_trees_queued with its in-bag and OOB masks, shared by its column jobs.
+#[cfg(feature = "threads")]
+type QueuedTree<Y, SplitIndex> = Arc<(Mask, Mask, Tree<Y, SplitIndex>, ImportanceBase<Y, <SplitIndex as ColumnIdentifiable>::Col>)>;
+
+// Job of the queue of RandomForest::run_trees_queued, growing a tree or permuting a column of a grown one.
+#[cfg(feature = "threads")]
+enum QueueJob<Y, SplitIndex: ColumnIdentifiable> {
+    Grow(usize),
+    Column(usize, SplitIndex::Col, QueuedTree<Y, SplitIndex>)
+}
+
+// Next tree to grow, trees being grown and column jobs waiting, by tree index.
+#[cfg(feature = "threads")]
+struct TreeQueue<Y, SplitIndex: ColumnIdentifiable> {
+    next_tree: usize,
+    growing: usize,
+    columns: VecDeque<(usize, SplitIndex::Col, QueuedTree<Y, SplitIndex>)>
+}
+
+// Grown tree with columns still being permuted, and the importance and timing of the columns done.
+#[cfg(feature = "threads")]
+struct PendingTree<Y, SplitIndex: ColumnIdentifiable> {
+    grown: QueuedTree<Y, SplitIndex>,
+    mda: ImportanceTree<SplitIndex::Col>,
+    timings: Vec<TreeTiming>,
+    remaining: usize
+}
+
+struct TreeResult<T> {
+    mda: Option<ImportanceTree<T>>,
+    mdi: Option<ImpurityTree<T>>,
+    timing: Option<TreeTiming>,
+    weight: f64,
+    oob_n: usize,
+    masks: Option<(usize, TreeMasks)>,
+    local: Option<(Mask, LocalImportanceTree<T>)>,
+    split_cols: Vec<T>,
+    shadow_max: Option<(usize, f64)>,
+    shape: TreeShape,
+    // OOB rows with whether the tree votes for their class, with RandomForest::set_keep_oob_votes.
+    oob_right: Option<(Mask, Vec<bool>)>
+}
+
+// Weighted running MDA per column, plus per tree values only when Measures::per_tree is set.
+// MDI is summed already weighted.
+struct ForestResult<T> {
+    // MDA of every tree in tree order with its OOB size and weight.
+    mda_trees: Vec<(usize, f64, ImportanceTree<T>)>,
+    mda_stats: HashMap<T, WeightedStats>,
+    per_tree: bool,
+    mdi: HashMap<T, f64>,
+    total_weight: f64,
+    oob_total: usize,
+    n_trees: usize,
+    masks: Vec<(usize, TreeMasks)>,
+    // Weighted error increase per column and sample, with the weight of trees each sample was OOB in.
+    local: HashMap<T, Vec<f64>>,
+    local_weights: Vec<f64>,
+    tree_counts: HashMap<T, usize>,
+    shadow_max: Vec<(usize, f64)>,
+    shapes: Vec<TreeShape>,
+    // Right and total OOB votes per sample, all zero without RandomForest::set_keep_oob_votes.
+    oob_votes: Vec<(usize, usize)>
+}
+
+impl<T> ForestResult<T> {
+    fn new(nrow: usize, per_tree: bool) -> Self {
+        return ForestResult{mda_trees: vec![], mda_stats: HashMap::new(), per_tree, mdi: HashMap::new(), total_weight: 0., oob_total: 0, n_trees: 0, masks: vec![],
+                            local: HashMap::new(), local_weights: vec![0.; nrow], tree_counts: HashMap::new(), shadow_max: vec![], shapes: vec![], oob_votes: vec![(0, 0); nrow]};
+    }
+
+    // Share of samples voted OOB whose votes do not favour their class, ties counting as errors. None when no
+    // sample got an OOB vote.
+    fn oob_error(&self) -> Option<f64> {
+        let voted: Vec<&(usize, usize)> = self.oob_votes.iter().filter(|(_, n)| *n > 0).collect();
+        if voted.is_empty() {
+            return None;
+        }
+        return Some(voted.iter().filter(|(right, n)| 2 * right <= *n).count() as f64 / voted.len() as f64);
+    }
+}
+
+fn zscores<T: Hash + Eq + Copy>(forest_res: &ForestResult<T>) -> Importance<T> {
+    let mut res: Importance<T> = Importance::new();
+    for (key, stats) in forest_res.mda_stats.iter() {
+        res.insert(*key, stats.mean()/stats.var().sqrt());
+    }
+    add_unused(forest_res, &mut res, 0.);
+    return res;
+}
+
+fn zscore_details<T: Hash + Eq + Copy>(forest_res: &ForestResult<T>) -> HashMap<T, ZScore> {
+    let mut res: HashMap<T, ZScore> = HashMap::new();
+    for (key, stats) in forest_res.mda_stats.iter() {
+        let score = stats.mean()/stats.var().sqrt();
+        res.insert(*key, ZScore{score, n_trees: stats.n, unstable: stats.n < 2 || !score.is_finite()});
+    }
+    add_unused(forest_res, &mut res, ZScore{score: 0., n_trees: 0, unstable: true});
+    return res;
+}
+
+fn mean_decrease_impurity<T: Hash + Eq + Copy>(forest_res: &ForestResult<T>) -> Importance<T> {
+    let mut res = forest_res.mdi.iter().map(|(&col, &val)| (col, val / forest_res.total_weight)).collect();
+    add_unused(forest_res, &mut res, 0.);
+    return res;
+}
+
+// Weighted mean error increase of each key scaled by the mean OOB size and n_perm, the MDA of importance.
+pub(crate) fn mda_means<K, I>(stats: I, oob_total: usize, n_trees: usize, n_perm: usize) -> HashMap<K, f64>
+where
+    K: Hash + Eq,
+    I: IntoIterator<Item=(K, WeightedStats)>
+{
+    let oob_n = oob_total as f64 / n_trees as f64;
+    return stats.into_iter().map(|(key, stats)| (key, stats.mean() / oob_n / n_perm as f64)).collect();
+}
+
+// Columns no tree split on, which are in tree_counts only with include_unused.
+fn add_unused<T: Hash + Eq + Copy, V: Clone>(forest_res: &ForestResult<T>, res: &mut HashMap<T, V>, zero: V) {
+    for (&col, _) in forest_res.tree_counts.iter().filter(|(_, &n)| n == 0) {
+        res.insert(col, zero.clone());
+    }
+}
+
+// Weighted mean and population variance updated one value at a time (West's variant of Welford),
+// so forest importance needs memory per column and not per tree. Kept in Float, see the f32 feature.
+#[derive(Clone, Copy, Default, Debug)]
+pub(crate) struct WeightedStats {
+    n: usize,
+    sum_w: Float,
+    mean: Float,
+    m2: Float
+}
+
+impl WeightedStats {
+    pub(crate) fn push(&mut self, x: f64, w: f64) {
+        let (x, w) = (x as Float, w as Float);
+        self.n += 1;
+        self.sum_w += w;
+        if self.sum_w == 0. {
+            return;
+        }
+        let delta = x - self.mean;
+        self.mean += delta * w / self.sum_w;
+        self.m2 += w * delta * (x - self.mean);
+    }
+
+    // NaN when no weight was pushed.
+    fn mean(&self) -> f64 {
+        return match self.sum_w > 0. {
+            true => self.mean as f64,
+            false => f64::NAN
+        };
+    }
+
+    fn var(&self) -> f64 {
+        return (self.m2 / self.sum_w) as f64;
+    }
+}
+
+pub struct RandomForest<Y, SplitIndex> where
+    SplitIndex: ColumnIdentifiable
+{
     a: PhantomData<Y>,
     b: PhantomData<SplitIndex>,
     ntree: usize,
-    seed: u64
+    seed: u64,
+    salt: u64,
+    importance_batch: Option<usize>,
+    permutation_cache: bool,
+    parallel_columns: bool,
+    importance_queue: bool,
+    tree_weighting: bool,
+    leaf_counts: bool,
+    leaf_rows: bool,
+    split_weight: SplitWeight,
+    ties: TiePolicy,
+    split_penalty: SplitPenalty,
+    min_node_size: usize,
+    split_subsample: Option<usize>,
+    inbag_masks: Option<Vec<Mask>>,
+    matched_sets: Option<Vec<usize>>,
+    bootstrap: bool,
+    keep_masks: bool,
+    keep_oob_votes: bool,
+    snapshot: Option<(usize, SnapshotCallback<Y, SplitIndex>)>,
+    n_perm: usize,
+    collect_stats: bool,
+    stats: Mutex<Option<RunStats>>,
+    manifest: Mutex<Option<RunManifest>>,
+    masks: Mutex<Option<Vec<TreeMasks>>>,
+    include_unused: bool,
+    tree_counts: Mutex<Option<HashMap<SplitIndex::Col, usize>>>,
+    depth_stats: Mutex<Option<DepthStats>>,
+    time_budget: Option<Duration>,
+    deadline: Option<Instant>,
+    truncation: Mutex<Option<Truncation>>,
+    error_metric: ErrorMetric,
+    custom_metric: Option<Arc<dyn LossMetric<Y>>>
 }
 
 impl <Y, SplitIndex> RandomForest<Y, SplitIndex> where
-    Y:Copy + Send + Sync + Debug,
+    Y:Copy + Send + Sync + Debug + PartialEq,
     SplitIndex: ColumnIdentifiable + Clone + Copy + Send + Sync
 {
     pub fn new(seed: u64) -> Self {
@@ -48,130 +483,1869 @@ impl <Y, SplitIndex> RandomForest<Y, SplitIndex> where
             a: PhantomData,
             b: PhantomData,
             ntree: 0,
-            seed: seed
+            seed: seed,
+            salt: DEFAULT_SALT,
+            importance_batch: None,
+            permutation_cache: false,
+            parallel_columns: false,
+            importance_queue: false,
+            tree_weighting: false,
+            leaf_counts: false,
+            leaf_rows: false,
+            split_weight: SplitWeight::default(),
+            ties: TiePolicy::default(),
+            split_penalty: SplitPenalty::default(),
+            min_node_size: 1,
+            split_subsample: None,
+            inbag_masks: None,
+            matched_sets: None,
+            bootstrap: false,
+            keep_masks: false,
+            keep_oob_votes: false,
+            snapshot: None,
+            n_perm: 1,
+            collect_stats: false,
+            stats: Mutex::new(None),
+            manifest: Mutex::new(None),
+            masks: Mutex::new(None),
+            include_unused: false,
+            tree_counts: Mutex::new(None),
+            depth_stats: Mutex::new(None),
+            time_budget: None,
+            deadline: None,
+            truncation: Mutex::new(None),
+            error_metric: ErrorMetric::default(),
+            custom_metric: None
+        };
+    }
+
+    // Salt added to the seed of every random stream, see RngFactory.
+    pub fn set_salt(&mut self, salt: u64) {
+        self.salt = salt;
+    }
+
+    // Permute up to batch columns per tree traversal when computing importance.
+    pub fn set_importance_batch(&mut self, batch: Option<usize>) {
+        self.importance_batch = batch;
+    }
+
+    // Permute each column once per thread and reuse it across trees instead of permuting OOB rows per tree.
+    // Trades memory (up to a copy of the data per thread) for fewer permutations.
+    pub fn set_permutation_cache(&mut self, permutation_cache: bool) {
+        self.permutation_cache = permutation_cache;
+    }
+
+    // Spend the multithread budget on permuting columns of one tree at a time instead of building trees in parallel.
+    // Pays off for deep trees using many columns. Ignored with importance batches or the permutation cache.
+    pub fn set_parallel_columns(&mut self, parallel_columns: bool) {
+        self.parallel_columns = parallel_columns;
+    }
+
+    // Take growing trees and their (tree, column) permutation tasks from one queue shared by the multithread budget
+    // instead of giving each thread whole trees, balancing load when a few columns are used by many trees. A thread
+    // grows a tree only when no column task waits. Results equal those of trees built in parallel. Takes precedence over set_parallel_columns and is ignored in the same cases.
+    pub fn set_importance_queue(&mut self, importance_queue: bool) {
+        self.importance_queue = importance_queue;
+    }
+
+    // Weight each tree's importance by its OOB accuracy, lowering the influence of degenerate trees. Forests of
+    // fit weigh the votes of their trees the same way in prediction.
+    pub fn set_tree_weighting(&mut self, tree_weighting: bool) {
+        self.tree_weighting = tree_weighting;
+    }
+
+    // Weight of splits in impurity importance (MDI of gini_importance and importance_two_way). SplitWeight::Depth
+    // weights the impurity decrease per sample by depth instead of node size.
+    pub fn set_split_weight(&mut self, split_weight: SplitWeight) {
+        self.split_weight = split_weight;
+    }
+
+    // Class of leaves whose in-bag samples are of several classes. The default TiePolicy::Fixed uses no
+    // random draws, so predictions stay put when the RNG stream changes; CoinFlip breaks ties randomly.
+    pub fn set_tie_policy(&mut self, ties: TiePolicy) {
+        self.ties = ties;
+    }
+
+    // Correct split scores for the number of levels of candidate columns, so columns with many levels are not
+    // chosen (and ranked by impurity importance) for the extra pivots they offer. Off by default.
+    pub fn set_split_penalty(&mut self, split_penalty: SplitPenalty) {
+        split_penalty.check();
+        self.split_penalty = split_penalty;
+    }
+
+    // See Tree::set_min_node_size.
+    pub fn set_min_node_size(&mut self, min_node_size: usize) {
+        if min_node_size == 0 {
+            panic!("Minimum node size must be larger than 0.");
+        }
+        self.min_node_size = min_node_size;
+    }
+
+    // Keep in-bag class counts in the leaves of trees grown by fit, for Forest::predict_proba.
+    pub fn set_leaf_counts(&mut self, leaf_counts: bool) {
+        self.leaf_counts = leaf_counts;
+    }
+
+    // Keep in-bag rows in the leaves of trees grown by fit, for Forest::predict_quantiles.
+    pub fn set_leaf_rows(&mut self, leaf_rows: bool) {
+        self.leaf_rows = leaf_rows;
+    }
+
+    // Nodes with more than split_subsample samples rank split candidates on a random subsample of that size,
+    // only the pivot of the winner is fitted on all samples. Speeds up trees on large data.
+    pub fn set_split_subsample(&mut self, split_subsample: Option<usize>) {
+        if split_subsample == Some(0) {
+            panic!("Split subsample must be larger than 0.");
+        }
+        self.split_subsample = split_subsample;
+    }
+
+    // In-bag rows of every tree, indexed by tree, instead of random draws of SAMPLE_FRACTION of rows.
+    // Allows resampling schemes of the caller, e.g. matched case-control sampling. OOB rows are the rest.
+    pub fn set_inbag_masks(&mut self, inbag_masks: Option<Vec<Mask>>) {
+        self.inbag_masks = inbag_masks;
+    }
+
+    // Matched set id of each row (e.g. a case and its matched controls). Trees sample SAMPLE_FRACTION of the
+    // sets instead of rows, keeping every set entirely in-bag or entirely OOB, as conditional designs require.
+    pub fn set_matched_sets(&mut self, matched_sets: Option<Vec<usize>>) {
+        self.matched_sets = matched_sets;
+    }
+
+    // Trees draw as many rows as the data has with replacement instead of SAMPLE_FRACTION of rows without.
+    // Rows drawn more than once weigh splits and leaves by their count. Caller masks and matched sets take precedence.
+    pub fn set_bootstrap(&mut self, bootstrap: bool) {
+        self.bootstrap = bootstrap;
+    }
+
+    // Keep in-bag and OOB masks of each run, available afterwards from get_masks.
+    pub fn set_keep_masks(&mut self, keep_masks: bool) {
+        self.keep_masks = keep_masks;
+    }
+
+    // Record the OOB votes of each tree grown by fit, available from Forest::get_oob_votes. Off by default, as
+    // it keeps a prediction for roughly a third of the rows per tree. importance_scored then also gives the OOB
+    // error of its forest.
+    pub fn set_keep_oob_votes(&mut self, keep_oob_votes: bool) {
+        self.keep_oob_votes = keep_oob_votes;
+    }
+
+    // Call callback with the trees grown so far after every every-th tree of fit and fit_resume, e.g. to save
+    // long fits to disk. The callback runs on the fitting thread, so slow saves slow the fit down.
+    pub fn set_snapshot<F>(&mut self, every: usize, callback: F)
+    where
+        F: Fn(&ForestSnapshot<Y, SplitIndex>) + Send + Sync + 'static
+    {
+        if every == 0 {
+            panic!("Snapshot interval must be positive.");
+        }
+        self.snapshot = Some((every, Box::new(callback)));
+    }
+
+    // Masks of the trees of the last run, in tree order.
+    pub fn get_masks(&self) -> Option<Vec<TreeMasks>> {
+        return self.masks.lock().unwrap().clone();
+    }
+
+    // Give columns no tree split on zero importance instead of leaving them out of the result,
+    // shadow columns included when shadow_vars is on.
+    // Per tree importance is left as is.
+    pub fn set_include_unused(&mut self, include_unused: bool) {
+        self.include_unused = include_unused;
+    }
+
+    // Number of trees splitting on each column in the last run. Unused columns have 0 with include_unused.
+    pub fn get_tree_counts(&self) -> Option<HashMap<SplitIndex::Col, usize>> {
+        return self.tree_counts.lock().unwrap().clone();
+    }
+
+    // Depths and leaf counts of the trees of the last run.
+    pub fn get_depth_stats(&self) -> Option<DepthStats> {
+        return *self.depth_stats.lock().unwrap();
+    }
+
+    // Wall-clock budget of a run. No new tree is started once it is spent, the run then gives the results of the
+    // trees done so far, see get_truncation. The first tree is always grown.
+    pub fn set_time_budget(&mut self, time_budget: Option<Duration>) {
+        self.time_budget = time_budget;
+    }
+
+    // Point in time no new tree is started after, as set_time_budget but shared by several runs, e.g. the forests
+    // of a Boruta run. The earlier of the two stops a run.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    // Trees done and planned when the last run stopped at the time budget, None when all trees were done.
+    pub fn get_truncation(&self) -> Option<Truncation> {
+        return *self.truncation.lock().unwrap();
+    }
+
+    // Loss permutation importance measures the increase of, e.g. BalancedError for imbalanced phenotypes.
+    // Importance is then the mean increase of that loss per OOB sample.
+    pub fn set_error_metric(&mut self, error_metric: ErrorMetric) {
+        self.error_metric = error_metric;
+        if error_metric != ErrorMetric::Custom {
+            self.custom_metric = None;
+        }
+    }
+
+    // Loss of the caller for permutation importance, e.g. a cost weighted error. The error metric becomes
+    // ErrorMetric::Custom, and trees read back from a file measure nothing with it until it is set again.
+    pub fn set_custom_error_metric(&mut self, metric: Arc<dyn LossMetric<Y>>) {
+        self.error_metric = ErrorMetric::Custom;
+        self.custom_metric = Some(metric);
+    }
+
+    // Permute each column n_perm times per tree and average the error increase.
+    pub fn set_n_perm(&mut self, n_perm: usize) {
+        if n_perm == 0 {
+            panic!("Number of permutation rounds must be larger than 0.");
+        }
+        self.n_perm = n_perm;
+    }
+
+    // Collect timings of each run, available afterwards from get_stats.
+    pub fn set_collect_stats(&mut self, collect_stats: bool) {
+        self.collect_stats = collect_stats;
+    }
+
+    pub fn get_stats(&self) -> Option<RunStats> {
+        return *self.stats.lock().unwrap();
+    }
+
+    // Settings and data dimensions of the last run.
+    pub fn get_manifest(&self) -> Option<RunManifest> {
+        return self.manifest.lock().unwrap().clone();
+    }
+
+    fn add_aggregation_time(&self, start: Option<Instant>) {
+        if let (Some(start), Some(stats)) = (start, self.stats.lock().unwrap().as_mut()) {
+            let elapsed = start.elapsed();
+            stats.aggregation += elapsed;
+            stats.total += elapsed;
+        }
+    }
+
+    fn tree_importance<T, U>(&self, tree: &mut Tree<Y, SplitIndex>, df: &T, y: &U, oob_mask: &Mask, perm_cache: &mut PermutationCache<SplitIndex::Col, T::InternalType>, column_threads: usize) -> ImportanceTree<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync,
+        U: Response<Y> + Sync
+    {
+        let _span = debug_span!("tree_importance", oob = oob_mask.len()).entered();
+        #[cfg(feature = "threads")]
+        if column_threads > 1 {
+            return tree.importance_parallel(df, y, oob_mask, self.n_perm, column_threads);
+        }
+        #[cfg(not(feature = "threads"))]
+        let _ = column_threads;
+
+        let perm_cache = match self.permutation_cache {
+            true => Some(perm_cache),
+            false => None
+        };
+
+        return match self.importance_batch {
+            Some(batch) => tree.importance_batched(df, y, oob_mask, self.n_perm, batch, perm_cache),
+            None => tree.importance(df, y, oob_mask, self.n_perm, perm_cache)
         };
     }
 
-    fn next_tree<T, U>(&self, df: &T, y: &U , mtry: usize, shadow_vars: bool, rng_factory: &RngFactory, max_tree_depth: Option<usize>, ith_tree: usize) -> (Mask, Tree<Y, SplitIndex>,)
+    fn next_tree<T, U>(&self, df: &T, y: &U , mtry: usize, shadow_vars: bool, rng_factory: &RngFactory, max_tree_depth: Option<usize>, ith_tree: usize, track_impurity: bool) -> (Mask, Tree<Y, SplitIndex>,)
     where
         T: DataInterface<SplitIndex, Y> + Sync + Send,
         U: Response<Y> + Sync + Send
     {
+        let _span = debug_span!("tree_build", ith_tree).entered();
         let mut tree = Tree::new(ith_tree, rng_factory);
+        tree.set_timing(self.collect_stats);
+        tree.set_track_impurity(track_impurity);
+        tree.set_split_weight(self.split_weight);
+        tree.set_tie_policy(self.ties);
+        tree.set_split_penalty(self.split_penalty);
+        tree.set_min_node_size(self.min_node_size);
+        tree.set_split_subsample(self.split_subsample);
+        match &self.custom_metric {
+            Some(metric) => tree.set_custom_error_metric(metric.clone()),
+            None => tree.set_error_metric(self.error_metric)
+        }
+        tree.set_leaf_counts(self.leaf_counts);
+        tree.set_leaf_rows(self.leaf_rows);
         let mut rng = rng_factory.new_rng_tree_mask(ith_tree);
-        let mask = Mask::random_mask(y.len(), SAMPLE_FRACTION, &mut rng);
+        let mask = match (&self.inbag_masks, &self.matched_sets) {
+            (Some(masks), _) => masks[ith_tree].clone(),
+            (None, Some(sets)) => Mask::random_matched_mask(sets, SAMPLE_FRACTION, &mut rng),
+            (None, None) => match self.bootstrap {
+                true => Mask::random_bootstrap(y.len(), BOOTSTRAP_FRACTION, &mut rng),
+                false => Mask::random_mask(y.len(), SAMPLE_FRACTION, &mut rng)
+            }
+        };
         tree.build_tree(df, y, &mask, mtry, shadow_vars, max_tree_depth);
         return (mask, tree);
     }
 
-    fn importance_per_tree<T, U> (&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> HashMap<SplitIndex::Col, Vec<i64>>
-        where
-            T: DataInterface<SplitIndex, Y> + Sync + Send,
-            U: Response<Y> + Sync + Send
+    fn tree_result<T, U>(&self, df: &T, y: &U, mtry: usize, shadow_vars: bool, rng_factory: &RngFactory, max_tree_depth: Option<usize>, ith_tree: usize, measures: Measures, perm_cache: &mut PermutationCache<SplitIndex::Col, T::InternalType>, column_threads: usize) -> TreeResult<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
     {
-        let rng_factory = RngFactory::new(
-            self.seed,
-            Some(df.get_ncol()),
-            Some(ntree)
-        );
+        let (mask, mut tree) = self.next_tree(df, y, mtry, shadow_vars, rng_factory, max_tree_depth, ith_tree, measures.mdi);
 
-        let mut imp: HashMap<SplitIndex::Col, Vec<i64>>;
-
-        if multithread.is_some() {
-            imp = HashMap::new();
-            let thrs = multithread.unwrap(); // TODO this should be given by std::thread::available_parallelism
-            let df_arc_tmp = Arc::new(df);
-            let y_arc_tmp = Arc::new(y);
-            let rng_factory_tmp= Arc::new(rng_factory);
-            let (tx, rx) = channel();
-            let ith_tree_mut = Arc::new(Mutex::new(0usize));
-
-            thread::scope(|s| {
-                for _ in 0..thrs {
-                    let tx = tx.clone();
-                    let ith_tree_mut = Arc::clone(&ith_tree_mut);
-                    let y_arc = Arc::clone(&y_arc_tmp);
-                    let df_arc = Arc::clone(&df_arc_tmp);
-                    let rng_factory_arc = Arc::clone(&rng_factory_tmp);
-
-                    s.spawn(move || {
-                        loop {
-                            let mut ith_tree_guard = ith_tree_mut.lock().unwrap();
-                            let ith_tree = *ith_tree_guard;
-                            if ith_tree >= ntree {
-                                drop(ith_tree_guard);
-                                break;
-                            }
-                            *ith_tree_guard += 1;
-                            drop(ith_tree_guard); // unlock
+        let oob_mask = mask.inverse(&(0..y.len()).collect::<Vec<usize>>());
+        let mda = match measures.mda {
+            true => Some(self.tree_importance(&mut tree, df, y, &oob_mask, perm_cache, column_threads)),
+            false => None
+        };
+        return self.finish_tree_result(df, y, shadow_vars, ith_tree, measures, mask, oob_mask, tree, mda);
+    }
 
-                            let (mask, mut tree) = self.next_tree(*df_arc, *y_arc, mtry, shadow_vars, &*rng_factory_arc, max_tree_depth, ith_tree);
-                            let oob_mask = mask.inverse(&(0..(*y_arc).len()).collect::<Vec<usize>>());
+    // Result of a grown tree with its permutation importance mda, if computed.
+    fn finish_tree_result<T, U>(&self, df: &T, y: &U, shadow_vars: bool, ith_tree: usize, measures: Measures, mask: Mask, oob_mask: Mask, mut tree: Tree<Y, SplitIndex>, mda: Option<ImportanceTree<SplitIndex::Col>>) -> TreeResult<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        // Shadows no split used have zero importance.
+        let shadow_max = match (&mda, shadow_vars) {
+            (Some(tree_imp), true) => {
+                let max = tree_imp.iter().filter(|(col, _)| SplitIndex::is_shadow_col(col))
+                    .map(|(_, &val)| val / oob_mask.len() as f64 / self.n_perm as f64)
+                    .fold(0., f64::max);
+                Some((ith_tree, max))
+            },
+            _ => None
+        };
 
-                            let tree_imp = tree.importance(*df_arc, *y_arc, &oob_mask);
-                            tx.send(tree_imp).unwrap();
-                        }
-                    });
-                }
-            });
-            for _ in 0..ntree {
-                let tree_imp = rx.recv().unwrap();
-                for (sp, val) in tree_imp.iter() {
-                    imp.entry(*sp).and_modify(|row| {
-                        row.push(*val);
-                    }).or_insert(vec![*val]);
+        let local = match measures.local {
+            true => Some(tree.local_importance(df, y, &oob_mask, self.n_perm)),
+            false => None
+        };
+
+        let weight = match self.tree_weighting {
+            true => tree.oob_accuracy(df, y, &oob_mask),
+            false => 1.
+        };
+
+        let oob_right = match self.keep_oob_votes {
+            true => {
+                let mut mask_ranks = vec![usize::MAX; y.len()];
+                for (rank, &row) in oob_mask.get_mask().iter().enumerate() {
+                    mask_ranks[row] = rank;
                 }
-            }
-        } else {
-            imp = HashMap::new();
-            for ith_tree in 0..ntree {
-                let (mask, mut tree) = self.next_tree(df, y, mtry, shadow_vars, &rng_factory, max_tree_depth, ith_tree);
+                let labels = y.as_vector_ref();
+                let right = tree.predict(df, &oob_mask, None, &mask_ranks).iter().zip(oob_mask.get_mask().iter())
+                    .map(|(vote, &row)| *vote == labels[row])
+                    .collect();
+                Some((oob_mask.clone(), right))
+            },
+            false => None
+        };
 
-                let oob_mask = mask.inverse(&(0..y.len()).collect::<Vec<usize>>());
+        return TreeResult {
+            mda,
+            mdi: tree.get_impurity_decrease().cloned(),
+            timing: tree.get_timing(),
+            weight,
+            oob_n: oob_mask.len(),
+            masks: self.keep_masks.then(|| (ith_tree, TreeMasks{inbag: mask, oob: oob_mask.clone()})),
+            local: local.map(|local| (oob_mask, local)),
+            split_cols: tree.get_split_cols().iter().cloned().collect(),
+            shadow_max,
+            shape: tree.shape(),
+            oob_right
+        };
+    }
 
-                let tree_imp = tree.importance(df, y, &oob_mask);
-                for (sp, val) in tree_imp.iter() {
-                    imp.entry(*sp).and_modify(|row| {
-                        row.push(*val)
-                    }).or_insert(vec![*val]);
+    fn add_tree_result(&self, res: &mut ForestResult<SplitIndex::Col>, tree_res: TreeResult<SplitIndex::Col>, stats: &mut RunStats) {
+        let agg_start = self.collect_stats.then(Instant::now);
+        let weight = tree_res.weight;
+        res.total_weight += weight;
+        res.oob_total += tree_res.oob_n;
+        res.n_trees += 1;
+        if let Some(masks) = tree_res.masks {
+            res.masks.push(masks);
+        }
+        for col in tree_res.split_cols {
+            *res.tree_counts.entry(col).or_insert(0) += 1;
+        }
+        if let Some(shadow_max) = tree_res.shadow_max {
+            res.shadow_max.push(shadow_max);
+        }
+        res.shapes.push(tree_res.shape);
+        if let Some((oob_mask, right)) = tree_res.oob_right {
+            for (&i, &right) in oob_mask.get_mask().iter().zip(right.iter()) {
+                res.oob_votes[i].0 += right as usize;
+                res.oob_votes[i].1 += 1;
+            }
+        }
+        if let Some(tree_imp) = tree_res.mda {
+            for (sp, val) in tree_imp.iter() {
+                res.mda_stats.entry(*sp).or_default().push(*val, weight);
+            }
+            if res.per_tree {
+                res.mda_trees.push((tree_res.oob_n, weight, tree_imp));
+            }
+        }
+        if let Some(tree_mdi) = tree_res.mdi {
+            for (sp, val) in tree_mdi {
+                *res.mdi.entry(sp).or_insert(0.) += weight * val;
+            }
+        }
+        if let Some((oob_mask, tree_local)) = tree_res.local {
+            for &i in oob_mask.get_mask().iter() {
+                res.local_weights[i] += weight;
+            }
+            let nrow = res.local_weights.len();
+            for (sp, vals) in tree_local {
+                let col_local = res.local.entry(sp).or_insert_with(|| vec![0.; nrow]);
+                for (&i, &val) in oob_mask.get_mask().iter().zip(vals.iter()) {
+                    col_local[i] += weight * val;
                 }
             }
         }
+        if let (Some(timing), Some(agg_start)) = (tree_res.timing, agg_start) {
+            stats.add_tree(timing);
+            stats.aggregation += agg_start.elapsed();
+        }
+    }
 
-        return imp;
+    // Importance of trees out of a forest of ntree trees. Each tree draws its random streams from its index, so
+    // disjoint ranges computed separately (e.g. on different machines, see split_trees and write_tsv) join
+    // with PartialImportance::merge into the result of a single run.
+    pub fn partial_importance<T, U>(&self, df: &T, y: &U, ntree: usize, trees: Range<usize>, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> PartialImportance<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y> + Sync + Send,
+            U: Response<Y> + Sync + Send
+    {
+        let measures = Measures{mda: true, mdi: false, local: false, per_tree: true};
+        let forest_res = self.run_trees(df, y, ntree, trees.clone(), mtry, shadow_vars, max_tree_depth, multithread, measures);
+        let per_tree = forest_res.mda_trees.into_iter()
+            .map(|(oob_n, weight, importance)| PartialTree{oob_n, weight, importance})
+            .collect();
+        return PartialImportance{ntree, seed: self.seed, salt: self.salt, n_perm: self.n_perm, trees, per_tree};
     }
 
-    pub fn zscore<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> Importance<SplitIndex::Col>
-    where
-        T: DataInterface<SplitIndex, Y> + Sync + Send,
-        U: Response<Y> + Sync + Send
+    // Per tree importance of each column for trees out of a forest of ntree trees, values of a column only
+    // from the trees it was split on. Tree weights are not applied, see partial_importance.
+    pub fn importance_per_tree_range<T, U> (&self, df: &T, y: &U, ntree: usize, trees: Range<usize>, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> ImportancePerTree<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y> + Sync + Send,
+            U: Response<Y> + Sync + Send
     {
-        let imp_per_tree = self.importance_per_tree(df, y, ntree, mtry, shadow_vars, max_tree_depth, multithread);
-        let mut res: Importance<SplitIndex::Col> = Importance::new();
+        return self.partial_importance(df, y, ntree, trees, mtry, shadow_vars, max_tree_depth, multithread).per_column();
+    }
 
-        for (key, val) in imp_per_tree.iter() {
-            let mean = val.iter().sum::<i64>() as f64 / val.len() as f64;
-            let var = val.iter().map(|&x| (x as f64-mean).powi(2)).sum::<f64>() as f64/val.len() as f64;
+    // Permutation importance and its z-score along with the importance within each tree, e.g. for paired
+    // tests of columns against each other over trees.
+    pub fn importance_by_tree<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> ImportanceByTree<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y> + Sync + Send,
+            U: Response<Y> + Sync + Send
+    {
+        let measures = Measures{mda: true, mdi: false, local: false, per_tree: true};
+        let forest_res = self.run_trees(df, y, ntree, 0..ntree, mtry, shadow_vars, max_tree_depth, multithread, measures);
+        let agg_start = self.collect_stats.then(Instant::now);
+        let importance = self.mean_decrease_accuracy(&forest_res);
+        let zscore = zscores(&forest_res);
+        let trees = forest_res.mda_trees.into_iter()
+            .map(|(oob_n, _, tree_imp)| tree_imp.into_iter().map(|(col, val)| (col, val / oob_n as f64 / self.n_perm as f64)).collect())
+            .collect();
+        self.add_aggregation_time(agg_start);
+        return ImportanceByTree{importance, zscore, trees};
+    }
 
-            res.insert(key.clone(), mean/var.sqrt());
+    // Panics when in-bag masks or matched sets given by the caller do not fit the data.
+    fn check_sampling(&self, ntree: usize, nrow: usize) {
+        if let Some(masks) = &self.inbag_masks {
+            if masks.len() != ntree {
+                panic!("Number of in-bag masks must equal number of trees.");
+            }
+            if !masks.iter().all(|mask| mask.within_bounds(nrow)) {
+                panic!("In-bag mask index out of data range.");
+            }
+            if self.matched_sets.is_some() {
+                panic!("In-bag masks and matched sets cannot be used together.");
+            }
+        }
+        if self.matched_sets.as_ref().is_some_and(|sets| sets.len() != nrow) {
+            panic!("Number of matched set ids must equal number of rows.");
         }
-        return res;
     }
 
-    pub fn importance<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> Importance<SplitIndex::Col>
-    where
-        T: DataInterface<SplitIndex, Y> + Sync + Send,
-        U: Response<Y> + Sync + Send
-    {
-
-        let imp_per_tree = self.importance_per_tree(df, y, ntree, mtry, shadow_vars, max_tree_depth, multithread);
-        let mut res: Importance<SplitIndex::Col> = Importance::new();
-        let oob_n = y.len() as f64 - (y.len() as f64 * SAMPLE_FRACTION).floor();
+    fn rng_factory(&self, ncol: usize, ntree: usize) -> RngFactory {
+        let mut rng_factory = RngFactory::new(
+            self.seed,
+            Some(ncol),
+            Some(ntree)
+        );
+        rng_factory.set_nperm(self.n_perm);
+        rng_factory.set_salt(self.salt);
+        return rng_factory;
+    }
 
-        for (key, val) in imp_per_tree.iter() {
-            res.insert(key.clone(), val.iter().sum::<i64>() as f64 / val.len() as f64 / oob_n);
+    fn run_trees<T, U> (&self, df: &T, y: &U, ntree: usize, trees: Range<usize>, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>, measures: Measures) -> ForestResult<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y> + Sync + Send,
+            U: Response<Y> + Sync + Send
+    {
+        if trees.end > ntree {
+            panic!("Tree range exceeds number of trees.");
         }
+        self.check_sampling(ntree, y.len());
+        let _span = info_span!("importance", ntree, trees_start = trees.start, trees_end = trees.end).entered();
+        let start = self.collect_stats.then(Instant::now);
+        let deadline = self.time_budget.map(|budget| Instant::now() + budget).into_iter().chain(self.deadline).min();
+        let trees_planned = trees.len();
+        *self.manifest.lock().unwrap() = Some(RunManifest {
+            crate_version: env!("CARGO_PKG_VERSION"),
+            seed: self.seed,
+            salt: self.salt,
+            ntree,
+            trees: trees.clone(),
+            mtry,
+            shadow_vars,
+            sample_fraction: match self.bootstrap {
+                true => BOOTSTRAP_FRACTION,
+                false => SAMPLE_FRACTION
+            },
+            max_tree_depth,
+            threads: multithread.filter(|_| cfg!(feature = "threads")),
+            n_perm: self.n_perm,
+            importance_batch: self.importance_batch,
+            permutation_cache: self.permutation_cache,
+            split_subsample: self.split_subsample,
+            custom_masks: self.inbag_masks.is_some(),
+            matched_sets: self.matched_sets.is_some(),
+            bootstrap: self.bootstrap && self.inbag_masks.is_none() && self.matched_sets.is_none(),
+            error_metric: self.error_metric,
+            f32: cfg!(feature = "f32"),
+            nrow: y.len(),
+            ncol: df.get_ncol()
+        });
+        let mut stats = RunStats::default();
+
+        let rng_factory = self.rng_factory(df.get_ncol(), ntree);
+
+        // Column level parallelism only applies to plain permutation importance, otherwise trees are built in parallel.
+        let plain_mda = measures.mda && self.importance_batch.is_none() && !self.permutation_cache;
+        let parallel_columns = self.parallel_columns && !self.importance_queue && plain_mda;
+        let mut res = match multithread {
+            #[cfg(feature = "threads")]
+            Some(thrs) if self.importance_queue && plain_mda => self.run_trees_queued(df, y, &rng_factory, trees, mtry, shadow_vars, max_tree_depth, thrs, measures, deadline, &mut stats),
+            #[cfg(feature = "threads")]
+            Some(thrs) if !parallel_columns => self.run_trees_threaded(df, y, &rng_factory, trees, mtry, shadow_vars, max_tree_depth, thrs, measures, deadline, &mut stats),
+            _ => {
+                let column_threads = multithread.filter(|_| parallel_columns).unwrap_or(1);
+                self.run_trees_serial(df, y, &rng_factory, trees, mtry, shadow_vars, max_tree_depth, measures, column_threads, deadline, &mut stats)
+            }
+        };
+        *self.truncation.lock().unwrap() = (res.n_trees < trees_planned).then_some(Truncation{trees_done: res.n_trees, trees_planned});
+
+        if let Some(start) = start {
+            stats.total = start.elapsed();
+            *self.stats.lock().unwrap() = Some(stats);
+        }
+        if self.include_unused {
+            let shadows = match shadow_vars {
+                true => df.get_shadow_col_ids(),
+                false => vec![]
+            };
+            for col in df.get_col_ids().into_iter().chain(shadows) {
+                res.tree_counts.entry(col).or_insert(0);
+            }
+        }
+        *self.tree_counts.lock().unwrap() = Some(res.tree_counts.clone());
+        *self.depth_stats.lock().unwrap() = DepthStats::from_shapes(&res.shapes);
+        if self.keep_masks {
+            res.masks.sort_by_key(|x| x.0);
+            *self.masks.lock().unwrap() = Some(res.masks.drain(..).map(|x| x.1).collect());
+        }
+
+        return res;
+    }
+
+    fn run_trees_serial<T, U>(&self, df: &T, y: &U, rng_factory: &RngFactory, trees: Range<usize>, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, measures: Measures, column_threads: usize, deadline: Option<Instant>, stats: &mut RunStats) -> ForestResult<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y> + Sync + Send,
+            U: Response<Y> + Sync + Send
+    {
+        let mut res = ForestResult::new(y.len(), measures.per_tree);
+        let mut perm_cache = PermutationCache::new();
+        for ith_tree in trees.clone() {
+            if ith_tree > trees.start && out_of_time(deadline) {
+                break;
+            }
+            let tree_res = self.tree_result(df, y, mtry, shadow_vars, rng_factory, max_tree_depth, ith_tree, measures, &mut perm_cache, column_threads);
+            self.add_tree_result(&mut res, tree_res, stats);
+        }
+        return res;
+    }
+
+    // All threads take jobs from one queue: growing the next tree, or permuting a column of a grown tree. Column
+    // jobs go first, so a thread grows a tree only when no column waits and few trees are held at once. The thread
+    // finishing the last column of a tree finishes the tree. Per tree results are added in tree order, as in
+    // run_trees_threaded.
+    #[cfg(feature = "threads")]
+    fn run_trees_queued<T, U>(&self, df: &T, y: &U, rng_factory: &RngFactory, trees: Range<usize>, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, thrs: usize, measures: Measures, deadline: Option<Instant>, stats: &mut RunStats) -> ForestResult<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y> + Sync + Send,
+            U: Response<Y> + Sync + Send
+    {
+        let mut res = ForestResult::new(y.len(), measures.per_tree);
+        let parent_span = Span::current();
+        let queue: Mutex<TreeQueue<Y, SplitIndex>> = Mutex::new(TreeQueue{next_tree: trees.start, growing: 0, columns: VecDeque::new()});
+        let job_ready = Condvar::new();
+        let pending: Mutex<HashMap<usize, PendingTree<Y, SplitIndex>>> = Mutex::new(HashMap::new());
+        let (tx, rx) = channel();
+
+        thread::scope(|s| {
+            for _ in 0..thrs {
+                let tx = tx.clone();
+                let parent_span = parent_span.clone();
+                let (queue, job_ready, pending, trees) = (&queue, &job_ready, &pending, trees.clone());
+                s.spawn(move || {
+                    let _span = parent_span.enter();
+                    let mut preds_perm = vec![];
+                    loop {
+                        let job = {
+                            let mut q = queue.lock().unwrap();
+                            loop {
+                                if let Some((ith_tree, col, grown)) = q.columns.pop_front() {
+                                    break Some(QueueJob::Column(ith_tree, col, grown));
+                                }
+                                if q.next_tree < trees.end && !(q.next_tree > trees.start && out_of_time(deadline)) {
+                                    q.next_tree += 1;
+                                    q.growing += 1;
+                                    break Some(QueueJob::Grow(q.next_tree - 1));
+                                }
+                                if q.growing == 0 {
+                                    break None;
+                                }
+                                q = job_ready.wait(q).unwrap();
+                            }
+                        };
+                        match job {
+                            None => break,
+                            Some(QueueJob::Grow(ith_tree)) => {
+                                let (mask, mut tree) = self.next_tree(df, y, mtry, shadow_vars, rng_factory, max_tree_depth, ith_tree, measures.mdi);
+                                let oob_mask = mask.inverse(&(0..y.len()).collect::<Vec<usize>>());
+                                let cols: Vec<SplitIndex::Col> = tree.get_split_cols().iter().copied().collect();
+                                let columns = match cols.is_empty() {
+                                    true => {
+                                        let tree_res = self.finish_tree_result(df, y, shadow_vars, ith_tree, measures, mask, oob_mask, tree, Some(ImportanceTree::new()));
+                                        tx.send((ith_tree, tree_res)).unwrap();
+                                        vec![]
+                                    },
+                                    false => {
+                                        let base = tree.importance_base(df, y, &oob_mask);
+                                        let grown = Arc::new((mask, oob_mask, tree, base));
+                                        pending.lock().unwrap().insert(ith_tree, PendingTree{grown: Arc::clone(&grown), mda: ImportanceTree::new(), timings: vec![], remaining: cols.len()});
+                                        cols.into_iter().map(|col| (ith_tree, col, Arc::clone(&grown))).collect()
+                                    }
+                                };
+                                let mut q = queue.lock().unwrap();
+                                q.columns.extend(columns);
+                                q.growing -= 1;
+                                drop(q);
+                                job_ready.notify_all();
+                            },
+                            Some(QueueJob::Column(ith_tree, col, grown)) => {
+                                let (_, oob_mask, tree, base) = &*grown;
+                                preds_perm.clear();
+                                preds_perm.extend_from_slice(base.get_preds());
+                                let mut timing = TreeTiming::default();
+                                let val = tree.importance_col(base, df, y, oob_mask, col, self.n_perm, None, &mut preds_perm, &mut timing);
+                                // Released before the count, so the last column finds the tree held by pending only.
+                                drop(grown);
+                                let mut pending_guard = pending.lock().unwrap();
+                                let pending_tree = pending_guard.get_mut(&ith_tree).unwrap();
+                                pending_tree.mda.insert(col, val);
+                                pending_tree.timings.push(timing);
+                                pending_tree.remaining -= 1;
+                                if pending_tree.remaining == 0 {
+                                    let done = pending_guard.remove(&ith_tree).unwrap();
+                                    drop(pending_guard);
+                                    let Ok((mask, oob_mask, mut tree, _)) = Arc::try_unwrap(done.grown) else {
+                                        panic!("Column jobs must release their tree before it is finished.");
+                                    };
+                                    for timing in done.timings {
+                                        tree.add_importance_timing(timing);
+                                    }
+                                    let tree_res = self.finish_tree_result(df, y, shadow_vars, ith_tree, measures, mask, oob_mask, tree, Some(done.mda));
+                                    tx.send((ith_tree, tree_res)).unwrap();
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        drop(tx);
+        // Trees are taken in order, so those done once the time budget is spent are the ones before the counter.
+        let trees_taken = queue.lock().unwrap().next_tree;
+        let mut done: HashMap<usize, TreeResult<SplitIndex::Col>> = rx.into_iter().collect();
+        for ith_tree in trees.start..trees_taken {
+            let tree_res = done.remove(&ith_tree).unwrap();
+            self.add_tree_result(&mut res, tree_res, stats);
+        }
+        return res;
+    }
+
+    #[cfg(feature = "threads")]
+    fn run_trees_threaded<T, U>(&self, df: &T, y: &U, rng_factory: &RngFactory, trees: Range<usize>, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, thrs: usize, measures: Measures, deadline: Option<Instant>, stats: &mut RunStats) -> ForestResult<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y> + Sync + Send,
+            U: Response<Y> + Sync + Send
+    {
+        // TODO thread count should be given by std::thread::available_parallelism
+        let mut res = ForestResult::new(y.len(), measures.per_tree);
+        let df_arc_tmp = Arc::new(df);
+        let y_arc_tmp = Arc::new(y);
+        let rng_factory_tmp= Arc::new(rng_factory);
+        let (tx, rx) = channel();
+        let ith_tree_mut = Arc::new(Mutex::new(trees.start));
+        let (trees_start, trees_end) = (trees.start, trees.end);
+        let parent_span = Span::current();
+
+        thread::scope(|s| {
+            for _ in 0..thrs {
+                let tx = tx.clone();
+                let ith_tree_mut = Arc::clone(&ith_tree_mut);
+                let y_arc = Arc::clone(&y_arc_tmp);
+                let df_arc = Arc::clone(&df_arc_tmp);
+                let rng_factory_arc = Arc::clone(&rng_factory_tmp);
+
+                let parent_span = parent_span.clone();
+
+                s.spawn(move || {
+                    let _span = parent_span.enter();
+                    let mut perm_cache = PermutationCache::new();
+                    loop {
+                        let mut ith_tree_guard = ith_tree_mut.lock().unwrap();
+                        let ith_tree = *ith_tree_guard;
+                        if ith_tree >= trees_end || (ith_tree > trees_start && out_of_time(deadline)) {
+                            drop(ith_tree_guard);
+                            break;
+                        }
+                        *ith_tree_guard += 1;
+                        drop(ith_tree_guard); // unlock
+
+                        let tree_res = self.tree_result(*df_arc, *y_arc, mtry, shadow_vars, *rng_factory_arc, max_tree_depth, ith_tree, measures, &mut perm_cache, 1);
+                        tx.send((ith_tree, tree_res)).unwrap();
+                    }
+                });
+            }
+        });
+        // Added in tree order, as the running MDA statistics depend on it in the last bits. Trees are taken in
+        // order too, so those done once the time budget is spent are the ones before the counter.
+        let trees_taken = *ith_tree_mut.lock().unwrap();
+        let mut pending = HashMap::new();
+        for ith_tree in trees.start..trees_taken {
+            let tree_res = match pending.remove(&ith_tree) {
+                Some(tree_res) => tree_res,
+                None => loop {
+                    let (ith_recv, tree_res) = rx.recv().unwrap();
+                    if ith_recv == ith_tree {
+                        break tree_res;
+                    }
+                    pending.insert(ith_recv, tree_res);
+                }
+            };
+            self.add_tree_result(&mut res, tree_res, stats);
+        }
+        return res;
+    }
+
+    // Grows the trees importance would grow with the same arguments and keeps them for prediction on new data.
+    pub fn fit<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>) -> Forest<Y, SplitIndex>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        return self.fit_forest(df, y, ntree, mtry, shadow_vars, max_tree_depth, self.keep_oob_votes);
+    }
+
+    // Margin, strength and correlation of the forest fit would grow, e.g. to compare mtry values by the
+    // correlation they give at similar strength, or to check strength has settled for ntree. None when no row
+    // is out-of-bag of any tree, e.g. for very few trees.
+    pub fn diagnostics<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>) -> Option<ForestDiagnostics>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        let forest = self.fit_forest(df, y, ntree, mtry, shadow_vars, max_tree_depth, true);
+        return forest.diagnostics(y);
+    }
+
+    // Finishes the fit snapshot was taken from. The arguments must be those of the interrupted fit, and the forest
+    // config the same as well; seed, salt, ntree and data dimensions are checked. OOB votes are kept when the
+    // snapshot has them.
+    pub fn fit_resume<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, snapshot: ForestSnapshot<Y, SplitIndex>) -> Forest<Y, SplitIndex>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        if (snapshot.seed, snapshot.salt, snapshot.ntree) != (self.seed, self.salt, ntree) {
+            panic!("Snapshot must come from a fit with the same seed, salt and ntree.");
+        }
+        if (snapshot.nrow, snapshot.ncol) != (y.len(), df.get_ncol()) {
+            panic!("Snapshot must come from a fit on data of the same dimensions.");
+        }
+        return self.grow_snapshot(df, y, mtry, shadow_vars, max_tree_depth, snapshot);
+    }
+
+    fn fit_forest<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, keep_oob_votes: bool) -> Forest<Y, SplitIndex>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        let snapshot = ForestSnapshot{
+            seed: self.seed,
+            salt: self.salt,
+            ntree,
+            nrow: y.len(),
+            ncol: df.get_ncol(),
+            forest: Forest{trees: Vec::with_capacity(ntree), oob_votes: keep_oob_votes.then(|| Vec::with_capacity(ntree)), calibration: None, calibration_other: None,
+                           tree_weights: self.tree_weighting.then(|| Vec::with_capacity(ntree)), sample_ids: df.get_sample_ids().map(|ids| ids.to_vec())}
+        };
+        return self.grow_snapshot(df, y, mtry, shadow_vars, max_tree_depth, snapshot);
+    }
+
+    fn grow_snapshot<T, U>(&self, df: &T, y: &U, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, mut snapshot: ForestSnapshot<Y, SplitIndex>) -> Forest<Y, SplitIndex>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        let ntree = snapshot.ntree;
+        self.check_sampling(ntree, y.len());
+        let rng_factory = self.rng_factory(df.get_ncol(), ntree);
+        for ith_tree in snapshot.n_done()..ntree {
+            let (mask, mut tree) = self.next_tree(df, y, mtry, shadow_vars, &rng_factory, max_tree_depth, ith_tree, false);
+            let oob = mask.inverse(&(0..y.len()).collect::<Vec<usize>>());
+            if let Some(tree_weights) = snapshot.forest.tree_weights.as_mut() {
+                tree_weights.push(tree.oob_accuracy(df, y, &oob));
+            }
+            if let Some(oob_votes) = snapshot.forest.oob_votes.as_mut() {
+                let mut mask_ranks = vec![usize::MAX; y.len()];
+                for (rank, &row) in oob.get_mask().iter().enumerate() {
+                    mask_ranks[row] = rank;
+                }
+                let votes = tree.predict(df, &oob, None, &mask_ranks);
+                oob_votes.push(TreeOobVotes{oob, votes});
+            }
+            snapshot.forest.trees.push(tree);
+            if let Some((every, callback)) = &self.snapshot {
+                if (ith_tree + 1) % every == 0 && ith_tree + 1 < ntree {
+                    callback(&snapshot);
+                }
+            }
+        }
+        return snapshot.forest;
+    }
+
+    // Digest of all trees of the forest in tree order, e.g. to check in CI that a refactor or another platform
+    // grows the same forest. Only builds the trees, no importance is computed.
+    pub fn model_hash<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>) -> u64
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send,
+        Y: Hash,
+        SplitIndex: Hash
+    {
+        self.check_sampling(ntree, y.len());
+        let rng_factory = self.rng_factory(df.get_ncol(), ntree);
+        let mut hasher = StableHasher::new();
+        for ith_tree in 0..ntree {
+            let (_, tree) = self.next_tree(df, y, mtry, shadow_vars, &rng_factory, max_tree_depth, ith_tree, false);
+            hasher.write_u64(tree.structure_hash());
+        }
+        return hasher.finish();
+    }
+
+    // Mean decrease in accuracy per OOB sample, averaged over trees and permutation rounds.
+    // OOB size differs between trees only with in-bag masks of the caller, then the mean size is used.
+    fn mean_decrease_accuracy(&self, forest_res: &ForestResult<SplitIndex::Col>) -> Importance<SplitIndex::Col> {
+        let mut res = mda_means(forest_res.mda_stats.iter().map(|(&key, &stats)| (key, stats)), forest_res.oob_total, forest_res.n_trees, self.n_perm);
+        add_unused(forest_res, &mut res, 0.);
+        return res;
+    }
+
+    // Mean decrease in gini impurity, averaged over trees. Skips the permutation step entirely.
+    pub fn gini_importance<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> Importance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        let measures = Measures{mda: false, mdi: true, local: false, per_tree: false};
+        let res = self.run_trees(df, y, ntree, 0..ntree, mtry, shadow_vars, max_tree_depth, multithread, measures);
+        return mean_decrease_impurity(&res);
+    }
+
+    // Permutation (MDA) and impurity (MDI) importance from a single forest.
+    pub fn importance_two_way<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> TwoWayImportance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        let measures = Measures{mda: true, mdi: true, local: false, per_tree: false};
+        let res = self.run_trees(df, y, ntree, 0..ntree, mtry, shadow_vars, max_tree_depth, multithread, measures);
+        let agg_start = self.collect_stats.then(Instant::now);
+        let mda = self.mean_decrease_accuracy(&res);
+        let mdi = mean_decrease_impurity(&res);
+        self.add_aggregation_time(agg_start);
+        return TwoWayImportance{mda, mdi};
+    }
+
+    // Mean over standard deviation of the tree importances. Columns with a single tree or constant importance
+    // across trees get +-inf or NaN, zscore_checked flags them.
+    pub fn zscore<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> Importance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        let measures = Measures{mda: true, mdi: false, local: false, per_tree: false};
+        let forest_res = self.run_trees(df, y, ntree, 0..ntree, mtry, shadow_vars, max_tree_depth, multithread, measures);
+        let agg_start = self.collect_stats.then(Instant::now);
+        let res = zscores(&forest_res);
+        self.add_aggregation_time(agg_start);
+        return res;
+    }
+
+    // As zscore, but flags columns whose score is not a usable estimate, see ZScore.
+    pub fn zscore_checked<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> HashMap<SplitIndex::Col, ZScore>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        let measures = Measures{mda: true, mdi: false, local: false, per_tree: false};
+        let forest_res = self.run_trees(df, y, ntree, 0..ntree, mtry, shadow_vars, max_tree_depth, multithread, measures);
+        let agg_start = self.collect_stats.then(Instant::now);
+        let res = zscore_details(&forest_res);
+        self.add_aggregation_time(agg_start);
+        return res;
+    }
+
+    // Importance and zscore of the same forest, at the cost of one.
+    pub fn importance_scored<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> ScoredImportance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        let measures = Measures{mda: true, mdi: false, local: false, per_tree: false};
+        let forest_res = self.run_trees(df, y, ntree, 0..ntree, mtry, shadow_vars, max_tree_depth, multithread, measures);
+        let agg_start = self.collect_stats.then(Instant::now);
+        let res = ScoredImportance{importance: self.mean_decrease_accuracy(&forest_res), zscore: zscores(&forest_res), oob_error: forest_res.oob_error()};
+        self.add_aggregation_time(agg_start);
+        return res;
+    }
+
+    // Permutation importance grown with shadow_vars, split into real and shadow columns, e.g. for thresholds
+    // on the shadow importance as in Boruta.
+    pub fn importance_shadow<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, max_tree_depth: Option<usize>, multithread: Option<usize>) -> ShadowImportance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        let measures = Measures{mda: true, mdi: false, local: false, per_tree: false};
+        let mut forest_res = self.run_trees(df, y, ntree, 0..ntree, mtry, true, max_tree_depth, multithread, measures);
+        let agg_start = self.collect_stats.then(Instant::now);
+        let (shadow, real) = self.mean_decrease_accuracy(&forest_res).into_iter()
+            .partition(|(col, _)| SplitIndex::is_shadow_col(col));
+        forest_res.shadow_max.sort_by_key(|x| x.0);
+        let shadow_max = forest_res.shadow_max.iter().map(|x| x.1).collect();
+        self.add_aggregation_time(agg_start);
+        return ShadowImportance{real, shadow, shadow_max};
+    }
+
+    // Per sample permutation importance (casewise MDA): error increase of each sample averaged over the trees
+    // it was out-of-bag in. Columns never split on get no entry unless include_unused, samples never out-of-bag are NaN.
+    pub fn local_importance<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> LocalImportance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        let measures = Measures{mda: false, mdi: false, local: true, per_tree: false};
+        let forest_res = self.run_trees(df, y, ntree, 0..ntree, mtry, shadow_vars, max_tree_depth, multithread, measures);
+        let agg_start = self.collect_stats.then(Instant::now);
+        let weights = &forest_res.local_weights;
+        let mut res = forest_res.local.iter().map(|(&col, vals)| {
+            (col, vals.iter().zip(weights.iter()).map(|(&val, &w)| val / w / self.n_perm as f64).collect())
+        }).collect();
+        add_unused(&forest_res, &mut res, vec![0.; y.len()]);
+        self.add_aggregation_time(agg_start);
+        return LocalImportance::new(res, y.len(), df.get_sample_ids().map(|ids| ids.to_vec()));
+    }
+
+    // Permutation importance within subgroups of samples (e.g. sex or ancestry), groups holding the group id of each
+    // sample. Error increases of the out-of-bag samples of a group are pooled over trees, so a column whose effect
+    // exists only in one group stands out there. Groups never out-of-bag get NaN.
+    pub fn importance_by_group<T, U>(&self, df: &T, y: &U, groups: &[usize], ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> GroupImportance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        if groups.len() != y.len() {
+            panic!("groups must have one id for each sample.");
+        }
+
+        let measures = Measures{mda: false, mdi: false, local: true, per_tree: false};
+        let forest_res = self.run_trees(df, y, ntree, 0..ntree, mtry, shadow_vars, max_tree_depth, multithread, measures);
+        let agg_start = self.collect_stats.then(Instant::now);
+        let mut group_ids = groups.to_vec();
+        group_ids.sort();
+        group_ids.dedup();
+
+        let pooled = |rows: &dyn Fn(usize) -> bool| {
+            let w: f64 = forest_res.local_weights.iter().enumerate().filter(|&(i, _)| rows(i)).map(|(_, &w)| w).sum();
+            let mut res: Importance<SplitIndex::Col> = forest_res.local.iter().map(|(&col, vals)| {
+                let val: f64 = vals.iter().enumerate().filter(|&(i, _)| rows(i)).map(|(_, &val)| val).sum();
+                (col, val / w / self.n_perm as f64)
+            }).collect();
+            add_unused(&forest_res, &mut res, 0.);
+            res
+        };
+        let overall = pooled(&|_| true);
+        let per_group = group_ids.into_iter().map(|g| (g, pooled(&|i| groups[i] == g))).collect();
+        self.add_aggregation_time(agg_start);
+        return GroupImportance{overall, per_group};
+    }
+
+    pub fn importance<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> Importance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+
+        let measures = Measures{mda: true, mdi: false, local: false, per_tree: false};
+        let forest_res = self.run_trees(df, y, ntree, 0..ntree, mtry, shadow_vars, max_tree_depth, multithread, measures);
+        let agg_start = self.collect_stats.then(Instant::now);
+        let res = self.mean_decrease_accuracy(&forest_res);
+        self.add_aggregation_time(agg_start);
 
         return res;
     }
-}
\ No newline at end of file
+
+    // importance keyed by the column keys of df, see XDf::set_col_keys. Shadows and columns without a key are
+    // left out, None when df has no keys.
+    pub fn importance_by_key<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> Option<Importance<String>>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        let keys = df.get_col_keys()?;
+        return Some(keys.by_key(&self.importance(df, y, ntree, mtry, shadow_vars, max_tree_depth, multithread)));
+    }
+}
+
+// Importance over all samples and within each group of RandomForest::importance_by_group, keyed by group id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupImportance<T: Hash + Eq> {
+    pub overall: Importance<T>,
+    pub per_group: HashMap<usize, Importance<T>>
+}
+
+// Trees grown by RandomForest::fit.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "Y: serde::Serialize, SplitIndex: serde::Serialize, SplitIndex::Col: serde::Serialize",
+    deserialize = "Y: serde::Deserialize<'de>, SplitIndex: serde::Deserialize<'de>, SplitIndex::Col: serde::Deserialize<'de>"
+)))]
+pub struct Forest<Y, SplitIndex> where
+    SplitIndex: ColumnIdentifiable
+{
+    trees: Vec<Tree<Y, SplitIndex>>,
+    oob_votes: Option<Vec<TreeOobVotes<Y>>>,
+    // Class and calibrator of Forest::calibrate.
+    #[cfg_attr(feature = "serde", serde(default))]
+    calibration: Option<(Y, Calibrator)>,
+    // The other class when the response of Forest::calibrate had two, its probability being the complement.
+    #[cfg_attr(feature = "serde", serde(default))]
+    calibration_other: Option<Y>,
+    // OOB accuracy of each tree when fit with RandomForest::set_tree_weighting, weighting its votes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    tree_weights: Option<Vec<f64>>,
+    // Ids of the training rows when the data had them, see XDf::set_sample_ids.
+    #[cfg_attr(feature = "serde", serde(default))]
+    sample_ids: Option<Vec<String>>
+}
+
+impl<Y, SplitIndex> Forest<Y, SplitIndex> where
+    Y: Copy + Debug + PartialEq,
+    SplitIndex: ColumnIdentifiable + Clone + Copy
+{
+    pub fn get_trees(&self) -> &[Tree<Y, SplitIndex>] {
+        return &self.trees;
+    }
+
+    #[cfg(feature = "import")]
+    pub(crate) fn from_trees(trees: Vec<Tree<Y, SplitIndex>>) -> Forest<Y, SplitIndex> {
+        return Forest{trees, oob_votes: None, calibration: None, calibration_other: None, tree_weights: None, sample_ids: None};
+    }
+
+    // Permutation importance of the trees on the rows of mask, e.g. held-out rows of an imported forest whose
+    // in-bag rows are unknown. Per sample error increase averaged over permutation rounds and the trees splitting
+    // on the column, as in RandomForest::importance. Trees take the permutation streams of n_perm rounds.
+    pub fn permutation_importance<T, U>(&self, df: &T, y: &U, mask: &Mask, n_perm: usize) -> Importance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y>,
+        U: Response<Y>
+    {
+        return self.permutation_importance_with(mask, n_perm, |tree, rng_factory| tree.importance_with_streams(df, y, mask, n_perm, rng_factory));
+    }
+
+    // As permutation_importance, the unpermuted leaves of each tree on the rows of data_id kept in cache. A
+    // second call on the same data, e.g. with another n_perm, skips the unpermuted predictions. Call
+    // ApplyCache::invalidate when the data behind data_id changes.
+    pub fn permutation_importance_cached<T, U>(&self, df: &T, y: &U, mask: &Mask, n_perm: usize, cache: &ApplyCache, data_id: u64) -> Importance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y>,
+        U: Response<Y>
+    {
+        return self.permutation_importance_with(mask, n_perm, |tree, rng_factory| tree.importance_cached(df, y, mask, n_perm, rng_factory, cache, data_id));
+    }
+
+    fn permutation_importance_with<F>(&self, mask: &Mask, n_perm: usize, tree_importance: F) -> Importance<SplitIndex::Col>
+    where
+        F: Fn(&Tree<Y, SplitIndex>, &RngFactory) -> ImportanceTree<SplitIndex::Col>
+    {
+        if n_perm == 0 {
+            panic!("Number of permutation rounds must be larger than 0.");
+        }
+        let mut stats: HashMap<SplitIndex::Col, (f64, usize)> = HashMap::new();
+        for tree in self.trees.iter() {
+            let mut rng_factory = tree.get_rng_factory().clone();
+            rng_factory.set_nperm(n_perm);
+            for (col, val) in tree_importance(tree, &rng_factory) {
+                let entry = stats.entry(col).or_insert((0., 0));
+                entry.0 += val;
+                entry.1 += 1;
+            }
+        }
+        return stats.into_iter()
+            .map(|(col, (sum, n))| (col, sum / n as f64 / mask.len() as f64 / n_perm as f64))
+            .collect();
+    }
+
+    // Votes of each tree on its OOB rows, in tree order, when fit with RandomForest::set_keep_oob_votes.
+    // Enough for custom ensemble statistics such as margins or Breiman's strength and correlation.
+    pub fn get_oob_votes(&self) -> Option<&[TreeOobVotes<Y>]> {
+        return self.oob_votes.as_deref();
+    }
+
+    // Ids of the training rows when the data fit on had them.
+    pub fn get_sample_ids(&self) -> Option<&[String]> {
+        return self.sample_ids.as_deref();
+    }
+
+    // oob_vote_shares keyed by the ids of the training rows, rows in-bag of every tree left out. None without
+    // OOB votes or sample ids.
+    pub fn oob_vote_shares_by_id(&self, class: Y) -> Option<HashMap<String, f64>> {
+        let ids = self.sample_ids.as_ref()?;
+        let shares = self.oob_vote_shares(ids.len(), class)?;
+        return Some(ids.iter().zip(shares)
+            .filter_map(|(id, share)| share.map(|share| (id.clone(), share)))
+            .collect());
+    }
+
+    // Share of OOB votes for class of each of the nrow training rows, None for rows in-bag of every tree.
+    pub fn oob_vote_shares(&self, nrow: usize, class: Y) -> Option<Vec<Option<f64>>> {
+        let oob_votes = self.oob_votes.as_ref()?;
+        let mut counts = vec![(0usize, 0usize); nrow];
+        for tree_votes in oob_votes.iter() {
+            for (&row, &vote) in tree_votes.oob.get_mask().iter().zip(tree_votes.votes.iter()) {
+                counts[row].1 += 1;
+                if vote == class {
+                    counts[row].0 += 1;
+                }
+            }
+        }
+        return Some(counts.into_iter()
+            .map(|(n_class, n)| (n > 0).then(|| n_class as f64 / n as f64))
+            .collect());
+    }
+
+    // Fits a calibrator of the probability of class on the OOB vote shares of the training rows, y being the
+    // response the forest was fit on, and applies it in predict_proba of class. With a binary response
+    // predict_proba of the other class is the complement. Needs RandomForest::set_keep_oob_votes, rows in-bag of
+    // every tree are left out.
+    pub fn calibrate<U>(&mut self, y: &U, class: Y, method: CalibrationMethod)
+    where
+        U: Response<Y>
+    {
+        let labels = y.as_vector_ref();
+        let mut others: Vec<Y> = vec![];
+        for &label in labels.iter() {
+            if label != class && !others.contains(&label) {
+                others.push(label);
+            }
+        }
+        let shares = match self.oob_vote_shares(labels.len(), class) {
+            Some(shares) => shares,
+            None => panic!("Calibration needs OOB votes, see RandomForest::set_keep_oob_votes.")
+        };
+        let (scores, labels): (Vec<f64>, Vec<bool>) = shares.iter().zip(labels.iter())
+            .filter_map(|(share, &label)| share.map(|share| (share, label == class)))
+            .unzip();
+        self.calibration = Some((class, Calibrator::fit(method, &scores, &labels)));
+        self.calibration_other = match others.len() {
+            1 => Some(others[0]),
+            _ => None
+        };
+    }
+
+    pub fn get_calibrator(&self) -> Option<&Calibrator> {
+        return self.calibration.as_ref().map(|(_, calibrator)| calibrator);
+    }
+
+    // Breiman's margin diagnostics from the OOB votes, None without RandomForest::set_keep_oob_votes or when no
+    // row got an OOB vote. y is the response the forest was fit on.
+    pub fn diagnostics<U>(&self, y: &U) -> Option<ForestDiagnostics>
+    where
+        U: Response<Y>
+    {
+        let oob_votes = self.oob_votes.as_ref()?;
+        let labels = y.as_vector_ref();
+        let mut row_votes: Vec<Vec<(Y, usize)>> = vec![vec![]; labels.len()];
+        for tree_votes in oob_votes.iter() {
+            for (&row, &vote) in tree_votes.oob.get_mask().iter().zip(tree_votes.votes.iter()) {
+                match row_votes[row].iter_mut().find(|(class, _)| *class == vote) {
+                    Some((_, n)) => *n += 1,
+                    None => row_votes[row].push((vote, 1))
+                }
+            }
+        }
+
+        // Most voted class other than the true one, None when all votes of the row are for its class
+        let mut runner_up: Vec<Option<Y>> = vec![None; labels.len()];
+        let mut margins = vec![None; labels.len()];
+        for (row, votes) in row_votes.iter().enumerate() {
+            let n: usize = votes.iter().map(|(_, n)| n).sum();
+            if n == 0 {
+                continue;
+            }
+            let n_true = votes.iter().find(|(class, _)| *class == labels[row]).map_or(0, |(_, n)| *n);
+            let other = votes.iter().filter(|(class, _)| *class != labels[row]).max_by_key(|(_, n)| *n);
+            runner_up[row] = other.map(|(class, _)| *class);
+            margins[row] = Some((n_true as f64 - other.map_or(0, |(_, n)| *n) as f64) / n as f64);
+        }
+
+        let voted: Vec<f64> = margins.iter().flatten().cloned().collect();
+        if voted.is_empty() {
+            return None;
+        }
+        let strength = voted.iter().sum::<f64>() / voted.len() as f64;
+        let margin_var = voted.iter().map(|m| m * m).sum::<f64>() / voted.len() as f64 - strength * strength;
+
+        // Standard deviation of each tree's raw margin, I(vote is true class) - I(vote is runner-up), over its OOB
+        // rows. Trees without OOB rows are left out.
+        let with_oob: Vec<&TreeOobVotes<Y>> = oob_votes.iter().filter(|tree_votes| tree_votes.oob.len() > 0).collect();
+        let sd_mean = with_oob.iter().map(|tree_votes| {
+            let (mut n_true, mut n_runner_up) = (0, 0);
+            for (&row, &vote) in tree_votes.oob.get_mask().iter().zip(tree_votes.votes.iter()) {
+                if vote == labels[row] {
+                    n_true += 1;
+                } else if Some(vote) == runner_up[row] {
+                    n_runner_up += 1;
+                }
+            }
+            let p1 = n_true as f64 / tree_votes.oob.len() as f64;
+            let p2 = n_runner_up as f64 / tree_votes.oob.len() as f64;
+            (p1 + p2 - (p1 - p2).powi(2)).sqrt()
+        }).sum::<f64>() / with_oob.len() as f64;
+
+        // Trees right on every OOB row have no margin variance, nor do their votes correlate
+        let correlation = match sd_mean > 0. {
+            true => margin_var / (sd_mean * sd_mean),
+            false => 0.
+        };
+        let error_bound = match strength > 0. {
+            true => correlation * (1. - strength * strength) / (strength * strength),
+            false => f64::INFINITY
+        };
+        return Some(ForestDiagnostics{margins, strength, correlation, error_bound});
+    }
+
+    // Share of training rows whose OOB votes do not favour their class, ties counting as errors. None without
+    // RandomForest::set_keep_oob_votes, rows in-bag of every tree are left out.
+    pub fn oob_error<U>(&self, y: &U) -> Option<f64>
+    where
+        U: Response<Y>
+    {
+        let margins = self.diagnostics(y)?.margins;
+        let voted: Vec<f64> = margins.into_iter().flatten().collect();
+        if voted.is_empty() {
+            return None;
+        }
+        return Some(voted.iter().filter(|&&margin| margin <= 0.).count() as f64 / voted.len() as f64);
+    }
+
+    // Share of tree votes for class of each row in mask, in mask order. Rows missing values of split columns
+    // are routed by na_policy, with NaPolicy::Weighted a tree splits its vote over the leaves the row reaches.
+    pub fn predict_votes<R>(&self, rows: &R, mask: &Mask, class: Y, na_policy: NaPolicy) -> Vec<f64>
+    where
+        R: RowView<SplitIndex>
+    {
+        let mut res = vec![0.; mask.len()];
+        self.predict_votes_into(rows, mask, class, na_policy, &mut res);
+        return res;
+    }
+
+    // predict_votes keyed by the sample ids of the rows of mask, None when df has no sample ids.
+    pub fn predict_votes_by_id<T>(&self, df: &T, mask: &Mask, class: Y, na_policy: NaPolicy) -> Option<HashMap<String, f64>>
+    where
+        T: RowView<SplitIndex> + DataInterface<SplitIndex, Y>
+    {
+        let ids = df.get_sample_ids()?;
+        let shares = self.predict_votes(df, mask, class, na_policy);
+        return Some(mask.get_mask().iter().zip(shares).map(|(&row, share)| (ids[row].clone(), share)).collect());
+    }
+
+    // As predict_votes under NaPolicy::Majority, the leaves of each tree on the rows of data_id kept in cache, so
+    // repeated predictions on the same data walk the trees once. Call ApplyCache::invalidate when the data behind
+    // data_id changes.
+    pub fn predict_votes_cached<R>(&self, rows: &R, mask: &Mask, class: Y, cache: &ApplyCache, data_id: u64) -> Vec<f64>
+    where
+        R: RowView<SplitIndex>
+    {
+        let (tree_weights, total) = self.vote_weights();
+        let mut votes = vec![0.; mask.len()];
+        for (tree, &tree_weight) in self.trees.iter().zip(tree_weights.iter()) {
+            for (vote, pred) in votes.iter_mut().zip(tree.predict_rows_cached(rows, mask, cache, data_id)) {
+                if pred == class {
+                    *vote += tree_weight;
+                }
+            }
+        }
+        return votes.into_iter().map(|vote| vote / total).collect();
+    }
+
+    // As predict_votes, with the share of mask[i] written to out[i], e.g. to reuse one buffer over many masks.
+    pub fn predict_votes_into<R>(&self, rows: &R, mask: &Mask, class: Y, na_policy: NaPolicy, out: &mut [f64])
+    where
+        R: RowView<SplitIndex>
+    {
+        if out.len() != mask.len() {
+            panic!("Output must have a place for each row of the mask.");
+        }
+        let (tree_weights, total) = self.vote_weights();
+        for (share, &row) in out.iter_mut().zip(mask.get_mask().iter()) {
+            let votes: f64 = self.trees.iter().zip(tree_weights.iter())
+                .flat_map(|(tree, &tree_weight)| tree.predict_class_weights(rows, row, na_policy).into_iter().map(move |(leaf_class, weight)| (leaf_class, tree_weight * weight)))
+                .filter(|(leaf_class, _)| *leaf_class == class)
+                .map(|(_, weight)| weight)
+                .sum();
+            *share = votes / total;
+        }
+    }
+
+    // Weight of each tree's vote with their sum. Trees weigh their OOB accuracy when fit with
+    // RandomForest::set_tree_weighting, one otherwise or when no tree is right on any OOB row.
+    fn vote_weights(&self) -> (Vec<f64>, f64) {
+        if self.trees.is_empty() {
+            panic!("Forest must have at least one tree to predict.");
+        }
+        let total: f64 = self.tree_weights.iter().flatten().sum();
+        return match &self.tree_weights {
+            Some(weights) if total > 0. => (weights.clone(), total),
+            _ => (vec![1.; self.trees.len()], self.trees.len() as f64)
+        };
+    }
+
+    // Number of trees splitting on cols[i] above a split on cols[j] on some root-to-leaf path, at [i][j].
+    // Pairs frequently sharing paths hint at interactions, e.g. to screen top ranked columns for epistasis.
+    // The diagonal counts trees splitting on a column twice on a path.
+    pub fn split_pair_counts(&self, cols: &[SplitIndex::Col]) -> Vec<Vec<usize>> {
+        let mut res = vec![vec![0; cols.len()]; cols.len()];
+        for tree in self.trees.iter() {
+            let pairs = tree.split_pairs();
+            for (i, &upper) in cols.iter().enumerate() {
+                for (j, &lower) in cols.iter().enumerate() {
+                    if pairs.contains(&(upper, lower)) {
+                        res[i][j] += 1;
+                    }
+                }
+            }
+        }
+        return res;
+    }
+
+    // Probability of class for each row in mask, in mask order, averaged over trees. Leaf class frequencies are
+    // smoothed with alpha, see Tree::predict_proba. Requires RandomForest::set_leaf_counts before fit.
+    // For the class of Forest::calibrate, the calibrator maps the vote shares of predict_votes instead, as it was
+    // fitted on OOB vote shares, and alpha is unused. For the other class of a binary response it is the
+    // complement of that.
+    pub fn predict_proba<R>(&self, rows: &R, mask: &Mask, class: Y, na_policy: NaPolicy, alpha: f64) -> Vec<f64>
+    where
+        R: RowView<SplitIndex>
+    {
+        if let Some((cal_class, calibrator)) = &self.calibration {
+            if *cal_class == class {
+                return self.predict_votes(rows, mask, class, na_policy).into_iter().map(|p| calibrator.calibrate(p)).collect();
+            }
+            if self.calibration_other == Some(class) {
+                return self.predict_votes(rows, mask, *cal_class, na_policy).into_iter().map(|p| 1. - calibrator.calibrate(p)).collect();
+            }
+        }
+
+        let (tree_weights, total) = self.vote_weights();
+        return mask.get_mask().iter().map(|&row| {
+            let p: f64 = self.trees.iter().zip(tree_weights.iter())
+                .flat_map(|(tree, &tree_weight)| tree.predict_proba(rows, row, na_policy, alpha).into_iter().map(move |(tree_class, p)| (tree_class, tree_weight * p)))
+                .filter(|(tree_class, _)| *tree_class == class)
+                .map(|(_, p)| p)
+                .sum();
+            p / total
+        }).collect();
+    }
+
+    // Conditional quantiles of values, given per training row, for each row in mask, in mask order. Training
+    // rows are weighted by the leaves they share with the row, averaged over trees, see Tree::predict_row_weights.
+    // Each quantile q is the lowest value whose weighted cumulative share reaches q. Requires
+    // RandomForest::set_leaf_rows before fit.
+    pub fn predict_quantiles<R>(&self, rows: &R, mask: &Mask, values: &[f64], quantiles: &[f64], na_policy: NaPolicy) -> Vec<Vec<f64>>
+    where
+        R: RowView<SplitIndex>
+    {
+        if quantiles.iter().any(|q| !(0. ..=1.).contains(q)) {
+            panic!("Quantiles must be within [0, 1].");
+        }
+        let (tree_weights, total) = self.vote_weights();
+        return mask.get_mask().iter().map(|&row| {
+            let mut weighted: Vec<(f64, f64)> = self.trees.iter().zip(tree_weights.iter())
+                .flat_map(|(tree, &tree_weight)| tree.predict_row_weights(rows, row, na_policy).into_iter().map(move |(i, w)| (i, tree_weight * w / total)))
+                .map(|(i, w)| match values.get(i) {
+                    Some(&value) => (value, w),
+                    None => panic!("Values must have a value per training row.")
+                })
+                .collect();
+            weighted.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let sum: f64 = weighted.iter().map(|(_, w)| w).sum();
+            quantiles.iter().map(|&q| {
+                // Slack for rounding of the cumulative sum, e.g. the median of two rows of weight 0.5
+                let target = q * sum * (1. - 1e-12);
+                let mut cum = 0.;
+                for &(value, w) in weighted.iter() {
+                    cum += w;
+                    if cum >= target {
+                        return value;
+                    }
+                }
+                return weighted.last().map_or(f64::NAN, |(value, _)| *value);
+            }).collect()
+        }).collect();
+    }
+}
+
+impl<Y, SplitIndex> Forest<Y, SplitIndex> where
+    Y: Copy + Debug + PartialEq + CompactClass,
+    SplitIndex: ColumnIdentifiable + Clone + Copy + CompactSplit
+{
+    // Compact binary form keeping everything prediction, diagnostics and calibration use, a few bytes per node
+    // instead of the serde one, see compact. Growth settings of trees are left out, so the read forest predicts
+    // as this one but cannot go on growing. Buffer w, it takes many small writes.
+    pub fn write_compact<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(COMPACT_MAGIC)?;
+        write_varint(w, self.trees.len() as u64)?;
+        for tree in self.trees.iter() {
+            tree.write_compact(w)?;
+        }
+        write_flag(w, self.oob_votes.is_some())?;
+        for tree_votes in self.oob_votes.iter().flatten() {
+            write_mask(w, &tree_votes.oob)?;
+            for vote in tree_votes.votes.iter() {
+                write_class(w, vote)?;
+            }
+        }
+        write_flag(w, self.calibration.is_some())?;
+        if let Some((class, calibrator)) = &self.calibration {
+            write_class(w, class)?;
+            write_calibrator(w, calibrator)?;
+            write_flag(w, self.calibration_other.is_some())?;
+            if let Some(other) = &self.calibration_other {
+                write_class(w, other)?;
+            }
+        }
+        write_flag(w, self.tree_weights.is_some())?;
+        for &weight in self.tree_weights.iter().flatten() {
+            write_f64(w, weight)?;
+        }
+        write_flag(w, self.sample_ids.is_some())?;
+        if let Some(ids) = &self.sample_ids {
+            write_varint(w, ids.len() as u64)?;
+            for id in ids.iter() {
+                write_string(w, id)?;
+            }
+        }
+        return Ok(());
+    }
+
+    // Forest of write_compact. Buffer r, it takes many small reads.
+    pub fn read_compact<R: Read>(r: &mut R) -> Result<Forest<Y, SplitIndex>, CompactError> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if magic[..3] != COMPACT_MAGIC[..3] {
+            return Err(CompactError::Magic);
+        }
+        if magic[3] != COMPACT_MAGIC[3] {
+            return Err(CompactError::Version(magic[3]));
+        }
+        let ntree = read_usize(r)?;
+        let trees = read_vec(r, ntree, Tree::read_compact)?;
+        let oob_votes = match read_flag(r)? {
+            true => Some(read_vec(r, ntree, |r| {
+                let oob = read_mask(r)?;
+                let votes = read_vec(r, oob.len(), read_class)?;
+                Ok(TreeOobVotes{oob, votes})
+            })?),
+            false => None
+        };
+        let (calibration, calibration_other) = match read_flag(r)? {
+            true => {
+                let calibration = (read_class(r)?, read_calibrator(r)?);
+                let other = match read_flag(r)? {
+                    true => Some(read_class(r)?),
+                    false => None
+                };
+                (Some(calibration), other)
+            },
+            false => (None, None)
+        };
+        let tree_weights = match read_flag(r)? {
+            true => Some(read_vec(r, ntree, read_f64)?),
+            false => None
+        };
+        let sample_ids = match read_flag(r)? {
+            true => {
+                let n_ids = read_usize(r)?;
+                Some(read_vec(r, n_ids, read_string)?)
+            },
+            false => None
+        };
+        return Ok(Forest{trees, oob_votes, calibration, calibration_other, tree_weights, sample_ids});
+    }
+}
+
+// Importance of a forest grown on several binary responses at once. Combined importance counts
+// errors over all responses, so it is the sum of the per response ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultiResponseImportance<T: Hash + Eq> {
+    pub combined: Importance<T>,
+    pub per_response: Vec<Importance<T>>
+}
+
+impl <SplitIndex> RandomForest<MultiY, SplitIndex> where
+    SplitIndex: ColumnIdentifiable + Clone + Copy + Send + Sync
+{
+    // Splits minimise gini averaged over responses. The trees are grown once and the permuted predictions of
+    // each are scored against all responses and each response alone, so combined equals importance on y.
+    pub fn importance_multi_response<T>(&self, df: &T, y: &YMultiBool, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> MultiResponseImportance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, MultiY> + Sync + Send
+    {
+        self.check_sampling(ntree, y.len());
+        let _span = info_span!("importance_multi_response", ntree, n_responses = y.n_responses()).entered();
+        let rng_factory = self.rng_factory(df.get_ncol(), ntree);
+        let ys: Vec<YMultiBool> = std::iter::once(None).chain((0..y.n_responses()).map(Some)).map(|r| y.scoring(r)).collect();
+        let all_rows: Vec<usize> = (0..y.len()).collect();
+        let trees = map_trees(0..ntree, multithread, |ith_tree| {
+            let (mask, mut tree) = self.next_tree(df, &ys[0], mtry, shadow_vars, &rng_factory, max_tree_depth, ith_tree, false);
+            let oob_mask = mask.inverse(&all_rows);
+            let weight = match self.tree_weighting {
+                true => tree.oob_accuracy(df, &ys[0], &oob_mask),
+                false => 1.
+            };
+            (oob_mask.len(), weight, tree.importance_each(df, &ys, &oob_mask, self.n_perm, &rng_factory))
+        });
+
+        // Running MDA per response as in run_trees, trees added in tree order.
+        let mut stats: Vec<HashMap<SplitIndex::Col, WeightedStats>> = ys.iter().map(|_| HashMap::new()).collect();
+        let mut oob_total = 0;
+        for (oob_n, weight, tree_imps) in trees {
+            oob_total += oob_n;
+            for (response_stats, tree_imp) in stats.iter_mut().zip(tree_imps) {
+                for (col, val) in tree_imp {
+                    response_stats.entry(col).or_default().push(val, weight);
+                }
+            }
+        }
+        let unused = match self.include_unused {
+            true => df.get_col_ids().into_iter().chain(df.get_shadow_col_ids().into_iter().filter(|_| shadow_vars)).collect(),
+            false => vec![]
+        };
+        let mut per_response: Vec<Importance<SplitIndex::Col>> = stats.into_iter().map(|response_stats| {
+            let mut res = mda_means(response_stats, oob_total, ntree, self.n_perm);
+            for &col in unused.iter() {
+                res.entry(col).or_insert(0.);
+            }
+            res
+        }).collect();
+        let combined = per_response.remove(0);
+        return MultiResponseImportance{combined, per_response};
+    }
+}
+
+// Permutation importance of a forest next to that of the same forest fit on a permuted response, see
+// RandomForest::null_importance. null_max is the highest null importance; within_null lists the columns with
+// positive real importance not above it, sorted by importance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NullImportance<T: Hash + Eq> {
+    pub real: Importance<T>,
+    pub null: Importance<T>,
+    pub null_max: f64,
+    pub within_null: Vec<T>
+}
+
+impl <SplitIndex> RandomForest<bool, SplitIndex> where
+    SplitIndex: ColumnIdentifiable + Clone + Copy + Send + Sync
+{
+    // Quick sanity check before a full Boruta run: importance on y and on one permutation of y, which has no
+    // association with any column. Warns when positive real scores fall within the null range, as they are
+    // then no larger than importance arising by chance.
+    pub fn null_importance<T>(&self, df: &T, y: &YBool, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> NullImportance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, bool> + Sync + Send
+    {
+        let _span = info_span!("null_importance").entered();
+        let real = self.importance(df, y, ntree, mtry, shadow_vars, max_tree_depth, multithread);
+
+        let mut y_null = y.as_vector();
+        self.rng_factory(df.get_ncol(), ntree).new_rng_response().shuffle(&mut y_null);
+        let null = self.importance(df, &YBool::new(&y_null), ntree, mtry, shadow_vars, max_tree_depth, multithread);
+        let null_max = null.values().cloned().fold(0., f64::max);
+
+        // Stable sort keeps column order among ties
+        let mut within_null: Vec<(SplitIndex::Col, f64)> = df.get_col_ids().into_iter()
+            .filter_map(|col| real.get(&col).map(|&imp| (col, imp)))
+            .filter(|&(_, imp)| imp > 0. && imp <= null_max)
+            .collect();
+        within_null.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let n_positive = real.values().filter(|&&imp| imp > 0.).count();
+        if !within_null.is_empty() {
+            warn!(within_null = within_null.len(), positive = n_positive, null_max, "Positive importance within the null range");
+        }
+
+        let within_null = within_null.into_iter().map(|(col, _)| col).collect();
+        return NullImportance{real, null, null_max, within_null};
+    }
+}
+
+impl <Y, SplitIndex> RandomForest<Y, SplitIndex> where
+    Y: Copy + Debug + PartialEq + Send + Sync,
+    SplitIndex: ColumnIdentifiable + Clone + Copy + Send + Sync
+{
+    // Permutation importance on the smoothed leaf probabilities of Tree::proba_importance, which keeps small leaves
+    // from counting a lucky hard vote in full. ErrorMetric::CrossEntropy gives the log loss of the probabilities.
+    // Normalized as importance. Requires set_leaf_counts.
+    pub fn proba_importance<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>, alpha: f64) -> Importance<SplitIndex::Col>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        if !self.leaf_counts {
+            panic!("Leaf counts must be kept for probability importance, see set_leaf_counts.");
+        }
+        self.check_sampling(ntree, y.len());
+        let rng_factory = self.rng_factory(df.get_ncol(), ntree);
+        let all_rows: Vec<usize> = (0..y.len()).collect();
+        let trees = map_trees(0..ntree, multithread, |ith_tree| {
+            let (mask, mut tree) = self.next_tree(df, y, mtry, shadow_vars, &rng_factory, max_tree_depth, ith_tree, false);
+            let oob_mask = mask.inverse(&all_rows);
+            let weight = match self.tree_weighting {
+                true => tree.oob_accuracy(df, y, &oob_mask),
+                false => 1.
+            };
+            (oob_mask.len(), weight, tree.proba_importance(df, y, &oob_mask, self.n_perm, alpha))
+        });
+
+        let mut stats: HashMap<SplitIndex::Col, WeightedStats> = HashMap::new();
+        let mut oob_total = 0;
+        for (oob_n, weight, tree_imp) in trees {
+            oob_total += oob_n;
+            for (col, val) in tree_imp {
+                stats.entry(col).or_default().push(val, weight);
+            }
+        }
+        return mda_means(stats, oob_total, ntree, self.n_perm);
+    }
+}
+
+impl <Y, SplitIndex> RandomForest<Y, SplitIndex> where
+    Y: Copy + Debug + PartialEq + Send + Sync,
+    SplitIndex: PivotIdentifiable + Clone + Copy + Send + Sync
+{
+    // Permutation importance broken down by the pivots of a column: a column is permuted only at its splits on one
+    // pivot. For genotypes, splits of NotRed (0 against 1 or 2) carry dominant effects of the alternative allele and
+    // splits of NotBlue (2 against 0 or 1) recessive ones. As importance, a pair is averaged over the trees using it
+    // and weighted, scaled by mean OOB size and n_perm.
+    pub fn pivot_importance<T, U>(&self, df: &T, y: &U, ntree: usize, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, multithread: Option<usize>) -> PivotImportance<SplitIndex::Col, SplitIndex::Pivot>
+    where
+        T: DataInterface<SplitIndex, Y> + Sync + Send,
+        U: Response<Y> + Sync + Send
+    {
+        self.check_sampling(ntree, y.len());
+        let rng_factory = self.rng_factory(df.get_ncol(), ntree);
+        let all_rows: Vec<usize> = (0..y.len()).collect();
+        let trees = map_trees(0..ntree, multithread, |ith_tree| {
+            let (mask, mut tree) = self.next_tree(df, y, mtry, shadow_vars, &rng_factory, max_tree_depth, ith_tree, false);
+            let oob_mask = mask.inverse(&all_rows);
+            let weight = match self.tree_weighting {
+                true => tree.oob_accuracy(df, y, &oob_mask),
+                false => 1.
+            };
+            (oob_mask.len(), weight, tree.pivot_importance(df, y, &oob_mask, self.n_perm))
+        });
+
+        // Trees added in tree order, so the result does not depend on the number of threads.
+        let mut stats: HashMap<(SplitIndex::Col, SplitIndex::Pivot), WeightedStats> = HashMap::new();
+        let mut oob_total = 0;
+        for (oob_n, weight, tree_imp) in trees {
+            oob_total += oob_n;
+            for (key, val) in tree_imp {
+                stats.entry(key).or_default().push(val, weight);
+            }
+        }
+        return mda_means(stats, oob_total, ntree, self.n_perm);
+    }
+}
+
+// Joins per tree importance computed for disjoint tree ranges of the same forest, values of a column in the
+// order of parts. PartialImportance::merge also checks the parts cover the forest.
+pub fn merge_importance_per_tree<T>(parts: Vec<ImportancePerTree<T>>) -> ImportancePerTree<T>
+where
+    T: Hash + Eq
+{
+    let mut res: ImportancePerTree<T> = HashMap::new();
+    for part in parts {
+        for (col, val) in part {
+            res.entry(col).or_insert(vec![]).extend(val);
+        }
+    }
+    return res;
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::assert_approx_eq;
+    use crate::gini::Float;
+    use crate::data_interface::multi_x::ColSplitIndex;
+    use crate::data_interface::y_bool::{Y, YBool};
+    use crate::mask::Mask;
+    use crate::random_forest::{Forest, TreeOobVotes, WeightedStats};
+    #[cfg(feature = "serde")]
+    use crate::random_forest::{ForestSnapshot, RandomForest};
+    #[cfg(feature = "serde")]
+    use crate::data_interface::multi_x::{MultiX, XDf};
+    #[cfg(feature = "serde")]
+    use crate::data_interface::three_val::ThreeValCol;
+
+    #[test]
+    fn weighted_stats_two_pass() {
+        let x = [3., -1., 4., 1.5, 0., 9.];
+        let w = [1., 0.5, 2., 0., 1., 0.25];
+        let mut stats = WeightedStats::default();
+        for (&x, &w) in x.iter().zip(w.iter()) {
+            stats.push(x, w);
+        }
+
+        let sum_w: f64 = w.iter().sum();
+        let mean = x.iter().zip(w.iter()).map(|(&x, &w)| x * w).sum::<f64>() / sum_w;
+        let var = x.iter().zip(w.iter()).map(|(&x, &w)| w * (x - mean).powi(2)).sum::<f64>() / sum_w;
+        // Accumulated in Float, so compared to its precision
+        let eps = 1e3 * Float::EPSILON as f64;
+        assert_approx_eq!(f64, stats.mean(), mean, epsilon = eps);
+        assert_approx_eq!(f64, stats.var(), var, epsilon = eps);
+        assert!(WeightedStats::default().mean().is_nan());
+    }
+
+    #[test]
+    fn forest_diagnostics_from_votes() {
+        let y = YBool::new(&[true, true, false]);
+        let forest: Forest<Y, ColSplitIndex> = Forest{trees: vec![], oob_votes: Some(vec![
+            TreeOobVotes{oob: Mask::new(vec![0, 1, 2]), votes: vec![true, false, false]},
+            TreeOobVotes{oob: Mask::new(vec![0, 2]), votes: vec![true, true]}
+        ]), calibration: None, calibration_other: None, tree_weights: None, sample_ids: None};
+        let diag = forest.diagnostics(&y).unwrap();
+
+        assert_eq!(diag.margins, [Some(1.), Some(-1.), Some(0.)]);
+        assert_approx_eq!(f64, diag.strength, 0., epsilon = 1e-12);
+        // Trees' raw margins have variance 8/9 (p1 = 2/3, p2 = 1/3) and 1 (p1 = p2 = 1/2)
+        let sd_mean = ((8f64 / 9.).sqrt() + 1.) / 2.;
+        assert_approx_eq!(f64, diag.correlation, 2. / 3. / (sd_mean * sd_mean), epsilon = 1e-12);
+        assert_eq!(diag.error_bound, f64::INFINITY);
+        // The tie of row 2 counts as an error
+        assert_approx_eq!(f64, forest.oob_error(&y).unwrap(), 2. / 3., epsilon = 1e-12);
+
+        let unvoted: Forest<Y, ColSplitIndex> = Forest{trees: vec![], oob_votes: None, calibration: None, calibration_other: None, tree_weights: None, sample_ids: None};
+        assert!(unvoted.diagnostics(&y).is_none());
+        assert!(unvoted.oob_error(&y).is_none());
+
+        // No row out-of-bag, and trees right on all their OOB rows
+        let no_oob: Forest<Y, ColSplitIndex> = Forest{trees: vec![], oob_votes: Some(vec![
+            TreeOobVotes{oob: Mask::new(vec![]), votes: vec![]}
+        ]), calibration: None, calibration_other: None, tree_weights: None, sample_ids: None};
+        assert!(no_oob.diagnostics(&y).is_none());
+        assert!(no_oob.oob_error(&y).is_none());
+        let perfect: Forest<Y, ColSplitIndex> = Forest{trees: vec![], oob_votes: Some(vec![
+            TreeOobVotes{oob: Mask::new(vec![]), votes: vec![]},
+            TreeOobVotes{oob: Mask::new(vec![0, 2]), votes: vec![true, false]}
+        ]), calibration: None, calibration_other: None, tree_weights: None, sample_ids: None};
+        let diag = perfect.diagnostics(&y).unwrap();
+        assert_eq!((diag.strength, diag.correlation, diag.error_bound), (1., 0., 0.));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn fit_resume_from_serialized_snapshot() {
+        use std::sync::{Arc, Mutex};
+        let x1: Vec<i8> = (0..60).map(|i| (i % 3) as i8).collect();
+        let x2: Vec<i8> = (0..60).map(|i| (i / 7 % 3) as i8).collect();
+        let df = XDf::new(vec![MultiX::ThreeVal(ThreeValCol::new(&x1)), MultiX::ThreeVal(ThreeValCol::new(&x2))]);
+        let y = YBool::new(&(0..60).map(|i| i % 3 == 1 || i % 11 == 0).collect::<Vec<bool>>());
+
+        let saved = Arc::new(Mutex::new(vec![]));
+        let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(3);
+        rf.set_keep_oob_votes(true);
+        let saved_cb = Arc::clone(&saved);
+        rf.set_snapshot(4, move |snapshot| saved_cb.lock().unwrap().push(serde_json::to_string(snapshot).unwrap()));
+        let full = rf.fit(&df, &y, 10, 1, false, None);
+        assert_eq!(saved.lock().unwrap().len(), 2);
+
+        // As if the fit crashed after tree 4
+        let snapshot: ForestSnapshot<Y, ColSplitIndex> = serde_json::from_str(&saved.lock().unwrap()[0]).unwrap();
+        assert_eq!(snapshot.n_done(), 4);
+        let resumed = rf.fit_resume(&df, &y, 10, 1, false, None, snapshot);
+        let hashes = |forest: &Forest<Y, ColSplitIndex>| forest.get_trees().iter().map(|tree| tree.structure_hash()).collect::<Vec<u64>>();
+        assert_eq!(hashes(&resumed), hashes(&full));
+        assert_eq!(resumed.get_oob_votes(), full.get_oob_votes());
+
+        let other_seed: RandomForest<Y, ColSplitIndex> = RandomForest::new(4);
+        let snapshot: ForestSnapshot<Y, ColSplitIndex> = serde_json::from_str(&saved.lock().unwrap()[1]).unwrap();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| other_seed.fit_resume(&df, &y, 10, 1, false, None, snapshot)));
+        assert!(res.is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn calibrated_forest_serialized() {
+        use crate::calibration::CalibrationMethod;
+        use crate::tree::NaPolicy;
+        let x1: Vec<i8> = (0..60).map(|i| (i % 3) as i8).collect();
+        let x2: Vec<i8> = (0..60).map(|i| (i / 7 % 3) as i8).collect();
+        let df = XDf::new(vec![MultiX::ThreeVal(ThreeValCol::new(&x1)), MultiX::ThreeVal(ThreeValCol::new(&x2))]);
+        let y = YBool::new(&(0..60).map(|i| i % 3 == 1 || i % 11 == 0).collect::<Vec<bool>>());
+
+        let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(3);
+        rf.set_keep_oob_votes(true);
+        rf.set_leaf_counts(true);
+        let mut forest = rf.fit(&df, &y, 10, 1, false, None);
+        forest.calibrate(&y, true, CalibrationMethod::Platt);
+
+        let restored: Forest<Y, ColSplitIndex> = serde_json::from_str(&serde_json::to_string(&forest).unwrap()).unwrap();
+        assert!(restored.get_calibrator().is_some());
+        let mask = Mask::new((0..60).collect());
+        let restored_p = restored.predict_proba(&df, &mask, true, NaPolicy::Majority, 0.);
+        for (p, q) in restored_p.iter().zip(forest.predict_proba(&df, &mask, true, NaPolicy::Majority, 0.)) {
+            assert_approx_eq!(f64, *p, q, epsilon = 1e-12);
+        }
+    }
+}
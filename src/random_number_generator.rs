@@ -21,12 +21,11 @@ const ROTATE: u32 = 59;
 const XSHIFT: u32 = 18;
 const SPARE: u32 = 27;
 
-const SALT: u64 =  match cfg!(test) {
-    false => 77585124950184,
-    true => 0
-};
+// Added to every seed unless another salt is given explicitly.
+pub const DEFAULT_SALT: u64 = 77585124950184;
 
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rng {
     state: u64,
     increment: u64,
@@ -38,11 +37,15 @@ pub struct Rng {
 //Implementation inspired by rust-random/rand pcg32 generator and mbq/wybr package
 impl Rng {
     pub fn new(seed: u64, increment: u64) -> Self {
+        return Rng::with_salt(seed, increment, DEFAULT_SALT);
+    }
+
+    pub fn with_salt(seed: u64, increment: u64, salt: u64) -> Self {
         if increment == 0 {
             panic!("Increment must be larger than 0.");
         }
 
-        let mut pcg = Rng {state: seed+SALT, increment: increment};
+        let mut pcg = Rng {state: seed.wrapping_add(salt), increment: increment};
         pcg.state = pcg.state.wrapping_add(pcg.increment);
         pcg.step();
         pcg
@@ -115,11 +118,34 @@ impl Rng {
         }
     }
 
+    // Index of the element sample(x, 1) picks for x of length n, from the same draws but without allocations.
+    pub fn sample_index(&mut self, n: usize) -> usize {
+        if n == 0 {
+            panic!("Cannot sample from an empty range.");
+        }
+
+        // sample with k = 1, powers of 1/k are kept so draws round as there
+        let mut res = 0;
+        let mut w = self.rand_uni().ln().exp();
+        let mut i = 0;
+        while i <= n {
+            i += (self.rand_uni().ln()/(1.-w).ln()).floor() as usize + 1;
+            if i < n {
+                res = i;
+                w *= self.rand_uni().ln().exp();
+            }
+        }
+        return res;
+    }
+
     // Reservoir sampling algorithm L
     pub fn sample<T: Copy>(&mut self, x: &[T], k: usize) -> Vec<T> {
         if k > x.len() {
             panic!("Cannot sample when k is greater than n.");
         }
+        if k == 0 {
+            return vec![];
+        }
 
         let mut res: Vec<_> = (0..k).collect();
         let mut w = (self.rand_uni().ln()/k as f64).exp();
@@ -143,9 +169,10 @@ mod tests {
     use float_cmp::assert_approx_eq;
     use super::*;
 
+    // Reference values are for unsalted seeds.
     #[test]
     fn cmp_with_c() {
-        let mut x = Rng::new(21, 1);
+        let mut x = Rng::with_salt(21, 1, 0);
         let x_out: Vec<u32> = (0..6).map(|_| x.next_u32()).collect();
         //For seed=21 & stream "0" (streams are *2+1 because stream number must be odd)
         let pcg32_c_out = vec![
@@ -153,7 +180,7 @@ mod tests {
         ];
         assert_eq!(x_out, pcg32_c_out);
 
-        let mut x = Rng::new(33198495, 1);
+        let mut x = Rng::with_salt(33198495, 1, 0);
         let x_out: Vec<u32> = (0..6).map(|_| x.next_u32()).collect();
         //For seed=33198495 & stream "0" (streams are *2+1 because stream number must be odd)
         let pcg32_c_out = vec![
@@ -164,7 +191,7 @@ mod tests {
 
     #[test]
     fn upto() {
-        let mut x = Rng::new(912, 1);
+        let mut x = Rng::with_salt(912, 1, 0);
         let n = 3;
         let mut cc: Vec<u32> = std::iter::repeat(0).take(n).collect();
         for _ in 0..100 {
@@ -175,7 +202,7 @@ mod tests {
 
     #[test]
     fn shuffle() {
-        let mut x = Rng::new(81, 1);
+        let mut x = Rng::with_salt(81, 1, 0);
         let mut v: Vec<u32> = vec![];
         x.shuffle(&mut v);
         v.push(1);
@@ -209,10 +236,40 @@ mod tests {
         assert_approx_eq!(f64, res[3], 0.25, epsilon=0.01);
     }
 
+    #[test]
+    fn salt_changes_stream() {
+        let mut x = Rng::new(21, 1);
+        let mut y = Rng::with_salt(21, 1, DEFAULT_SALT);
+        let mut z = Rng::with_salt(21, 1, 0);
+        let x_out: Vec<u32> = (0..6).map(|_| x.next_u32()).collect();
+        assert_eq!(x_out, (0..6).map(|_| y.next_u32()).collect::<Vec<u32>>());
+        assert_ne!(x_out, (0..6).map(|_| z.next_u32()).collect::<Vec<u32>>());
+    }
+
     #[test]
     fn sample_k_eq_n() {
         let mut rng = Rng::new(7, 1);
         let x = ["A", "B", "C", "D", "E", "F", "G"];
         assert_eq!(rng.sample(&x, 7), &x);
     }
+
+    #[test]
+    fn sample_k_0() {
+        let mut rng = Rng::new(7, 1);
+        assert!(rng.sample(&["A", "B"], 0).is_empty());
+        assert!(rng.sample::<usize>(&[], 0).is_empty());
+    }
+
+    #[test]
+    fn sample_index_matches_sample_1() {
+        for n in [1, 2, 3, 10, 1000] {
+            let x: Vec<usize> = (0..n).collect();
+            let mut rng = Rng::new(11, 1);
+            let mut rng_ref = Rng::new(11, 1);
+            for _ in 0..200 {
+                assert_eq!(rng.sample_index(n), rng_ref.sample(&x, 1)[0]);
+            }
+            assert_eq!(rng.next_u64(), rng_ref.next_u64());
+        }
+    }
 }
\ No newline at end of file
@@ -14,28 +14,59 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::random_number_generator::Rng;
+use std::io::{self, Read, Write};
+
+use crate::compact::{read_opt, read_usize, read_varint, write_opt, write_varint, CompactError};
+use crate::random_number_generator::{Rng, DEFAULT_SALT};
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RngFactory {
     seed: u64,
+    salt: u64,
     ncol: Option<usize>,
-    ntree: Option<usize>
+    ntree: Option<usize>,
+    nperm: usize
 }
 
 impl RngFactory {
     pub fn new(seed: u64, ncol: Option<usize>, ntree: Option<usize>) -> RngFactory {
-        return RngFactory {seed, ncol, ntree};
+        return RngFactory {seed, salt: DEFAULT_SALT, ncol, ntree, nperm: 1};
+    }
+
+    // Salt added to the seed of every stream.
+    pub fn set_salt(&mut self, salt: u64) {
+        self.salt = salt;
+    }
+
+    #[inline]
+    fn new_rng(&self, incr: u64) -> Rng {
+        return Rng::with_salt(self.seed, incr, self.salt);
+    }
+
+    // Number of permutation rounds per tree to reserve streams for.
+    pub fn set_nperm(&mut self, nperm: usize) {
+        if nperm == 0 {
+            panic!("Number of permutation rounds must be larger than 0.");
+        }
+        self.nperm = nperm;
+    }
+
+    // Streams of outer iterations (e.g. Boruta iterations) deriving seeds of the factories they use.
+    // Shares streams with new_rng_shadow, so a factory should be used for one or the other.
+    #[inline]
+    pub fn new_rng_iteration(&self, iter: usize) -> Rng {
+        return self.new_rng((iter + 1) as u64);
     }
 
     #[inline]
     pub fn new_rng_shadow(&self, col_id: usize) -> Rng {
-        return Rng::new(self.seed, (col_id + 1) as u64);
+        return self.new_rng((col_id + 1) as u64);
     }
 
     #[inline]
     pub fn new_rng_tree(&self, ith_tree: usize) -> Rng {
-        return Rng::new(self.seed, (self.ncol.expect("No ncol provided") + ith_tree + 1) as u64);
+        return self.new_rng((self.ncol.expect("No ncol provided") + ith_tree + 1) as u64);
     }
 
     #[inline]
@@ -43,15 +74,64 @@ impl RngFactory {
         let incr = self.ncol.expect("No ncol provided") +
             self.ntree.expect("No ntree provided") +
             ith_tree + 1;
-        return Rng::new(self.seed, incr as u64);
+        return self.new_rng(incr as u64);
+    }
+
+    #[inline]
+    pub fn new_rng_permutation(&self, ith_tree: usize, col_id: usize, round: usize) -> Rng {
+        let ncol = self.ncol.expect("No ncol provided");
+        let ntree = self.ntree.expect("No ntree provided");
+        if round >= self.nperm {
+            panic!("Permutation round exceeds number of rounds.");
+        }
+        let incr = ncol +
+            ntree * 2 +
+            (round*ntree + ith_tree)*ncol + col_id + 1;
+        return self.new_rng(incr as u64);
+    }
+
+    // Streams for permutations of whole columns shared by all trees, placed after per tree permutations.
+    #[inline]
+    pub fn new_rng_permutation_round(&self, round: usize, col_id: usize) -> Rng {
+        let ncol = self.ncol.expect("No ncol provided");
+        let ntree = self.ntree.expect("No ntree provided");
+        let incr = ncol +
+            ntree * 2 +
+            self.nperm*ntree*ncol +
+            round*ncol + col_id + 1;
+        return self.new_rng(incr as u64);
     }
 
+    // Stream of response permutations, e.g. for null importance, placed after all column permutations.
     #[inline]
-    pub fn new_rng_permutation(&self, ith_tree: usize, col_id: usize) -> Rng {
+    pub fn new_rng_response(&self) -> Rng {
         let ncol = self.ncol.expect("No ncol provided");
+        let ntree = self.ntree.expect("No ntree provided");
         let incr = ncol +
-            self.ntree.expect("No ntree provided") * 2 +
-            ith_tree*ncol + col_id + 1;
-        return Rng::new(self.seed, incr as u64);
+            ntree * 2 +
+            self.nperm*ntree*ncol +
+            self.nperm*ncol + 1;
+        return self.new_rng(incr as u64);
     }
-}
\ No newline at end of file
+
+    pub(crate) fn get_ncol(&self) -> Option<usize> {
+        return self.ncol;
+    }
+
+    pub(crate) fn write_compact<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_varint(w, self.seed)?;
+        write_varint(w, self.salt)?;
+        write_opt(w, self.ncol)?;
+        write_opt(w, self.ntree)?;
+        return write_varint(w, self.nperm as u64);
+    }
+
+    pub(crate) fn read_compact<R: Read>(r: &mut R) -> Result<RngFactory, CompactError> {
+        let (seed, salt, ncol, ntree) = (read_varint(r)?, read_varint(r)?, read_opt(r)?, read_opt(r)?);
+        let nperm = match read_usize(r)? {
+            0 => return Err(CompactError::Invalid("zero permutation rounds")),
+            nperm => nperm
+        };
+        return Ok(RngFactory{seed, salt, ncol, ntree, nperm});
+    }
+}
@@ -0,0 +1,260 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::io::{self, Write};
+
+use crate::random_forest::Importance;
+use crate::stats::mid_ranks;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RankedEntry<T> {
+    pub col: T,
+    pub name: String,
+    pub score: f64,
+    pub zscore: Option<f64>,
+    pub hits: Option<usize>
+}
+
+// Agreement of two rankings, e.g. of runs with different seeds or parameters. See RankedImportance::compare.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RankComparison {
+    // Columns with non NaN score in both rankings, spearman and sign_agreement are computed on these.
+    pub n_common: usize,
+    // Spearman correlation of scores with mean ranks of ties. NaN for fewer than two columns or constant scores.
+    pub spearman: f64,
+    // (k, share of the top k columns of one ranking that are in the top k of the other), k capped at the shorter ranking.
+    pub overlap: Vec<(usize, f64)>,
+    // Share of columns scored with the same sign, zero counting as a sign of its own.
+    pub sign_agreement: f64
+}
+
+// Importance sorted from the most to the least important column. Columns with NaN score go last,
+// ties are broken by name so the order is stable between runs.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RankedImportance<T>(Vec<RankedEntry<T>>);
+
+impl<T: Hash + Eq + Copy> RankedImportance<T> {
+    pub fn new<F>(score: &Importance<T>, zscore: Option<&Importance<T>>, hits: Option<&HashMap<T, usize>>, name: F) -> Self
+    where
+        F: Fn(&T) -> String
+    {
+        let mut entries: Vec<RankedEntry<T>> = score.iter().map(|(col, &score)| RankedEntry {
+            col: *col,
+            name: name(col),
+            score,
+            zscore: zscore.and_then(|z| z.get(col).copied()),
+            hits: hits.and_then(|h| h.get(col).copied())
+        }).collect();
+
+        entries.sort_by(|a, b| match (a.score.is_nan(), b.score.is_nan()) {
+            (false, false) => b.score.total_cmp(&a.score),
+            (a_nan, b_nan) => a_nan.cmp(&b_nan)
+        }.then_with(|| a.name.cmp(&b.name)));
+
+        return RankedImportance(entries);
+    }
+
+    pub fn entries(&self) -> &[RankedEntry<T>] {
+        return &self.0;
+    }
+
+    // Compares the ranking with other of the same columns, with overlap of top k for each of ks. Each k must be
+    // above 0, overlap with an empty ranking is 0.
+    pub fn compare(&self, other: &RankedImportance<T>, ks: &[usize]) -> RankComparison {
+        if ks.contains(&0) {
+            panic!("k must be above 0.");
+        }
+        let other_score: HashMap<T, f64> = other.0.iter().map(|e| (e.col, e.score)).collect();
+        let (a, b): (Vec<f64>, Vec<f64>) = self.0.iter()
+            .filter_map(|e| other_score.get(&e.col).map(|&s| (e.score, s)))
+            .filter(|(a, b)| !a.is_nan() && !b.is_nan())
+            .unzip();
+
+        let overlap = ks.iter().map(|&k| {
+            let k_top = k.min(self.0.len()).min(other.0.len());
+            let top: HashSet<T> = self.0[..k_top].iter().map(|e| e.col).collect();
+            let common = other.0[..k_top].iter().filter(|e| top.contains(&e.col)).count();
+            return match k_top {
+                0 => (k, 0.),
+                _ => (k, common as f64 / k_top as f64)
+            };
+        }).collect();
+
+        let same_sign = a.iter().zip(b.iter()).filter(|&(a, b)| a.partial_cmp(&0.) == b.partial_cmp(&0.)).count();
+        return RankComparison {
+            n_common: a.len(),
+            spearman: pearson(&mid_ranks(&a), &mid_ranks(&b)),
+            overlap,
+            sign_agreement: same_sign as f64 / a.len() as f64
+        };
+    }
+
+    pub fn to_csv<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "rank,name,score,zscore,hits")?;
+        for (i, e) in self.0.iter().enumerate() {
+            writeln!(w, "{},{},{},{},{}", i+1, csv_field(&e.name), e.score,
+                     e.zscore.map_or(String::new(), |z| z.to_string()),
+                     e.hits.map_or(String::new(), |h| h.to_string()))?;
+        }
+        return Ok(());
+    }
+
+    pub fn to_json<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write!(w, "[")?;
+        for (i, e) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(w, ",")?;
+            }
+            write!(w, "{{\"rank\":{},\"name\":{},\"score\":{},\"zscore\":{},\"hits\":{}}}", i+1, json_string(&e.name),
+                   json_number(Some(e.score)), json_number(e.zscore),
+                   e.hits.map_or("null".to_string(), |h| h.to_string()))?;
+        }
+        writeln!(w, "]")?;
+        return Ok(());
+    }
+}
+
+fn pearson(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let mean_x = x.iter().sum::<f64>() / n;
+    let mean_y = y.iter().sum::<f64>() / n;
+    let cov: f64 = x.iter().zip(y.iter()).map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let var_x: f64 = x.iter().map(|x| (x - mean_x).powi(2)).sum();
+    let var_y: f64 = y.iter().map(|y| (y - mean_y).powi(2)).sum();
+    return cov / (var_x * var_y).sqrt();
+}
+
+pub(crate) fn csv_field(s: &str) -> String {
+    return match s.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string()
+    };
+}
+
+fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len()+2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c)
+        }
+    }
+    res.push('"');
+    return res;
+}
+
+// JSON has no representation for NaN and infinities.
+fn json_number(x: Option<f64>) -> String {
+    return match x {
+        Some(x) if x.is_finite() => x.to_string(),
+        _ => "null".to_string()
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use float_cmp::assert_approx_eq;
+    use crate::random_forest::Importance;
+    use crate::ranked_importance::RankedImportance;
+
+    fn example() -> RankedImportance<usize> {
+        let names = vec!["a", "b,c", "d\"e", "f"];
+        let score: Importance<usize> = HashMap::from([(0, 0.1), (1, 0.5), (2, f64::NAN), (3, 0.5)]);
+        let zscore: Importance<usize> = HashMap::from([(0, 1.), (1, 4.5), (3, 3.)]);
+        let hits = HashMap::from([(0, 2), (1, 10), (2, 0), (3, 9)]);
+        return RankedImportance::new(&score, Some(&zscore), Some(&hits), |&c| names[c].to_string());
+    }
+
+    #[test]
+    fn ranked_order() {
+        let ranked = example();
+        let order: Vec<usize> = ranked.entries().iter().map(|e| e.col).collect();
+        assert_eq!(order, vec![1, 3, 0, 2]);
+        assert_eq!(ranked.entries()[3].zscore, None);
+    }
+
+    #[test]
+    fn ranked_to_csv() {
+        let mut out = vec![];
+        example().to_csv(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "rank,name,score,zscore,hits\n1,\"b,c\",0.5,4.5,10\n2,f,0.5,3,9\n3,a,0.1,1,2\n4,\"d\"\"e\",NaN,,0\n");
+    }
+
+    #[test]
+    fn ranked_to_json() {
+        let mut out = vec![];
+        example().to_json(&mut out).unwrap();
+        let parsed: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(parsed[0]["name"], "b,c");
+        assert_eq!(parsed[3]["name"], "d\"e");
+        assert!(parsed[3]["score"].is_null());
+        assert!(parsed[3]["zscore"].is_null());
+        assert_eq!(parsed[1]["hits"], 9);
+    }
+
+    #[test]
+    fn ranked_compare() {
+        let ranked = example();
+        let same = ranked.compare(&ranked, &[1, 2, 10]);
+        assert_eq!(same.n_common, 3);
+        assert_approx_eq!(f64, same.spearman, 1.);
+        assert_eq!(same.overlap, vec![(1, 1.), (2, 1.), (10, 1.)]);
+        assert_eq!(same.sign_agreement, 1.);
+
+        let score: Importance<usize> = HashMap::from([(0, 0.7), (1, -0.2), (2, 0.3), (3, 0.1)]);
+        let other = RankedImportance::new(&score, None, None, |c| c.to_string());
+        let res = ranked.compare(&other, &[1, 3]);
+        assert_eq!(res.n_common, 3);
+        // Ranks (1, 2.5, 2.5) against (3, 1, 2) for columns 0, 1 and 3
+        assert_approx_eq!(f64, res.spearman, -1.5 / 3f64.sqrt());
+        assert_eq!(res.overlap, vec![(1, 0.), (3, 2. / 3.)]);
+        assert_approx_eq!(f64, res.sign_agreement, 2. / 3.);
+
+        let empty = RankedImportance::new(&Importance::<usize>::new(), None, None, |c| c.to_string());
+        let res = ranked.compare(&empty, &[2]);
+        assert!(res.spearman.is_nan());
+        assert_eq!(res.overlap, vec![(2, 0.)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "k must be above 0.")]
+    fn ranked_compare_rejects_zero_k() {
+        example().compare(&example(), &[0]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn ranked_serde() {
+        let ranked = example();
+        // The last entry has NaN score, which JSON cannot represent
+        let json = serde_json::to_string(&ranked.entries()[..3]).unwrap();
+        let parsed: Vec<crate::ranked_importance::RankedEntry<usize>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, ranked.entries()[..3]);
+    }
+}
@@ -0,0 +1,187 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::collections::{BTreeMap, HashMap};
+
+use tracing::info_span;
+
+use crate::boruta::{boruta_with_config, BorutaConfig, BorutaRes, Decision};
+use crate::data_interface::multi_x::{ColSplitIndex, SplitColId, XDf};
+use crate::data_interface::{ColumnData, Response, Shadowable};
+use crate::data_interface::y_bool::{Y, YBool};
+use crate::random_forest::{Importance, RandomForest};
+
+// Genomic position of a column, see XDf::set_col_loci.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Locus {
+    pub chrom: String,
+    pub pos: u64
+}
+
+impl Locus {
+    pub fn new(chrom: &str, pos: u64) -> Self {
+        return Locus{chrom: chrom.to_string(), pos};
+    }
+}
+
+// Grouping of columns into regions run separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partition {
+    Chromosome,
+    // Windows of the given width in base pairs within each chromosome, starting from position 0.
+    Window(u64),
+}
+
+// Positions [start, end) of a chromosome. For Partition::Chromosome these are the first and past the last
+// position of its columns.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Region {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64
+}
+
+// Importance of each region's own forest. Forests of different regions see other columns, so their
+// values compare only roughly across regions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionImportance {
+    pub regions: Vec<(Region, Importance<SplitColId>)>,
+    // Importance of the columns of all regions by locus.
+    pub merged: HashMap<Locus, f64>
+}
+
+#[derive(Debug)]
+pub struct RegionBoruta {
+    pub regions: Vec<(Region, BorutaRes<ColSplitIndex>)>,
+    // Final decision of the columns of all regions by locus, rough fixed ones included.
+    pub decisions: HashMap<Locus, Decision>
+}
+
+// Columns of df grouped by partition, regions ordered by chromosome name and start. Columns keep their data
+// frame order within a region, those without a locus are left out.
+pub fn regions(df: &XDf, partition: Partition) -> Vec<(Region, Vec<SplitColId>)> {
+    if partition == Partition::Window(0) {
+        panic!("Window width must be above 0.");
+    }
+
+    let mut groups: BTreeMap<(String, u64), (u64, u64, Vec<SplitColId>)> = BTreeMap::new();
+    for col in ColumnData::<ColSplitIndex>::get_col_ids(df) {
+        if let Some(locus) = df.get_locus(col.col_id) {
+            let start = match partition {
+                Partition::Chromosome => 0,
+                Partition::Window(width) => locus.pos / width * width
+            };
+            let group = groups.entry((locus.chrom.clone(), start)).or_insert((locus.pos, locus.pos, vec![]));
+            group.0 = group.0.min(locus.pos);
+            group.1 = group.1.max(locus.pos);
+            group.2.push(col);
+        }
+    }
+
+    return groups.into_iter().map(|((chrom, start), (first, last, cols))| {
+        let (start, end) = match partition {
+            Partition::Chromosome => (first, last + 1),
+            Partition::Window(width) => (start, start.saturating_add(width))
+        };
+        (Region{chrom, start, end}, cols)
+    }).collect();
+}
+
+// RandomForest::importance of each region with the same forest settings. Shadows are not supported, as merged
+// results are keyed by locus.
+pub fn importance_per_region(rf: &RandomForest<Y, ColSplitIndex>, df: &XDf, y: &YBool, partition: Partition, ntree: usize, mtry: usize, max_tree_depth: Option<usize>, multithread: Option<usize>) -> RegionImportance {
+    let mut res = RegionImportance{regions: vec![], merged: HashMap::new()};
+    for (region, cols) in regions(df, partition) {
+        let _span = info_span!("region_importance", chrom = region.chrom.as_str(), start = region.start).entered();
+        let importance = rf.importance(&df.subset(&cols), y, ntree, mtry, false, max_tree_depth, multithread);
+        for (col, &value) in importance.iter() {
+            res.merged.insert(df.get_locus(col.col_id).unwrap().clone(), value);
+        }
+        res.regions.push((region, importance));
+    }
+    return res;
+}
+
+// Boruta on each region with the same config, seed included.
+pub fn boruta_per_region(df: &XDf, y: &YBool, partition: Partition, config: &BorutaConfig) -> RegionBoruta {
+    let mut res = RegionBoruta{regions: vec![], decisions: HashMap::new()};
+    for (region, cols) in regions(df, partition) {
+        let _span = info_span!("region_boruta", chrom = region.chrom.as_str(), start = region.start).entered();
+        let boruta: BorutaRes<ColSplitIndex> = boruta_with_config(df.subset(&cols), YBool::new(y.as_vector_ref()), config);
+        let decided = [
+            (boruta.get_confirmed(), Decision::Confirmed),
+            (boruta.get_rejected(), Decision::Rejected),
+            (boruta.get_tentative(), Decision::Tentative)
+        ];
+        for (cols, decision) in decided {
+            for col in cols {
+                res.decisions.insert(df.get_locus(col.col_id).unwrap().clone(), decision);
+            }
+        }
+        res.regions.push((region, boruta));
+    }
+    return res;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_interface::multi_x::{MultiX, SplitColId, XDf};
+    use crate::data_interface::three_val::ThreeValCol;
+    use crate::mask::Mask;
+    use crate::regions::{regions, Locus, Partition, Region};
+
+    fn df() -> XDf {
+        let mut df = XDf::new((0..5).map(|_| MultiX::ThreeVal(ThreeValCol::new(&[0, 1, 2]))).collect());
+        df.set_col_loci(vec![
+            Some(Locus::new("2", 1_500)),
+            Some(Locus::new("1", 900)),
+            None,
+            Some(Locus::new("2", 2_100)),
+            Some(Locus::new("1", 1_200))
+        ]);
+        return df;
+    }
+
+    #[test]
+    fn columns_by_region() {
+        let col = |col_id| SplitColId{col_id, shadow: false};
+        let df = df();
+        assert_eq!(regions(&df, Partition::Chromosome), vec![
+            (Region{chrom: "1".to_string(), start: 900, end: 1_201}, vec![col(1), col(4)]),
+            (Region{chrom: "2".to_string(), start: 1_500, end: 2_101}, vec![col(0), col(3)])
+        ]);
+        let windows = regions(&df.subset_rows(&Mask::new(vec![0, 2])), Partition::Window(1_000));
+        assert_eq!(windows.iter().map(|(region, cols)| (region.start, region.end, cols.len())).collect::<Vec<_>>(),
+                   vec![(0, 1_000, 1), (1_000, 2_000, 1), (1_000, 2_000, 1), (2_000, 3_000, 1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Loci must not outnumber the column ids.")]
+    fn too_many_loci() {
+        let mut df = XDf::new(vec![MultiX::ThreeVal(ThreeValCol::new(&[0, 1]))]);
+        df.set_col_loci(vec![None, None]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Columns must have distinct loci.")]
+    fn duplicated_loci() {
+        let mut df = XDf::new((0..2).map(|_| MultiX::ThreeVal(ThreeValCol::new(&[0, 1]))).collect());
+        df.set_col_loci(vec![Some(Locus::new("1", 5)), Some(Locus::new("1", 5))]);
+    }
+}
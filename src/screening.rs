@@ -0,0 +1,92 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::data_interface::{ColumnIdentifiable, DataInterface, Response};
+use crate::data_interface::y_bool::Y;
+use crate::mask::Mask;
+use crate::stats::chi2_sf;
+
+// Univariate pre-screen for wide data. Each column is scored by its best single split against y, as trees
+// score split candidates. For a binary response the gini decrease of a split over the gini impurity of y
+// is phi^2 of the 2x2 table, so n times it is the chi-square statistic with 1 degree of freedom.
+// Taking the best split of a column lowers its p-value, so screening errs on keeping columns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScreeningRes<T: Hash + Eq> {
+    // Both in column order.
+    pub kept: Vec<T>,
+    pub dropped: Vec<T>,
+    // Columns that cannot split the data, e.g. constant ones, have p-value 1.
+    pub pvalues: HashMap<T, f64>,
+}
+
+// Drops columns with p-value above pval_th. A high threshold (e.g. 0.5) only drops the clearly uninformative tail.
+pub fn screen_columns<T, U, SplitIndex>(df: &T, y: &U, pval_th: f64) -> ScreeningRes<SplitIndex::Col>
+where
+    SplitIndex: ColumnIdentifiable,
+    T: DataInterface<SplitIndex, Y>,
+    U: Response<Y>
+{
+    if !(0. ..=1.).contains(&pval_th) {
+        panic!("P-value threshold must be within [0, 1].");
+    }
+
+    let mask = Mask::new((0..y.len()).collect());
+    let impurity = y.impurity(&mask);
+    let mut res = ScreeningRes{kept: vec![], dropped: vec![], pvalues: HashMap::new()};
+
+    for col in df.get_col_ids() {
+        let pvalue = match (df.split_score(col, &mask, y), impurity > 0.) {
+            (Some(score), true) => chi2_sf(y.len() as f64 * (impurity - score).max(0.) / impurity, 1.),
+            _ => 1.
+        };
+        match pvalue > pval_th {
+            true => res.dropped.push(col),
+            false => res.kept.push(col)
+        }
+        res.pvalues.insert(col, pvalue);
+    }
+    return res;
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::assert_approx_eq;
+
+    use crate::data_interface::multi_x::{ColSplitIndex, MultiX, SplitColId, XDf};
+    use crate::data_interface::three_val::ThreeValCol;
+    use crate::data_interface::y_bool::YBool;
+    use crate::screening::screen_columns;
+
+    #[test]
+    fn screen_drops_uninformative() {
+        // 15/5 cases against 5/15 gives chi-square 10
+        let y: Vec<bool> = (0..40).map(|i| (i < 15) || (20..25).contains(&i)).collect();
+        let informative: Vec<i8> = (0..40).map(|i| (i < 20) as i8).collect();
+        let noise: Vec<i8> = (0..40).map(|i| (i % 2) as i8).collect();
+        let constant = vec![1; 40];
+        let df = XDf::new([informative, noise, constant].iter().map(|x| MultiX::ThreeVal(ThreeValCol::new(x))).collect());
+
+        let res = screen_columns::<_, _, ColSplitIndex>(&df, &YBool::new(&y), 0.5);
+        let col = |col_id| SplitColId{col_id, shadow: false};
+        assert_eq!(res.kept, vec![col(0)]);
+        assert_eq!(res.dropped, vec![col(1), col(2)]);
+        assert_approx_eq!(f64, res.pvalues[&col(0)], 0.001565402, epsilon = 1e-6);
+        assert_eq!(res.pvalues[&col(2)], 1.);
+    }
+}
@@ -0,0 +1,452 @@
+// Variant Forest
+// Copyright (C) 2023 Krzysztof Piwoński <piwonski.kris@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::f64;
+use std::collections::HashMap;
+
+
+/// Constant value for `ln(pi)`
+pub const LN_PI: f64 = 1.1447298858494001741434273513530587116472948129153;
+
+
+/// Constant value for `ln(2 * sqrt(e / pi))`
+pub const LN_2_SQRT_E_OVER_PI: f64 = 0.6207822376352452223455184457816472122518527279025978;
+
+/// Standard epsilon, maximum relative precision of IEEE 754 double-precision
+/// floating point numbers (64 bit) e.g. `2^-53`
+pub const F64_PREC: f64 = 0.00000000000000011102230246251565;
+
+/// Auxiliary variable when evaluating the `gamma_ln` function
+const GAMMA_R: f64 = 10.900511;
+
+/// Polynomial coefficients for approximating the `gamma_ln` function
+const GAMMA_DK: &[f64] = &[
+    2.48574089138753565546e-5,
+    1.05142378581721974210,
+    -3.45687097222016235469,
+    4.51227709466894823700,
+    -2.98285225323576655721,
+    1.05639711577126713077,
+    -1.95428773191645869583e-1,
+    1.70970543404441224307e-2,
+    -5.71926117404305781283e-4,
+    4.63399473359905636708e-6,
+    -2.71994908488607703910e-9,
+];
+
+
+// function to silence clippy on the special case when comparing to zero.
+#[inline(always)]
+fn is_zero(x: f64) -> bool {
+    x == 0.
+}
+
+pub fn binom_cdf(k: u64, n: u64, p: f64) -> f64 {
+    if k >= n {
+        1.0
+    } else {
+        checked_beta_reg((n - k) as f64, k as f64 + 1.0, 1.0 - p)
+    }
+}
+
+// P(X <= k) of the hypergeometric distribution, i.e. of drawing at most k of the n_succ successes
+// in n_draw draws without replacement out of n_pop.
+pub fn hypergeom_cdf(k: u64, n_pop: u64, n_succ: u64, n_draw: u64) -> f64 {
+    let (lo, hi) = hypergeom_support(n_pop, n_succ, n_draw);
+    if k >= hi {
+        return 1.;
+    }
+    let res: f64 = (lo..=k).map(|i| hypergeom_pmf(i, n_pop, n_succ, n_draw)).sum();
+    return res.min(1.);
+}
+
+// P(X >= k) of the hypergeometric distribution. Summed directly, so small upper tails keep their precision.
+pub fn hypergeom_sf(k: u64, n_pop: u64, n_succ: u64, n_draw: u64) -> f64 {
+    let (lo, hi) = hypergeom_support(n_pop, n_succ, n_draw);
+    if k <= lo {
+        return 1.;
+    }
+    let res: f64 = (k..=hi).map(|i| hypergeom_pmf(i, n_pop, n_succ, n_draw)).sum();
+    return res.min(1.);
+}
+
+fn hypergeom_support(n_pop: u64, n_succ: u64, n_draw: u64) -> (u64, u64) {
+    if n_succ > n_pop || n_draw > n_pop {
+        panic!("Successes and draws must not exceed the population.");
+    }
+    return (n_draw.saturating_sub(n_pop - n_succ), n_succ.min(n_draw));
+}
+
+fn hypergeom_pmf(k: u64, n_pop: u64, n_succ: u64, n_draw: u64) -> f64 {
+    return (ln_choose(n_succ, k) + ln_choose(n_pop - n_succ, n_draw - k) - ln_choose(n_pop, n_draw)).exp();
+}
+
+fn ln_choose(n: u64, k: u64) -> f64 {
+    return ln_gamma(n as f64 + 1.) - ln_gamma(k as f64 + 1.) - ln_gamma((n - k) as f64 + 1.);
+}
+
+
+/// Computes the logarithm of the gamma function
+/// with an accuracy of 16 floating point digits.
+/// The implementation is derived from
+/// "An Analysis of the Lanczos Gamma Approximation",
+/// Glendon Ralph Pugh, 2004 p. 116
+pub fn ln_gamma(x: f64) -> f64 {
+    if x < 0.5 {
+        let s = GAMMA_DK
+            .iter()
+            .enumerate()
+            .skip(1)
+            .fold(GAMMA_DK[0], |s, t| s + t.1 / (t.0 as f64 - x));
+
+        LN_PI
+            - (f64::consts::PI * x).sin().ln()
+            - s.ln()
+            - LN_2_SQRT_E_OVER_PI
+            - (0.5 - x) * ((0.5 - x + GAMMA_R) / f64::consts::E).ln()
+    } else {
+        let s = GAMMA_DK
+            .iter()
+            .enumerate()
+            .skip(1)
+            .fold(GAMMA_DK[0], |s, t| s + t.1 / (x + t.0 as f64 - 1.0));
+
+        s.ln()
+            + LN_2_SQRT_E_OVER_PI
+            + (x - 0.5) * ((x - 0.5 + GAMMA_R) / f64::consts::E).ln()
+    }
+}
+
+/// Computes the regularized lower incomplete beta function
+/// `I_x(a,b) = 1/Beta(a,b) * int(t^(a-1)*(1-t)^(b-1), t=0..x)`
+/// `a > 0`, `b > 0`, `1 >= x >= 0` where `a` is the first beta parameter,
+/// `b` is the second beta parameter, and `x` is the upper limit of the
+/// integral.
+///
+/// # Errors
+///
+/// if `a <= 0.0`, `b <= 0.0`, `x < 0.0`, or `x > 1.0`
+pub fn checked_beta_reg(a: f64, b: f64, x: f64) -> f64 {
+        let bt = if is_zero(x) || x == 1. {
+            0.0
+        } else {
+            (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b)
+                + a * x.ln()
+                + b * (1.0 - x).ln())
+                .exp()
+        };
+        let symm_transform = x >= (a + 1.0) / (a + b + 2.0);
+        let eps = F64_PREC;
+        let fpmin = f64::MIN_POSITIVE / eps;
+
+        let mut a = a;
+        let mut b = b;
+        let mut x = x;
+        if symm_transform {
+            let swap = a;
+            x = 1.0 - x;
+            a = b;
+            b = swap;
+        }
+
+        let qab = a + b;
+        let qap = a + 1.0;
+        let qam = a - 1.0;
+        let mut c = 1.0;
+        let mut d = 1.0 - qab * x / qap;
+
+        if d.abs() < fpmin {
+            d = fpmin;
+        }
+        d = 1.0 / d;
+        let mut h = d;
+
+        for m in 1..141 {
+            let m = f64::from(m);
+            let m2 = m * 2.0;
+            let mut aa = m * (b - m) * x / ((qam + m2) * (a + m2));
+            d = 1.0 + aa * d;
+
+            if d.abs() < fpmin {
+                d = fpmin;
+            }
+
+            c = 1.0 + aa / c;
+            if c.abs() < fpmin {
+                c = fpmin;
+            }
+
+            d = 1.0 / d;
+            h = h * d * c;
+            aa = -(a + m) * (qab + m) * x / ((a + m2) * (qap + m2));
+            d = 1.0 + aa * d;
+
+            if d.abs() < fpmin {
+                d = fpmin;
+            }
+
+            c = 1.0 + aa / c;
+
+            if c.abs() < fpmin {
+                c = fpmin;
+            }
+
+            d = 1.0 / d;
+            let del = d * c;
+            h *= del;
+
+            if (del - 1.0).abs() <= eps {
+                return if symm_transform {
+                    return 1.0 - bt * h / a;
+                } else {
+                    return bt * h / a;
+                };
+            }
+        }
+
+        if symm_transform {
+            return 1.0 - bt * h / a;
+        } else {
+            return bt * h / a;
+        }
+}
+
+// Chi-square distribution with df degrees of freedom, P(X <= x).
+pub fn chi2_cdf(x: f64, df: f64) -> f64 {
+    return gamma_reg_lower(df / 2., x / 2.);
+}
+
+// Chi-square upper tail P(X > x), precise also for small p-values.
+pub fn chi2_sf(x: f64, df: f64) -> f64 {
+    return gamma_reg_upper(df / 2., x / 2.);
+}
+
+// Standard normal distribution, P(X <= x).
+pub fn normal_cdf(x: f64) -> f64 {
+    let tail = 0.5 * gamma_reg_upper(0.5, x * x / 2.);
+    return match x < 0. {
+        true => tail,
+        false => 1. - tail
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alternative {
+    TwoSided,
+    Greater,
+    Less,
+}
+
+// Wilcoxon signed-rank test of differences d against location 0, by the normal approximation with tie and
+// continuity correction as R wilcox.test(exact = FALSE). Zero differences are dropped, p-value is 1 without any left.
+pub fn wilcoxon_signed_rank(d: &[f64], alternative: Alternative) -> f64 {
+    let d: Vec<f64> = d.iter().cloned().filter(|&x| x != 0.).collect();
+    if d.is_empty() {
+        return 1.;
+    }
+    let n = d.len() as f64;
+    let ranks = mid_ranks(&d.iter().map(|x| x.abs()).collect::<Vec<f64>>());
+    let stat: f64 = d.iter().zip(ranks.iter()).filter(|(&x, _)| x > 0.).map(|(_, &r)| r).sum();
+
+    // Each group of t tied ranks lowers the variance by (t^3 - t) / 48
+    let mut ties: HashMap<u64, f64> = HashMap::new();
+    for r in ranks.iter() {
+        *ties.entry(r.to_bits()).or_insert(0.) += 1.;
+    }
+    let tie_corr: f64 = ties.values().map(|t| t.powi(3) - t).sum::<f64>() / 48.;
+    let sd = (n * (n + 1.) * (2. * n + 1.) / 24. - tie_corr).sqrt();
+    let z = stat - n * (n + 1.) / 4.;
+
+    return match alternative {
+        Alternative::Greater => normal_cdf(-(z - 0.5) / sd),
+        Alternative::Less => normal_cdf((z + 0.5) / sd),
+        Alternative::TwoSided => {
+            let corr = match is_zero(z) {
+                true => 0.,
+                false => z.signum() * 0.5
+            };
+            (2. * normal_cdf(-((z - corr) / sd).abs())).min(1.)
+        }
+    };
+}
+
+// Ranks from 1 in ascending order, ties get the mean of their ranks.
+pub fn mid_ranks(x: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..x.len()).collect();
+    order.sort_by(|&i, &j| x[i].total_cmp(&x[j]));
+    let mut res = vec![0.; x.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let end = start + order[start..].iter().take_while(|&&i| x[i] == x[order[start]]).count();
+        let rank = (start + end + 1) as f64 / 2.;
+        for &i in order[start..end].iter() {
+            res[i] = rank;
+        }
+        start = end;
+    }
+    return res;
+}
+
+// Regularized lower incomplete gamma function P(a, x), a > 0.
+fn gamma_reg_lower(a: f64, x: f64) -> f64 {
+    if x <= 0. {
+        return 0.;
+    }
+    return match x < a + 1. {
+        true => gamma_series(a, x),
+        false => 1. - gamma_cont_frac(a, x)
+    };
+}
+
+// Regularized upper incomplete gamma function Q(a, x) = 1 - P(a, x), a > 0.
+fn gamma_reg_upper(a: f64, x: f64) -> f64 {
+    if x <= 0. {
+        return 1.;
+    }
+    return match x < a + 1. {
+        true => 1. - gamma_series(a, x),
+        false => gamma_cont_frac(a, x)
+    };
+}
+
+// Series of P(a, x), converges quickly for x < a + 1 (Numerical Recipes gser).
+fn gamma_series(a: f64, x: f64) -> f64 {
+    let mut ap = a;
+    let mut del = 1. / a;
+    let mut sum = del;
+    for _ in 0..1000 {
+        ap += 1.;
+        del *= x / ap;
+        sum += del;
+        if del.abs() < sum.abs() * F64_PREC {
+            break;
+        }
+    }
+    return sum * (-x + a * x.ln() - ln_gamma(a)).exp();
+}
+
+// Continued fraction of Q(a, x) by the modified Lentz method, converges quickly for x >= a + 1 (Numerical Recipes gcf).
+fn gamma_cont_frac(a: f64, x: f64) -> f64 {
+    let fpmin = f64::MIN_POSITIVE / F64_PREC;
+    let mut b = x + 1. - a;
+    let mut c = 1. / fpmin;
+    let mut d = 1. / b;
+    let mut h = d;
+    for i in 1..1000 {
+        let an = -(i as f64) * (i as f64 - a);
+        b += 2.;
+        d = an * d + b;
+        if d.abs() < fpmin {
+            d = fpmin;
+        }
+        c = b + an / c;
+        if c.abs() < fpmin {
+            c = fpmin;
+        }
+        d = 1. / d;
+        let del = d * c;
+        h *= del;
+        if (del - 1.).abs() <= F64_PREC {
+            break;
+        }
+    }
+    return (-x + a * x.ln() - ln_gamma(a)).exp() * h;
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::assert_approx_eq;
+    use crate::stats::{checked_beta_reg, binom_cdf, hypergeom_cdf, hypergeom_sf, chi2_cdf, chi2_sf, normal_cdf, wilcoxon_signed_rank, mid_ranks, Alternative};
+
+    #[test]
+    fn beta_cdf_calculated_correctly() {
+        assert_approx_eq!(f64, checked_beta_reg(2., 2., 0.5), 0.5, epsilon=0.000001);
+        assert_approx_eq!(f64, checked_beta_reg(2., 2., 0.05), 0.00725, epsilon=0.000001);
+        assert_approx_eq!(f64, checked_beta_reg(2., 2., 0.95), 0.99275, epsilon=0.000001);
+    }
+
+    #[test]
+    fn binom_cdf_calculated_correctly() {
+        assert_approx_eq!(f64, binom_cdf(0, 10, 0.5), 0.0009765625, epsilon=0.000001);
+        assert_approx_eq!(f64, binom_cdf(1, 10, 0.5), 0.01074219, epsilon=0.000001);
+        assert_approx_eq!(f64, binom_cdf(9, 10, 0.5), 0.9990234, epsilon=0.000001);
+        assert_approx_eq!(f64, binom_cdf(10, 10, 0.5), 1., epsilon=0.000001);
+    }
+
+    #[test]
+    fn hypergeom_sf_calculated_correctly() {
+        assert_approx_eq!(f64, hypergeom_sf(4, 50, 10, 8), 0.04069878, epsilon=0.000001);
+        assert_approx_eq!(f64, hypergeom_sf(1, 50, 10, 8), 0.8567559, epsilon=0.000001);
+        assert_approx_eq!(f64, hypergeom_sf(8, 50, 10, 8), 8.381782e-08, epsilon=1e-12);
+        assert_approx_eq!(f64, hypergeom_sf(3, 1000, 40, 30), 0.1140991, epsilon=0.000001);
+        assert_approx_eq!(f64, hypergeom_sf(2, 10, 3, 3), 0.1833333, epsilon=0.000001);
+        assert_eq!(hypergeom_sf(0, 50, 10, 8), 1.);
+        assert_eq!(hypergeom_sf(9, 50, 10, 8), 0.);
+    }
+
+    #[test]
+    fn hypergeom_cdf_calculated_correctly() {
+        assert_approx_eq!(f64, hypergeom_cdf(0, 50, 10, 8), 0.1432441, epsilon=0.000001);
+        assert_approx_eq!(f64, hypergeom_cdf(1, 50, 10, 8), 0.4905024, epsilon=0.000001);
+        assert_approx_eq!(f64, hypergeom_cdf(3, 1000, 40, 30), 0.9716621, epsilon=0.000001);
+        assert_approx_eq!(f64, hypergeom_cdf(3, 50, 10, 8) + hypergeom_sf(4, 50, 10, 8), 1., epsilon=1e-12);
+        assert_eq!(hypergeom_cdf(8, 50, 10, 8), 1.);
+    }
+
+    #[test]
+    fn chi2_calculated_correctly() {
+        assert_approx_eq!(f64, chi2_cdf(3.841459, 1.), 0.95, epsilon=0.000001);
+        assert_approx_eq!(f64, chi2_sf(10., 1.), 0.001565402, epsilon=1e-9);
+        assert_approx_eq!(f64, chi2_cdf(5., 3.), 0.8282029, epsilon=0.000001);
+        assert_approx_eq!(f64, chi2_sf(10., 4.), 0.04042768, epsilon=1e-8);
+        assert_eq!(chi2_cdf(0., 2.), 0.);
+        assert_eq!(chi2_sf(0., 2.), 1.);
+    }
+
+    #[test]
+    fn normal_cdf_calculated_correctly() {
+        assert_approx_eq!(f64, normal_cdf(0.), 0.5, epsilon=1e-12);
+        assert_approx_eq!(f64, normal_cdf(-1.), 0.1586553, epsilon=0.000001);
+        assert_approx_eq!(f64, normal_cdf(1.959964), 0.975, epsilon=0.000001);
+        assert_approx_eq!(f64, normal_cdf(-8.), 6.220961e-16, epsilon=1e-21);
+    }
+
+    #[test]
+    fn wilcoxon_signed_rank_calculated_correctly() {
+        // Depression scores of R wilcox.test examples, V = 40
+        let x = [1.83, 0.50, 1.62, 2.48, 1.68, 1.88, 1.55, 3.06, 1.30];
+        let y = [0.878, 0.647, 0.598, 2.05, 1.06, 1.29, 1.06, 3.14, 1.29];
+        let d: Vec<f64> = x.iter().zip(y.iter()).map(|(x, y)| x - y).collect();
+        assert_approx_eq!(f64, wilcoxon_signed_rank(&d, Alternative::Greater), 0.02200549, epsilon=0.000001);
+        assert_approx_eq!(f64, wilcoxon_signed_rank(&d, Alternative::Less), 0.9835153, epsilon=0.000001);
+        assert_approx_eq!(f64, wilcoxon_signed_rank(&d, Alternative::TwoSided), 0.04401098, epsilon=0.000001);
+
+        // Ties and a zero difference
+        let d = [1., -1., 2., 2., 0., 3., -2., 1., 4., 1.];
+        assert_approx_eq!(f64, wilcoxon_signed_rank(&d, Alternative::Greater), 0.05268573, epsilon=0.000001);
+        assert_approx_eq!(f64, wilcoxon_signed_rank(&d, Alternative::TwoSided), 0.1053715, epsilon=0.000001);
+        assert_eq!(wilcoxon_signed_rank(&[0., 0.], Alternative::Less), 1.);
+    }
+
+    #[test]
+    fn mid_ranks_of_ties() {
+        assert_eq!(mid_ranks(&[3., 1., 3., 2., 3.]), vec![4., 1., 4., 2., 4.]);
+        assert!(mid_ranks(&[]).is_empty());
+    }
+}
\ No newline at end of file
@@ -18,277 +18,1616 @@
 //TODO handle NA values
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
-use std::hash::Hash;
-
-use crate::data_interface::{ColumnIdentifiable, DataInterface, Predicted, Response};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use crate::clock::Instant;
+
+use crate::compact::{read_class, read_codes, read_flag, read_usize, read_varint, read_vec, write_class, write_codes, write_flag, write_varint, CompactClass, CompactError, CompactSplit, LEAF_CODE};
+use crate::local_importance::tsv_field;
+use crate::data_interface::{ColumnIdentifiable, DataInterface, ErrorMetric, LossMetric, PermutationCache, PivotIdentifiable, Predicted, Response, RowView, SplitPenalty, TiePolicy};
 use crate::mask::Mask;
 use crate::random_number_generator::factory::RngFactory;
 use crate::random_number_generator::Rng;
+use crate::stable_hash::StableHasher;
 
 type NodeHandle = usize;
 type DfRowId = usize;
-pub type ImportanceTree<T> = HashMap<T, i64>;
+pub type ImportanceTree<T> = HashMap<T, f64>;
+pub type ImpurityTree<T> = HashMap<T, f64>;
+// Error increase per OOB sample, in mask order, summed over permutation rounds.
+pub type LocalImportanceTree<T> = HashMap<T, Vec<f64>>;
+
+// Terminal node of each row per tree and dataset, shared by trees (and threads) evaluating the same data
+// repeatedly. Entries are keyed by tree index and data id and hold the identity of the tree that stored them,
+// so a tree of another forest at the same index recomputes its leaves instead of reading foreign ones.
+#[derive(Default)]
+pub struct ApplyCache {
+    leaves: Mutex<HashMap<(usize, u64), (u64, Vec<NodeHandle>)>>
+}
+
+impl ApplyCache {
+    pub fn new() -> Self {
+        return ApplyCache::default();
+    }
+
+    // Leaves stored by the tree of uid at ith_tree, indexed by row, NodeHandle::MAX for rows not walked yet.
+    // Taken out of the cache, so other threads are not blocked while rows are walked; put them back with store.
+    fn take(&self, ith_tree: usize, uid: u64, data_id: u64) -> Vec<NodeHandle> {
+        return match self.leaves.lock().unwrap().remove(&(ith_tree, data_id)) {
+            Some((stored_uid, leaves)) if stored_uid == uid => leaves,
+            _ => vec![]
+        };
+    }
+
+    fn store(&self, ith_tree: usize, uid: u64, data_id: u64, leaves: Vec<NodeHandle>) {
+        self.leaves.lock().unwrap().insert((ith_tree, data_id), (uid, leaves));
+    }
+
+    // Drops terminal nodes computed on data_id.
+    pub fn invalidate(&self, data_id: u64) {
+        self.leaves.lock().unwrap().retain(|key, _| key.1 != data_id);
+    }
+
+    // Number of cached (tree, dataset) pairs.
+    pub fn len(&self) -> usize {
+        return self.leaves.lock().unwrap().len();
+    }
+}
+
+// Working space shared by all nodes while building a tree. Each node owns a range of one row array
+// instead of a mask, so nodes need no allocations of their own.
+#[derive(Default)]
+struct BuildBuffers {
+    mask: Mask,
+    scratch: Vec<usize>,
+}
 
+// Time spent in the phases of building a tree and computing its importance.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TreeTiming {
+    pub split_search: Duration,
+    pub prediction: Duration,
+    pub permutation: Duration,
+}
+
+// Node masks and predictions of an unpermuted prediction, reused by predictions with a permuted column.
+// Kept apart from the tree, so predicting needs no mutable tree and a tree can be shared between threads.
+// Entries are in node order, the last one is the root.
+pub struct PredictWorkspace<Y, Col> {
+    mask_cache: Vec<Mask>,
+    preds_cache: Vec<(Y, DfRowId)>,
+    split_mask_map: HashMap<Col, Vec<usize>>,
+    split_idx_cache_range: Vec<(usize, usize)>,
+    preds_cache_range: Vec<(usize, usize)>,
+}
+
+// Unpermuted prediction of a tree on its OOB rows, shared by the columns permuted against it, see Tree::importance_col.
+pub(crate) struct ImportanceBase<Y, Col> {
+    ws: PredictWorkspace<Y, Col>,
+    mask_ranks: Vec<usize>,
+    preds: Predicted<Y>,
+    pred_err: f64
+}
+
+impl<Y, Col> ImportanceBase<Y, Col> {
+    #[cfg(feature = "threads")]
+    pub(crate) fn get_preds(&self) -> &Predicted<Y> {
+        return &self.preds;
+    }
+}
+
+impl<Y, Col> Default for PredictWorkspace<Y, Col> {
+    fn default() -> Self {
+        return PredictWorkspace{
+            mask_cache: vec![],
+            preds_cache: vec![],
+            split_mask_map: HashMap::new(),
+            split_idx_cache_range: vec![],
+            preds_cache_range: vec![]
+        };
+    }
+}
+
+impl<Y, Col> PredictWorkspace<Y, Col> where
+    Col: Hash + Eq
+{
+    pub fn new() -> Self {
+        return PredictWorkspace::default();
+    }
+
+    fn clear(&mut self) {
+        self.mask_cache.clear();
+        self.preds_cache.clear();
+        self.split_mask_map.clear();
+        self.split_idx_cache_range.clear();
+        self.preds_cache_range.clear();
+    }
+
+    // True when filled by an unpermuted prediction on mask.
+    fn is_filled_for(&self, mask: &Mask) -> bool {
+        return self.mask_cache.last() == Some(mask);
+    }
+}
+
+// Depth of the deepest leaf, the root being at depth 0, and number of leaves.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TreeShape {
+    pub depth: usize,
+    pub n_leaves: usize,
+}
+
+// Routing of rows missing the value of a split column at prediction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NaPolicy {
+    // Down the child more in-bag rows went to.
+    #[default]
+    Majority,
+    // Down both children, weighted by their share of in-bag rows.
+    Weighted,
+}
+
+// Weight of the impurity decrease per sample of a split in impurity importance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SplitWeight {
+    // Share of the in-bag rows reaching the node, as in MDI.
+    #[default]
+    NodeSize,
+    // 2^-depth, the root split counts fully and each level below half as much, whatever its size.
+    Depth,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "Y: serde::Serialize, SplitIndex: serde::Serialize, SplitIndex::Col: serde::Serialize",
+    deserialize = "Y: serde::Deserialize<'de>, SplitIndex: serde::Deserialize<'de>, SplitIndex::Col: serde::Deserialize<'de>"
+)))]
 pub struct Tree<Y, SplitIndex> where
     SplitIndex: ColumnIdentifiable
 {
     tree: Vec<Node<Y, SplitIndex>>,
+    // In-bag rows of each node, in node order.
+    node_n: Vec<usize>,
+    // In-bag class counts of each node, empty for splits, and all classes of the in-bag rows, kept with
+    // set_leaf_counts.
+    leaf_counts: Option<Vec<Vec<(Y, usize)>>>,
+    // In-bag rows of each node with their multiplicity, empty for splits, kept with set_leaf_rows.
+    #[cfg_attr(feature = "serde", serde(default))]
+    leaf_rows: Option<Vec<Vec<(usize, u32)>>>,
+    classes: Vec<Y>,
     split_cols: HashSet<SplitIndex::Col>,
-    mask_cache: Vec<Mask>,
-    preds_cache: Vec<(Y, DfRowId)>,
-    split_mask_map: HashMap<SplitIndex::Col, Vec<usize>>,
-    split_idx_cache_range: Vec<(usize, usize)>,
-    preds_cache_range: Vec<(usize, usize)>,
     rng: Rng,
     rng_factory: RngFactory,
     ith_tree: usize,
+    timing: Option<TreeTiming>,
+    impurity_decrease: Option<ImpurityTree<SplitIndex::Col>>,
+    split_weight: SplitWeight,
+    ties: TiePolicy,
+    split_penalty: SplitPenalty,
+    root_n: usize,
+    oob_accuracy: Option<f64>,
+    split_subsample: Option<usize>,
+    error_metric: ErrorMetric,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    custom_metric: Option<Arc<dyn LossMetric<Y>>>,
+    min_node_size: usize,
+    // Identity of the nodes for ApplyCache, renewed whenever a node is added.
+    #[cfg_attr(feature = "serde", serde(skip, default = "next_tree_uid"))]
+    uid: u64,
+}
+
+static NEXT_TREE_UID: AtomicU64 = AtomicU64::new(0);
+
+fn next_tree_uid() -> u64 {
+    return NEXT_TREE_UID.fetch_add(1, Ordering::Relaxed);
 }
 
 impl<Y, SplitIndex> Tree<Y, SplitIndex> where
-    Y: Copy + Debug,
+    Y: Copy + Debug + PartialEq,
     SplitIndex: ColumnIdentifiable + Clone + Copy
 {
     pub fn new(ith_tree: usize, rng_factory: &RngFactory) -> Tree<Y, SplitIndex> {
         let rng = rng_factory.new_rng_tree(ith_tree);
         Tree {
             tree: Vec::new(),
+            node_n: Vec::new(),
+            leaf_counts: None,
+            leaf_rows: None,
+            classes: Vec::new(),
             split_cols: HashSet::new(),
-            mask_cache: vec![],
-            preds_cache: vec![],
-            split_mask_map: HashMap::new(),
-            split_idx_cache_range: vec![],
-            preds_cache_range: vec![],
             rng,
             rng_factory: rng_factory.clone(),
-            ith_tree
+            ith_tree,
+            timing: None,
+            impurity_decrease: None,
+            split_weight: SplitWeight::default(),
+            ties: TiePolicy::default(),
+            split_penalty: SplitPenalty::default(),
+            root_n: 0,
+            oob_accuracy: None,
+            split_subsample: None,
+            error_metric: ErrorMetric::default(),
+            custom_metric: None,
+            min_node_size: 1,
+            uid: next_tree_uid()
+        }
+    }
+
+    // Keep in-bag class counts of leaves while building the tree, for predict_proba. Off by default.
+    pub fn set_leaf_counts(&mut self, leaf_counts: bool) {
+        self.leaf_counts = match leaf_counts {
+            true => Some(vec![vec![]; self.tree.len()]),
+            false => None
+        };
+    }
+
+    // Keep the in-bag rows of leaves while building the tree, for predict_row_weights. Off by default.
+    pub fn set_leaf_rows(&mut self, leaf_rows: bool) {
+        self.leaf_rows = match leaf_rows {
+            true => Some(vec![vec![]; self.tree.len()]),
+            false => None
+        };
+    }
+
+    // Sum impurity decrease of splits per column while building the tree. Off by default.
+    pub fn set_track_impurity(&mut self, track_impurity: bool) {
+        self.impurity_decrease = match track_impurity {
+            true => Some(ImpurityTree::new()),
+            false => None
+        };
+    }
+
+    // Weight of splits in the tracked impurity decrease, node size by default.
+    pub fn set_split_weight(&mut self, split_weight: SplitWeight) {
+        self.split_weight = split_weight;
+    }
+
+    // Class of leaves holding several classes, TiePolicy::Fixed by default.
+    pub fn set_tie_policy(&mut self, ties: TiePolicy) {
+        self.ties = ties;
+    }
+
+    // Correction of split scores for the levels of candidate columns, none by default.
+    pub fn set_split_penalty(&mut self, split_penalty: SplitPenalty) {
+        split_penalty.check();
+        self.split_penalty = split_penalty;
+    }
+
+    // Decrease in impurity (gini) of each split column, splits weighted by set_split_weight.
+    pub fn get_impurity_decrease(&self) -> Option<&ImpurityTree<SplitIndex::Col>> {
+        return self.impurity_decrease.as_ref();
+    }
+
+    // Nodes with more samples search candidate splits on a random subsample of this size.
+    pub fn set_split_subsample(&mut self, split_subsample: Option<usize>) {
+        if split_subsample == Some(0) {
+            panic!("Split subsample must be larger than 0.");
+        }
+        self.split_subsample = split_subsample;
+    }
+
+    // Nodes with fewer in-bag rows become leaves of their majority class. 1 by default, splitting every impure node.
+    pub fn set_min_node_size(&mut self, min_node_size: usize) {
+        if min_node_size == 0 {
+            panic!("Minimum node size must be larger than 0.");
+        }
+        self.min_node_size = min_node_size;
+    }
+
+    // Loss importance is measured with, misclassification count by default.
+    pub fn set_error_metric(&mut self, error_metric: ErrorMetric) {
+        self.error_metric = error_metric;
+        if error_metric != ErrorMetric::Custom {
+            self.custom_metric = None;
+        }
+    }
+
+    // Measure importance with a loss of the caller, the error metric becomes ErrorMetric::Custom.
+    pub fn set_custom_error_metric(&mut self, metric: Arc<dyn LossMetric<Y>>) {
+        self.error_metric = ErrorMetric::Custom;
+        self.custom_metric = Some(metric);
+    }
+
+    fn loss<U: Response<Y>>(&self, y: &U, mask: &Mask, preds: &Predicted<Y>) -> f64 {
+        return match (&self.custom_metric, self.error_metric) {
+            (Some(metric), ErrorMetric::Custom) => metric.loss(&mask.get_by_mask(y.as_vector_ref()), preds),
+            (_, error_metric) => y.pred_loss(mask, preds, error_metric)
+        };
+    }
+
+    // Record time spent in split search, prediction and permutation. Off by default.
+    pub fn set_timing(&mut self, timing: bool) {
+        self.timing = match timing {
+            true => Some(TreeTiming::default()),
+            false => None
+        };
+    }
+
+    pub fn get_timing(&self) -> Option<TreeTiming> {
+        return self.timing;
+    }
+
+    // Columns of the splits of this tree.
+    pub fn get_split_cols(&self) -> &HashSet<SplitIndex::Col> {
+        return &self.split_cols;
+    }
+
+    // Digest of the nodes with their split pivots and leaf classes, equal on every platform for identically grown trees.
+    pub fn structure_hash(&self) -> u64
+        where
+            Y: Hash,
+            SplitIndex: Hash
+    {
+        let mut hasher = StableHasher::new();
+        self.tree.hash(&mut hasher);
+        return hasher.finish();
+    }
+
+    // Depth of each node, indexed as in to_tsv. Children are pushed before their parent, so the root is last.
+    pub fn node_depths(&self) -> Vec<usize> {
+        let mut depths = vec![0; self.tree.len()];
+        for node_id in (0..self.tree.len()).rev() {
+            if let Node::Sp(split) = &self.tree[node_id] {
+                depths[split.l_child_idx] = depths[node_id] + 1;
+                depths[split.r_child_idx] = depths[node_id] + 1;
+            }
+        }
+        return depths;
+    }
+
+    // Ordered pairs of split columns (upper, lower) where a split on upper is an ancestor of a split on lower,
+    // i.e. both lie on one root-to-leaf path. A column splitting twice on a path pairs with itself.
+    pub fn split_pairs(&self) -> HashSet<(SplitIndex::Col, SplitIndex::Col)> {
+        let mut res = HashSet::new();
+        if self.tree.is_empty() {
+            return res;
+        }
+        // Nodes with the columns of the splits above them
+        let mut stack = vec![(self.tree.len() - 1, vec![])];
+        while let Some((node_id, ancestors)) = stack.pop() {
+            let split = match &self.tree[node_id] {
+                Node::Sp(split) => split,
+                Node::Lf(_) => continue
+            };
+            let col = split.split_index.get_col_id();
+            for &upper in ancestors.iter() {
+                res.insert((upper, col));
+            }
+            let mut ancestors = ancestors;
+            ancestors.push(col);
+            stack.push((split.l_child_idx, ancestors.clone()));
+            stack.push((split.r_child_idx, ancestors));
+        }
+        return res;
+    }
+
+    pub fn shape(&self) -> TreeShape {
+        let depths = self.node_depths();
+        let leaf_depths = self.tree.iter().zip(depths.iter())
+            .filter(|(node, _)| matches!(node, Node::Lf(_)))
+            .map(|(_, &depth)| depth);
+        return leaf_depths.fold(TreeShape::default(), |shape, depth| TreeShape{depth: shape.depth.max(depth), n_leaves: shape.n_leaves + 1});
+    }
+
+    // One line per node with its depth, children and split column or leaf class, NA where not applicable.
+    pub fn to_tsv<W, F>(&self, w: &mut W, col_name: F) -> io::Result<()>
+        where
+            W: Write,
+            F: Fn(&SplitIndex::Col) -> String
+    {
+        writeln!(w, "node\tdepth\tleft\tright\tcol\tclass")?;
+        for (node_id, (node, depth)) in self.tree.iter().zip(self.node_depths()).enumerate() {
+            match node {
+                Node::Sp(split) => writeln!(w, "{}\t{}\t{}\t{}\t{}\tNA", node_id, depth, split.l_child_idx, split.r_child_idx,
+                                            tsv_field(&col_name(&split.split_index.get_col_id())))?,
+                Node::Lf(leaf) => writeln!(w, "{}\t{}\tNA\tNA\tNA\t{}", node_id, depth, tsv_field(&format!("{:?}", leaf.get_class())))?
+            }
         }
+        return Ok(());
+    }
+
+    // Accuracy on the mask importance was last computed on.
+    pub fn get_oob_accuracy(&self) -> Option<f64> {
+        return self.oob_accuracy;
+    }
+
+    // Accuracy on mask, reusing the value from importance when available.
+    pub fn oob_accuracy<T, U>(&mut self, df: &T, y: &U, mask: &Mask) -> f64
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        if let Some(accuracy) = self.oob_accuracy {
+            return accuracy;
+        }
+
+        let mut mask_ranks = vec![usize::MAX; y.len()];
+        for (rank, &mask) in mask.get_mask().iter().enumerate() {
+            mask_ranks[mask] = rank;
+        }
+        let preds = self.predict(df, mask, None, &mask_ranks);
+        self.set_oob_accuracy(y.pred_error(mask, &preds));
+        return self.oob_accuracy.unwrap();
     }
 
     #[inline]
-    fn push_node(&mut self, node: Node<Y, SplitIndex>) -> NodeHandle {
+    // Takes the error rate rather than the count, which for multiple responses counts each response.
+    fn set_oob_accuracy(&mut self, pred_error: f64) {
+        self.oob_accuracy = Some(1. - pred_error);
+    }
+
+    #[inline]
+    fn start_timer(&self) -> Option<Instant> {
+        return self.timing.as_ref().map(|_| Instant::now());
+    }
+
+    #[inline]
+    fn stop_timer(&mut self, start: Option<Instant>, phase: fn(&mut TreeTiming) -> &mut Duration) {
+        if let (Some(start), Some(timing)) = (start, self.timing.as_mut()) {
+            *phase(timing) += start.elapsed();
+        }
+    }
+
+    #[inline]
+    fn push_node(&mut self, node: Node<Y, SplitIndex>, n: usize) -> NodeHandle {
         self.tree.push(node);
+        self.node_n.push(n);
+        self.uid = next_tree_uid();
+        if let Some(leaf_counts) = self.leaf_counts.as_mut() {
+            leaf_counts.push(vec![]);
+        }
+        if let Some(leaf_rows) = self.leaf_rows.as_mut() {
+            leaf_rows.push(vec![]);
+        }
         return self.tree.len() - 1;
     }
 
+    fn push_leaf<U: Response<Y>>(&mut self, y: &U, mask: &Mask, class: Y) -> NodeHandle {
+        let node_id = self.push_node(Node::create_leaf(class), mask.n_draws());
+        if let Some(leaf_counts) = self.leaf_counts.as_mut() {
+            leaf_counts[node_id] = y.class_counts(mask);
+        }
+        if let Some(leaf_rows) = self.leaf_rows.as_mut() {
+            leaf_rows[node_id] = mask.iter_weighted().collect();
+        }
+        return node_id;
+    }
+
+    // Nodes of a tree grown elsewhere, e.g. by an importer, children before their parent so the root comes last.
+    // n is the number of in-bag rows reaching the node, routing rows with missing values.
+    #[cfg(feature = "import")]
+    pub(crate) fn push_imported_leaf(&mut self, class: Y, n: usize) -> NodeHandle {
+        self.root_n = n;
+        return self.push_node(Node::create_leaf(class), n);
+    }
+
+    #[cfg(feature = "import")]
+    pub(crate) fn push_imported_split(&mut self, split_index: SplitIndex, l_child_idx: NodeHandle, r_child_idx: NodeHandle, n: usize) -> NodeHandle {
+        self.root_n = n;
+        self.split_cols.insert(split_index.get_col_id());
+        return self.push_node(Node::create_split(split_index, l_child_idx, r_child_idx), n);
+    }
+
     pub fn build_tree<T, U>(&mut self, df: &T, y: &U, mask: &Mask, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>)
         where
             T: DataInterface<SplitIndex, Y>,
             U: Response<Y>
     {
-        self._build_tree(df, y, &mask, mtry, shadow_vars, max_tree_depth, 0);
+        self.root_n = mask.n_draws();
+        if self.leaf_counts.is_some() {
+            self.classes = y.class_counts(mask).into_iter().map(|(class, _)| class).collect();
+        }
+        let mut rows = mask.get_mask().clone();
+        // Node masks are refilled from rows, keeping the in-bag multiplicity of the rows for split search and leaves
+        let mut bufs = BuildBuffers::default();
+        bufs.mask = bufs.mask.with_multiplicity_of(mask);
+        self._build_tree(df, y, &mut rows, mtry, shadow_vars, max_tree_depth, 0, &mut bufs);
     }
 
-    fn _build_tree<T, U>(&mut self, df: &T, y: &U, mask: &Mask, mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, tree_depth: usize) -> NodeHandle
+    fn _build_tree<T, U>(&mut self, df: &T, y: &U, rows: &mut [usize], mtry: usize, shadow_vars: bool, max_tree_depth: Option<usize>, tree_depth: usize, bufs: &mut BuildBuffers) -> NodeHandle
         where
             T: DataInterface<SplitIndex, Y>,
             U: Response<Y>
     {
+        // Response and split search still take a mask, it is refilled from rows instead of allocated
+        let mut mask = std::mem::take(&mut bufs.mask);
+        mask.assign(rows);
+
         // If y is single class create node and return
         let class = y.get_class(&mask);
 
         if let Some(x) = class {
-            let node = Node::create_leaf(x);
-            return self.push_node(node);
+            let node_id = self.push_leaf(y, &mask, x);
+            bufs.mask = mask;
+            return node_id;
+        }
+
+        if max_tree_depth.is_some_and(|max_depth| tree_depth >= max_depth) || mask.n_draws() < self.min_node_size {
+            let major_class = y.get_major_class_with(&mask, &mut self.rng, self.ties);
+            let node_id = self.push_leaf(y, &mask, major_class);
+            bufs.mask = mask;
+            return node_id;
+        }
+
+        // find best split, if no column can split the node terminate with leaf
+        let timer = self.start_timer();
+        let split_idx = match self.split_subsample {
+            Some(n) if mask.len() > n => self.find_min_idx_subsample(df, y, &mask, n, mtry, shadow_vars),
+            _ => df.find_min_idx_penalized(&mask, y, mtry, &mut self.rng, &self.rng_factory, shadow_vars, self.split_penalty)
+        };
+        self.stop_timer(timer, |t| &mut t.split_search);
+        let split_idx = match split_idx {
+            Some(x) => x,
+            None => {
+                let major_class = y.get_major_class_with(&mask, &mut self.rng, self.ties);
+                let node_id = self.push_leaf(y, &mask, major_class);
+                bufs.mask = mask;
+                return node_id;
+            }
+        };
+
+        // Make split, rows of the left child end up first
+        let n_left = df.partition(split_idx, rows, &self.rng_factory, &mut bufs.scratch);
+
+        // If one split branch is empty terminate with leaf
+        if n_left == 0 || n_left == rows.len() {
+            let major_class = y.get_major_class_with(&mask, &mut self.rng, self.ties);
+            let node_id = self.push_leaf(y, &mask, major_class);
+            bufs.mask = mask;
+            return node_id;
+        }
+
+        let n_node = mask.n_draws();
+        if self.impurity_decrease.is_some() {
+            let n_node = n_node as f64;
+            let parent = n_node * y.impurity(&mask);
+            mask.assign(&rows[..n_left]);
+            let left = mask.n_draws() as f64 * y.impurity(&mask);
+            mask.assign(&rows[n_left..]);
+            let right = mask.n_draws() as f64 * y.impurity(&mask);
+            let node_decrease = match self.split_weight {
+                SplitWeight::NodeSize => (parent - left - right) / self.root_n as f64,
+                SplitWeight::Depth => (parent - left - right) / n_node * 0.5_f64.powi(tree_depth as i32)
+            };
+            *self.impurity_decrease.as_mut().unwrap().entry(split_idx.get_col_id()).or_insert(0.) += node_decrease;
+        }
+        bufs.mask = mask;
+
+        // Otherwise continue further down
+        let (l_rows, r_rows) = rows.split_at_mut(n_left);
+        let l_node = self._build_tree(df, y, l_rows, mtry, shadow_vars, max_tree_depth, tree_depth + 1, bufs);
+        let r_node = self._build_tree(df, y, r_rows, mtry, shadow_vars, max_tree_depth, tree_depth + 1, bufs);
+        self.split_cols.insert(split_idx.get_col_id());
+        let node = Node::create_split(split_idx, l_node, r_node);
+        return self.push_node(node, n_node);
+    }
+
+    // Ranks the mtry candidates by gini on n sampled rows of the node, then fits the pivot of the winner
+    // on all rows. Candidates are scored with the gini of each side scaled by its n / (n - 1): a small side of
+    // a subsample looks purer than it is in the node, and the correction weighs most on small sides, so it can
+    // reorder candidates that cut off few rows. Falls back to the full node when no candidate splits the subsample.
+    fn find_min_idx_subsample<T, U>(&mut self, df: &T, y: &U, mask: &Mask, n: usize, mtry: usize, shadow_vars: bool) -> Option<SplitIndex>
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        let sub_mask = Mask::new(self.rng.sample(mask.get_mask(), n));
+        return match df.find_min_idx_subsample(&sub_mask, y, mtry, &mut self.rng, &self.rng_factory, shadow_vars, self.split_penalty) {
+            Some(split_idx) => Some(df.refine_split(split_idx, mask, y, &self.rng_factory)),
+            None => df.find_min_idx_penalized(mask, y, mtry, &mut self.rng, &self.rng_factory, shadow_vars, self.split_penalty)
+        };
+    }
+
+    pub fn predict<T>(&self, df: &T, mask: &Mask, permuted_col: Option<SplitIndex::Col>, mask_ranks: &[usize]) -> Predicted<Y>
+        where
+            T: DataInterface<SplitIndex, Y>,
+    {
+        return self.predict_with(&mut PredictWorkspace::new(), df, mask, permuted_col, mask_ranks);
+    }
+
+    // As predict, but unpermuted predictions fill ws and predictions with permuted_col reuse it.
+    // ws is filled first when it holds no unpermuted prediction on mask.
+    pub fn predict_with<T>(&self, ws: &mut PredictWorkspace<Y, SplitIndex::Col>, df: &T, mask: &Mask, permuted_col: Option<SplitIndex::Col>, mask_ranks: &[usize]) -> Predicted<Y>
+        where
+            T: DataInterface<SplitIndex, Y>,
+    {
+        let mut preds = vec![self.any_class(); mask.len()];
+        self.predict_into(ws, df, mask, permuted_col, mask_ranks, &mut preds);
+        return preds;
+    }
+
+    // As predict_with, but writes the predictions to out, so loops predicting many times allocate nothing.
+    // Prediction of row mask[i] goes to out[mask_ranks[mask[i]]].
+    pub fn predict_into<T>(&self, ws: &mut PredictWorkspace<Y, SplitIndex::Col>, df: &T, mask: &Mask, permuted_col: Option<SplitIndex::Col>, mask_ranks: &[usize], out: &mut [Y])
+        where
+            T: DataInterface<SplitIndex, Y>,
+    {
+        if out.len() != mask.len() {
+            panic!("Output must have a place for each row of the mask.");
+        }
+
+        match permuted_col {
+            None => {
+                ws.clear();
+                let _ = self._predict_write_cache(ws, df, mask, None, out, &mask_ranks, 0);
+            },
+            Some(col) => {
+                if !ws.is_filled_for(mask) {
+                    self.predict_into(ws, df, mask, None, mask_ranks, out);
+                }
+                let permuted_vec = df.permute_index(col, &self.rng_factory, &mask, self.ith_tree, 0);
+                self._predict(ws, df, mask, permuted_col, &permuted_vec, None, false, out, &mask_ranks)
+            }
+        };
+    }
+
+    // Walks each row from the root separately. With a row-major view this has better locality than predict
+    // for large masks, but it fills no caches, so importance still goes through predict. Rows missing the
+    // value of a split column follow NaPolicy::Majority.
+    pub fn predict_rows<R>(&self, rows: &R, mask: &Mask) -> Predicted<Y>
+        where
+            R: RowView<SplitIndex>
+    {
+        return mask.get_mask().iter().map(|&row| self.leaf_class(self.leaf_of(rows, row))).collect();
+    }
+
+    // As predict_rows, with the prediction of mask[i] written to out[i].
+    pub fn predict_rows_into<R>(&self, rows: &R, mask: &Mask, out: &mut [Y])
+        where
+            R: RowView<SplitIndex>
+    {
+        if out.len() != mask.len() {
+            panic!("Output must have a place for each row of the mask.");
+        }
+        for (pred, &row) in out.iter_mut().zip(mask.get_mask().iter()) {
+            *pred = self.leaf_class(self.leaf_of(rows, row));
+        }
+    }
+
+    // As predict_rows, but terminal nodes of rows are kept in cache under data_id and reused by later calls of
+    // this tree. Call ApplyCache::invalidate when the data behind data_id changes.
+    pub fn predict_rows_cached<R>(&self, rows: &R, mask: &Mask, cache: &ApplyCache, data_id: u64) -> Predicted<Y>
+        where
+            R: RowView<SplitIndex>
+    {
+        let mut leaves = cache.take(self.ith_tree, self.uid, data_id);
+        let preds = mask.get_mask().iter().map(|&row| {
+            if row >= leaves.len() {
+                leaves.resize(row+1, NodeHandle::MAX);
+            }
+            if leaves[row] == NodeHandle::MAX {
+                leaves[row] = self.leaf_of(rows, row);
+            }
+            self.leaf_class(leaves[row])
+        }).collect();
+        cache.store(self.ith_tree, self.uid, data_id, leaves);
+        return preds;
+    }
+
+    #[inline]
+    fn leaf_of<R>(&self, rows: &R, row: usize) -> NodeHandle
+        where
+            R: RowView<SplitIndex>
+    {
+        let mut node_id = self.tree.len() - 1;
+        loop {
+            match &self.tree[node_id] {
+                Node::Lf(_) => return node_id,
+                Node::Sp(split) => node_id = match rows.try_goes_left(row, &split.split_index) {
+                    Some(true) => split.l_child_idx,
+                    Some(false) => split.r_child_idx,
+                    None => self.majority_child(split)
+                }
+            }
+        }
+    }
+
+    // Classes of the leaves row falls into with their weights summing to 1. Rows missing the value of a split
+    // column are routed by na_policy, otherwise there is a single leaf of weight 1.
+    pub fn predict_class_weights<R>(&self, rows: &R, row: usize, na_policy: NaPolicy) -> Vec<(Y, f64)>
+        where
+            R: RowView<SplitIndex>
+    {
+        return self.leaf_weights(rows, row, na_policy).into_iter()
+            .map(|(node_id, weight)| (self.leaf_class(node_id), weight))
+            .collect();
+    }
+
+    // Probability of each class of the in-bag rows for row, from the class counts of the leaves it falls into
+    // with Laplace smoothing: (n_class + alpha) / (n_leaf + alpha * n_classes). alpha 0 gives plain leaf
+    // frequencies. Leaves of the tree are weighted as in predict_class_weights. Requires set_leaf_counts.
+    pub fn predict_proba<R>(&self, rows: &R, row: usize, na_policy: NaPolicy, alpha: f64) -> Vec<(Y, f64)>
+        where
+            R: RowView<SplitIndex>
+    {
+        if alpha < 0. {
+            panic!("Smoothing alpha must be non-negative.");
+        }
+        let leaf_counts = match &self.leaf_counts {
+            Some(leaf_counts) => leaf_counts,
+            None => panic!("Leaf counts must be kept to predict probabilities, see set_leaf_counts.")
+        };
+        let mut res: Vec<(Y, f64)> = self.classes.iter().map(|&class| (class, 0.)).collect();
+        for (node_id, weight) in self.leaf_weights(rows, row, na_policy) {
+            for (class, p) in res.iter_mut() {
+                *p += weight * self.leaf_proba(&leaf_counts[node_id], *class, alpha);
+            }
+        }
+        return res;
+    }
+
+    // In-bag rows sharing the leaves row falls into, each weighted by its share of the leaf draws times the leaf
+    // weight of predict_class_weights, so weights sum to 1. Averaged over trees these are the weights of quantile
+    // regression forests (Meinshausen 2006). Requires set_leaf_rows.
+    pub fn predict_row_weights<R>(&self, rows: &R, row: usize, na_policy: NaPolicy) -> Vec<(usize, f64)>
+        where
+            R: RowView<SplitIndex>
+    {
+        let leaf_rows = match &self.leaf_rows {
+            Some(leaf_rows) => leaf_rows,
+            None => panic!("Leaf rows must be kept to predict row weights, see set_leaf_rows.")
+        };
+        let mut res = vec![];
+        for (node_id, weight) in self.leaf_weights(rows, row, na_policy) {
+            let n_draws: u32 = leaf_rows[node_id].iter().map(|(_, w)| w).sum();
+            res.extend(leaf_rows[node_id].iter().map(|&(i, w)| (i, weight * w as f64 / n_draws as f64)));
+        }
+        return res;
+    }
+
+    // Smoothed probability of class from the counts of a leaf, see predict_proba.
+    fn leaf_proba(&self, counts: &[(Y, usize)], class: Y, alpha: f64) -> f64 {
+        let n: usize = counts.iter().map(|(_, n)| n).sum();
+        let n_class = counts.iter().find(|(c, _)| *c == class).map_or(0, |(_, n)| *n);
+        return (n_class as f64 + alpha) / (n as f64 + alpha * self.classes.len() as f64);
+    }
+
+    // Leaves row falls into with their weights, see predict_class_weights.
+    fn leaf_weights<R>(&self, rows: &R, row: usize, na_policy: NaPolicy) -> Vec<(NodeHandle, f64)>
+        where
+            R: RowView<SplitIndex>
+    {
+        let mut res = vec![];
+        let mut stack = vec![(self.tree.len() - 1, 1.)];
+        while let Some((node_id, weight)) = stack.pop() {
+            let split = match &self.tree[node_id] {
+                Node::Lf(_) => {
+                    res.push((node_id, weight));
+                    continue;
+                },
+                Node::Sp(split) => split
+            };
+            let (n_left, n_right) = (self.node_n[split.l_child_idx], self.node_n[split.r_child_idx]);
+            match (rows.try_goes_left(row, &split.split_index), na_policy) {
+                (Some(true), _) => stack.push((split.l_child_idx, weight)),
+                (Some(false), _) => stack.push((split.r_child_idx, weight)),
+                (None, NaPolicy::Majority) => stack.push((self.majority_child(split), weight)),
+                (None, NaPolicy::Weighted) => {
+                    let left_share = n_left as f64 / (n_left + n_right) as f64;
+                    stack.push((split.r_child_idx, weight * (1. - left_share)));
+                    stack.push((split.l_child_idx, weight * left_share));
+                }
+            }
+        }
+        return res;
+    }
+
+    // Child more in-bag rows went to, where NaPolicy::Majority sends rows missing the split value.
+    #[inline]
+    fn majority_child(&self, split: &Split<SplitIndex>) -> NodeHandle {
+        return match self.node_n[split.l_child_idx] >= self.node_n[split.r_child_idx] {
+            true => split.l_child_idx,
+            false => split.r_child_idx
+        };
+    }
+
+    // Class of the first leaf, filling prediction buffers before every place is written.
+    fn any_class(&self) -> Y {
+        return self.tree.iter().find_map(|node| match node {
+            Node::Lf(leaf) => Some(leaf.get_class()),
+            Node::Sp(_) => None
+        }).unwrap();
+    }
+
+    #[inline]
+    fn leaf_class(&self, node_id: NodeHandle) -> Y {
+        return match &self.tree[node_id] {
+            Node::Lf(leaf) => leaf.get_class(),
+            Node::Sp(_) => unreachable!()
+        };
+    }
+
+    // Predict with permuted_col replaced by an already permuted vector into preds. Requires caches from predict
+    // without permutation.
+    fn _predict_permuted<T>(&self, ws: &PredictWorkspace<Y, SplitIndex::Col>, df: &T, mask: &Mask, permuted_col: SplitIndex::Col, permuted_vec: &T::InternalType, mask_ranks: &[usize], preds: &mut [Y])
+        where
+            T: DataInterface<SplitIndex, Y>,
+    {
+        self._predict(ws, df, mask, Some(permuted_col), permuted_vec, None, false, preds, &mask_ranks);
+    }
+
+    fn _predict_write_cache<T>(&self, ws: &mut PredictWorkspace<Y, SplitIndex::Col>, df: &T, mask: &Mask, node_id: Option<NodeHandle>, preds: &mut [Y], mask_ranks: &[usize], split_idx: usize) -> usize
+        where
+            T: DataInterface<SplitIndex, Y>
+    {
+        let node = match node_id {
+            None => self.tree.last().unwrap().clone(),
+            Some(nid) => self.tree[nid].clone()
+        };
+
+        match node {
+            Node::Lf(leaf) => {
+                ws.mask_cache.push(mask.clone());
+                ws.preds_cache_range.push((ws.preds_cache.len(), ws.preds_cache.len()+mask.len()));
+
+                for &i in mask.get_mask().iter() {
+                    preds[mask_ranks[i]] = leaf.get_class();
+
+                    ws.preds_cache.push((leaf.get_class(), mask_ranks[i]))
+                }
+                ws.split_idx_cache_range.push((split_idx, split_idx));
+                return split_idx;
+            }
+
+            Node::Sp(split) => {
+                // make split
+                let masks = df.make_split(split.split_index, &mask, &self.rng_factory, None);
+                let preds_len_0 = ws.preds_cache.len();
+
+                let mut new_idx = self._predict_write_cache(ws, df, &masks[0], Some(split.l_child_idx.clone()), preds, &mask_ranks, split_idx);
+                new_idx = self._predict_write_cache(ws, df, &masks[1], Some(split.r_child_idx.clone()), preds, &mask_ranks, new_idx);
+
+                ws.mask_cache.push(mask.clone());
+                ws.split_idx_cache_range.push((split_idx, new_idx));
+                ws.preds_cache_range.push((preds_len_0, ws.preds_cache.len()));
+
+                ws.split_mask_map.entry(split.split_index.get_col_id())
+                    .and_modify(|val| val.push(new_idx))
+                    .or_insert(vec![new_idx]);
+                return new_idx + 1;
+            }
+        }
+    }
+
+    #[inline]
+    fn _col_in_subtree(&self, ws: &PredictWorkspace<Y, SplitIndex::Col>, node_id: usize, col: &SplitIndex::Col) -> bool {
+        match ws.split_mask_map.get(col) {
+            None => false,
+            Some(idxs) => idxs.iter().any(|&idx| {
+                ws.split_idx_cache_range[node_id].0 <= idx && ws.split_idx_cache_range[node_id].1 >= idx
+            })
+        }
+    }
+
+    #[inline]
+    fn _preds_read_cache(&self, ws: &PredictWorkspace<Y, SplitIndex::Col>, node_id: usize, permuted_col: &SplitIndex::Col, preds: &mut [Y]) -> bool {
+        let node = &self.tree[node_id];
+        if let Node::Sp(_) = node {
+            let should_get_from_cache = !self._col_in_subtree(ws, node_id, permuted_col);
+
+            if should_get_from_cache {
+                if ws.preds_cache_range[node_id].0 < ws.preds_cache.len() {
+                    for i in ws.preds_cache_range[node_id].0..ws.preds_cache_range[node_id].1 {
+                        let pred = ws.preds_cache[i];
+                        preds[pred.1] = pred.0;
+                    }
+                    return true;
+                }
+            }
+        }
+        return false;
+    }
+
+    fn _predict<T>(&self, ws: &PredictWorkspace<Y, SplitIndex::Col>, df: &T, mask: &Mask, permuted_col: Option<SplitIndex::Col>, permuted_vec: &T::InternalType, node_id: Option<NodeHandle>, altered: bool, preds: &mut [Y], mask_ranks: &[usize])
+        where
+            T: DataInterface<SplitIndex, Y>
+    {
+        let node = match node_id {
+            None => self.tree.last().unwrap().clone(),
+            Some(nid) => self.tree[nid].clone()
+        };
+
+        match node {
+            Node::Lf(leaf) => {
+                for &i in mask.get_mask().iter() {
+                    preds[mask_ranks[i]] = leaf.get_class();
+                }
+            }
+
+            Node::Sp(split) => {
+                // make split
+                let permute = permuted_col.is_some() && permuted_col.unwrap() == split.split_index.get_col_id();
+                let permuted_vec_arg = match permute {
+                    true => Some(permuted_vec),
+                    false => None
+                };
+
+                let masks_own;
+                let masks = match ws.mask_cache.len() > 0 && !permute && !altered {
+                    true => [&ws.mask_cache[split.l_child_idx], &ws.mask_cache[split.r_child_idx]],
+                    false => {
+                        masks_own = df.make_split(split.split_index, &mask, &self.rng_factory, permuted_vec_arg);
+                        [&masks_own[0], &masks_own[1]]
+                    }
+                };
+
+
+                if permute || altered || !self._preds_read_cache(ws, split.l_child_idx, &permuted_col.unwrap(), preds) {
+                    self._predict(ws, df, &masks[0], permuted_col, &permuted_vec, Some(split.l_child_idx.clone()), altered || permute, preds, &mask_ranks);
+                }
+
+                if permute || altered || !self._preds_read_cache(ws, split.r_child_idx, &permuted_col.unwrap(), preds) {
+                    self._predict(ws, df, &masks[1], permuted_col, &permuted_vec, Some(split.r_child_idx.clone()), altered || permute, preds, &mask_ranks);
+                }
+            }
+        }
+    }
+
+    // Error increase of each split column is summed over n_perm permutation rounds.
+    pub fn importance<T, U>(&mut self, df: &T, y: &U, mask: &Mask, n_perm: usize, mut perm_cache: Option<&mut PermutationCache<SplitIndex::Col, T::InternalType>>) -> ImportanceTree<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        let base = self.importance_base(df, y, mask);
+        let mut preds_perm = base.preds.clone();
+        let mut timing = TreeTiming::default();
+        let mut importance = ImportanceTree::new();
+        for &col in self.split_cols.iter() {
+            importance.insert(col, self.importance_col(&base, df, y, mask, col, n_perm, perm_cache.as_deref_mut(), &mut preds_perm, &mut timing));
+        }
+        self.add_importance_timing(timing);
+        return importance;
+    }
+
+    // Unpermuted predictions on mask the columns of importance are permuted against. Sets the OOB accuracy.
+    pub(crate) fn importance_base<T, U>(&mut self, df: &T, y: &U, mask: &Mask) -> ImportanceBase<Y, SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        let timer = self.start_timer();
+        let base = self.predict_base(df, y, mask);
+        self.stop_timer(timer, |t| &mut t.prediction);
+        self.set_oob_accuracy(y.pred_error(&mask, &base.preds));
+        return base;
+    }
+
+    // As importance_base, leaving the tree as is.
+    fn predict_base<T, U>(&self, df: &T, y: &U, mask: &Mask) -> ImportanceBase<Y, SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        let mut mask_ranks = vec![usize::MAX; y.len()];
+        for (rank, &mask) in mask.get_mask().iter().enumerate() {
+            mask_ranks[mask] = rank;
+        }
+
+        let mut ws = PredictWorkspace::new();
+        let preds = self.predict_with(&mut ws, df, &mask, None, &mask_ranks);
+        let pred_err = self.loss(y, &mask, &preds);
+        return ImportanceBase{ws, mask_ranks, preds, pred_err};
+    }
+
+    // As importance without a permutation cache, but columns are permuted by the streams of rng_factory and the
+    // tree is left as is, e.g. to permute a fitted tree with a number of rounds it was not grown with.
+    pub(crate) fn importance_with_streams<T, U>(&self, df: &T, y: &U, mask: &Mask, n_perm: usize, rng_factory: &RngFactory) -> ImportanceTree<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        let base = self.predict_base(df, y, mask);
+        let mut preds_perm = base.preds.clone();
+        let mut timing = TreeTiming::default();
+        let mut importance = ImportanceTree::new();
+        for &col in self.split_cols.iter() {
+            importance.insert(col, self.importance_col_with(rng_factory, &base, df, y, mask, col, n_perm, None, &mut preds_perm, &mut timing));
+        }
+        return importance;
+    }
+
+    // As importance_with_streams, scoring the same permuted predictions against each of ys, e.g. the responses of
+    // a YMultiBool one at a time. Importance against ys[i] is at [i].
+    pub(crate) fn importance_each<T, U>(&self, df: &T, ys: &[U], mask: &Mask, n_perm: usize, rng_factory: &RngFactory) -> Vec<ImportanceTree<SplitIndex::Col>>
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        let mut mask_ranks = vec![usize::MAX; mask.get_mask().last().map_or(0, |&row| row + 1)];
+        for (rank, &row) in mask.get_mask().iter().enumerate() {
+            mask_ranks[row] = rank;
+        }
+        let mut ws = PredictWorkspace::new();
+        let preds = self.predict_with(&mut ws, df, mask, None, &mask_ranks);
+        let base_errs: Vec<f64> = ys.iter().map(|y| self.loss(y, mask, &preds)).collect();
+        let mut preds_perm = preds.clone();
+        let mut res = vec![ImportanceTree::new(); ys.len()];
+        for &col in self.split_cols.iter() {
+            let mut col_imps = vec![0.; ys.len()];
+            for round in 0..n_perm {
+                let permuted_vec = df.permute_index(col, rng_factory, mask, self.ith_tree, round);
+                self._predict_permuted(&ws, df, mask, col, &permuted_vec, &mask_ranks, &mut preds_perm);
+                for ((col_imp, y), base_err) in col_imps.iter_mut().zip(ys.iter()).zip(base_errs.iter()) {
+                    *col_imp += self.loss(y, mask, &preds_perm) - base_err;
+                }
+            }
+            for (importance, col_imp) in res.iter_mut().zip(col_imps) {
+                importance.insert(col, col_imp);
+            }
+        }
+        return res;
+    }
+
+    // As importance_with_streams, with the unpermuted leaves of the rows kept in cache under data_id. Importance
+    // on the same data again skips the unpermuted prediction, and a permuted column reroutes only the rows whose
+    // path splits on it. Call ApplyCache::invalidate when the data behind data_id changes.
+    pub(crate) fn importance_cached<T, U>(&self, df: &T, y: &U, mask: &Mask, n_perm: usize, rng_factory: &RngFactory, cache: &ApplyCache, data_id: u64) -> ImportanceTree<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        let root = self.tree.len() - 1;
+        // Leaves are indexed by row, so rows are their own ranks
+        let row_ranks: Vec<usize> = (0..y.len()).collect();
+        let mut leaves = cache.take(self.ith_tree, self.uid, data_id);
+        if leaves.len() < y.len() {
+            leaves.resize(y.len(), NodeHandle::MAX);
+        }
+        let missing: Vec<usize> = mask.get_mask().iter().copied().filter(|&row| leaves[row] == NodeHandle::MAX).collect();
+        if !missing.is_empty() {
+            self._leaves_permuted(df, &Mask::from_sorted(missing), root, None, &mut leaves, &row_ranks);
+        }
+        let preds: Predicted<Y> = mask.get_mask().iter().map(|&row| self.leaf_class(leaves[row])).collect();
+        let pred_err = self.loss(y, mask, &preds);
+
+        let path_cols = self.path_cols();
+        let mut preds_perm = preds.clone();
+        let mut moved = leaves.clone();
+        let mut importance = ImportanceTree::new();
+        for &col in self.split_cols.iter() {
+            // Rank and row of the rows a permutation of col can move, the others keep their leaf
+            let affected: Vec<(usize, usize)> = mask.get_mask().iter().copied().enumerate()
+                .filter(|&(_, row)| path_cols[leaves[row]].contains(&col))
+                .collect();
+            let affected_mask = Mask::from_sorted(affected.iter().map(|&(_, row)| row).collect());
+            let mut col_imp = 0.;
+            for round in 0..n_perm {
+                let permuted_vec = df.permute_index(col, rng_factory, mask, self.ith_tree, round);
+                self._leaves_permuted(df, &affected_mask, root, Some((col, &permuted_vec)), &mut moved, &row_ranks);
+                for &(rank, row) in affected.iter() {
+                    preds_perm[rank] = self.leaf_class(moved[row]);
+                }
+                col_imp += self.loss(y, mask, &preds_perm) - pred_err;
+            }
+            for &(rank, _) in affected.iter() {
+                preds_perm[rank] = preds[rank];
+            }
+            importance.insert(col, col_imp);
+        }
+        cache.store(self.ith_tree, self.uid, data_id, leaves);
+        return importance;
+    }
+
+    // Columns split on above each node, indexed by node.
+    fn path_cols(&self) -> Vec<Vec<SplitIndex::Col>> {
+        let mut res = vec![vec![]; self.tree.len()];
+        // Parents come after their children, so walking back from the root fills parents first
+        for node_id in (0..self.tree.len()).rev() {
+            if let Node::Sp(split) = &self.tree[node_id] {
+                let mut cols = res[node_id].clone();
+                cols.push(split.split_index.get_col_id());
+                res[split.l_child_idx] = cols.clone();
+                res[split.r_child_idx] = cols;
+            }
+        }
+        return res;
+    }
+
+    // Permutation importance on the smoothed leaf probabilities of predict_proba: the loss of a row is one minus
+    // the probability of its class in its leaf, so a small leaf getting a row right by chance counts only partly.
+    // With ErrorMetric::CrossEntropy it is minus the log of that probability, which needs a positive alpha to stay
+    // finite. Summed over rows and rounds as importance. Requires set_leaf_counts.
+    pub fn proba_importance<T, U>(&self, df: &T, y: &U, mask: &Mask, n_perm: usize, alpha: f64) -> ImportanceTree<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        if alpha < 0. {
+            panic!("Smoothing alpha must be non-negative.");
+        }
+        let cross_entropy = self.error_metric == ErrorMetric::CrossEntropy;
+        if cross_entropy && alpha == 0. {
+            panic!("Smoothing alpha must be positive for cross entropy.");
         }
+        let leaf_counts = match &self.leaf_counts {
+            Some(leaf_counts) => leaf_counts,
+            None => panic!("Leaf counts must be kept for probability importance, see set_leaf_counts.")
+        };
+        let mut mask_ranks = vec![usize::MAX; y.len()];
+        for (rank, &row) in mask.get_mask().iter().enumerate() {
+            mask_ranks[row] = rank;
+        }
+        let classes = y.as_vector_ref();
+        let loss = |leaves: &[NodeHandle]| -> f64 {
+            return mask.get_mask().iter().zip(leaves.iter())
+                .map(|(&i, &node_id)| self.leaf_proba(&leaf_counts[node_id], classes[i], alpha))
+                .map(|p| match cross_entropy {
+                    true => -p.ln(),
+                    false => 1. - p
+                })
+                .sum();
+        };
+
+        let root = self.tree.len() - 1;
+        let mut leaves = vec![root; mask.len()];
+        self._leaves_permuted(df, mask, root, None, &mut leaves, &mask_ranks);
+        let base_loss = loss(&leaves);
+        let mut importance = HashMap::new();
+        for &col in self.split_cols.iter() {
+            let mut col_imp = 0.;
+            for round in 0..n_perm {
+                let permuted_vec = df.permute_index(col, &self.rng_factory, mask, self.ith_tree, round);
+                self._leaves_permuted(df, mask, root, Some((col, &permuted_vec)), &mut leaves, &mask_ranks);
+                col_imp += loss(&leaves) - base_loss;
+            }
+            importance.insert(col, col_imp);
+        }
+        return importance;
+    }
 
-        if max_tree_depth.is_some() && tree_depth >= max_tree_depth.unwrap() {
-            let major_class = y.get_major_class(&mask, &mut self.rng);
-            let node = Node::create_leaf(major_class);
-            return self.push_node(node);
+    // Leaf of each row of mask, in mask order, with the column of permuted taking its permuted values.
+    fn _leaves_permuted<T>(&self, df: &T, mask: &Mask, node_id: NodeHandle, permuted: Option<(SplitIndex::Col, &T::InternalType)>, leaves: &mut [NodeHandle], mask_ranks: &[usize])
+        where
+            T: DataInterface<SplitIndex, Y>
+    {
+        match &self.tree[node_id] {
+            Node::Lf(_) => {
+                for &i in mask.get_mask().iter() {
+                    leaves[mask_ranks[i]] = node_id;
+                }
+            }
+            Node::Sp(split) => {
+                let permuted_vec = permuted.filter(|(col, _)| *col == split.split_index.get_col_id()).map(|(_, vec)| vec);
+                let masks = df.make_split(split.split_index, mask, &self.rng_factory, permuted_vec);
+                self._leaves_permuted(df, &masks[0], split.l_child_idx, permuted, leaves, mask_ranks);
+                self._leaves_permuted(df, &masks[1], split.r_child_idx, permuted, leaves, mask_ranks);
+            }
+        }
+    }
+
+    // Streams of the tree, the ones of the forest it was grown in.
+    pub(crate) fn get_rng_factory(&self) -> &RngFactory {
+        return &self.rng_factory;
+    }
+
+    // Importance of col against base, its entry in importance. Permuted vectors come from perm_cache when given,
+    // preds_perm is a buffer as long as the base predictions. Time spent is added to timing, so columns of a tree
+    // can be permuted on several threads.
+    pub(crate) fn importance_col<T, U>(&self, base: &ImportanceBase<Y, SplitIndex::Col>, df: &T, y: &U, mask: &Mask, col: SplitIndex::Col, n_perm: usize, perm_cache: Option<&mut PermutationCache<SplitIndex::Col, T::InternalType>>, preds_perm: &mut Predicted<Y>, timing: &mut TreeTiming) -> f64
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        return self.importance_col_with(&self.rng_factory, base, df, y, mask, col, n_perm, perm_cache, preds_perm, timing);
+    }
+
+    fn importance_col_with<T, U>(&self, rng_factory: &RngFactory, base: &ImportanceBase<Y, SplitIndex::Col>, df: &T, y: &U, mask: &Mask, col: SplitIndex::Col, n_perm: usize, mut perm_cache: Option<&mut PermutationCache<SplitIndex::Col, T::InternalType>>, preds_perm: &mut Predicted<Y>, timing: &mut TreeTiming) -> f64
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        let mut col_imp = 0.;
+        for round in 0..n_perm {
+            let timer = self.start_timer();
+            let permuted_vec_own;
+            let permuted_vec = match perm_cache.as_deref_mut() {
+                Some(cache) => {
+                    cache.fill(df, col, round, rng_factory);
+                    cache.get(col, round).unwrap()
+                },
+                None => {
+                    permuted_vec_own = df.permute_index(col, rng_factory, &mask, self.ith_tree, round);
+                    &permuted_vec_own
+                }
+            };
+            if let Some(timer) = timer {
+                timing.permutation += timer.elapsed();
+            }
+
+            let timer = self.start_timer();
+            self._predict_permuted(&base.ws, df, &mask, col, permuted_vec, &base.mask_ranks, preds_perm);
+            if let Some(timer) = timer {
+                timing.prediction += timer.elapsed();
+            }
+            col_imp += self.loss(y, &mask, preds_perm) - base.pred_err;
+        }
+        return col_imp;
+    }
+
+    pub(crate) fn add_importance_timing(&mut self, timing: TreeTiming) {
+        if let Some(tree_timing) = self.timing.as_mut() {
+            tree_timing.permutation += timing.permutation;
+            tree_timing.prediction += timing.prediction;
+        }
+    }
+
+    // As importance, but error increase is kept per OOB sample. Always counts misclassifications.
+    pub fn local_importance<T, U>(&mut self, df: &T, y: &U, mask: &Mask, n_perm: usize) -> LocalImportanceTree<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        let mut mask_ranks = vec![usize::MAX; y.len()];
+        for (rank, &mask) in mask.get_mask().iter().enumerate() {
+            mask_ranks[mask] = rank;
+        }
+
+        let mut ws = PredictWorkspace::new();
+        let preds = self.predict_with(&mut ws, df, &mask, None, &mask_ranks);
+        let pred_err = loss_per_sample(y, mask, &preds);
+        self.set_oob_accuracy(y.pred_error(&mask, &preds));
+        let mut importance = LocalImportanceTree::new();
+        let mut preds_perm = preds.clone();
+
+        for &col in self.split_cols.clone().iter() {
+            let mut col_imp = vec![0.; mask.len()];
+            for round in 0..n_perm {
+                let permuted_vec = df.permute_index(col, &self.rng_factory, &mask, self.ith_tree, round);
+                self._predict_permuted(&ws, df, &mask, col, &permuted_vec, &mask_ranks, &mut preds_perm);
+                let pred_perm_err = loss_per_sample(y, mask, &preds_perm);
+                for ((imp, &err), &perm_err) in col_imp.iter_mut().zip(pred_err.iter()).zip(pred_perm_err.iter()) {
+                    *imp += perm_err - err;
+                }
+            }
+            importance.insert(col, col_imp);
+        }
+        return importance;
+    }
+
+    // Same as importance, but columns are split between threads. Each thread permutes and predicts its columns,
+    // so results equal importance without a permutation cache.
+    #[cfg(feature = "threads")]
+    pub fn importance_parallel<T, U>(&mut self, df: &T, y: &U, mask: &Mask, n_perm: usize, threads: usize) -> ImportanceTree<SplitIndex::Col>
+        where
+            T: DataInterface<SplitIndex, Y> + Sync,
+            U: Response<Y> + Sync,
+            Y: Send + Sync,
+            SplitIndex: Send + Sync
+    {
+        if threads == 0 {
+            panic!("Number of threads must be larger than 0.");
         }
 
-        // find best split
-        let split_idx = df.find_min_idx(&mask, y, mtry, &mut self.rng, &self.rng_factory, shadow_vars);
-
-        // Make split
-        let masks = df.make_split(split_idx, &mask, &self.rng_factory, None);
+        let base = self.importance_base(df, y, mask);
+        let cols: Vec<SplitIndex::Col> = self.split_cols.iter().cloned().collect();
+        let chunk_size = ((cols.len() + threads - 1) / threads).max(1);
+        let tree = &*self;
+        let base = &base;
+
+        let results: Vec<(Vec<(SplitIndex::Col, f64)>, TreeTiming)> = std::thread::scope(|s| {
+            let handles: Vec<_> = cols.chunks(chunk_size).map(|chunk| s.spawn(move || {
+                let mut timing = TreeTiming::default();
+                let mut preds_perm = base.preds.clone();
+                let imp = chunk.iter()
+                    .map(|&col| (col, tree.importance_col(base, df, y, mask, col, n_perm, None, &mut preds_perm, &mut timing)))
+                    .collect();
+                (imp, timing)
+            })).collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
 
-        // If one split branch is empty terminate with leaf
-        if masks[0].get_mask().len() == 0 || masks[1].get_mask().len() == 0 {
-            let major_class = y.get_major_class(&mask, &mut self.rng);
-            let node = Node::create_leaf(major_class);
-            return self.push_node(node);
+        let mut importance = ImportanceTree::new();
+        for (imp, timing) in results {
+            importance.extend(imp);
+            self.add_importance_timing(timing);
         }
-
-        // Otherwise continue further down
-        let l_node = self._build_tree(df, y, &masks[0], mtry, shadow_vars, max_tree_depth, tree_depth + 1);
-        let r_node = self._build_tree(df, y, &masks[1], mtry, shadow_vars, max_tree_depth, tree_depth + 1);
-        self.split_cols.insert(split_idx.get_col_id());
-        let node = Node::create_split(split_idx, l_node, r_node);
-        return self.push_node(node);
+        return importance;
     }
 
-    pub fn predict<T>(&mut self, df: &T, mask: &Mask, permuted_col: Option<SplitIndex::Col>, mask_ranks: &[usize]) -> Predicted<Y>
+    // Same as importance, but permutes up to batch_size (column, round) pairs in a single tree traversal.
+    pub fn importance_batched<T, U>(&mut self, df: &T, y: &U, mask: &Mask, n_perm: usize, batch_size: usize, mut perm_cache: Option<&mut PermutationCache<SplitIndex::Col, T::InternalType>>) -> ImportanceTree<SplitIndex::Col>
         where
             T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
     {
-        let mut preds = vec![None; mask.len()];
+        if batch_size == 0 {
+            panic!("Batch size must be larger than 0.");
+        }
 
-        match permuted_col {
-            None => { let _ = self._predict_write_cache(df, mask, None, &mut preds, &mask_ranks, 0);},
-            Some(_) => {
-                let permuted_vec = df.permute_index(permuted_col.unwrap().clone(), &self.rng_factory, &mask, self.ith_tree);
-                self._predict(df, mask, permuted_col, &permuted_vec, None, false, &mut preds, &mask_ranks)
+        let mut mask_ranks = vec![usize::MAX; y.len()];
+        for (rank, &mask) in mask.get_mask().iter().enumerate() {
+            mask_ranks[mask] = rank;
+        }
+
+        let timer = self.start_timer();
+        let mut ws = PredictWorkspace::new();
+        let preds = self.predict_with(&mut ws, df, &mask, None, &mask_ranks);
+        self.stop_timer(timer, |t| &mut t.prediction);
+        let mut importance = ImportanceTree::new();
+        let pred_err = self.loss(y, &mask, &preds);
+        self.set_oob_accuracy(y.pred_error(&mask, &preds));
+        let mut preds_batch: Vec<Predicted<Y>> = vec![];
+        let items: Vec<(SplitIndex::Col, usize)> = self.split_cols.iter()
+            .flat_map(|&col| (0..n_perm).map(move |round| (col, round)))
+            .collect();
+
+        for batch in items.chunks(batch_size) {
+            let timer = self.start_timer();
+            let permuted_vecs_own: Vec<T::InternalType>;
+            let permuted_vecs: Vec<&T::InternalType> = match perm_cache.as_deref_mut() {
+                Some(cache) => {
+                    for &(col, round) in batch.iter() {
+                        cache.fill(df, col, round, &self.rng_factory);
+                    }
+                    batch.iter().map(|&(col, round)| cache.get(col, round).unwrap()).collect()
+                },
+                None => {
+                    permuted_vecs_own = batch.iter()
+                        .map(|&(col, round)| df.permute_index(col, &self.rng_factory, &mask, self.ith_tree, round))
+                        .collect();
+                    permuted_vecs_own.iter().collect()
+                }
+            };
+            self.stop_timer(timer, |t| &mut t.permutation);
+            let cols: Vec<SplitIndex::Col> = batch.iter().map(|&(col, _)| col).collect();
+            // Only altered rows are written, the others keep the unpermuted predictions
+            preds_batch.resize_with(batch.len(), || preds.clone());
+            for preds_perm in preds_batch.iter_mut() {
+                preds_perm.copy_from_slice(&preds);
             }
-        };
+            let variants = (0..batch.len()).map(|k| (k, mask.clone(), false)).collect();
 
-        return preds.iter().map(|&x| x.unwrap()).collect();
+            let timer = self.start_timer();
+            self._predict_batch(&ws, df, &cols, &permuted_vecs, self.tree.len() - 1, variants, &mut preds_batch, &mask_ranks);
+            self.stop_timer(timer, |t| &mut t.prediction);
+
+            for (k, &col) in cols.iter().enumerate() {
+                let pred_perm_err = self.loss(y, &mask, &preds_batch[k]);
+                *importance.entry(col).or_insert(0.) += pred_perm_err - pred_err;
+            }
+        }
+        return importance;
     }
 
-    fn _predict_write_cache<T>(&mut self, df: &T, mask: &Mask, node_id: Option<NodeHandle>, preds: &mut Vec<Option<Y>>, mask_ranks: &[usize], split_idx: usize) -> usize
+    // Variants are (index in batch, mask reaching the node, altered by permutation above the node).
+    // Unaltered variants are dropped once no split below uses their column, as their predictions
+    // are the unpermuted ones.
+    fn _predict_batch<T>(&self, ws: &PredictWorkspace<Y, SplitIndex::Col>, df: &T, cols: &[SplitIndex::Col], permuted_vecs: &[&T::InternalType], node_id: NodeHandle, variants: Vec<(usize, Mask, bool)>, preds: &mut [Vec<Y>], mask_ranks: &[usize])
         where
             T: DataInterface<SplitIndex, Y>
     {
-        let node = match node_id {
-            None => self.tree.last().unwrap().clone(),
-            Some(nid) => self.tree[nid].clone()
-        };
-
-        match node {
+        match &self.tree[node_id] {
             Node::Lf(leaf) => {
-                self.mask_cache.push(mask.clone());
-                self.preds_cache_range.push((self.preds_cache.len(), self.preds_cache.len()+mask.len()));
-
-                for &i in mask.get_mask().iter() {
-                    preds[mask_ranks[i]] = Some(leaf.get_class());
-
-                    self.preds_cache.push((leaf.get_class(), mask_ranks[i]))
+                for (k, mask, altered) in variants.iter() {
+                    if *altered {
+                        for &i in mask.get_mask().iter() {
+                            preds[*k][mask_ranks[i]] = leaf.get_class();
+                        }
+                    }
                 }
-                self.split_idx_cache_range.push((split_idx, split_idx));
-                return split_idx;
             }
 
             Node::Sp(split) => {
-                // make split
-                let masks = df.make_split(split.split_index, &mask, &self.rng_factory, None);
-                let preds_len_0 = self.preds_cache.len();
-
-                let mut new_idx = self._predict_write_cache(df, &masks[0], Some(split.l_child_idx.clone()), preds, &mask_ranks, split_idx);
-                new_idx = self._predict_write_cache(df, &masks[1], Some(split.r_child_idx.clone()), preds, &mask_ranks, new_idx);
+                let mut l_variants = vec![];
+                let mut r_variants = vec![];
+
+                for (k, mask, altered) in variants.into_iter() {
+                    let permute = cols[k] == split.split_index.get_col_id();
+                    if !permute && !altered {
+                        if !self._col_in_subtree(ws, node_id, &cols[k]) {
+                            continue;
+                        }
+                        l_variants.push((k, ws.mask_cache[split.l_child_idx].clone(), false));
+                        r_variants.push((k, ws.mask_cache[split.r_child_idx].clone(), false));
+                        continue;
+                    }
 
-                self.mask_cache.push(mask.clone());
-                self.split_idx_cache_range.push((split_idx, new_idx));
-                self.preds_cache_range.push((preds_len_0, self.preds_cache.len()));
+                    let permuted_vec_arg = match permute {
+                        true => Some(permuted_vecs[k]),
+                        false => None
+                    };
+                    let [l_mask, r_mask] = df.make_split(split.split_index, &mask, &self.rng_factory, permuted_vec_arg);
+                    l_variants.push((k, l_mask, true));
+                    r_variants.push((k, r_mask, true));
+                }
 
-                self.split_mask_map.entry(split.split_index.get_col_id())
-                    .and_modify(|val| val.push(new_idx))
-                    .or_insert(vec![new_idx]);
-                return new_idx + 1;
+                if l_variants.len() > 0 {
+                    self._predict_batch(ws, df, cols, permuted_vecs, split.l_child_idx, l_variants, preds, mask_ranks);
+                }
+                if r_variants.len() > 0 {
+                    self._predict_batch(ws, df, cols, permuted_vecs, split.r_child_idx, r_variants, preds, mask_ranks);
+                }
             }
         }
     }
+}
 
-    #[inline]
-    fn _preds_read_cache(&self, node_id: usize, permuted_col: &SplitIndex::Col, preds: &mut Vec<Option<Y>>) -> bool {
-        let node = &self.tree[node_id];
-        if let Node::Sp(_) = node {
-            let should_get_from_cache = match self.split_mask_map.get(permuted_col) {
-                None => true,
-                Some(idxs) => {
-                    for &idx in idxs.iter() {
-                        if self.split_idx_cache_range[node_id].0 <= idx && self.split_idx_cache_range[node_id].1 >= idx {
-                            return false;
-                        }
-                    }
-                    true
-                }
-            };
+impl<Y, SplitIndex> Tree<Y, SplitIndex> where
+    Y: Copy + Debug + PartialEq,
+    SplitIndex: PivotIdentifiable + Clone + Copy
+{
+    // As importance, but a column is permuted only at its splits on one pivot, giving an entry per (column, pivot)
+    // the tree splits on. Uses the permutations of importance, so the pivots of a column see the same shuffled rows.
+    pub fn pivot_importance<T, U>(&self, df: &T, y: &U, mask: &Mask, n_perm: usize) -> HashMap<(SplitIndex::Col, SplitIndex::Pivot), f64>
+        where
+            T: DataInterface<SplitIndex, Y>,
+            U: Response<Y>
+    {
+        let mut mask_ranks = vec![usize::MAX; y.len()];
+        for (rank, &row) in mask.get_mask().iter().enumerate() {
+            mask_ranks[row] = rank;
+        }
 
-            if should_get_from_cache {
-                if self.preds_cache_range[node_id].0 < self.preds_cache.len() {
-                    for i in self.preds_cache_range[node_id].0..self.preds_cache_range[node_id].1 {
-                        let pred = self.preds_cache[i];
-                        preds[pred.1] = Some(pred.0);
-                    }
-                    return true;
+        let preds = self.predict(df, mask, None, &mask_ranks);
+        let pred_err = self.loss(y, mask, &preds);
+
+        let mut keys: Vec<(SplitIndex::Col, SplitIndex::Pivot)> = vec![];
+        for node in self.tree.iter() {
+            if let Node::Sp(split) = node {
+                let key = (split.split_index.get_col_id(), split.split_index.get_pivot());
+                if !keys.contains(&key) {
+                    keys.push(key);
                 }
             }
         }
-        return false;
+
+        let mut importance = HashMap::new();
+        let mut preds_perm = preds.clone();
+        for key in keys {
+            let mut key_imp = 0.;
+            for round in 0..n_perm {
+                let permuted_vec = df.permute_index(key.0, &self.rng_factory, mask, self.ith_tree, round);
+                self._predict_pivot_permuted(df, mask, self.tree.len() - 1, key, &permuted_vec, &mut preds_perm, &mask_ranks);
+                key_imp += self.loss(y, mask, &preds_perm) - pred_err;
+            }
+            importance.insert(key, key_imp);
+        }
+        return importance;
     }
 
-    fn _predict<T>(&self, df: &T, mask: &Mask, permuted_col: Option<SplitIndex::Col>, permuted_vec: &T::InternalType, node_id: Option<NodeHandle>, altered: bool, preds: &mut Vec<Option<Y>>, mask_ranks: &[usize])
+    fn _predict_pivot_permuted<T>(&self, df: &T, mask: &Mask, node_id: NodeHandle, key: (SplitIndex::Col, SplitIndex::Pivot), permuted_vec: &T::InternalType, preds: &mut [Y], mask_ranks: &[usize])
         where
             T: DataInterface<SplitIndex, Y>
     {
-        let node = match node_id {
-            None => self.tree.last().unwrap().clone(),
-            Some(nid) => self.tree[nid].clone()
-        };
-
-        match node {
+        match &self.tree[node_id] {
             Node::Lf(leaf) => {
                 for &i in mask.get_mask().iter() {
-                    preds[mask_ranks[i]] = Some(leaf.get_class());
+                    preds[mask_ranks[i]] = leaf.get_class();
                 }
             }
-
             Node::Sp(split) => {
-                // make split
-                let permute = permuted_col.is_some() && permuted_col.unwrap() == split.split_index.get_col_id();
-                let permuted_vec_arg = match permute {
-                    true => Some(permuted_vec),
-                    false => None
-                };
-
-                let masks_own;
-                let masks = match self.mask_cache.len() > 0 && !permute && !altered {
-                    true => [&self.mask_cache[split.l_child_idx], &self.mask_cache[split.r_child_idx]],
-                    false => {
-                        masks_own = df.make_split(split.split_index, &mask, &self.rng_factory, permuted_vec_arg);
-                        [&masks_own[0], &masks_own[1]]
-                    }
-                };
-
+                let permute = (split.split_index.get_col_id(), split.split_index.get_pivot()) == key;
+                let masks = df.make_split(split.split_index, mask, &self.rng_factory, permute.then_some(permuted_vec));
+                self._predict_pivot_permuted(df, &masks[0], split.l_child_idx, key, permuted_vec, preds, mask_ranks);
+                self._predict_pivot_permuted(df, &masks[1], split.r_child_idx, key, permuted_vec, preds, mask_ranks);
+            }
+        }
+    }
+}
 
-                if permute || altered || !self._preds_read_cache(split.l_child_idx, &permuted_col.unwrap(), preds) {
-                    self._predict(df, &masks[0], permuted_col, &permuted_vec, Some(split.l_child_idx.clone()), altered || permute, preds, &mask_ranks);
-                }
+impl<Y, SplitIndex> Tree<Y, SplitIndex> where
+    Y: Copy + Debug + PartialEq + CompactClass,
+    SplitIndex: ColumnIdentifiable + Clone + Copy + CompactSplit
+{
+    // Nodes, in-bag node sizes, leaf counts and random streams, i.e. what prediction needs. Growth settings,
+    // timings and impurity are left out.
+    pub(crate) fn write_compact<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.rng_factory.write_compact(w)?;
+        write_varint(w, self.ith_tree as u64)?;
+        write_varint(w, self.tree.len() as u64)?;
+        let codes: Vec<u8> = self.tree.iter().map(|node| match node {
+            Node::Sp(split) => split.split_index.pivot_code(),
+            Node::Lf(_) => LEAF_CODE
+        }).collect();
+        write_codes(w, &codes)?;
+        for (node_id, node) in self.tree.iter().enumerate() {
+            match node {
+                Node::Sp(split) => {
+                    write_varint(w, split.split_index.col_code())?;
+                    write_varint(w, (node_id - split.l_child_idx) as u64)?;
+                    write_varint(w, (node_id - split.r_child_idx) as u64)?;
+                },
+                Node::Lf(leaf) => write_class(w, &leaf.class)?
+            }
+        }
+        for &n in self.node_n.iter() {
+            write_varint(w, n as u64)?;
+        }
 
-                if permute || altered || !self._preds_read_cache(split.r_child_idx, &permuted_col.unwrap(), preds) {
-                    self._predict(df, &masks[1], permuted_col, &permuted_vec, Some(split.r_child_idx.clone()), altered || permute, preds, &mask_ranks);
+        write_flag(w, self.leaf_counts.is_some())?;
+        if let Some(leaf_counts) = &self.leaf_counts {
+            write_varint(w, self.classes.len() as u64)?;
+            for class in self.classes.iter() {
+                write_class(w, class)?;
+            }
+            for node_id in (0..self.tree.len()).filter(|&node_id| matches!(self.tree[node_id], Node::Lf(_))) {
+                let counts = &leaf_counts[node_id];
+                write_varint(w, counts.len() as u64)?;
+                for (class, n) in counts.iter() {
+                    write_class(w, class)?;
+                    write_varint(w, *n as u64)?;
                 }
             }
         }
+        return Ok(());
     }
 
-    pub fn importance<T, U>(&mut self, df: &T, y: &U, mask: &Mask) -> ImportanceTree<SplitIndex::Col>
-        where
-            T: DataInterface<SplitIndex, Y>,
-            U: Response<Y>
-    {
-        let mut mask_ranks = vec![usize::MAX; y.len()];
-        for (rank, &mask) in mask.get_mask().iter().enumerate() {
-            mask_ranks[mask] = rank;
+    pub(crate) fn read_compact<R: Read>(r: &mut R) -> Result<Tree<Y, SplitIndex>, CompactError> {
+        let rng_factory = RngFactory::read_compact(r)?;
+        if rng_factory.get_ncol().is_none() {
+            return Err(CompactError::Invalid("tree streams without ncol"));
         }
-
-        let preds = self.predict(df, &mask, None, &mask_ranks);
-        let mut importance = ImportanceTree::new();
-        let pred_err = y.pred_incorrect(&mask, &preds);
-
-        for &col in self.split_cols.clone().iter() {
-            let preds_perm = self.predict(df, &mask, Some(col.clone()), &mask_ranks);
-            let pred_perm_err = y.pred_incorrect(&mask, &preds_perm);
-            importance.insert(col, pred_perm_err as i64 - pred_err as i64);
+        let mut tree = Tree::new(read_usize(r)?, &rng_factory);
+        let n_nodes = match read_usize(r)? {
+            0 => return Err(CompactError::Invalid("tree without nodes")),
+            n_nodes => n_nodes
+        };
+        let codes = read_codes(r, n_nodes)?;
+        for (node_id, &code) in codes.iter().enumerate() {
+            let node = match code {
+                LEAF_CODE => Node::create_leaf(read_class(r)?),
+                _ => {
+                    let split_index = SplitIndex::from_codes(code, read_varint(r)?).ok_or(CompactError::Invalid("unknown split code"))?;
+                    let (l_delta, r_delta) = (read_usize(r)?, read_usize(r)?);
+                    if l_delta == 0 || r_delta == 0 || l_delta > node_id || r_delta > node_id {
+                        return Err(CompactError::Invalid("child not before its parent"));
+                    }
+                    tree.split_cols.insert(split_index.get_col_id());
+                    Node::create_split(split_index, node_id - l_delta, node_id - r_delta)
+                }
+            };
+            tree.tree.push(node);
         }
-        return importance;
+        tree.node_n = read_vec(r, n_nodes, read_usize)?;
+        tree.root_n = tree.node_n.last().cloned().unwrap_or(0);
+
+        if read_flag(r)? {
+            let n_classes = read_usize(r)?;
+            tree.classes = read_vec(r, n_classes, read_class)?;
+            let mut leaf_counts = vec![vec![]; n_nodes];
+            for node_id in (0..n_nodes).filter(|&node_id| codes[node_id] == LEAF_CODE) {
+                let n_counts = read_usize(r)?;
+                leaf_counts[node_id] = read_vec(r, n_counts, |r| Ok((read_class(r)?, read_usize(r)?)))?;
+            }
+            tree.leaf_counts = Some(leaf_counts);
+        }
+        return Ok(tree);
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+// On a single sample every error metric is its misclassification.
+fn loss_per_sample<Y, U>(y: &U, mask: &Mask, preds: &Predicted<Y>) -> Vec<f64>
+where
+    Y: Copy + PartialEq,
+    U: Response<Y>
+{
+    return mask.get_mask().iter().zip(preds.iter())
+        .map(|(&i, &pred)| y.pred_loss(&Mask::from_sorted(vec![i]), &vec![pred], ErrorMetric::Misclassification))
+        .collect();
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 enum Node<T, U> {
     Sp(Split<U>),
     Lf(Leaf<T>),
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct Split<T> {
     split_index: T,
     l_child_idx: NodeHandle,
     r_child_idx: NodeHandle,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct Leaf<T> {
     class: T,
 }
@@ -317,12 +1656,13 @@ impl<T, U> Node<T, U> {
 #[cfg(test)]
 mod tests {
     use crate::mask::Mask;
-    use crate::data_interface::{ColumnIdentifiable, DataInterface, Permutable, Predicted, Response};
+    use crate::data_interface::{ColumnData, ColumnIdentifiable, DataInterface, Permutable, Predicted, Response, RowView, TiePolicy};
     use crate::random_number_generator::Rng;
-    use crate::tree::{Node, Tree};
+    use crate::tree::{ApplyCache, NaPolicy, Node, PredictWorkspace, SplitWeight, Tree, TreeShape};
     use std::collections::{HashMap, HashSet};
     use std::marker::PhantomData;
     use crate::random_number_generator::factory::RngFactory;
+    use float_cmp::assert_approx_eq;
 
     struct MyDf();
 
@@ -342,21 +1682,25 @@ mod tests {
         }
     }
 
-    impl DataInterface<Sp, usize> for MyDf {
+    // Matches the splits of make_split below
+    impl RowView<Sp> for MyDf {
+        fn goes_left(&self, row: usize, idx: &Sp) -> bool {
+            return match idx {
+                Sp(1) => row <= 3,
+                _ => row <= 2
+            };
+        }
+    }
+
+    impl ColumnData<Sp> for MyDf {
         type InternalType = Void;
 
         fn get_ncol(&self) -> usize {
             unimplemented!();
         }
 
-        fn find_min_idx<T>(&self, mask: &Mask, y: &T, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool) -> Sp
-            where T: Response<usize>
-        {
-            match mask.get_mask().as_slice() {
-                &[1, 2, 3, 4, 5] => Sp(1),
-                &[1, 2, 3] => Sp(2),
-                _ => panic!("Unexpected mask in test Data Interface")
-            }
+        fn get_col_ids(&self) -> Vec<usize> {
+            unimplemented!();
         }
 
         fn make_split(&self, idx: Sp, mask: &Mask, rng_factory: &RngFactory, permute: Option<&Void>) -> [Mask; 2] {
@@ -370,11 +1714,33 @@ mod tests {
             }
         }
 
-        fn permute_index(&self, idx: usize, rng_factory: &RngFactory, oob_mask: &Mask, ith_tree: usize) -> Void {
+        fn permute_index(&self, idx: usize, rng_factory: &RngFactory, oob_mask: &Mask, ith_tree: usize, round: usize) -> Void {
+            return Void();
+        }
+
+        fn permute_index_all(&self, idx: usize, rng_factory: &RngFactory, round: usize) -> Void {
             return Void();
         }
     }
 
+    impl DataInterface<Sp, usize> for MyDf {
+        fn find_min_idx<T>(&self, mask: &Mask, y: &T, mtry: usize, rng: &mut Rng, rng_factory: &RngFactory, shadow_vars: bool) -> Option<Sp>
+            where T: Response<usize>
+        {
+            match mask.get_mask().as_slice() {
+                &[1, 2, 3, 4, 5] => Some(Sp(1)),
+                &[1, 2, 3] => Some(Sp(2)),
+                _ => panic!("Unexpected mask in test Data Interface")
+            }
+        }
+
+        fn split_score<T>(&self, col: usize, mask: &Mask, y: &T) -> Option<f64>
+            where T: Response<usize>
+        {
+            unimplemented!();
+        }
+    }
+
     impl Response<usize> for Y {
         fn pred_incorrect(&self, mask: &Mask, preds: &Predicted<usize>) -> u64 {
             0
@@ -393,6 +1759,13 @@ mod tests {
             unimplemented!();
         }
 
+        fn impurity(&self, mask: &Mask) -> f64 {
+            return match mask.get_mask().as_slice() {
+                &[1, 2] | &[3] | &[4, 5] => 0.,
+                _ => 0.5
+            };
+        }
+
         fn pred_error(&self, mask: &Mask, preds: &Predicted<usize>) -> f64 {
             return 0.;
         }
@@ -428,6 +1801,58 @@ mod tests {
         assert_eq!(tree.tree, expected_res)
     }
 
+    #[test]
+    fn build_tree_impurity_decrease() {
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+        let mut tree: Tree<usize, Sp> = Tree::new(1, &rng_factory);
+        let mask = Mask::new(vec![1, 2, 3, 4, 5]);
+        assert_eq!(tree.get_impurity_decrease(), None);
+
+        tree.set_track_impurity(true);
+        tree.build_tree(&MyDf(), &Y(), &mask, 1, false, None);
+        let decrease = tree.get_impurity_decrease().unwrap();
+        assert_approx_eq!(f64, decrease[&1], (5.*0.5 - 3.*0.5)/5.);
+        assert_approx_eq!(f64, decrease[&2], 3.*0.5/5.);
+    }
+
+    #[test]
+    fn build_tree_impurity_decrease_depth_weight() {
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+        let mut tree: Tree<usize, Sp> = Tree::new(1, &rng_factory);
+        tree.set_track_impurity(true);
+        tree.set_split_weight(SplitWeight::Depth);
+        tree.build_tree(&MyDf(), &Y(), &Mask::new(vec![1, 2, 3, 4, 5]), 1, false, None);
+        let decrease = tree.get_impurity_decrease().unwrap();
+        assert_approx_eq!(f64, decrease[&1], (5.*0.5 - 3.*0.5)/5.);
+        assert_approx_eq!(f64, decrease[&2], 3.*0.5/3. * 0.5);
+    }
+
+    #[test]
+    fn split_pairs_on_paths() {
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+        let mut tree: Tree<usize, Sp> = Tree::new(1, &rng_factory);
+        assert!(tree.split_pairs().is_empty());
+
+        tree.build_tree(&MyDf(), &Y(), &Mask::new(vec![1, 2, 3, 4, 5]), 1, false, None);
+        assert_eq!(tree.split_pairs(), HashSet::from([(1, 2)]));
+    }
+
+    #[test]
+    fn shape_and_to_tsv() {
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+        let mut tree: Tree<usize, Sp> = Tree::new(1, &rng_factory);
+        assert_eq!(tree.shape(), TreeShape{depth: 0, n_leaves: 0});
+
+        tree.build_tree(&MyDf(), &Y(), &Mask::new(vec![1, 2, 3, 4, 5]), 1, false, None);
+        assert_eq!(tree.node_depths(), vec![2, 2, 1, 1, 0]);
+        assert_eq!(tree.shape(), TreeShape{depth: 2, n_leaves: 3});
+
+        let mut out = vec![];
+        tree.to_tsv(&mut out, |col| format!("x{}", col)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "node\tdepth\tleft\tright\tcol\tclass\n\
+                   0\t2\tNA\tNA\tNA\t1\n1\t2\tNA\tNA\tNA\t2\n2\t1\t0\t1\tx2\tNA\n3\t1\tNA\tNA\tNA\t3\n4\t0\t2\t3\tx1\tNA\n");
+    }
+
     #[test]
     fn predict() {
         let rng_factory = RngFactory::new(1, Some(100), Some(100));
@@ -444,6 +1869,130 @@ mod tests {
         assert_eq!(res, vec![1, 1, 3])
     }
 
+    #[test]
+    fn predict_rows() {
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+        let mut tree = Tree::new(1, &rng_factory);
+        tree.tree = vec![
+            Node::create_leaf(1 as usize),
+            Node::create_leaf(2 as usize),
+            Node::create_split(Sp(2usize), 0, 1),
+            Node::create_leaf(3 as usize),
+            Node::create_split(Sp(1usize), 2, 3),
+        ];
+
+        let mask = Mask::new(vec![1, 2, 3, 4, 5]);
+        let res = tree.predict_rows(&MyDf(), &mask);
+        assert_eq!(res, tree.predict(&MyDf(), &mask, None, &vec![10, 0, 1, 2, 3, 4]));
+        assert_eq!(res, vec![1, 1, 2, 3, 3]);
+    }
+
+    #[test]
+    fn predict_rows_missing() {
+        // Row 5 misses the value of split 1, rows go left otherwise
+        struct MissingRows();
+        impl RowView<Sp> for MissingRows {
+            fn goes_left(&self, _row: usize, _idx: &Sp) -> bool {
+                panic!("Rows are walked through try_goes_left.");
+            }
+
+            fn try_goes_left(&self, row: usize, idx: &Sp) -> Option<bool> {
+                return match (row, idx) {
+                    (5, Sp(1)) => None,
+                    _ => Some(true)
+                };
+            }
+        }
+
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+        let mut tree = Tree::new(1, &rng_factory);
+        tree.tree = vec![
+            Node::create_leaf(1 as usize),
+            Node::create_leaf(2 as usize),
+            Node::create_split(Sp(2usize), 0, 1),
+            Node::create_leaf(3 as usize),
+            Node::create_split(Sp(1usize), 2, 3),
+        ];
+        tree.node_n = vec![1, 1, 2, 3, 5];
+
+        let mask = Mask::new(vec![4, 5]);
+        assert_eq!(tree.predict_rows(&MissingRows(), &mask), vec![1, 3]);
+        tree.node_n = vec![2, 1, 3, 2, 5];
+        assert_eq!(tree.predict_rows(&MissingRows(), &mask), vec![1, 1]);
+        assert_eq!(tree.predict_class_weights(&MissingRows(), 5, NaPolicy::Majority), vec![(1, 1.)]);
+    }
+
+    #[test]
+    fn predict_into_buffer() {
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+        let mut tree = Tree::new(1, &rng_factory);
+        tree.tree = vec![
+            Node::create_leaf(1 as usize),
+            Node::create_leaf(2 as usize),
+            Node::create_split(Sp(2usize), 0, 1),
+            Node::create_leaf(3 as usize),
+            Node::create_split(Sp(1usize), 2, 3),
+        ];
+
+        let mut ws = PredictWorkspace::new();
+        let mut out = vec![0; 3];
+        let mask = Mask::new(vec![1, 2, 5]);
+        tree.predict_into(&mut ws, &MyDf(), &mask, None, &vec![10, 0, 1, 10, 10, 2], &mut out);
+        assert_eq!(out, tree.predict(&MyDf(), &mask, None, &vec![10, 0, 1, 10, 10, 2]));
+        let mask = Mask::new(vec![3, 4, 5]);
+        tree.predict_rows_into(&MyDf(), &mask, &mut out);
+        assert_eq!(out, vec![2, 3, 3]);
+
+        let res = std::panic::catch_unwind(|| {
+            tree.predict_rows_into(&MyDf(), &Mask::new(vec![1, 2]), &mut vec![0; 3]);
+        });
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn predict_rows_cached() {
+        use std::cell::Cell;
+
+        struct CountingRows(Cell<usize>);
+        impl RowView<Sp> for CountingRows {
+            fn goes_left(&self, row: usize, idx: &Sp) -> bool {
+                self.0.set(self.0.get() + 1);
+                return MyDf().goes_left(row, idx);
+            }
+        }
+
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+        let mut tree = Tree::new(1, &rng_factory);
+        tree.tree = vec![
+            Node::create_leaf(1 as usize),
+            Node::create_leaf(2 as usize),
+            Node::create_split(Sp(2usize), 0, 1),
+            Node::create_leaf(3 as usize),
+            Node::create_split(Sp(1usize), 2, 3),
+        ];
+
+        let cache = ApplyCache::new();
+        let rows = CountingRows(Cell::new(0));
+        let mask = Mask::new(vec![1, 2, 3, 4, 5]);
+        assert_eq!(tree.predict_rows_cached(&rows, &mask, &cache, 7), vec![1, 1, 2, 3, 3]);
+        let walked = rows.0.get();
+        assert_eq!(tree.predict_rows_cached(&rows, &Mask::new(vec![2, 5]), &cache, 7), vec![1, 3]);
+        assert_eq!(rows.0.get(), walked);
+        assert_eq!(cache.len(), 1);
+
+        cache.invalidate(7);
+        assert_eq!(cache.len(), 0);
+        assert_eq!(tree.predict_rows_cached(&rows, &mask, &cache, 7), vec![1, 1, 2, 3, 3]);
+        assert_eq!(rows.0.get(), 2 * walked);
+
+        // Another tree at the same index walks its own rows
+        let mut other = Tree::new(1, &rng_factory);
+        other.tree = vec![Node::create_leaf(4 as usize)];
+        assert_eq!(other.predict_rows_cached(&rows, &mask, &cache, 7), vec![4; 5]);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(tree.predict_rows_cached(&rows, &mask, &cache, 7), vec![1, 1, 2, 3, 3]);
+    }
+
     #[test]
     fn importance() {
         let rng_factory = RngFactory::new(1, Some(100), Some(100));
@@ -457,7 +2006,24 @@ mod tests {
         ];
         tree.split_cols = HashSet::from([1usize, 2usize]);
 
-        let res = tree.importance(&MyDf(), &Y(), &Mask::new(vec![1, 2, 3, 4, 5]));
-        assert_eq!(res, HashMap::from([(1usize, 0), (2usize, 0)]));
+        let res = tree.importance(&MyDf(), &Y(), &Mask::new(vec![1, 2, 3, 4, 5]), 1, None);
+        assert_eq!(res, HashMap::from([(1usize, 0.), (2usize, 0.)]));
+    }
+
+    #[test]
+    fn importance_batched() {
+        let rng_factory = RngFactory::new(1, Some(100), Some(100));
+        let mut tree = Tree::new(1, &rng_factory);
+        tree.tree = vec![
+            Node::create_leaf(1 as usize),
+            Node::create_leaf(2 as usize),
+            Node::create_split(Sp(2usize), 0, 1),
+            Node::create_leaf(3 as usize),
+            Node::create_split(Sp(1usize), 2, 3),
+        ];
+        tree.split_cols = HashSet::from([1usize, 2usize]);
+
+        let res = tree.importance_batched(&MyDf(), &Y(), &Mask::new(vec![1, 2, 3, 4, 5]), 1, 2, None);
+        assert_eq!(res, HashMap::from([(1usize, 0.), (2usize, 0.)]));
     }
 }
\ No newline at end of file
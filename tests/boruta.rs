@@ -15,13 +15,18 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 // use serde_json::json;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
 use float_cmp::assert_approx_eq;
 
 use variant_forest::data_interface::multi_x::{XDf, MultiX, ColSplitIndex, SplitColId};
 use variant_forest::data_interface::y_bool::{YBool, Y};
 use variant_forest::data_interface::three_val::ThreeValCol;
 use variant_forest::random_forest::RandomForest;
-use variant_forest::boruta::{boruta, BorutaRes};
+use variant_forest::boruta_report::{BorutaReport, HTML_TEMPLATE};
+use variant_forest::boruta::{boruta, boruta_two_stage, boruta_with_config, boruta_with_provider, BorutaConfig, BorutaConfigError, BorutaRes, Decision, DecisionTest, ImportanceProvider, ImportanceSource, ProvidedImportance, ShadowPolicy};
+use variant_forest::data_interface::ColumnData;
+use variant_forest::regions::{boruta_per_region, importance_per_region, Locus, Partition};
 // use variant_forest::tree::Tree;
 // use variant_forest::mask::Mask;
 use variant_forest::random_number_generator::Rng;
@@ -82,26 +87,58 @@ fn serde_array_to_three_val(x: &serde_json::Value) -> MultiX {
     new_threeval_col(&arr_i8)
 }
 
-#[test]
-fn boruta_srx() {
+// SRX data of the R Boruta package: Y = A xor B, AoB, AnB and nA derived from A and B, N1-N3 noise.
+fn srx_df() -> (XDf, YBool) {
     let data_str = "{\"A\":[2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1],\"B\":[2,2,1,1,2,2,1,1,2,2,1,1,2,2,1,1,2,2,1,1,2,2,1,1,2,2,1,1,2,2,1,1],\"N1\":[2,2,2,2,1,1,1,1,2,2,2,2,1,1,1,1,2,2,2,2,1,1,1,1,2,2,2,2,1,1,1,1],\"N2\":[2,2,2,2,2,2,2,2,1,1,1,1,1,1,1,1,2,2,2,2,2,2,2,2,1,1,1,1,1,1,1,1],\"N3\":[2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1],\"AoB\":[2,2,2,1,2,2,2,1,2,2,2,1,2,2,2,1,2,2,2,1,2,2,2,1,2,2,2,1,2,2,2,1],\"AnB\":[2,1,1,1,2,1,1,1,2,1,1,1,2,1,1,1,2,1,1,1,2,1,1,1,2,1,1,1,2,1,1,1],\"nA\":[1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2,1,2],\"Y\":[false,true,true,false,false,true,true,false,false,true,true,false,false,true,true,false,false,true,true,false,false,true,true,false,false,true,true,false,false,true,true,false]}";
     let df_json: serde_json::Value = serde_json::from_str(data_str).unwrap();
     let y_vec: Vec<bool> = df_json["Y"].as_array().unwrap().iter().map(|x| x.as_bool().unwrap()).collect();
-    let y = YBool::new(&y_vec);
+    let cols = ["A", "B", "N1", "N2", "N3", "AoB", "AnB", "nA"].iter()
+        .map(|&name| serde_array_to_three_val(&df_json[name]))
+        .collect();
+    return (XDf::new(cols), YBool::new(&y_vec));
+}
 
+#[test]
+fn boruta_srx() {
+    let (my_df, y) = srx_df();
+
+    let boruta_res: BorutaRes<ColSplitIndex> = boruta(my_df, y, 0.05, 100, 1000);
+    let mut res_confirmed = boruta_res.get_confirmed().iter()
+        .map(|split_col| split_col.col_id)
+        .collect::<Vec<_>>();
+    res_confirmed.sort();
+    assert_eq!(res_confirmed, [0, 1, 5, 6, 7]);
 
-    let A = serde_array_to_three_val(&df_json["A"]);
-    let B = serde_array_to_three_val(&df_json["B"]);
-    let N1 = serde_array_to_three_val(&df_json["N1"]);
-    let N2 = serde_array_to_three_val(&df_json["N2"]);
-    let N3 = serde_array_to_three_val(&df_json["N3"]);
-    let AoB = serde_array_to_three_val(&df_json["AoB"]);
-    let AnB = serde_array_to_three_val(&df_json["AnB"]);
-    let nA = serde_array_to_three_val(&df_json["nA"]);
+    let mut res_rejected = boruta_res.get_rejected().iter()
+        .map(|split_col| split_col.col_id)
+        .collect::<Vec<_>>();
+    res_rejected.sort();
+    assert_eq!(res_rejected, [2, 3, 4]);
+}
 
-    let my_df = XDf::new(vec![A, B, N1, N2, N3, AoB, AnB, nA]);
+#[test]
+fn boruta_srx_col_keys() {
+    let (mut my_df, y) = srx_df();
+    my_df.set_col_keys(Some((0..my_df.get_ncol()).map(|i| format!("rs{}", i)).collect())).unwrap();
 
     let boruta_res: BorutaRes<ColSplitIndex> = boruta(my_df, y, 0.05, 100, 1000);
+    let mut confirmed_keys = boruta_res.get_confirmed_keys().unwrap();
+    confirmed_keys.sort();
+    assert_eq!(confirmed_keys, ["rs0", "rs1", "rs5", "rs6", "rs7"]);
+
+    let mut rejected_keys = boruta_res.get_rejected_keys().unwrap();
+    rejected_keys.sort();
+    assert_eq!(rejected_keys, ["rs2", "rs3", "rs4"]);
+    assert_eq!(boruta_res.get_tentative_keys(), Some(vec![]));
+}
+
+#[test]
+fn boruta_srx_gini() {
+    let (my_df, y) = srx_df();
+
+    let mut config = BorutaConfig::new(0.05, 100, 1000);
+    config.set_importance_source(ImportanceSource::Gini).unwrap();
+    let boruta_res: BorutaRes<ColSplitIndex> = boruta_with_config(my_df, y, &config);
     let mut res_confirmed = boruta_res.get_confirmed().iter()
         .map(|split_col| split_col.col_id)
         .collect::<Vec<_>>();
@@ -113,4 +150,353 @@ fn boruta_srx() {
         .collect::<Vec<_>>();
     res_rejected.sort();
     assert_eq!(res_rejected, [2, 3, 4]);
-}
\ No newline at end of file
+}
+
+#[test]
+fn boruta_srx_rough_fix() {
+    let (my_df, y) = srx_df();
+
+    let mut config = BorutaConfig::new(0.05, 3, 200);
+    config.set_rough_fix(true);
+    let boruta_res: BorutaRes<ColSplitIndex> = boruta_with_config(my_df, y, &config);
+    assert!(boruta_res.get_tentative().is_empty());
+    assert_eq!(boruta_res.get_rough_fixed().len(), 8);
+
+    let mut res_confirmed = boruta_res.get_confirmed().iter()
+        .map(|split_col| split_col.col_id)
+        .collect::<Vec<_>>();
+    res_confirmed.sort();
+    assert_eq!(res_confirmed, [0, 1, 5, 6, 7]);
+}
+
+#[test]
+fn boruta_srx_custom_decision() {
+    let (my_df, y) = srx_df();
+
+    let mut config = BorutaConfig::new(0.05, 100, 200);
+    config.set_decision_rule(|hits, iters| match iters >= 5 {
+        true if 2*hits > iters => Decision::Confirmed,
+        true => Decision::Rejected,
+        false => Decision::Tentative
+    });
+    let boruta_res: BorutaRes<ColSplitIndex> = boruta_with_config(my_df, y, &config);
+    assert_eq!(boruta_res.get_iterations(), 5);
+    assert_eq!(boruta_res.get_hits().len(), 8);
+    assert_eq!(*boruta_res.get_hits().get(&SplitColId{col_id: 0, shadow: false}).unwrap(), 5);
+
+    let mut res_confirmed = boruta_res.get_confirmed().iter()
+        .map(|split_col| split_col.col_id)
+        .collect::<Vec<_>>();
+    res_confirmed.sort();
+    assert_eq!(res_confirmed, [0, 1, 5, 6, 7]);
+}
+
+#[test]
+fn boruta_screening() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..60).map(|_| sample_0_1(&mut rng, 500)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let mut config = BorutaConfig::new(0.01, 50, 200);
+    config.set_screening(Some(0.5));
+    let boruta_res: BorutaRes<ColSplitIndex> = boruta_with_config(my_df, y, &config);
+
+    let mut res_confirmed = boruta_res.get_confirmed().iter().map(|col| col.col_id).collect::<Vec<_>>();
+    res_confirmed.sort();
+    assert_eq!(res_confirmed, [0, 1]);
+
+    // Roughly half of the noise has p-value above 0.5
+    let screened = boruta_res.get_screened();
+    assert!(screened.len() > 15 && screened.len() < 45);
+    assert!(screened.iter().all(|col| col.col_id > 1 && boruta_res.get_rejected().contains(col)));
+}
+
+#[test]
+fn boruta_two_stage_gini_screen() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..60).map(|_| sample_0_1(&mut rng, 500)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let res = boruta_two_stage::<_, _, ColSplitIndex>(my_df, y, 0.5, 100, &BorutaConfig::new(0.01, 50, 200));
+
+    assert_eq!(res.get_screen_importance().len(), 60);
+    let dropped = res.get_screen_dropped();
+    assert_eq!(dropped.len(), 30);
+    assert!(dropped.iter().all(|col| col.col_id > 1));
+
+    let mut res_confirmed = res.get_confirmed().iter().map(|col| col.col_id).collect::<Vec<_>>();
+    res_confirmed.sort();
+    assert_eq!(res_confirmed, [0, 1]);
+    assert_eq!(res.get_boruta().get_hits().len(), 30);
+    assert_eq!(res.get_rejected().len() + res.get_boruta().get_tentative().len(), 58);
+}
+
+#[test]
+fn boruta_seed_and_shadow_policy() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..30).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y_vec: Vec<bool> = xs[0].iter().zip(xs[1].iter()).map(|(&a, &b)| a == 1 && b == 1).collect();
+    let run = |seed, policy| {
+        let mut config = BorutaConfig::new(0.01, 20, 100);
+        config.set_seed(seed);
+        config.set_shadow_policy(policy);
+        let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+        let res: BorutaRes<ColSplitIndex> = boruta_with_config(my_df, YBool::new(&y_vec), &config);
+        res
+    };
+
+    let res = run(1, ShadowPolicy::PerIteration);
+    let rerun = run(1, ShadowPolicy::PerIteration);
+    assert_eq!(res.get_seed(), 1);
+    assert_eq!(res.get_hits(), rerun.get_hits());
+    assert_eq!(res.get_confirmed(), rerun.get_confirmed());
+    assert_eq!(res.get_rejected(), rerun.get_rejected());
+    assert_eq!(res.get_tentative(), rerun.get_tentative());
+    assert_ne!(res.get_hits(), run(2, ShadowPolicy::PerIteration).get_hits());
+
+    // Final forest covers confirmed and tentative attributes only
+    let final_zscore = res.get_final_zscore();
+    assert_eq!(final_zscore.len(), res.get_confirmed().len() + res.get_tentative().len());
+    assert!(res.get_confirmed().iter().all(|col| final_zscore[col] > 0. && res.get_final_importance()[col] > 0.));
+    assert!(res.get_rejected().iter().all(|col| !final_zscore.contains_key(col)));
+
+    let fixed = run(1, ShadowPolicy::Fixed);
+    assert_ne!(res.get_hits(), fixed.get_hits());
+    let mut confirmed = fixed.get_confirmed().iter().map(|col| col.col_id).collect::<Vec<_>>();
+    confirmed.sort();
+    assert_eq!(confirmed, [0, 1]);
+}
+
+#[test]
+fn boruta_time_budget() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..10).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y_vec: Vec<bool> = xs[0].iter().map(|&x| x == 1).collect();
+    let run = |time_budget| {
+        let mut config = BorutaConfig::new(0.01, 20, 50);
+        config.set_time_budget(time_budget);
+        let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+        let res: BorutaRes<ColSplitIndex> = boruta_with_config(my_df, YBool::new(&y_vec), &config);
+        res
+    };
+
+    // Spent before the first iteration, which runs regardless
+    let res = run(Some(Duration::ZERO));
+    assert!(res.is_truncated());
+    assert_eq!(res.get_iterations(), 1);
+    assert_eq!(res.get_tentative().len() + res.get_confirmed().len() + res.get_rejected().len(), 10);
+    assert!(res.get_final_importance().is_empty());
+
+    let res = run(Some(Duration::from_secs(3600)));
+    assert!(!res.is_truncated());
+    assert_eq!(res.get_hits(), run(None).get_hits());
+}
+
+#[test]
+fn boruta_narrow_df() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 300);
+    let xp2 = sample_0_1(&mut rng, 300);
+    let y_vec: Vec<bool> = xp1.iter().map(|&x| x == 1).collect();
+
+    let one_col: BorutaRes<ColSplitIndex> = boruta(XDf::new(vec![new_threeval_col(&xp1)]), YBool::new(&y_vec), 0.01, 20, 50);
+    assert_eq!(one_col.get_confirmed().iter().map(|col| col.col_id).collect::<Vec<_>>(), [0]);
+
+    let two_col: BorutaRes<ColSplitIndex> = boruta(XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]), YBool::new(&y_vec), 0.01, 20, 50);
+    assert_eq!(two_col.get_confirmed().iter().map(|col| col.col_id).collect::<Vec<_>>(), [0]);
+    assert!(two_col.get_tentative().iter().chain(two_col.get_rejected().iter()).all(|col| col.col_id == 1));
+}
+
+#[test]
+fn boruta_wilcoxon_decision() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..30).map(|_| sample_0_1(&mut rng, 500)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let mut config = BorutaConfig::new(0.01, 20, 200);
+    config.set_decision_test(DecisionTest::Wilcoxon).unwrap();
+    assert_eq!(config.set_importance_source(ImportanceSource::Gini), Err(BorutaConfigError::WilcoxonNeedsPermutation));
+    let mut gini_config = BorutaConfig::new(0.01, 20, 200);
+    gini_config.set_importance_source(ImportanceSource::Gini).unwrap();
+    assert_eq!(gini_config.set_decision_test(DecisionTest::Wilcoxon), Err(BorutaConfigError::WilcoxonNeedsPermutation));
+    let boruta_res: BorutaRes<ColSplitIndex> = boruta_with_config(my_df, y, &config);
+
+    let mut res_confirmed = boruta_res.get_confirmed().iter().map(|col| col.col_id).collect::<Vec<_>>();
+    res_confirmed.sort();
+    assert_eq!(res_confirmed, [0, 1]);
+    assert!(boruta_res.get_rejected().len() > 20);
+}
+
+#[test]
+fn boruta_history_csv() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..10).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let mut config = BorutaConfig::new(0.01, 15, 100);
+    config.set_keep_history(true);
+    let boruta_res: BorutaRes<ColSplitIndex> = boruta_with_config(my_df, y, &config);
+
+    let n_iter = boruta_res.get_shadow_max_history().len();
+    assert!(n_iter > 0);
+    let n_rows: usize = boruta_res.get_zscore_history().values().map(|h| h.len()).sum();
+    assert_eq!(boruta_res.get_zscore_history().len(), 10);
+    for history in boruta_res.get_zscore_history().values() {
+        assert_eq!(history[0].0, 1);
+        assert!(history.last().unwrap().0 <= n_iter);
+    }
+
+    let mut out = vec![];
+    boruta_res.write_history_csv(&mut out, |col| format!("x{}", col.col_id)).unwrap();
+    let csv = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "iteration,name,zscore,decision");
+    assert_eq!(lines.len(), 1 + n_iter + n_rows);
+    assert!(lines[1].starts_with("1,shadowMax,") && lines[1].ends_with(','));
+    assert!(lines.iter().any(|l| l.starts_with("1,x0,") && l.ends_with(",Confirmed")));
+
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let boruta_res: BorutaRes<ColSplitIndex> = boruta_with_config(my_df, y, &BorutaConfig::new(0.01, 15, 100));
+    assert!(boruta_res.get_zscore_history().is_empty());
+    assert!(boruta_res.get_shadow_max_history().is_empty());
+}
+
+#[test]
+fn boruta_report() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..10).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let mut config = BorutaConfig::new(0.01, 15, 100);
+    config.set_keep_history(true);
+    let boruta_res: BorutaRes<ColSplitIndex> = boruta_with_config(my_df, y, &config);
+    let oob_error = boruta_res.get_final_oob_error().unwrap();
+    assert!(oob_error < 0.05);
+
+    let mut report = BorutaReport::new(&boruta_res, &config);
+    report.set_title("Toy <run>");
+    let mut out = vec![];
+    report.write_json(&mut out, |col| format!("x{}", col.col_id)).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(json["title"], "Toy <run>");
+    assert_eq!(json["params"]["ntree"], 100);
+    assert_eq!(json["params"]["decision"], "Binomial");
+    assert!(json["params"]["time_budget_secs"].is_null());
+    assert_eq!(json["iterations"], boruta_res.get_iterations());
+    assert_eq!(json["truncated"], false);
+    assert_eq!(json["final_oob_error"], oob_error);
+    assert_eq!(json["shadow"]["iterations"], boruta_res.get_shadow_max_history().len());
+    let confirmed: Vec<&str> = json["confirmed"].as_array().unwrap().iter().map(|row| row["name"].as_str().unwrap()).collect();
+    assert_eq!(confirmed.len(), boruta_res.get_confirmed().len());
+    assert!(confirmed.contains(&"x0") && confirmed.contains(&"x1"));
+    let rejected = json["rejected"].as_array().unwrap();
+    assert_eq!(rejected.len(), boruta_res.get_rejected().len());
+    assert!(rejected.iter().all(|row| row["final_zscore"].is_null() && row["mean_zscore"].is_f64()));
+    assert!(rejected.windows(2).all(|w| w[0]["hits"].as_u64() >= w[1]["hits"].as_u64()));
+
+    let mut out = vec![];
+    report.write_html(&mut out, HTML_TEMPLATE, |col| format!("x{}", col.col_id)).unwrap();
+    let html = String::from_utf8(out).unwrap();
+    assert!(html.contains("<title>Toy &lt;run&gt;</title>"));
+    assert!(html.contains("<td>x0</td>") && html.contains("<td>ntree</td><td>100</td>"));
+    assert!(!html.contains("{{"));
+}
+
+// External scores: the first two columns are important, everything else scores as the shadows
+struct FixedScores {
+    calls: Cell<usize>,
+    truncated: bool
+}
+
+impl ImportanceProvider<XDf, YBool, ColSplitIndex> for FixedScores {
+    fn importance(&self, df: &XDf, _y: &YBool, _seed: u64, _ntree: usize, _per_tree: bool, _deadline: Option<Instant>) -> ProvidedImportance<SplitColId> {
+        self.calls.set(self.calls.get() + 1);
+        let importance = df.get_col_ids().into_iter()
+            .map(|col| (col, match !col.shadow && col.col_id < 2 {
+                true => 1.,
+                false => 0.
+            }))
+            .collect();
+        return ProvidedImportance{importance, per_tree: vec![], truncated: self.truncated};
+    }
+}
+
+#[test]
+fn boruta_importance_provider() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..8).map(|_| sample_0_1(&mut rng, 100)).collect();
+    let y = YBool::new(&xs[0].iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let provider = FixedScores{calls: Cell::new(0), truncated: false};
+    let boruta_res: BorutaRes<ColSplitIndex> = boruta_with_provider(my_df, y, &BorutaConfig::new(0.01, 30, 10), &provider);
+    let mut confirmed: Vec<usize> = boruta_res.get_confirmed().iter().map(|col| col.col_id).collect();
+    confirmed.sort();
+    assert_eq!(confirmed, [0, 1]);
+    assert_eq!(boruta_res.get_rejected().len(), 6);
+    assert_eq!(provider.calls.get(), boruta_res.get_iterations());
+    // No final importance without ImportanceProvider::final_importance
+    assert!(boruta_res.get_final_importance().is_empty());
+
+    // A model stopped at the deadline ends the run after its iteration
+    let y = YBool::new(&xs[0].iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let provider = FixedScores{calls: Cell::new(0), truncated: true};
+    let boruta_res: BorutaRes<ColSplitIndex> = boruta_with_provider(my_df, y, &BorutaConfig::new(0.01, 30, 10), &provider);
+    assert!(boruta_res.is_truncated());
+    assert_eq!(boruta_res.get_iterations(), 1);
+
+    // The forest importance sources are providers themselves
+    let y = YBool::new(&xs[0].iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let mut config = BorutaConfig::new(0.01, 10, 50);
+    config.set_importance_source(ImportanceSource::Gini).unwrap();
+    let via_provider: BorutaRes<ColSplitIndex> = boruta_with_provider(my_df, y, &config, &ImportanceSource::Gini);
+    let y = YBool::new(&xs[0].iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let via_config: BorutaRes<ColSplitIndex> = boruta_with_config(my_df, y, &config);
+    assert_eq!(via_provider.get_confirmed(), via_config.get_confirmed());
+    assert_eq!(via_provider.get_hits(), via_config.get_hits());
+    assert_eq!(via_provider.get_final_importance(), via_config.get_final_importance());
+}
+
+#[test]
+fn boruta_per_chromosome() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..12).map(|_| sample_0_1(&mut rng, 300)).collect();
+    // One informative column on each chromosome
+    let y_vec: Vec<bool> = xs[0].iter().zip(xs[7].iter()).map(|(&a, &b)| a == 1 && b == 1).collect();
+    let mut my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    my_df.set_col_loci((0..12).map(|i| Some(Locus::new(if i < 6 {"1"} else {"2"}, 1_000 * i as u64))).collect());
+    let y = YBool::new(&y_vec);
+
+    let config = BorutaConfig::new(0.01, 30, 50);
+    let res = boruta_per_region(&my_df, &y, Partition::Chromosome, &config);
+    assert_eq!(res.regions.len(), 2);
+    assert_eq!(res.decisions.len(), 12);
+    assert_eq!(res.decisions[&Locus::new("1", 0)], Decision::Confirmed);
+    assert_eq!(res.decisions[&Locus::new("2", 7_000)], Decision::Confirmed);
+    let (region, chrom2) = &res.regions[1];
+    assert_eq!((region.chrom.as_str(), region.start, region.end), ("2", 6_000, 11_001));
+    assert_eq!(chrom2.get_confirmed(), vec![SplitColId{col_id: 7, shadow: false}]);
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let imp = importance_per_region(&rf, &my_df, &y, Partition::Window(3_000), 50, 2, None, None);
+    assert_eq!(imp.regions.len(), 4);
+    assert_eq!(imp.regions[2].0.start, 6_000);
+    let top = imp.merged.iter().max_by(|a, b| a.1.total_cmp(b.1)).unwrap().0;
+    assert!(*top == Locus::new("1", 0) || *top == Locus::new("2", 7_000));
+    assert_eq!(imp.merged[&Locus::new("2", 7_000)], imp.regions[2].1[&SplitColId{col_id: 7, shadow: false}]);
+}
@@ -14,16 +14,28 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 use serde_json::json;
 use float_cmp::assert_approx_eq;
 
-use variant_forest::data_interface::multi_x::{XDf, MultiX, ColSplitIndex, SplitColId};
+use variant_forest::data_interface::multi_x::{XDf, MultiX, MultiPivot, ColSplitIndex, RowMajorXDf, SplitColId};
 use variant_forest::data_interface::y_bool::{YBool, Y};
-use variant_forest::data_interface::three_val::ThreeValCol;
-use variant_forest::random_forest::RandomForest;
-use variant_forest::tree::Tree;
+use variant_forest::data_interface::y_multi_bool::{MultiY, YMultiBool};
+use variant_forest::data_interface::three_val::{ThreeValCol, ThreeValPivot};
+use variant_forest::data_interface::dosage::DosageCol;
+use variant_forest::data_interface::typed_x::{TypedSplitIndex, XDfTyped};
+use variant_forest::data_interface::{ColumnData, ErrorMetric, LossMetric, Response, SplitPenalty, Subsettable};
+use variant_forest::random_forest::{Forest, RandomForest, Truncation, merge_importance_per_tree};
+use variant_forest::partial_importance::{split_trees, PartialImportance, PartialImportanceError};
+use variant_forest::compact::{CompactError, COMPACT_MAGIC};
+use variant_forest::calibration::CalibrationMethod;
+use variant_forest::tree::{ApplyCache, NaPolicy, PredictWorkspace, SplitWeight, Tree};
 use variant_forest::mask::Mask;
-use variant_forest::random_number_generator::Rng;
+use variant_forest::random_number_generator::{Rng, DEFAULT_SALT};
+use variant_forest::ranked_importance::RankedImportance;
+use variant_forest::random_number_generator::factory::RngFactory;
 
 const SEED: u64 = 139547392210478;
 
@@ -215,4 +227,1435 @@ fn it_does_not_predict_xor_with_max_tree_depth_1() {
 //     let res = tree.importance(&my_df, &y, &Mask::new([9,8,7].to_vec()));
 //     dbg!(res);
 //     panic!()
-// }
\ No newline at end of file
+// }
+#[test]
+fn rf_importance_per_tree_range_merges_to_full_forest() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 100);
+    let xp2 = sample_0_1(&mut rng, 100);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let full = rf.importance_per_tree_range(&my_df, &y, 50, 0..50, 1, false, None, None);
+    let parts = vec![
+        rf.importance_per_tree_range(&my_df, &y, 50, 0..20, 1, false, None, None),
+        rf.importance_per_tree_range(&my_df, &y, 50, 20..50, 1, false, None, Some(2)),
+    ];
+    let merged = merge_importance_per_tree(parts);
+
+    assert_eq!(merged.len(), full.len());
+    for (col, val) in full.iter() {
+        let mut merged_val = merged.get(col).unwrap().clone();
+        let mut full_val = val.clone();
+        merged_val.sort_by(f64::total_cmp);
+        full_val.sort_by(f64::total_cmp);
+        assert_eq!(merged_val, full_val);
+    }
+}
+
+#[test]
+fn rf_partial_importance_over_tsv() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..4).map(|_| sample_0_1(&mut rng, 150)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter()).map(|(&x1, &x2)| x1 == 1 || x2 == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let col_name = |col: &SplitColId| match col.shadow {
+        true => format!("s{}", col.col_id),
+        false => col.col_id.to_string()
+    };
+    let parse_col = |name: &str| match name.strip_prefix('s') {
+        Some(id) => Some(SplitColId{col_id: id.parse().ok()?, shadow: true}),
+        None => Some(SplitColId{col_id: name.parse().ok()?, shadow: false})
+    };
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(3);
+    rf.set_tree_weighting(true);
+    let full = rf.importance(&my_df, &y, 40, 2, true, None, Some(2));
+    // Each part goes through its text form, as when computed in another process
+    let parts = split_trees(40, 3).into_iter()
+        .map(|trees| {
+            let mut buf = vec![];
+            rf.partial_importance(&my_df, &y, 40, trees, 2, true, None, None).write_tsv(&mut buf, col_name).unwrap();
+            PartialImportance::read_tsv(&buf[..], parse_col).unwrap()
+        })
+        .rev()
+        .collect();
+    let merged = PartialImportance::merge(parts).unwrap();
+
+    assert_eq!(merged.trees, 0..40);
+    let merged_imp = merged.importance();
+    assert_eq!(merged_imp.len(), full.len());
+    for (col, val) in full.iter() {
+        assert_approx_eq!(f64, merged_imp[col], *val, epsilon = 1e-9);
+    }
+
+    let other = RandomForest::<Y, ColSplitIndex>::new(4).partial_importance(&my_df, &y, 40, 0..10, 2, true, None, None);
+    let part = rf.partial_importance(&my_df, &y, 40, 10..40, 2, true, None, None);
+    assert!(matches!(PartialImportance::merge(vec![other, part]), Err(PartialImportanceError::Mismatch)));
+}
+
+#[cfg(feature = "distributed")]
+#[test]
+fn rf_partial_importance_over_tcp() {
+    use std::net::TcpListener;
+    use variant_forest::distributed::{drive, run_worker};
+
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..4).map(|_| sample_0_1(&mut rng, 150)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter()).map(|(&x1, &x2)| x1 == 1 || x2 == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let col_name = |col: &SplitColId| col.col_id.to_string();
+    let parse_col = |name: &str| Some(SplitColId{col_id: name.parse().ok()?, shadow: false});
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(3);
+    let full = rf.importance(&my_df, &y, 30, 2, false, None, Some(2));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    // Workers as threads with their own forest, as other processes with the same data and settings
+    let merged = std::thread::scope(|s| {
+        for _ in 0..3 {
+            s.spawn(|| {
+                let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(3);
+                run_worker(addr, |trees| rf.partial_importance(&my_df, &y, 30, trees, 2, false, None, None), col_name).unwrap();
+            });
+        }
+        return drive(&listener, 30, 3, parse_col).unwrap();
+    });
+
+    assert_eq!(merged.trees, 0..30);
+    let merged_imp = merged.importance();
+    assert_eq!(merged_imp.len(), full.len());
+    for (col, val) in full.iter() {
+        assert_approx_eq!(f64, merged_imp[col], *val, epsilon = 1e-9);
+    }
+}
+
+#[test]
+fn rf_importance_batched_equals_per_column() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 100);
+    let xp2 = sample_0_1(&mut rng, 100);
+    let y_ins: Vec<bool> = xp1.iter().zip(xp2.iter()).map(|row| *row.0 == 1 && *row.1 == 1).collect();
+    let y = YBool::new(&y_ins);
+
+    let mut my_df_vec = vec![new_threeval_col(&xp1), new_threeval_col(&xp2)];
+    for _ in 0..20 {
+        my_df_vec.push(new_threeval_col(&sample_0_1(&mut rng, 100)));
+    }
+    let my_df = XDf::new(my_df_vec);
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance(&my_df, &y, 100, 5, false, None, None);
+
+    for batch in [1, 3, 100] {
+        let mut rf_batched: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+        rf_batched.set_importance_batch(Some(batch));
+        let res_batched = rf_batched.importance(&my_df, &y, 100, 5, false, None, None);
+        assert_eq!(res_batched, res);
+    }
+}
+
+#[test]
+fn rf_importance_permutation_cache() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 100);
+    let xp2 = sample_0_1(&mut rng, 100);
+    let xp3 = sample_0_1(&mut rng, 100);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2), new_threeval_col(&xp3)]);
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_permutation_cache(true);
+    let res = rf.importance(&my_df, &y, 1000, 1, false, None, None);
+    assert!(*res.get(&SplitColId{col_id: 0, shadow: false}).unwrap() > 0.30);
+    assert_approx_eq!(f64, *res.get(&SplitColId{col_id: 1, shadow: false}).unwrap(), 0., epsilon=0.02);
+    assert_approx_eq!(f64, *res.get(&SplitColId{col_id: 2, shadow: false}).unwrap(), 0., epsilon=0.02);
+
+    rf.set_importance_batch(Some(2));
+    let res_batched = rf.importance(&my_df, &y, 1000, 1, false, None, Some(3));
+    assert_eq!(res_batched, res);
+}
+
+#[test]
+fn rf_importance_multiple_permutation_rounds() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 100);
+    let xp2 = sample_0_1(&mut rng, 100);
+    let xp3 = sample_0_1(&mut rng, 100);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2), new_threeval_col(&xp3)]);
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_n_perm(3);
+    let res = rf.importance(&my_df, &y, 300, 1, false, None, None);
+    assert!(*res.get(&SplitColId{col_id: 0, shadow: false}).unwrap() > 0.30);
+    assert_approx_eq!(f64, *res.get(&SplitColId{col_id: 1, shadow: false}).unwrap(), 0., epsilon=0.02);
+    assert_approx_eq!(f64, *res.get(&SplitColId{col_id: 2, shadow: false}).unwrap(), 0., epsilon=0.02);
+
+    rf.set_importance_batch(Some(4));
+    let res_batched = rf.importance(&my_df, &y, 300, 1, false, None, None);
+    assert_eq!(res_batched, res);
+}
+
+#[test]
+fn rf_collect_stats() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 100);
+    let xp2 = sample_0_1(&mut rng, 100);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance(&my_df, &y, 50, 1, false, None, Some(2));
+    assert_eq!(rf.get_stats(), None);
+
+    rf.set_collect_stats(true);
+    let res_stats = rf.importance(&my_df, &y, 50, 1, false, None, Some(2));
+    assert_eq!(res_stats, res);
+
+    let stats = rf.get_stats().unwrap();
+    assert_eq!(stats.ntree, 50);
+    assert!(stats.split_search > Duration::ZERO);
+    assert!(stats.prediction > Duration::ZERO);
+    assert!(stats.permutation > Duration::ZERO);
+}
+
+#[test]
+fn rf_manifest() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 100);
+    let xp2 = sample_0_1(&mut rng, 100);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(17);
+    assert_eq!(rf.get_manifest(), None);
+    rf.importance(&my_df, &y, 20, 1, false, Some(3), Some(2));
+
+    let manifest = rf.get_manifest().unwrap();
+    assert_eq!(manifest.seed, 17);
+    assert_eq!(manifest.ntree, 20);
+    assert_eq!(manifest.mtry, 1);
+    assert_eq!(manifest.max_tree_depth, Some(3));
+    assert_eq!(manifest.threads, cfg!(feature = "threads").then_some(2));
+    assert_eq!((manifest.nrow, manifest.ncol), (100, 2));
+}
+
+#[test]
+fn rf_salt() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 100);
+    let xp2 = sample_0_1(&mut rng, 100);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance(&my_df, &y, 20, 1, false, None, None);
+    rf.set_salt(DEFAULT_SALT);
+    assert_eq!(rf.importance(&my_df, &y, 20, 1, false, None, None), res);
+    rf.set_salt(0);
+    assert_ne!(rf.importance(&my_df, &y, 20, 1, false, None, None), res);
+    assert_eq!(rf.get_manifest().unwrap().salt, 0);
+}
+
+#[test]
+fn rf_importance_two_way() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let xp3 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2), new_threeval_col(&xp3)]);
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance_two_way(&my_df, &y, 100, 1, false, None, Some(2));
+    assert_eq!(res.mda, rf.importance(&my_df, &y, 100, 1, false, None, Some(2)));
+
+    let mdi = |col_id| *res.mdi.get(&SplitColId{col_id, shadow: false}).unwrap_or(&0.);
+    // col 0 fully determines y, so its split removes all impurity of the root
+    assert!(mdi(0) > 0.4);
+    assert!(mdi(1) < 0.05);
+    assert!(mdi(2) < 0.05);
+}
+
+#[test]
+fn rf_importance_parallel_columns() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..8).map(|_| sample_0_1(&mut rng, 200)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 || b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance(&my_df, &y, 50, 3, false, None, None);
+    rf.set_parallel_columns(true);
+    rf.set_n_perm(2);
+    let res_parallel = rf.importance(&my_df, &y, 50, 3, false, None, Some(3));
+    rf.set_parallel_columns(false);
+    assert_eq!(res_parallel, rf.importance(&my_df, &y, 50, 3, false, None, None));
+    rf.set_n_perm(1);
+    rf.set_parallel_columns(true);
+    assert_eq!(rf.importance(&my_df, &y, 50, 3, false, None, Some(4)), res);
+}
+
+#[test]
+fn rf_importance_queue() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..8).map(|_| sample_0_1(&mut rng, 200)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 || b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_n_perm(2);
+    rf.set_tree_weighting(true);
+    let res = rf.importance_by_tree(&my_df, &y, 50, 3, true, None, Some(2));
+    let local = rf.local_importance(&my_df, &y, 50, 3, false, None, Some(2));
+    rf.set_importance_queue(true);
+    // Windows of 3 trees do not divide 50 trees
+    assert_eq!(rf.importance_by_tree(&my_df, &y, 50, 3, true, None, Some(3)), res);
+    assert_eq!(rf.local_importance(&my_df, &y, 50, 3, false, None, Some(3)), local);
+}
+
+#[test]
+fn rf_reorder_by_importance() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..20).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y = YBool::new(&xs[7].iter().zip(xs[13].iter())
+        .map(|(&a, &b)| a == 1 || b == 1).collect::<Vec<bool>>());
+    let mut my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let col = |i| SplitColId{col_id: i, shadow: false};
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let screen = rf.gini_importance(&my_df, &y, 20, 4, false, None, None);
+    my_df.reorder_by(&screen);
+    let ids = my_df.get_col_ids();
+    assert!(ids[..2].contains(&col(7)) && ids[..2].contains(&col(13)));
+
+    // Same columns found, though from other trees than on the data in its first order
+    let res = rf.importance(&my_df, &y, 100, 4, false, None, None);
+    let mut top: Vec<usize> = res.iter().filter(|(_, &val)| val > 0.05).map(|(c, _)| c.col_id).collect();
+    top.sort();
+    assert_eq!(top, [7, 13]);
+}
+
+#[test]
+fn rf_time_budget() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..8).map(|_| sample_0_1(&mut rng, 200)).collect();
+    let y = YBool::new(&xs[0].iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let full: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    // Spent before the first tree, which is grown regardless
+    rf.set_time_budget(Some(Duration::ZERO));
+    for threads in [None, Some(2)] {
+        let res = rf.importance_per_tree_range(&my_df, &y, 20, 0..20, 3, false, None, threads);
+        assert_eq!(res, full.importance_per_tree_range(&my_df, &y, 20, 0..1, 3, false, None, threads));
+        assert_eq!(rf.get_truncation(), Some(Truncation{trees_done: 1, trees_planned: 20}));
+    }
+    // The queue takes trees one at a time as well
+    rf.set_importance_queue(true);
+    let res = rf.importance_per_tree_range(&my_df, &y, 20, 0..20, 3, false, None, Some(3));
+    assert_eq!(res, full.importance_per_tree_range(&my_df, &y, 20, 0..1, 3, false, None, None));
+    assert_eq!(rf.get_truncation(), Some(Truncation{trees_done: 1, trees_planned: 20}));
+
+    rf.set_time_budget(Some(Duration::from_secs(3600)));
+    assert_eq!(rf.importance(&my_df, &y, 20, 3, false, None, Some(3)), full.importance(&my_df, &y, 20, 3, false, None, Some(3)));
+    assert_eq!(rf.get_truncation(), None);
+    // A deadline already passed stops the run as a spent budget
+    rf.set_deadline(Some(Instant::now()));
+    rf.importance(&my_df, &y, 20, 3, false, None, None);
+    assert_eq!(rf.get_truncation(), Some(Truncation{trees_done: 1, trees_planned: 20}));
+}
+
+#[test]
+fn rf_tree_weighting() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let xp3 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1.iter().zip(xp2.iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2), new_threeval_col(&xp3)]);
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance(&my_df, &y, 100, 1, false, None, Some(2));
+    rf.set_tree_weighting(true);
+    let res_weighted = rf.importance(&my_df, &y, 100, 1, false, None, Some(2));
+    let mdi_weighted = rf.gini_importance(&my_df, &y, 100, 1, false, None, Some(2));
+
+    let imp = |res: &std::collections::HashMap<SplitColId, f64>, col_id| *res.get(&SplitColId{col_id, shadow: false}).unwrap_or(&0.);
+    assert_ne!(res, res_weighted);
+    assert!(imp(&res_weighted, 0) > imp(&res_weighted, 2));
+    assert!(imp(&res_weighted, 1) > imp(&res_weighted, 2));
+    assert!(imp(&mdi_weighted, 0) > imp(&mdi_weighted, 2));
+}
+
+#[test]
+fn rf_depth_weighted_mdi() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let xp3 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1.iter().zip(xp2.iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2), new_threeval_col(&xp3)]);
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let mdi = rf.gini_importance(&my_df, &y, 50, 1, false, None, None);
+    rf.set_split_weight(SplitWeight::Depth);
+    let mdi_depth = rf.gini_importance(&my_df, &y, 50, 1, false, None, None);
+    // Same trees, so the MDA of importance_two_way is unaffected
+    let two_way = rf.importance_two_way(&my_df, &y, 50, 1, false, None, None);
+
+    let imp = |res: &std::collections::HashMap<SplitColId, f64>, col_id| *res.get(&SplitColId{col_id, shadow: false}).unwrap_or(&0.);
+    assert_ne!(mdi, mdi_depth);
+    assert_eq!(two_way.mdi, mdi_depth);
+    assert_eq!(two_way.mda, RandomForest::<Y, ColSplitIndex>::new(0).importance(&my_df, &y, 50, 1, false, None, None));
+    assert!(imp(&mdi_depth, 0) > imp(&mdi_depth, 2));
+    assert!(imp(&mdi_depth, 1) > imp(&mdi_depth, 2));
+}
+
+#[test]
+fn tree_predict_rows_row_major() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..10).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 || b == 0).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let rows = RowMajorXDf::new(&my_df);
+    let rng_factory = RngFactory::new(SEED, Some(10), Some(5));
+
+    for ith_tree in 0..5 {
+        let mut tree: Tree<Y, ColSplitIndex> = Tree::new(ith_tree, &rng_factory);
+        tree.build_tree(&my_df, &y, &Mask::new((0..200).collect()), 3, false, None);
+        let oob_mask = Mask::new((200..300).collect());
+        let mut mask_ranks = vec![usize::MAX; 300];
+        for (rank, &i) in oob_mask.get_mask().iter().enumerate() {
+            mask_ranks[i] = rank;
+        }
+
+        let preds = tree.predict_rows(&rows, &oob_mask);
+        assert_eq!(preds, tree.predict(&my_df, &oob_mask, None, &mask_ranks));
+        assert_eq!(preds, tree.predict_rows(&my_df, &oob_mask));
+    }
+}
+
+#[test]
+fn tree_min_node_size() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..6).map(|_| sample_0_1(&mut rng, 200)).collect();
+    let y = YBool::new(&sample_0_1(&mut rng, 200).iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let rng_factory = RngFactory::new(SEED, Some(6), Some(1));
+    let mask = Mask::new((0..200).collect());
+
+    let mut full: Tree<Y, ColSplitIndex> = Tree::new(0, &rng_factory);
+    full.build_tree(&my_df, &y, &mask, 6, false, None);
+    let mut pruned: Tree<Y, ColSplitIndex> = Tree::new(0, &rng_factory);
+    pruned.set_min_node_size(50);
+    pruned.build_tree(&my_df, &y, &mask, 6, false, None);
+
+    // y is noise, so the full tree keeps splitting down to small nodes
+    assert!(pruned.shape().n_leaves < full.shape().n_leaves);
+    let res = std::panic::catch_unwind(|| Tree::<Y, ColSplitIndex>::new(0, &rng_factory).set_min_node_size(0));
+    assert!(res.is_err());
+}
+
+#[test]
+fn tree_predict_shared_between_threads() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..6).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let rng_factory = RngFactory::new(SEED, Some(6), Some(1));
+
+    let mut tree: Tree<Y, ColSplitIndex> = Tree::new(0, &rng_factory);
+    tree.build_tree(&my_df, &y, &Mask::new((0..200).collect()), 2, false, None);
+    let oob_mask = Mask::new((200..300).collect());
+    let mut mask_ranks = vec![usize::MAX; 300];
+    for (rank, &i) in oob_mask.get_mask().iter().enumerate() {
+        mask_ranks[i] = rank;
+    }
+
+    let mut ws = PredictWorkspace::new();
+    let cols: Vec<SplitColId> = (0..6).map(|col_id| SplitColId{col_id, shadow: false}).collect();
+    let expected: Vec<_> = cols.iter().map(|&col| tree.predict_with(&mut ws, &my_df, &oob_mask, Some(col), &mask_ranks)).collect();
+
+    let tree = &tree;
+    let preds: Vec<_> = std::thread::scope(|s| {
+        let handles: Vec<_> = cols.iter().map(|&col| {
+            let (my_df, oob_mask, mask_ranks) = (&my_df, &oob_mask, &mask_ranks);
+            s.spawn(move || tree.predict(my_df, oob_mask, Some(col), mask_ranks))
+        }).collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(preds, expected);
+}
+
+#[test]
+fn rf_split_subsample() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..6).map(|_| sample_0_1(&mut rng, 600)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance(&my_df, &y, 100, 2, false, None, Some(2));
+    rf.set_split_subsample(Some(50));
+    let res_sub = rf.importance(&my_df, &y, 100, 2, false, None, Some(2));
+    assert_eq!(rf.get_manifest().unwrap().split_subsample, Some(50));
+
+    let imp = |col_id| *res_sub.get(&SplitColId{col_id, shadow: false}).unwrap_or(&0.);
+    assert_ne!(res, res_sub);
+    assert!(imp(0) > 0.1);
+    assert!(imp(1) > 0.1);
+    for col_id in 2..6 {
+        assert_approx_eq!(f64, imp(col_id), 0., epsilon=0.02);
+    }
+}
+
+#[test]
+fn rf_inbag_masks() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_keep_masks(true);
+    let res = rf.importance(&my_df, &y, 30, 1, false, None, Some(2));
+    let masks = rf.get_masks().unwrap();
+    assert_eq!(masks.len(), 30);
+    for tree_masks in masks.iter() {
+        assert_eq!(tree_masks.inbag.len() + tree_masks.oob.len(), 200);
+        assert_eq!(tree_masks.inbag.intersection(&tree_masks.oob).len(), 0);
+    }
+
+    // Reusing the drawn masks gives the same forest
+    rf.set_inbag_masks(Some(masks.iter().map(|x| x.inbag.clone()).collect()));
+    assert_eq!(rf.importance(&my_df, &y, 30, 1, false, None, Some(2)), res);
+    assert!(rf.get_manifest().unwrap().custom_masks);
+
+    // Trees grown on the first half are evaluated on the second
+    rf.set_inbag_masks(Some(vec![Mask::new((0..100).collect()); 30]));
+    let res_half = rf.importance(&my_df, &y, 30, 1, false, None, Some(2));
+    assert!(rf.get_masks().unwrap().iter().all(|x| x.oob == Mask::new((100..200).collect())));
+    assert!(*res_half.get(&SplitColId{col_id: 0, shadow: false}).unwrap() > 0.3);
+}
+
+#[test]
+#[should_panic(expected = "Number of in-bag masks must equal number of trees.")]
+fn rf_inbag_masks_count() {
+    let my_df = XDf::new(vec![new_threeval_col(&[0, 1, 0, 1])]);
+    let y = YBool::new(&[false, true, false, true]);
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_inbag_masks(Some(vec![Mask::new(vec![0, 1])]));
+    rf.importance(&my_df, &y, 2, 1, false, None, None);
+}
+
+#[test]
+#[should_panic(expected = "Split penalty must be non-negative.")]
+fn rf_negative_split_penalty() {
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_split_penalty(SplitPenalty::LevelCount(-0.01));
+}
+
+#[test]
+fn rf_matched_sets() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+    // Pairs of consecutive rows
+    let sets: Vec<usize> = (0..200).map(|i| i / 2).collect();
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_keep_masks(true);
+    rf.set_matched_sets(Some(sets.clone()));
+    let res = rf.importance(&my_df, &y, 30, 1, false, None, Some(2));
+    assert!(rf.get_manifest().unwrap().matched_sets);
+    for tree_masks in rf.get_masks().unwrap() {
+        assert_eq!(tree_masks.inbag.len(), 132);
+        assert!(tree_masks.inbag.get_mask().chunks(2).all(|pair| sets[pair[0]] == sets[pair[1]]));
+        assert!(tree_masks.oob.get_mask().chunks(2).all(|pair| sets[pair[0]] == sets[pair[1]]));
+    }
+    assert!(*res.get(&SplitColId{col_id: 0, shadow: false}).unwrap() > 0.3);
+}
+
+#[test]
+fn rf_bootstrap() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..4).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_keep_masks(true);
+    rf.set_bootstrap(true);
+    let res = rf.importance(&my_df, &y, 50, 2, false, None, Some(2));
+    let manifest = rf.get_manifest().unwrap();
+    assert!(manifest.bootstrap);
+    assert_eq!(manifest.sample_fraction, 1.);
+    for tree_masks in rf.get_masks().unwrap() {
+        assert!(tree_masks.inbag.is_weighted());
+        assert_eq!(tree_masks.inbag.n_draws(), 300);
+        assert_eq!(tree_masks.inbag.len() + tree_masks.oob.len(), 300);
+    }
+
+    let imp = |col_id| *res.get(&SplitColId{col_id, shadow: false}).unwrap_or(&0.);
+    assert!(imp(0) > 0.1);
+    assert!(imp(1) > 0.1);
+    for col_id in 2..4 {
+        assert_approx_eq!(f64, imp(col_id), 0., epsilon=0.02);
+    }
+}
+
+#[test]
+fn rf_multi_response_importance() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let xp3 = sample_0_1(&mut rng, 200);
+    let y = YMultiBool::new(&[
+        xp1.iter().map(|&x| x == 1).collect(),
+        xp2.iter().map(|&x| x == 1).collect()
+    ]).unwrap();
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2), new_threeval_col(&xp3)]);
+
+    let rf: RandomForest<MultiY, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance_multi_response(&my_df, &y, 30, 2, false, None, Some(2));
+    let col = |i| SplitColId{col_id: i, shadow: false};
+
+    assert_eq!(res.per_response.len(), 2);
+    assert!(res.per_response[0][&col(0)] > 0.3);
+    assert_approx_eq!(f64, res.per_response[0][&col(1)], 0.);
+    assert!(res.per_response[1][&col(1)] > 0.3);
+    assert_approx_eq!(f64, res.per_response[1][&col(0)], 0.);
+    for i in 0..3 {
+        assert_approx_eq!(f64, res.combined[&col(i)], res.per_response[0][&col(i)] + res.per_response[1][&col(i)], epsilon = 1e-9);
+    }
+    // One forest scores every response, the same one importance grows
+    assert_eq!(res.combined, rf.importance(&my_df, &y, 30, 2, false, None, Some(2)));
+    assert_eq!(res, rf.importance_multi_response(&my_df, &y, 30, 2, false, None, None));
+}
+
+#[test]
+fn rf_typed_df_as_xdf() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..6).map(|_| sample_0_1(&mut rng, 200)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let typed_df = XDfTyped::new(xs.iter().map(|x| ThreeValCol::new(x)).collect());
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let rf_typed: RandomForest<Y, TypedSplitIndex<ThreeValPivot>> = RandomForest::new(0);
+    for mtry in [1, 3] {
+        assert_eq!(rf_typed.importance(&typed_df, &y, 30, mtry, true, None, None), rf.importance(&my_df, &y, 30, mtry, true, None, None));
+    }
+}
+
+#[test]
+fn rf_local_importance() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let local = rf.local_importance(&my_df, &y, 50, 2, false, None, Some(2));
+    assert_eq!(local.nrow(), 200);
+    let col_0 = local.get(&SplitColId{col_id: 0, shadow: false}).unwrap();
+    assert_eq!(col_0.len(), 200);
+    let known: Vec<f64> = col_0.iter().copied().filter(|x| !x.is_nan()).collect();
+    assert!(known.len() > 190);
+    assert!(known.iter().sum::<f64>() / known.len() as f64 > 0.3);
+
+    let mut tsv = vec![];
+    local.to_tsv(&mut tsv, |c| format!("x{}", c.col_id), |i| format!("s{}", i)).unwrap();
+    let tsv = String::from_utf8(tsv).unwrap();
+    assert_eq!(tsv.lines().count(), 201);
+    assert!(tsv.starts_with("sample\tx0"));
+}
+
+#[test]
+fn rf_importance_by_group() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 400);
+    let xp2 = sample_0_1(&mut rng, 400);
+    let xp3 = sample_0_1(&mut rng, 400);
+    // x0 drives y in group 0 only, x1 in group 1 only
+    let groups: Vec<usize> = (0..400).map(|i| i % 2).collect();
+    let y = YBool::new(&(0..400).map(|i| match groups[i] {
+        0 => xp1[i] == 1,
+        _ => xp2[i] == 1
+    }).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2), new_threeval_col(&xp3)]);
+    let col = |i| SplitColId{col_id: i, shadow: false};
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance_by_group(&my_df, &y, &groups, 50, 2, false, None, Some(2));
+    assert_eq!(res.per_group.len(), 2);
+    assert!(res.per_group[&0][&col(0)] > 5. * res.per_group[&1][&col(0)].abs());
+    assert!(res.per_group[&1][&col(1)] > 5. * res.per_group[&0][&col(1)].abs());
+    assert!(res.overall[&col(0)] < res.per_group[&0][&col(0)]);
+    assert!(res.overall[&col(0)] > res.per_group[&1][&col(0)]);
+    assert_eq!(rf.importance_by_group(&my_df, &y, &groups, 50, 2, false, None, None), res);
+}
+
+#[test]
+fn rf_pivot_importance() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1: Vec<i8> = (0..400).map(|_| (rng.rand_uni() * 3.) as i8).collect();
+    let xp2 = sample_0_1(&mut rng, 400);
+    // Recessive coding: only the 2 against 0 or 1 split matters
+    let y = YBool::new(&xp1.iter().map(|&x| x == 2).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+    let col = |i| SplitColId{col_id: i, shadow: false};
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.pivot_importance(&my_df, &y, 20, 2, false, None, None);
+    let not_blue = res[&(col(0), MultiPivot::ThreeVal(ThreeValPivot::NotBlue))];
+    assert!(not_blue > 0.1);
+    for ((c, pivot), val) in res.iter() {
+        if (*c, *pivot) != (col(0), MultiPivot::ThreeVal(ThreeValPivot::NotBlue)) {
+            assert!(*val < not_blue / 5.);
+        }
+    }
+    assert_eq!(rf.pivot_importance(&my_df, &y, 20, 2, false, None, None), res);
+    assert_eq!(rf.pivot_importance(&my_df, &y, 20, 2, false, None, Some(3)), res);
+}
+
+#[test]
+fn rf_local_importance_sample_ids() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    // Every tenth sample misses its phenotype and is dropped before fitting
+    let y: Vec<Option<bool>> = xp1.iter().enumerate().map(|(i, &x)| (i % 10 != 0).then_some(x == 1)).collect();
+    let mut my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+    my_df.set_sample_ids(Some((0..200).map(|i| format!("id{}", i)).collect()));
+    let (obs_df, obs_y, observed) = my_df.with_observed_y(&y);
+    assert_eq!(observed.len(), 180);
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let local = rf.local_importance(&obs_df, &obs_y, 50, 2, false, None, None);
+    let ids = local.sample_ids().unwrap();
+    assert_eq!(ids.len(), 180);
+    assert_eq!(ids[..3], ["id1", "id2", "id3"]);
+    let col_0 = SplitColId{col_id: 0, shadow: false};
+    assert_eq!(local.get_by_id(&col_0, "id0"), None);
+    assert_eq!(local.get_by_id(&col_0, "id11").map(|x| x.to_bits()), Some(local.get(&col_0).unwrap()[9].to_bits()));
+
+    // OOB votes and predictions follow the ids of the filtered rows too
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_keep_oob_votes(true);
+    let forest = rf.fit(&obs_df, &obs_y, 50, 2, false, None);
+    assert_eq!(forest.get_sample_ids(), Some(ids));
+    let shares = forest.oob_vote_shares(180, true).unwrap();
+    let by_id = forest.oob_vote_shares_by_id(true).unwrap();
+    assert!(!by_id.contains_key("id0"));
+    assert_eq!(by_id.get("id11"), shares[9].as_ref());
+    let mask = Mask::new(vec![0, 9]);
+    let preds = forest.predict_votes(&obs_df, &mask, true, NaPolicy::Majority);
+    let preds_by_id = forest.predict_votes_by_id(&obs_df, &mask, true, NaPolicy::Majority).unwrap();
+    assert_eq!((preds_by_id["id1"], preds_by_id["id11"]), (preds[0], preds[1]));
+    let mut out = vec![];
+    forest.write_compact(&mut out).unwrap();
+    let read: Forest<Y, ColSplitIndex> = Forest::read_compact(&mut out.as_slice()).unwrap();
+    assert_eq!(read.oob_vote_shares_by_id(true), Some(by_id));
+}
+
+#[test]
+fn rf_model_hash() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 100);
+    let xp2 = sample_0_1(&mut rng, 100);
+    let xp3 = sample_0_1(&mut rng, 100);
+    let y = YBool::new(&xp1.iter().zip(xp2.iter())
+        .map(|(&x1, &x2)| x1 == 1 && x2 == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2), new_threeval_col(&xp3)]);
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let hash = rf.model_hash(&my_df, &y, 20, 2, false, None);
+    assert_eq!(hash, rf.model_hash(&my_df, &y, 20, 2, false, None));
+    assert_ne!(hash, RandomForest::<Y, ColSplitIndex>::new(1).model_hash(&my_df, &y, 20, 2, false, None));
+    assert_ne!(hash, rf.model_hash(&my_df, &y, 20, 2, false, Some(1)));
+    // Pinned, so a change of tree building shows up even when importance values barely move
+    assert_eq!(hash, 11023906272560758981);
+}
+
+#[test]
+fn rf_include_unused() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 100);
+    let xp2 = sample_0_1(&mut rng, 100);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    // Constant column cannot be split on
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2), new_threeval_col(&vec![0; 100])]);
+    let col = |i| SplitColId{col_id: i, shadow: false};
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance(&my_df, &y, 20, 3, false, None, None);
+    assert!(!res.contains_key(&col(2)));
+    assert!(!rf.get_tree_counts().unwrap().contains_key(&col(2)));
+
+    rf.set_include_unused(true);
+    let res_all = rf.importance(&my_df, &y, 20, 3, false, None, None);
+    assert_eq!(res_all[&col(2)], 0.);
+    assert_eq!(res_all[&col(0)], res[&col(0)]);
+    assert_eq!(rf.zscore(&my_df, &y, 20, 3, false, None, None)[&col(2)], 0.);
+    assert_eq!(rf.importance_two_way(&my_df, &y, 20, 3, false, None, None).mdi[&col(2)], 0.);
+    let counts = rf.get_tree_counts().unwrap();
+    assert_eq!(counts[&col(0)], 20);
+    assert_eq!(counts[&col(2)], 0);
+
+    // Shadows of the constant column are never split on either
+    let shadow = SplitColId{col_id: 2, shadow: true};
+    assert!(!res_all.contains_key(&shadow));
+    let res_shadow = rf.importance(&my_df, &y, 20, 3, true, None, None);
+    assert_eq!(res_shadow[&shadow], 0.);
+    assert_eq!(rf.get_tree_counts().unwrap()[&shadow], 0);
+}
+
+#[test]
+fn rf_zscore_checked() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 100);
+    let xp2 = sample_0_1(&mut rng, 100);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2), new_threeval_col(&vec![0; 100])]);
+    let col = |i| SplitColId{col_id: i, shadow: false};
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_include_unused(true);
+    let res = rf.zscore_checked(&my_df, &y, 20, 3, false, None, None);
+    let zscore = rf.zscore(&my_df, &y, 20, 3, false, None, None);
+    assert_eq!(res[&col(0)].score, zscore[&col(0)]);
+    assert_eq!(res[&col(0)].n_trees, 20);
+    assert!(!res[&col(0)].unstable);
+    assert_eq!(res[&col(2)].score, 0.);
+    assert_eq!(res[&col(2)].n_trees, 0);
+    assert!(res[&col(2)].unstable);
+
+    // A single tree has no variance to scale by
+    let res = rf.zscore_checked(&my_df, &y, 1, 3, false, None, None);
+    assert_eq!(res[&col(0)].n_trees, 1);
+    assert!(res[&col(0)].unstable);
+    assert!(!res[&col(0)].score.is_finite());
+}
+
+#[test]
+fn rf_importance_shadow() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1
+        .iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance_shadow(&my_df, &y, 30, 2, None, Some(2));
+    let mixed = rf.importance(&my_df, &y, 30, 2, true, None, Some(2));
+    assert_eq!(res.real.len() + res.shadow.len(), mixed.len());
+    assert!(res.real.keys().all(|col| !col.shadow));
+    assert!(res.shadow.keys().all(|col| col.shadow));
+    assert_eq!(res.shadow_max.len(), 30);
+    assert!(res.shadow_max.iter().all(|&x| x >= 0.));
+    // Some trees split on shadows
+    assert!(res.shadow_max.iter().any(|&x| x > 0.));
+    assert!(res.real[&SplitColId{col_id: 0, shadow: false}] > 0.3);
+}
+
+#[test]
+fn rf_balanced_error_metric() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 300);
+    let xp2 = sample_0_1(&mut rng, 300);
+    let xp3 = sample_0_1(&mut rng, 300);
+    // About a quarter of cases
+    let y = YBool::new(&xp1.iter().zip(xp2.iter())
+        .map(|(&x1, &x2)| x1 == 1 && x2 == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2), new_threeval_col(&xp3)]);
+    let col = |i| SplitColId{col_id: i, shadow: false};
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance(&my_df, &y, 30, 2, false, None, Some(2));
+    rf.set_error_metric(ErrorMetric::BalancedError);
+    let res_balanced = rf.importance(&my_df, &y, 30, 2, false, None, Some(2));
+    assert_eq!(rf.get_manifest().unwrap().error_metric, ErrorMetric::BalancedError);
+
+    // Errors on the minority class weigh more
+    assert!(res_balanced[&col(0)] > res[&col(0)]);
+    assert!(res_balanced[&col(1)] > res[&col(1)]);
+    assert!(res_balanced[&col(2)].abs() < 0.05);
+}
+
+// Costs false negatives five times false positives.
+struct CostError;
+
+impl LossMetric<bool> for CostError {
+    fn loss(&self, truth: &[bool], preds: &[bool]) -> f64 {
+        return truth.iter().zip(preds.iter())
+            .map(|(&t, &p)| match (t, p) {
+                (true, false) => 5.,
+                (false, true) => 1.,
+                _ => 0.
+            })
+            .sum();
+    }
+}
+
+#[test]
+fn rf_custom_error_metric() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 300);
+    let xp2 = sample_0_1(&mut rng, 300);
+    let xp3 = sample_0_1(&mut rng, 300);
+    let y = YBool::new(&xp1.iter().zip(xp2.iter())
+        .map(|(&x1, &x2)| x1 == 1 && x2 == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2), new_threeval_col(&xp3)]);
+    let col = |i| SplitColId{col_id: i, shadow: false};
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.importance(&my_df, &y, 30, 2, false, None, Some(2));
+    rf.set_custom_error_metric(Arc::new(CostError));
+    let res_cost = rf.importance(&my_df, &y, 30, 2, false, None, Some(2));
+    assert_eq!(rf.get_manifest().unwrap().error_metric, ErrorMetric::Custom);
+    assert!(res_cost[&col(0)] > res[&col(0)]);
+    assert!(res_cost[&col(2)].abs() < 0.1);
+}
+
+#[test]
+fn rf_depth_stats() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..6).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    assert_eq!(rf.get_depth_stats(), None);
+    rf.importance(&my_df, &y, 20, 2, false, None, Some(2));
+    let unlimited = rf.get_depth_stats().unwrap();
+    assert!(unlimited.max_depth > 2);
+    assert!(unlimited.min_depth as f64 <= unlimited.median_depth && unlimited.median_depth <= unlimited.max_depth as f64);
+
+    rf.importance(&my_df, &y, 20, 2, false, Some(2), Some(2));
+    let limited = rf.get_depth_stats().unwrap();
+    assert_eq!(limited.max_depth, 2);
+    assert!(limited.max_leaves <= 4 && limited.max_leaves < unlimited.max_leaves);
+}
+
+#[test]
+fn rf_mtry_above_ncol() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1.iter().map(|&x| x == 1).collect::<Vec<bool>>());
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let narrow_df = XDf::new(vec![new_threeval_col(&xp1)]);
+    let res = rf.importance(&narrow_df, &y, 20, 5, true, None, None);
+    assert!(res[&SplitColId{col_id: 0, shadow: false}] > 0.3);
+
+    // mtry above the column count tries all columns, as mtry equal to it does
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+    assert_eq!(rf.importance(&my_df, &y, 20, 10, false, None, Some(2)), rf.importance(&my_df, &y, 20, 2, false, None, Some(2)));
+}
+
+#[test]
+fn rf_compare_seeds() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..10).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let ranked = |seed| {
+        let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(seed);
+        let res = rf.importance(&my_df, &y, 100, 3, false, None, Some(2));
+        return RankedImportance::new(&res, None, None, |col| col.col_id.to_string());
+    };
+    let (seed_0, seed_1) = (ranked(0), ranked(1));
+
+    let same = seed_0.compare(&ranked(0), &[2]);
+    assert_approx_eq!(f64, same.spearman, 1.);
+    let cmp = seed_0.compare(&seed_1, &[2, 10]);
+    assert_eq!(cmp.n_common, 10);
+    assert_eq!(cmp.overlap, vec![(2, 1.), (10, 1.)]);
+    assert!(cmp.spearman > 0. && cmp.spearman < 1.);
+}
+
+#[test]
+fn rf_predict_votes_missing() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1.iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let forest = rf.fit(&my_df, &y, 50, 2, false, None);
+    assert_eq!(forest.get_trees().len(), 50);
+
+    // Rows 0 and 1 are observed, rows 2 and 3 miss the informative column
+    let new_df = XDf::new(vec![
+        MultiX::ThreeVal(ThreeValCol::new_with_missing(&[Some(1), Some(0), None, None])),
+        MultiX::ThreeVal(ThreeValCol::new_with_missing(&[Some(0), Some(1), Some(0), None]))
+    ]);
+    let mask = Mask::new((0..4).collect());
+    let majority = forest.predict_votes(&new_df, &mask, true, NaPolicy::Majority);
+    let weighted = forest.predict_votes(&RowMajorXDf::new(&new_df), &mask, true, NaPolicy::Weighted);
+
+    assert_eq!(majority[..2], [1., 0.]);
+    assert_eq!(weighted[..2], [1., 0.]);
+    // Majority gives each tree a single vote, Weighted follows the in-bag class shares
+    assert!(majority[2..].iter().all(|&v| (v * 50.).fract() == 0.));
+    assert!(weighted[2..].iter().all(|&v| v > 0.3 && v < 0.7));
+    assert_approx_eq!(f64, forest.predict_votes(&new_df, &mask, false, NaPolicy::Weighted)[2], 1. - weighted[2], epsilon=1e-12);
+}
+
+#[test]
+fn rf_predict_votes_tree_weighting() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..4).map(|_| sample_0_1(&mut rng, 200)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter()).map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let mask = Mask::new((0..200).collect());
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_tree_weighting(true);
+    let forest = rf.fit(&my_df, &y, 20, 1, false, Some(1));
+
+    // Stumps on single columns differ in OOB accuracy, so their votes weigh differently
+    let weights: Vec<f64> = forest.get_trees().iter().map(|tree| tree.get_oob_accuracy().unwrap()).collect();
+    assert!(weights.iter().any(|&w| w != weights[0]));
+    let total: f64 = weights.iter().sum();
+    let votes = forest.predict_votes(&my_df, &mask, true, NaPolicy::Majority);
+    for (&row, &vote) in mask.get_mask().iter().zip(votes.iter()) {
+        let expected: f64 = forest.get_trees().iter().zip(weights.iter())
+            .map(|(tree, w)| w * tree.predict_class_weights(&my_df, row, NaPolicy::Majority).iter().filter(|(class, _)| *class).map(|(_, p)| p).sum::<f64>())
+            .sum::<f64>() / total;
+        assert_approx_eq!(f64, vote, expected, epsilon=1e-12);
+    }
+
+    let empty: Forest<Y, ColSplitIndex> = Forest::read_compact(&mut [COMPACT_MAGIC.as_slice(), &[0, 0, 0, 0, 0]].concat().as_slice()).unwrap();
+    let res = std::panic::catch_unwind(|| empty.predict_votes(&my_df, &mask, true, NaPolicy::Majority));
+    assert!(res.is_err());
+}
+
+#[test]
+fn rf_split_pair_counts() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..4).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 1 && b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let forest = rf.fit(&my_df, &y, 30, 4, false, None);
+    let col = |i| SplitColId{col_id: i, shadow: false};
+    let counts = forest.split_pair_counts(&[col(0), col(1), col(2)]);
+
+    // Every tree splits on one column of the interaction and then on the other, noise never splits pure nodes
+    assert_eq!(counts[0][1] + counts[1][0], 30);
+    assert!(counts[0][1] > 0 && counts[1][0] > 0);
+    assert_eq!(counts[2], [0, 0, 0]);
+    assert!(counts.iter().enumerate().all(|(i, row)| row[i] == 0 && row[2] == 0));
+    assert!(counts.iter().flatten().all(|&n| n <= 30));
+}
+
+#[test]
+fn rf_predict_proba_smoothed() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1.iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_leaf_counts(true);
+    let forest = rf.fit(&my_df, &y, 20, 2, false, None);
+    let new_df = XDf::new(vec![new_threeval_col(&[1, 0]), new_threeval_col(&[0, 1])]);
+    let mask = Mask::new(vec![0, 1]);
+
+    // Pure leaves give hard probabilities without smoothing, which Laplace smoothing pulls towards 1/2
+    assert_eq!(forest.predict_proba(&new_df, &mask, true, NaPolicy::Majority, 0.), [1., 0.]);
+    let smoothed = forest.predict_proba(&new_df, &mask, true, NaPolicy::Majority, 1.);
+    assert!(smoothed[0] > 0.9 && smoothed[0] < 1.);
+    assert!(smoothed[1] > 0. && smoothed[1] < 0.1);
+    let smoothed_false = forest.predict_proba(&new_df, &mask, false, NaPolicy::Majority, 1.);
+    assert_approx_eq!(f64, smoothed[0] + smoothed_false[0], 1., epsilon=1e-12);
+
+    // Stumps on the noise column keep both classes in their leaves
+    let stumps = rf.fit(&XDf::new(vec![new_threeval_col(&xp2)]), &y, 20, 1, false, Some(1));
+    let p = stumps.predict_proba(&XDf::new(vec![new_threeval_col(&[0, 1])]), &mask, true, NaPolicy::Majority, 0.);
+    assert!(p.iter().all(|&p| p > 0.2 && p < 0.8));
+}
+
+#[test]
+fn rf_predict_quantiles() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1.iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+    // A quantitative trait shifted by the signal column
+    let values: Vec<f64> = xp1.iter().enumerate().map(|(i, &x)| 10. * x as f64 + (i % 5) as f64).collect();
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_leaf_rows(true);
+    rf.set_bootstrap(true);
+    let forest = rf.fit(&my_df, &y, 20, 2, false, None);
+    let new_df = XDf::new(vec![new_threeval_col(&[1, 0]), new_threeval_col(&[0, 1])]);
+    let mask = Mask::new(vec![0, 1]);
+
+    // Pure leaves hold the values of one level of the signal column only
+    let q = forest.predict_quantiles(&new_df, &mask, &values, &[0., 0.05, 0.5, 0.95, 1.], NaPolicy::Majority);
+    assert!(q[0].windows(2).all(|w| w[0] <= w[1]));
+    assert!(q[0][0] >= 10. && q[0][4] <= 14.);
+    assert!(q[1][0] >= 0. && q[1][4] <= 4.);
+    assert_eq!(q[0][2], 12.);
+
+    // Stumps on the noise column mix both levels, giving wide intervals
+    let stumps = rf.fit(&XDf::new(vec![new_threeval_col(&xp2)]), &y, 20, 1, false, Some(1));
+    let q = stumps.predict_quantiles(&XDf::new(vec![new_threeval_col(&[0])]), &Mask::new(vec![0]), &values, &[0.05, 0.95], NaPolicy::Majority);
+    assert!(q[0][0] < 5. && q[0][1] >= 10.);
+}
+
+#[test]
+#[should_panic(expected = "Leaf rows must be kept to predict row weights, see set_leaf_rows.")]
+fn rf_predict_quantiles_without_leaf_rows() {
+    let my_df = XDf::new(vec![new_threeval_col(&[0, 1, 0, 1])]);
+    let y = YBool::new(&[false, true, false, true]);
+    let forest = RandomForest::<Y, ColSplitIndex>::new(0).fit(&my_df, &y, 2, 1, false, None);
+    forest.predict_quantiles(&my_df, &Mask::new(vec![0]), &[0., 1., 0., 1.], &[0.5], NaPolicy::Majority);
+}
+
+#[test]
+fn rf_proba_importance() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1.iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+    let col = |i| SplitColId{col_id: i, shadow: false};
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_leaf_counts(true);
+    // Leaves are pure, so without smoothing the loss is the misclassification of importance
+    let hard = rf.proba_importance(&my_df, &y, 20, 2, false, None, None, 0.);
+    let imp = rf.importance(&my_df, &y, 20, 2, false, None, None);
+    for (c, val) in imp.iter() {
+        assert_approx_eq!(f64, hard[c], *val, epsilon=1e-9);
+    }
+    // Smoothing keeps part of the loss in the leaves, so permuting costs less
+    let smoothed = rf.proba_importance(&my_df, &y, 20, 2, false, None, None, 1.);
+    assert!(smoothed[&col(0)] > 0.4 && smoothed[&col(0)] < hard[&col(0)]);
+    assert!(smoothed.get(&col(1)).map_or(true, |val| val.abs() < 0.05));
+    assert_eq!(rf.proba_importance(&my_df, &y, 20, 2, false, None, Some(3), 1.), smoothed);
+
+    // Log loss punishes the confident wrong leaves of permuted rows more than one minus the probability
+    rf.set_error_metric(ErrorMetric::CrossEntropy);
+    let cross_entropy = rf.proba_importance(&my_df, &y, 20, 2, false, None, None, 1.);
+    assert!(cross_entropy[&col(0)] > smoothed[&col(0)]);
+    assert!(cross_entropy.get(&col(1)).map_or(true, |val| val.abs() < 0.1));
+}
+
+#[test]
+#[should_panic(expected = "Smoothing alpha must be positive for cross entropy.")]
+fn rf_cross_entropy_unsmoothed() {
+    let my_df = XDf::new(vec![new_threeval_col(&[0, 1, 0, 1])]);
+    let y = YBool::new(&[false, true, false, true]);
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_leaf_counts(true);
+    rf.set_error_metric(ErrorMetric::CrossEntropy);
+    rf.proba_importance(&my_df, &y, 2, 1, false, None, None, 0.);
+}
+
+#[test]
+fn rf_calibrated_proba() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 300);
+    let xp2 = sample_0_1(&mut rng, 300);
+    let noise = sample_0_1(&mut rng, 300);
+    // x0 shifts the odds of y, so votes are confident beyond what the data support
+    let y = YBool::new(&(0..300).map(|i| match noise[i] == 1 && i % 3 == 0 {
+        true => xp1[i] == 0,
+        false => xp1[i] == 1
+    }).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+    let new_df = XDf::new(vec![new_threeval_col(&[1, 0]), new_threeval_col(&[0, 1])]);
+    let mask = Mask::new(vec![0, 1]);
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_leaf_counts(true);
+    let mut forest = rf.fit(&my_df, &y, 30, 2, false, None);
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| forest.calibrate(&y, true, CalibrationMethod::Platt)));
+    assert!(res.is_err());
+
+    rf.set_keep_oob_votes(true);
+    let mut forest = rf.fit(&my_df, &y, 30, 2, false, None);
+    // Trees vote by x0 alone, so raw vote shares are hard
+    assert_eq!(forest.predict_votes(&new_df, &mask, true, NaPolicy::Majority), [1., 0.]);
+    for method in [CalibrationMethod::Platt, CalibrationMethod::Isotonic] {
+        forest.calibrate(&y, true, method);
+        let calibrated = forest.predict_proba(&new_df, &mask, true, NaPolicy::Majority, 0.);
+        // A sixth of the labels are flipped, calibration brings the votes to about the observed rates
+        assert!(calibrated[0] > 0.75 && calibrated[0] < 0.9);
+        assert!(calibrated[1] > 0.1 && calibrated[1] < 0.25);
+        // The other class of the binary response gets the complement
+        let other = forest.predict_proba(&new_df, &mask, false, NaPolicy::Majority, 0.);
+        assert_approx_eq!(f64, other[0], 1. - calibrated[0], epsilon=1e-12);
+        assert_approx_eq!(f64, other[1], 1. - calibrated[1], epsilon=1e-12);
+    }
+}
+
+#[test]
+fn rf_compact_forest() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..50).map(|_| (0..400).map(|_| (rng.rand_uni() * 3.) as i8).collect()).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter())
+        .map(|(&a, &b)| a == 2 || b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let mask = Mask::new((0..400).collect());
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf.set_leaf_counts(true);
+    rf.set_keep_oob_votes(true);
+    let mut forest = rf.fit(&my_df, &y, 20, 7, false, None);
+    forest.calibrate(&y, true, CalibrationMethod::Isotonic);
+    let mut out = vec![];
+    forest.write_compact(&mut out).unwrap();
+    let n_nodes: usize = forest.get_trees().iter().map(|tree| tree.node_depths().len()).sum();
+    let n_votes: usize = forest.get_oob_votes().unwrap().iter().map(|votes| votes.oob.len()).sum();
+    // Node kind, column, two children and size take about 5 bytes, OOB votes 2
+    assert!(out.len() < 6 * n_nodes + 3 * n_votes + 1000);
+
+    let read: Forest<Y, ColSplitIndex> = Forest::read_compact(&mut out.as_slice()).unwrap();
+    for (tree, read_tree) in forest.get_trees().iter().zip(read.get_trees()) {
+        assert_eq!(read_tree.structure_hash(), tree.structure_hash());
+        assert_eq!(read_tree.get_split_cols(), tree.get_split_cols());
+    }
+    assert_eq!(read.get_oob_votes(), forest.get_oob_votes());
+    assert_eq!(read.get_calibrator(), forest.get_calibrator());
+    assert_eq!(read.diagnostics(&y), forest.diagnostics(&y));
+    assert_eq!(read.predict_votes(&my_df, &mask, true, NaPolicy::Weighted), forest.predict_votes(&my_df, &mask, true, NaPolicy::Weighted));
+    assert_eq!(read.predict_proba(&my_df, &mask, true, NaPolicy::Majority, 1.), forest.predict_proba(&my_df, &mask, true, NaPolicy::Majority, 1.));
+    assert_eq!(read.predict_proba(&my_df, &mask, false, NaPolicy::Majority, 1.), forest.predict_proba(&my_df, &mask, false, NaPolicy::Majority, 1.));
+
+    assert!(matches!(Forest::<Y, ColSplitIndex>::read_compact(&mut &out[1..]), Err(CompactError::Magic)));
+    let mut v1 = out.clone();
+    v1[3] = b'1';
+    assert!(matches!(Forest::<Y, ColSplitIndex>::read_compact(&mut v1.as_slice()), Err(CompactError::Version(b'1'))));
+    assert!(matches!(Forest::<Y, ColSplitIndex>::read_compact(&mut &out[..out.len() / 2]), Err(CompactError::Io(_))));
+
+    // Lengths are not trusted: 2^63 - 1 trees end with the input, a tree of no nodes is rejected
+    let mut huge = COMPACT_MAGIC.to_vec();
+    huge.extend([0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]);
+    assert!(matches!(Forest::<Y, ColSplitIndex>::read_compact(&mut huge.as_slice()), Err(CompactError::Io(_))));
+    let mut empty_tree = COMPACT_MAGIC.to_vec();
+    // One tree: seed, salt, ncol 1, no ntree, one permutation round, tree index, node count
+    empty_tree.extend([1, 0, 0, 2, 0, 1, 0, 0]);
+    assert!(matches!(Forest::<Y, ColSplitIndex>::read_compact(&mut empty_tree.as_slice()), Err(CompactError::Invalid(_))));
+}
+
+#[test]
+fn rf_forest_permutation_importance() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..5).map(|_| sample_0_1(&mut rng, 400)).collect();
+    let y_vec: Vec<bool> = xs[0].iter().map(|&x| x == 1).collect();
+    let y = YBool::new(&y_vec);
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let col = |i| SplitColId{col_id: i, shadow: false};
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let forest = rf.fit(&my_df.subset_rows(&Mask::new((0..200).collect())), &YBool::new(&y_vec[..200]), 20, 2, false, None);
+    let held_out = Mask::new((200..400).collect());
+    let mut before = vec![];
+    forest.write_compact(&mut before).unwrap();
+    let res = forest.permutation_importance(&my_df, &y, &held_out, 2);
+    // The forest keeps the streams it was grown with
+    let mut after = vec![];
+    forest.write_compact(&mut after).unwrap();
+    assert_eq!(after, before);
+    // Permuting x0 flips about half of the held-out predictions
+    assert!(res[&col(0)] > 0.3 && res[&col(0)] < 0.7);
+    assert!(res.iter().filter(|(c, _)| c.col_id != 0).all(|(_, &val)| val.abs() < 0.05));
+    assert_eq!(forest.permutation_importance(&my_df, &y, &held_out, 2), res);
+
+    // Cached leaves give the same importance and predictions, a second forest at the same tree indices reads none
+    let cache = ApplyCache::new();
+    for _ in 0..2 {
+        assert_eq!(forest.permutation_importance_cached(&my_df, &y, &held_out, 2, &cache, 1), res);
+        assert_eq!(cache.len(), 20);
+    }
+    assert_eq!(forest.predict_votes_cached(&my_df, &held_out, true, &cache, 1), forest.predict_votes(&my_df, &held_out, true, NaPolicy::Majority));
+    let other = RandomForest::<Y, ColSplitIndex>::new(1).fit(&my_df, &y, 20, 2, false, None);
+    assert_eq!(other.predict_votes_cached(&my_df, &held_out, true, &cache, 1), other.predict_votes(&my_df, &held_out, true, NaPolicy::Majority));
+    assert_eq!(other.permutation_importance_cached(&my_df, &y, &held_out, 2, &cache, 1), other.permutation_importance(&my_df, &y, &held_out, 2));
+}
+
+#[test]
+fn rf_oob_votes() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 200);
+    let xp2 = sample_0_1(&mut rng, 200);
+    let y = YBool::new(&xp1.iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    assert!(rf.fit(&my_df, &y, 5, 2, false, None).get_oob_votes().is_none());
+    rf.set_keep_oob_votes(true);
+    let forest = rf.fit(&my_df, &y, 30, 2, false, None);
+    let oob_votes = forest.get_oob_votes().unwrap();
+
+    assert_eq!(oob_votes.len(), 30);
+    for (tree, tree_votes) in forest.get_trees().iter().zip(oob_votes.iter()) {
+        assert!(tree_votes.oob.len() > 0 && tree_votes.oob.len() < 200);
+        assert_eq!(tree_votes.votes, tree.predict_rows(&my_df, &tree_votes.oob));
+    }
+    // The informative column decides every vote, so each OOB row gets all votes for its class
+    let shares = forest.oob_vote_shares(200, true).unwrap();
+    assert!(shares.iter().all(|share| share.is_some()));
+    assert!(shares.iter().zip(xp1.iter()).all(|(share, &x)| share.unwrap() == x as f64));
+}
+
+#[test]
+fn rf_diagnostics() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..6).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y = YBool::new(&xs[0].iter().zip(xs[1].iter()).map(|(&a, &b)| a == 1 || b == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let diag = rf.diagnostics(&my_df, &y, 50, 2, false, None).unwrap();
+    assert_eq!(diag.margins.len(), 300);
+    assert!(diag.margins.iter().flatten().all(|&m| (-1. ..=1.).contains(&m)));
+    assert!(diag.strength > 0.5 && diag.strength <= 1.);
+    assert!(diag.error_bound.is_finite() && diag.error_bound >= 0.);
+
+    // Same forest as fit with OOB votes kept
+    let mut rf_votes: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    rf_votes.set_keep_oob_votes(true);
+    let forest = rf_votes.fit(&my_df, &y, 50, 2, false, None);
+    assert_eq!(forest.diagnostics(&y).unwrap(), diag);
+
+    // Importance runs grow the same trees, so give the same OOB error without a refit
+    assert!(rf.importance_scored(&my_df, &y, 50, 2, false, None, None).oob_error.is_none());
+    assert_eq!(rf_votes.importance_scored(&my_df, &y, 50, 2, false, None, None).oob_error, forest.oob_error(&y));
+
+    // Stumps on the noise columns barely beat chance
+    let noise = XDf::new(xs[2..].iter().map(|x| new_threeval_col(x)).collect());
+    assert!(rf.diagnostics(&noise, &y, 50, 2, false, Some(1)).unwrap().strength < diag.strength);
+}
+
+#[test]
+fn rf_fit_snapshot() {
+    let mut rng = Rng::new(SEED, 1);
+    let xp1 = sample_0_1(&mut rng, 100);
+    let xp2 = sample_0_1(&mut rng, 100);
+    let y = YBool::new(&xp1.iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(vec![new_threeval_col(&xp1), new_threeval_col(&xp2)]);
+
+    let done = Arc::new(Mutex::new(vec![]));
+    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let done_cb = Arc::clone(&done);
+    rf.set_snapshot(3, move |snapshot| done_cb.lock().unwrap().push((snapshot.n_done(), snapshot.get_ntree())));
+    let forest = rf.fit(&my_df, &y, 10, 2, false, None);
+
+    // No snapshot of the finished forest, fit returns it
+    assert_eq!(*done.lock().unwrap(), [(3, 10), (6, 10), (9, 10)]);
+    assert_eq!(forest.get_trees().len(), 10);
+}
+
+#[test]
+fn rf_null_importance() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..10).map(|_| sample_0_1(&mut rng, 300)).collect();
+    let y = YBool::new(&xs[0].iter().map(|&x| x == 1).collect::<Vec<bool>>());
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let res = rf.null_importance(&my_df, &y, 50, 3, false, None, None);
+    let col = |i| SplitColId{col_id: i, shadow: false};
+
+    assert_eq!(res.real, rf.importance(&my_df, &y, 50, 3, false, None, None));
+    assert_eq!(res.null.len(), 10);
+    assert!(res.null_max > 0. && res.null_max < 0.1);
+    assert!(res.real[&col(0)] > 10. * res.null_max);
+    assert!(!res.within_null.contains(&col(0)));
+    assert!(res.within_null.iter().all(|c| res.real[c] > 0. && res.real[c] <= res.null_max));
+    assert!(res.within_null.windows(2).all(|w| res.real[&w[0]] >= res.real[&w[1]]));
+    assert_eq!(rf.null_importance(&my_df, &y, 50, 3, false, None, None), res);
+}
+
+#[test]
+fn rf_dosage_cols() {
+    let mut rng = Rng::new(SEED, 1);
+    // Dosages on the 0.01 grid, so storage rounding keeps the classes apart
+    let ds: Vec<Vec<f32>> = (0..5).map(|_| (0..300).map(|_| (rng.rand_uni() * 200.).round() as f32 / 100.).collect()).collect();
+    let y = YBool::new(&ds[0].iter().map(|&d| d > 1.3).collect::<Vec<bool>>());
+    let mut cols: Vec<MultiX> = ds.iter().map(|d| MultiX::Dosage(DosageCol::new(d))).collect();
+    cols.push(new_threeval_col(&sample_0_1(&mut rng, 300)));
+    let my_df = XDf::new(cols);
+    let mask = Mask::new((0..300).collect());
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    let forest = rf.fit(&my_df, &y, 20, 2, false, None);
+    let votes = forest.predict_votes(&my_df, &mask, true, NaPolicy::Majority);
+    assert!(votes.iter().zip(ds[0].iter()).all(|(&v, &d)| (v > 0.5) == (d > 1.3)));
+    assert_eq!(forest.predict_votes(&RowMajorXDf::new(&my_df), &mask, true, NaPolicy::Majority), votes);
+
+    let imp = rf.importance(&my_df, &y, 20, 2, false, None, None);
+    let col = |i| SplitColId{col_id: i, shadow: false};
+    assert!((1..6).all(|i| imp[&col(0)] > 10. * imp[&col(i)]));
+
+    let mut out = vec![];
+    forest.write_compact(&mut out).unwrap();
+    let read: Forest<Y, ColSplitIndex> = Forest::read_compact(&mut out.as_slice()).unwrap();
+    assert_eq!(read.predict_votes(&my_df, &mask, true, NaPolicy::Majority), votes);
+}
+
+#[test]
+fn rf_sample_subset() {
+    let mut rng = Rng::new(SEED, 1);
+    let xs: Vec<Vec<i8>> = (0..5).map(|_| sample_0_1(&mut rng, 200)).collect();
+    let y_vec: Vec<bool> = xs[0].iter().map(|&x| x == 1).collect();
+    let my_df = XDf::new(xs.iter().map(|x| new_threeval_col(x)).collect());
+    let y = YBool::new(&y_vec);
+
+    // Every third sample fails QC
+    let kept = Mask::new((0..200).filter(|i| i % 3 != 0).collect());
+    let rebuilt_df = XDf::new(xs.iter().map(|x| new_threeval_col(&kept.get_by_mask(x))).collect());
+    let rebuilt_y = YBool::new(&kept.get_by_mask(&y_vec));
+    assert_eq!(my_df.subset_rows(&kept), rebuilt_df);
+    assert_eq!(y.subset(&kept).as_vector(), rebuilt_y.as_vector());
+
+    let rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
+    assert_eq!(rf.model_hash(&my_df.subset_rows(&kept), &y.subset(&kept), 20, 2, false, None),
+               rf.model_hash(&rebuilt_df, &rebuilt_y, 20, 2, false, None));
+}
@@ -14,59 +14,30 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use variant_forest::data_interface::multi_x::{XDf, MultiX, ColSplitIndex, SplitColId};
-use variant_forest::data_interface::y_bool::{YBool, Y};
-use variant_forest::data_interface::three_val::ThreeValCol;
-use variant_forest::random_forest::RandomForest;
-use variant_forest::tree::Tree;
-use variant_forest::mask::Mask;
-use variant_forest::random_number_generator::Rng;
+// Timing runs of the scenarios in tests/perf_scenarios.txt, or in the file VARIANT_FOREST_PERF_SCENARIOS
+// points to. Run with cargo test --release --features perf --test rf_perf -- --nocapture.
+// Results are appended as JSON lines to VARIANT_FOREST_PERF_OUT, or printed when it is not set.
 
-const SEED: u64 = 139547392210478;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
 
-fn new_threeval_col(x: &[i8]) -> MultiX {
-    return MultiX::ThreeVal(ThreeValCol::new(x));
-}
-
-fn sample_0_1(rng: &mut Rng, k: usize) -> Vec<i8> {
-    (0..k).map(|_| (rng.rand_uni() > 0.5) as i8).collect::<Vec<i8>>()
-}
-
-fn setup(nrow: usize, ncol: usize) -> (XDf, YBool) {
-    let mut rng = Rng::new(SEED, 1);
-    let xp1 = sample_0_1(&mut rng, nrow);
-    let xp2 = sample_0_1(&mut rng, nrow);
-    let xp3 = sample_0_1(&mut rng, nrow);
+use variant_forest::perf::Scenario;
 
-    let y_ins: Vec<bool> = xp1.iter().zip(xp2.iter()).map(|row| *row.0 == 1 && *row.1 == 1).collect();
-    let y = YBool::new(&y_ins);
+const DEFAULT_SCENARIOS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/perf_scenarios.txt");
 
+#[test]
+fn bench_perf() {
+    let path = env::var("VARIANT_FOREST_PERF_SCENARIOS").unwrap_or(DEFAULT_SCENARIOS.to_string());
+    let config = fs::read_to_string(&path).expect("Cannot read scenarios.");
+    let scenarios = Scenario::parse_all(&config).unwrap();
 
-    let mut my_df_vec = vec![];
-
-    for _ in 0..ncol/20 {
-        let xp = sample_0_1(&mut rng, nrow);
-        let x = new_threeval_col(&xp);
-        my_df_vec.push(x);
-    }
+    let mut out: Box<dyn Write> = match env::var("VARIANT_FOREST_PERF_OUT") {
+        Ok(out) => Box::new(OpenOptions::new().create(true).append(true).open(out).unwrap()),
+        Err(_) => Box::new(io::stdout())
+    };
 
-    let mut res = vec![];
-    for _ in 0..20 {
-        res.extend(my_df_vec.clone());
+    for scenario in scenarios.iter() {
+        scenario.run().to_json(&mut out).unwrap();
     }
-    let my_df = XDf::new(res);
-
-    return (my_df, y);
-}
-
-fn rf_importance_performance_big_nrow(my_df: &XDf, y: &YBool, ntree: usize, multithred: Option<usize>) {
-    let mut rf: RandomForest<Y, ColSplitIndex> = RandomForest::new(0);
-    let res = rf.importance(my_df, y, ntree, 31, false, None, multithred);
-}
-
-#[test]
-fn bench_perf() {
-    let (my_df, y) = setup(128_000, 200_000);
-    // const TH: usize = 96;
-    rf_importance_performance_big_nrow(&my_df, &y, 4, None);
 }